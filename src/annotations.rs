@@ -0,0 +1,79 @@
+use rmcp::model::ToolAnnotations;
+
+/// What a built-in tool does to its environment, for clients to decide which calls need confirmation.
+struct Hints {
+    /// Changes nothing outside this server's own bookkeeping such as usage and audit records.
+    read_only: bool,
+    /// May overwrite or remove something, as opposed to only adding.
+    destructive: bool,
+    /// Calling again with the same arguments has no further effect.
+    idempotent: bool,
+    /// Talks to Gemini or another outside service rather than only to this machine.
+    open_world: bool,
+}
+
+const fn read_only(idempotent: bool, open_world: bool) -> Hints {
+    Hints { read_only: true, destructive: false, idempotent, open_world }
+}
+
+const fn writes(destructive: bool, idempotent: bool, open_world: bool) -> Hints {
+    Hints { read_only: false, destructive, idempotent, open_world }
+}
+
+/// Hints for every built-in tool, keyed by tool name.
+const TOOL_HINTS: &[(&str, Hints)] = &[
+    ("gemini_prompt", read_only(false, true)),
+    // Adds the turn to the session
+    ("gemini_chat", writes(false, false, true)),
+    ("gemini_batch_prompt", read_only(false, true)),
+    ("gemini_compare_models", read_only(false, true)),
+    ("gemini_review_diff", read_only(false, true)),
+    ("gemini_search_grounded", read_only(false, true)),
+    // Saves new image files
+    ("gemini_generate_image", writes(false, false, true)),
+    ("gemini_list_models", read_only(true, true)),
+    ("gemini_count_tokens", read_only(true, true)),
+    ("gemini_usage_report", read_only(true, false)),
+    ("gemini_doctor", read_only(true, true)),
+    ("gemini_config", read_only(true, false)),
+    ("gemini_fetch_continuation", read_only(false, false)),
+    // Appends to the CLI's saved conversation
+    ("gemini_resume", writes(false, false, true)),
+    ("gemini_cross_check", read_only(false, true)),
+    ("gemini_audit_search", read_only(true, false)),
+    ("gemini_summarize_url", read_only(false, true)),
+    ("gemini_web_search", read_only(false, true)),
+    ("gemini_list_extensions", read_only(true, false)),
+    ("gemini_install_extension", writes(false, false, true)),
+    // Changes the CLI's settings, but setting the same state twice is harmless
+    ("gemini_enable_extension", writes(true, true, false)),
+    ("gemini_pipeline", read_only(false, true)),
+    ("gemini_summarize_files", read_only(false, true)),
+    // Overwrites the file when asked to write, after backing it up
+    ("gemini_generate_file", writes(true, false, true)),
+    // Changes files in place when asked to apply, after backing them up
+    ("gemini_propose_patch", writes(true, false, true)),
+    ("gemini_generate_tests", writes(true, false, true)),
+    ("gemini_commit_message", read_only(false, true)),
+    ("gemini_pr_description", read_only(false, true)),
+    ("gemini_changelog", read_only(false, true)),
+    ("gemini_explain_error", read_only(false, true)),
+    ("gemini_summarize_logs", read_only(false, true)),
+    ("gemini_sessions_list", read_only(true, false)),
+    ("gemini_session_get", read_only(true, false)),
+    ("gemini_session_delete", writes(true, true, false)),
+    // Replaces the session's previous title
+    ("gemini_session_rename", writes(true, true, false)),
+];
+
+/// The annotations of the built-in tool `name`, if it is one.
+pub fn for_tool(name: &str) -> Option<ToolAnnotations> {
+    let (_, hints) = TOOL_HINTS.iter().find(|(tool, _)| *tool == name)?;
+    Some(ToolAnnotations {
+        title: None,
+        read_only_hint: Some(hints.read_only),
+        destructive_hint: Some(hints.destructive),
+        idempotent_hint: Some(hints.idempotent),
+        open_world_hint: Some(hints.open_world),
+    })
+}
@@ -0,0 +1,77 @@
+use clap::{Parser, Subcommand};
+
+use crate::config::Flags;
+
+/// MCP server exposing Gemini, through the gemini CLI or the Gemini API, as tools.
+#[derive(Debug, Parser)]
+#[command(name = "gemini-cli-mcp", version, args_conflicts_with_subcommands = true)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Without a subcommand the server runs with these flags, as with `serve`
+    #[command(flatten)]
+    serve: Flags,
+}
+
+impl Args {
+    /// The subcommand to run, `serve` when none was given.
+    pub fn command(self) -> Command {
+        self.command.unwrap_or(Command::Serve(self.serve))
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the MCP server (the default)
+    Serve(Flags),
+    /// Check the gemini binary, credentials and a test prompt, and print what to fix
+    Doctor {
+        #[command(flatten)]
+        flags: Flags,
+        /// Skip the test prompt
+        #[arg(long)]
+        skip_prompt: bool,
+        /// Print the checks as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Send prompts through the full request pipeline and print latency percentiles and failure rates
+    Bench {
+        #[command(flatten)]
+        flags: Flags,
+        /// How many prompts to send
+        #[arg(long, default_value_t = 20)]
+        requests: usize,
+        /// How many prompts are in flight at once; `--max-concurrency` still bounds how many run
+        #[arg(long, default_value_t = 4)]
+        concurrency: usize,
+        /// Approximate prompt sizes in tokens, taken in turn, e.g. `100,2000`
+        #[arg(long, value_delimiter = ',', default_value = "100")]
+        sizes: Vec<u32>,
+        /// Models or aliases taken in turn, e.g. `gemini-2.5-flash,gemini-2.5-pro` (default: the default model)
+        #[arg(long, value_delimiter = ',')]
+        models: Vec<String>,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print this server's version and the installed gemini CLI's
+    Version {
+        #[command(flatten)]
+        flags: Flags,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Load and validate the configuration, then print the settings in effect
+    Check {
+        #[command(flatten)]
+        flags: Flags,
+    },
+}
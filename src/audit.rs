@@ -0,0 +1,192 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rmcp::model::CallToolResult;
+use rmcp::Error as McpError;
+
+/// Longest prompt or response excerpt kept per entry, in bytes.
+const EXCERPT_BYTES: usize = 2000;
+
+/// Arguments that hold a tool's prompt, in order of preference.
+const PROMPT_ARGS: &[&str] = &["prompt", "message", "query", "text", "prompts"];
+
+/// One tool call as recorded in the audit log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub tool: String,
+    /// The id the call's log lines and result carry; missing in entries written
+    /// before calls had one.
+    pub request_id: Option<String>,
+    /// MCP client name of the caller.
+    pub client: Option<String>,
+    /// SHA-256 of the call's arguments, so identical calls can be matched
+    /// without the log holding everything they contained.
+    pub args_sha256: String,
+    pub model: Option<String>,
+    /// Start of the prompt, redacted.
+    pub prompt: Option<String>,
+    /// Start of the response text, redacted.
+    pub response: Option<String>,
+    /// `ok`, `tool_error` for results flagged as errors, or `error`.
+    pub status: String,
+    /// `data.kind` of a failed call, e.g. `timeout`.
+    pub error_kind: Option<String>,
+    pub duration_ms: u64,
+}
+
+impl AuditEntry {
+    /// Describe a finished call of `tool` with `args`.
+    pub fn new(
+        tool: String,
+        request_id: String,
+        client: Option<String>,
+        args: &serde_json::Value,
+        model: Option<String>,
+        result: &Result<CallToolResult, McpError>,
+        elapsed: Duration,
+    ) -> Self {
+        let prompt = PROMPT_ARGS.iter().find_map(|name| match &args[*name] {
+            serde_json::Value::String(prompt) => Some(prompt.clone()),
+            serde_json::Value::Array(prompts) => Some(
+                prompts.iter().filter_map(|prompt| prompt.as_str()).collect::<Vec<_>>().join("\n---\n"),
+            ),
+            _ => None,
+        });
+        let (status, response, error_kind) = match result {
+            Ok(result) => {
+                let status = if result.is_error == Some(true) { "tool_error" } else { "ok" };
+                let text = result.content.iter().find_map(|content| content.as_text()).map(|text| excerpt(&text.text));
+                (status, text, None)
+            }
+            Err(error) => {
+                let kind = error.data.as_ref().and_then(|data| data["kind"].as_str()).map(str::to_string);
+                ("error", Some(excerpt(&error.message)), kind)
+            }
+        };
+
+        Self {
+            timestamp: Utc::now(),
+            tool,
+            request_id: Some(request_id),
+            client,
+            args_sha256: hash_args(args),
+            model,
+            prompt: prompt.as_deref().map(excerpt),
+            response,
+            status: status.to_string(),
+            error_kind,
+            duration_ms: elapsed.as_millis() as u64,
+        }
+    }
+}
+
+/// Which entries `gemini_audit_search` returns.
+#[derive(Debug, Default)]
+pub struct AuditQuery<'a> {
+    /// Case-insensitive text to find in the prompt or response.
+    pub text: Option<&'a str>,
+    pub tool: Option<&'a str>,
+    pub request_id: Option<&'a str>,
+    pub status: Option<&'a str>,
+    pub since: Option<DateTime<Utc>>,
+    pub limit: usize,
+}
+
+/// Append-only JSONL record of every tool call, for teams that need to
+/// account for what was sent to Gemini.
+pub struct AuditLog {
+    path: PathBuf,
+    file: Mutex<std::fs::File>,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create audit log directory {}", dir.display()))?;
+        }
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open audit log {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn record(&self, entry: &AuditEntry) {
+        let mut line = match serde_json::to_string(entry) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::warn!("Failed to serialize audit entry: {}", e);
+                return;
+            }
+        };
+        line.push('\n');
+        // One write per entry keeps lines whole even with other processes appending
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = file.write_all(line.as_bytes()).and_then(|_| file.flush()) {
+            tracing::warn!("Failed to write audit log {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Entries matching `query`, newest first.
+    pub fn search(&self, query: &AuditQuery<'_>) -> Result<Vec<AuditEntry>> {
+        let file = std::fs::File::open(&self.path)
+            .with_context(|| format!("Failed to open audit log {}", self.path.display()))?;
+        let text = query.text.map(str::to_lowercase);
+        let contains = |field: &Option<String>, text: &str| {
+            field.as_deref().is_some_and(|field| field.to_lowercase().contains(text))
+        };
+
+        let mut matches = Vec::new();
+        for line in std::io::BufReader::new(file).lines() {
+            let line = line.context("Failed to read audit log")?;
+            let Ok(entry) = serde_json::from_str::<AuditEntry>(&line) else {
+                continue;
+            };
+            if query.tool.is_some_and(|tool| entry.tool != tool)
+                || query.request_id.is_some_and(|id| entry.request_id.as_deref() != Some(id))
+                || query.status.is_some_and(|status| entry.status != status)
+                || query.since.is_some_and(|since| entry.timestamp < since)
+                || text.as_deref().is_some_and(|text| !contains(&entry.prompt, text) && !contains(&entry.response, text))
+            {
+                continue;
+            }
+            matches.push(entry);
+        }
+
+        matches.reverse();
+        matches.truncate(query.limit);
+        Ok(matches)
+    }
+}
+
+/// Hex SHA-256 of the call's arguments; serde_json keeps object keys sorted, so
+/// the same arguments always hash alike.
+fn hash_args(args: &serde_json::Value) -> String {
+    let digest = Sha256::digest(args.to_string().as_bytes());
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The redacted start of `text`, cut at a character boundary.
+fn excerpt(text: &str) -> String {
+    let redacted = crate::redact::redact(text);
+    if redacted.len() <= EXCERPT_BYTES {
+        return redacted.into_owned();
+    }
+    let mut end = EXCERPT_BYTES;
+    while !redacted.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &redacted[..end])
+}
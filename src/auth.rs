@@ -0,0 +1,107 @@
+/// Why gemini could not authenticate, as far as its error output tells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthProblem {
+    /// No auth method is configured at all.
+    MissingCredentials,
+    InvalidApiKey,
+    /// The Google login's OAuth token has expired or was revoked.
+    ExpiredLogin,
+    /// A Vertex AI or Code Assist login without `GOOGLE_CLOUD_PROJECT`.
+    MissingProject,
+    /// The account or project may not use the Gemini API.
+    PermissionDenied,
+    /// Gemini rejected the credentials without saying why.
+    Unauthenticated,
+}
+
+/// Markers, matched case-insensitively against gemini's error output, in the
+/// order they are tried: the specific ones come before the generic ones.
+const MARKERS: &[(AuthProblem, &[&str])] = &[
+    (AuthProblem::InvalidApiKey, &["api key not valid", "api_key_invalid", "api key expired"]),
+    (AuthProblem::ExpiredLogin, &["invalid_grant", "token has been expired or revoked", "refresh token", "reauthenticate"]),
+    (AuthProblem::MissingProject, &["google_cloud_project", "requires a project", "project id is required"]),
+    (
+        AuthProblem::MissingCredentials,
+        &["please set an auth method", "no auth type", "gemini_api_key environment variable not found", "must specify the gemini_api_key"],
+    ),
+    (AuthProblem::PermissionDenied, &["permission_denied", "permission denied", "does not have permission"]),
+    (AuthProblem::Unauthenticated, &["unauthenticated", "invalid authentication credentials"]),
+];
+
+impl AuthProblem {
+    fn description(self) -> &'static str {
+        match self {
+            Self::MissingCredentials => "no credentials are configured",
+            Self::InvalidApiKey => "the API key is not valid",
+            Self::ExpiredLogin => "the Google login has expired",
+            Self::MissingProject => "no Google Cloud project is set",
+            Self::PermissionDenied => "the account may not use the Gemini API",
+            Self::Unauthenticated => "the credentials were rejected",
+        }
+    }
+
+    /// The exact step that fixes the problem.
+    pub fn remediation(self) -> &'static str {
+        match self {
+            Self::MissingCredentials => {
+                "Set GEMINI_API_KEY to a key from https://aistudio.google.com/apikey, or run `gemini` once interactively to log in with Google"
+            }
+            Self::InvalidApiKey => "Replace GEMINI_API_KEY with a valid key from https://aistudio.google.com/apikey",
+            Self::ExpiredLogin => {
+                "Run `gemini` interactively and log in again with `/auth`, or delete ~/.gemini/oauth_creds.json and restart it"
+            }
+            Self::MissingProject => "Set GOOGLE_CLOUD_PROJECT to the ID of your Google Cloud project",
+            Self::PermissionDenied => {
+                "Check that GOOGLE_CLOUD_PROJECT names a project with the Gemini API enabled that your account can use"
+            }
+            Self::Unauthenticated => {
+                "Check GEMINI_API_KEY, or run `gemini` interactively and log in again with `/auth`"
+            }
+        }
+    }
+
+    /// The environment variable the remediation is about, if any.
+    pub fn env_var(self) -> Option<&'static str> {
+        match self {
+            Self::MissingCredentials | Self::InvalidApiKey | Self::Unauthenticated => Some("GEMINI_API_KEY"),
+            Self::MissingProject | Self::PermissionDenied => Some("GOOGLE_CLOUD_PROJECT"),
+            Self::ExpiredLogin => None,
+        }
+    }
+}
+
+/// Returned when gemini failed because of its credentials.
+#[derive(Debug)]
+pub struct AuthError {
+    pub problem: AuthProblem,
+    /// What gemini itself said.
+    pub detail: String,
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Gemini authentication failed: {}. {}. Gemini said: {}",
+            self.problem.description(),
+            self.problem.remediation(),
+            self.detail
+        )
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+/// Recognize an authentication failure in gemini's error output.
+pub fn diagnose(output: &str) -> Option<AuthError> {
+    let lower = output.to_lowercase();
+    let problem = MARKERS
+        .iter()
+        .find(|(_, markers)| markers.iter().any(|marker| lower.contains(marker)))
+        .map(|(problem, _)| *problem)?;
+    Some(AuthError {
+        problem,
+        detail: output.trim().to_string(),
+    })
+}
@@ -0,0 +1,802 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use super::{
+    CancelledError, Citation, ExecOptions, GenerateRequest, GenerateResponse, Grounding, TimeoutError, Usage,
+    DEFAULT_MODEL,
+};
+use crate::config::{Config, VertexConfig};
+use crate::format::OutputFormat;
+use crate::functions::{FunctionCall, FunctionDeclaration};
+use crate::models::{self, ModelInfo};
+use crate::progress::ProgressReporter;
+use crate::safety::{SafetyBlock, SafetyRating, BLOCKING_FINISH_REASONS};
+use crate::tokens;
+
+/// How long an access token from gcloud is reused; they are valid for an hour.
+const ACCESS_TOKEN_LIFETIME: Duration = Duration::from_secs(45 * 60);
+
+/// Calls the `generativelanguage.googleapis.com` REST API, or the same API on Vertex AI, directly.
+pub struct ApiBackend {
+    client: reqwest::Client,
+    credentials: Credentials,
+    base_url: String,
+    vertex: bool,
+}
+
+/// How requests are authenticated.
+enum Credentials {
+    /// Sent as `x-goog-api-key`, for the Gemini API and Vertex AI express mode.
+    ApiKey(String),
+    /// OAuth tokens for the application default credentials, which Vertex AI projects need.
+    AccessToken(tokio::sync::Mutex<Option<(String, Instant)>>),
+}
+
+impl ApiBackend {
+    /// Build a backend from `GEMINI_API_KEY` (or `GOOGLE_API_KEY`) and the configured endpoint,
+    /// or from the `vertex` settings.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let (credentials, base_url) = match &config.vertex {
+            Some(vertex) => vertex_endpoint(vertex)?,
+            None => {
+                let api_key = std::env::var("GEMINI_API_KEY")
+                    .or_else(|_| std::env::var("GOOGLE_API_KEY"))
+                    .context("The api backend requires GEMINI_API_KEY or GOOGLE_API_KEY to be set")?;
+                (Credentials::ApiKey(api_key), config.api_base_url.trim_end_matches('/').to_string())
+            }
+        };
+
+        Ok(Self {
+            client: reqwest::Client::new(),
+            credentials,
+            base_url,
+            vertex: config.vertex.is_some(),
+        })
+    }
+
+    /// Add the credentials to `request`; `api_key` from the request's profile replaces a configured key.
+    async fn authorize(&self, request: reqwest::RequestBuilder, api_key: Option<&str>) -> Result<reqwest::RequestBuilder> {
+        match &self.credentials {
+            Credentials::ApiKey(key) => Ok(request.header("x-goog-api-key", api_key.unwrap_or(key))),
+            Credentials::AccessToken(cached) => Ok(request.bearer_auth(access_token(cached).await?)),
+        }
+    }
+
+    pub async fn generate(&self, request: &GenerateRequest, options: ExecOptions<'_>) -> Result<GenerateResponse> {
+        let model = request.model.as_deref().unwrap_or(DEFAULT_MODEL);
+        let url = format!("{}/models/{}:streamGenerateContent?alt=sse", self.base_url, model);
+        let body = GenerateContentRequest::from_request(request);
+        let api_key = request.api_key();
+
+        tracing::debug!("Calling Gemini API model {}", model);
+
+        // Dropping the in-flight request future aborts the HTTP call
+        let streamed = tokio::select! {
+            streamed = tokio::time::timeout(options.timeout, self.stream(&url, api_key, &body, options.progress)) => Some(streamed),
+            _ = options.ct.cancelled() => None,
+        };
+
+        match streamed {
+            Some(Ok(response)) => response,
+            Some(Err(_)) => Err(TimeoutError::new(options.timeout).into()),
+            None => Err(CancelledError.into()),
+        }
+    }
+
+    /// Count the tokens `text` occupies for `model` using the countTokens API.
+    pub async fn count_tokens(&self, model: &str, text: &str) -> Result<u32> {
+        let body = CountTokensRequest {
+            contents: vec![RequestContent {
+                role: "user",
+                parts: vec![RequestPart::Text { text }],
+            }],
+        };
+
+        let response = self.authorize(self.client.post(format!("{}/models/{}:countTokens", self.base_url, model)), None)
+            .await?
+            .json(&body)
+            .send()
+            .await
+            .context("Failed to send countTokens request")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("Gemini API countTokens failed ({}): {}", status, body);
+        }
+
+        let counted: CountTokensResponse = response.json().await
+            .context("Failed to parse countTokens response")?;
+        Ok(counted.total_tokens)
+    }
+
+    /// Generate images for `prompt` with an Imagen model.
+    pub async fn generate_image(&self, request: &ImageRequest<'_>, options: ExecOptions<'_>) -> Result<Vec<GeneratedImage>> {
+        let body = PredictRequest {
+            instances: vec![PredictInstance { prompt: request.prompt }],
+            parameters: PredictParameters {
+                sample_count: request.count,
+                aspect_ratio: request.aspect_ratio,
+            },
+        };
+
+        tracing::debug!("Calling Imagen model {}", request.model);
+
+        let send = async {
+            let response = self.authorize(self.client.post(format!("{}/models/{}:predict", self.base_url, request.model)), None)
+                .await?
+                .json(&body)
+                .send()
+                .await
+                .context("Failed to send image generation request")?;
+
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await.unwrap_or_default();
+                anyhow::bail!("Gemini API image generation failed ({}): {}", status, body);
+            }
+
+            let predicted: PredictResponse = response.json().await
+                .context("Failed to parse image generation response")?;
+            predicted.predictions
+                .into_iter()
+                .map(|prediction| {
+                    let data = base64::engine::general_purpose::STANDARD
+                        .decode(prediction.bytes_base64_encoded)
+                        .context("Image generation returned invalid base64 data")?;
+                    Ok(GeneratedImage {
+                        mime_type: prediction.mime_type.unwrap_or_else(|| "image/png".to_string()),
+                        data,
+                    })
+                })
+                .collect()
+        };
+
+        let sent = tokio::select! {
+            sent = tokio::time::timeout(options.timeout, send) => Some(sent),
+            _ = options.ct.cancelled() => None,
+        };
+
+        match sent {
+            Some(Ok(images)) => images,
+            Some(Err(_)) => Err(TimeoutError::new(options.timeout).into()),
+            None => Err(CancelledError.into()),
+        }
+    }
+
+    /// Enumerate the models available to this API key.
+    pub async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        if self.vertex {
+            anyhow::bail!("Vertex AI does not list its models");
+        }
+        let mut models = Vec::new();
+        let mut page_token: Option<String> = None;
+
+        loop {
+            let mut request = self.authorize(self.client.get(format!("{}/models", self.base_url)), None)
+                .await?
+                .query(&[("pageSize", "1000")]);
+            if let Some(token) = &page_token {
+                request = request.query(&[("pageToken", token.as_str())]);
+            }
+
+            let response = request.send().await
+                .context("Failed to list Gemini models")?;
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await.unwrap_or_default();
+                anyhow::bail!("Gemini API model listing failed ({}): {}", status, body);
+            }
+
+            let page: ListModelsResponse = response.json().await
+                .context("Failed to parse Gemini model listing")?;
+
+            models.extend(page.models.into_iter().map(ApiModel::into_info));
+
+            match page.next_page_token {
+                Some(token) if !token.is_empty() => page_token = Some(token),
+                _ => break,
+            }
+        }
+
+        Ok(models)
+    }
+
+    /// Send the request and accumulate the server-sent event stream, forwarding text deltas.
+    async fn stream(
+        &self,
+        url: &str,
+        api_key: Option<&str>,
+        body: &GenerateContentRequest<'_>,
+        progress: Option<&ProgressReporter>,
+    ) -> Result<GenerateResponse> {
+        let response = self.authorize(self.client.post(url), api_key)
+            .await?
+            .json(body)
+            .send()
+            .await
+            .context("Failed to send Gemini API request")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            if let Some(auth) = crate::auth::diagnose(&body) {
+                return Err(auth.into());
+            }
+            anyhow::bail!("Gemini API request failed ({}): {}", status, body);
+        }
+
+        let mut events = response.bytes_stream();
+        let mut buffer = Vec::new();
+        let mut text = String::new();
+        let mut usage = None;
+        let mut safety = None;
+        let mut grounding = None;
+        let mut function_calls = Vec::new();
+
+        while let Some(chunk) = events.next().await {
+            let chunk = chunk.context("Failed to read Gemini API response")?;
+            buffer.extend_from_slice(&chunk);
+
+            while let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=pos).collect();
+                let line = String::from_utf8_lossy(&line);
+                let Some(data) = line.trim().strip_prefix("data:") else {
+                    continue;
+                };
+
+                let event: GenerateContentResponse = serde_json::from_str(data.trim())
+                    .context("Failed to parse Gemini API response")?;
+
+                // Every event carries the running totals, so the last one wins
+                if let Some(metadata) = &event.usage_metadata {
+                    usage = Some(Usage {
+                        prompt_tokens: metadata.prompt_token_count,
+                        completion_tokens: metadata.candidates_token_count,
+                        estimated: false,
+                    });
+                }
+
+                if let Some(block) = event.safety_block() {
+                    safety = Some(block);
+                }
+
+                // Grounding metadata may be split across events, so merge everything seen
+                if let Some(metadata) = event.candidates.first().and_then(|c| c.grounding_metadata.as_ref()) {
+                    metadata.merge_into(grounding.get_or_insert_with(Grounding::default));
+                }
+
+                function_calls.extend(event.function_calls().cloned());
+
+                let delta = event.text();
+                if delta.is_empty() {
+                    continue;
+                }
+
+                text.push_str(&delta);
+                if let Some(progress) = progress {
+                    progress.report(delta).await;
+                }
+            }
+        }
+
+        let text = text.trim().to_owned();
+        let usage = usage.unwrap_or_else(|| Usage {
+            prompt_tokens: body.estimate_tokens(),
+            completion_tokens: tokens::estimate(&text),
+            estimated: true,
+        });
+
+        Ok(GenerateResponse {
+            text,
+            usage,
+            safety,
+            grounding,
+            function_calls,
+            ..Default::default()
+        })
+    }
+}
+
+/// The credentials and base URL for `vertex`: the project's regional endpoint with
+/// access tokens, or express mode with `GOOGLE_API_KEY`.
+fn vertex_endpoint(vertex: &VertexConfig) -> Result<(Credentials, String)> {
+    let Some(project) = &vertex.project else {
+        let api_key = std::env::var("GOOGLE_API_KEY")
+            .context("Vertex AI needs vertex.project (GOOGLE_CLOUD_PROJECT), or GOOGLE_API_KEY for express mode")?;
+        return Ok((Credentials::ApiKey(api_key), "https://aiplatform.googleapis.com/v1/publishers/google".to_string()));
+    };
+    let location = vertex.location();
+    let host = match location {
+        "global" => "aiplatform.googleapis.com".to_string(),
+        region => format!("{}-aiplatform.googleapis.com", region),
+    };
+    let base_url = format!("https://{}/v1/projects/{}/locations/{}/publishers/google", host, project, location);
+    Ok((Credentials::AccessToken(tokio::sync::Mutex::new(None)), base_url))
+}
+
+/// An access token for the application default credentials, from gcloud, reused until
+/// shortly before it expires.
+async fn access_token(cached: &tokio::sync::Mutex<Option<(String, Instant)>>) -> Result<String> {
+    let mut cached = cached.lock().await;
+    if let Some((token, fetched)) = cached.as_ref() {
+        if fetched.elapsed() < ACCESS_TOKEN_LIFETIME {
+            return Ok(token.clone());
+        }
+    }
+
+    let output = tokio::process::Command::new("gcloud")
+        .args(["auth", "application-default", "print-access-token"])
+        .kill_on_drop(true)
+        .output()
+        .await
+        .context("Vertex AI needs gcloud on PATH to get access tokens")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "`gcloud auth application-default print-access-token` failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let token = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    *cached = Some((token.clone(), Instant::now()));
+    Ok(token)
+}
+
+/// Parameters for an Imagen call.
+pub struct ImageRequest<'a> {
+    pub model: &'a str,
+    pub prompt: &'a str,
+    pub count: u32,
+    /// E.g. `1:1` or `16:9`; the model default when unset.
+    pub aspect_ratio: Option<&'a str>,
+}
+
+/// One image produced by Imagen.
+pub struct GeneratedImage {
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerateContentRequest<'a> {
+    contents: Vec<RequestContent<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<SystemInstruction<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generation_config: Option<GenerationConfig<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    safety_settings: Vec<ApiSafetySetting>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<ApiTool<'a>>,
+}
+
+impl<'a> GenerateContentRequest<'a> {
+    fn from_request(request: &'a GenerateRequest) -> Self {
+        let json = request.output_format == OutputFormat::Json;
+        let sampling = request.max_tokens.is_some()
+            || request.temperature.is_some()
+            || request.top_p.is_some()
+            || request.top_k.is_some()
+            || !request.stop_sequences.is_empty()
+            || request.seed.is_some();
+        let generation_config = if sampling || json {
+            Some(GenerationConfig {
+                max_output_tokens: request.max_tokens,
+                temperature: request.temperature,
+                top_p: request.top_p,
+                top_k: request.top_k,
+                stop_sequences: &request.stop_sequences,
+                seed: request.seed,
+                response_mime_type: json.then_some("application/json"),
+            })
+        } else {
+            None
+        };
+
+        // Images go first, as Gemini recommends for multimodal prompts
+        let mut parts: Vec<RequestPart> = request.images
+            .iter()
+            .map(|image| RequestPart::InlineData {
+                inline_data: InlineData {
+                    mime_type: image.mime_type,
+                    data: base64::engine::general_purpose::STANDARD.encode(&image.data),
+                },
+            })
+            .collect();
+        parts.push(RequestPart::Text { text: &request.prompt });
+        let mut contents = vec![RequestContent {
+            role: "user",
+            parts,
+        }];
+
+        // Continuing after function calls replays them, followed by what they returned
+        if !request.function_results.is_empty() {
+            contents.push(RequestContent {
+                role: "model",
+                parts: request.function_results
+                    .iter()
+                    .map(|result| RequestPart::FunctionCall {
+                        function_call: ApiFunctionCall { name: &result.name, args: &result.args },
+                    })
+                    .collect(),
+            });
+            contents.push(RequestContent {
+                role: "user",
+                parts: request.function_results
+                    .iter()
+                    .map(|result| RequestPart::FunctionResponse {
+                        function_response: ApiFunctionResponse {
+                            name: &result.name,
+                            response: match &result.response {
+                                serde_json::Value::Object(_) => result.response.clone(),
+                                other => serde_json::json!({ "result": other }),
+                            },
+                        },
+                    })
+                    .collect(),
+            });
+        }
+
+        let mut tools = Vec::new();
+        if request.google_search {
+            tools.push(ApiTool { google_search: Some(GoogleSearch {}), function_declarations: &[] });
+        }
+        if !request.functions.is_empty() {
+            tools.push(ApiTool { google_search: None, function_declarations: &request.functions });
+        }
+
+        Self {
+            contents,
+            system_instruction: request.system_instruction.as_deref().map(|text| SystemInstruction {
+                parts: vec![RequestPart::Text { text }],
+            }),
+            generation_config,
+            safety_settings: request.safety_settings
+                .as_ref()
+                .map(|settings| {
+                    settings.api_settings()
+                        .into_iter()
+                        .map(|(category, threshold)| ApiSafetySetting { category, threshold })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            tools,
+        }
+    }
+
+    /// Estimated prompt tokens, for when Gemini reports no usage.
+    fn estimate_tokens(&self) -> u32 {
+        self.contents
+            .iter()
+            .flat_map(|content| content.parts.iter())
+            .map(|part| match part {
+                RequestPart::Text { text } => tokens::estimate(text),
+                RequestPart::InlineData { .. } => tokens::IMAGE_TOKENS,
+                RequestPart::FunctionCall { function_call } => tokens::estimate(&function_call.args.to_string()),
+                RequestPart::FunctionResponse { function_response } => tokens::estimate(&function_response.response.to_string()),
+            })
+            .sum()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ApiSafetySetting {
+    category: &'static str,
+    threshold: &'static str,
+}
+
+/// One kind of tool per entry, as the API expects.
+#[derive(Debug, Serialize)]
+struct ApiTool<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    google_search: Option<GoogleSearch>,
+    #[serde(rename = "functionDeclarations", skip_serializing_if = "<[_]>::is_empty")]
+    function_declarations: &'a [FunctionDeclaration],
+}
+
+/// Serialized as `{}`; the tool takes no options.
+#[derive(Debug, Serialize)]
+struct GoogleSearch {}
+
+#[derive(Debug, Serialize)]
+struct CountTokensRequest<'a> {
+    contents: Vec<RequestContent<'a>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CountTokensResponse {
+    #[serde(default)]
+    total_tokens: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct RequestContent<'a> {
+    role: &'static str,
+    parts: Vec<RequestPart<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct SystemInstruction<'a> {
+    parts: Vec<RequestPart<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum RequestPart<'a> {
+    Text {
+        text: &'a str,
+    },
+    InlineData {
+        #[serde(rename = "inlineData")]
+        inline_data: InlineData,
+    },
+    FunctionCall {
+        #[serde(rename = "functionCall")]
+        function_call: ApiFunctionCall<'a>,
+    },
+    FunctionResponse {
+        #[serde(rename = "functionResponse")]
+        function_response: ApiFunctionResponse<'a>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct ApiFunctionCall<'a> {
+    name: &'a str,
+    args: &'a serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+struct ApiFunctionResponse<'a> {
+    name: &'a str,
+    /// Always an object.
+    response: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InlineData {
+    mime_type: &'static str,
+    /// Base64-encoded bytes.
+    data: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerationConfig<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_output_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_k: Option<u32>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    stop_sequences: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_mime_type: Option<&'static str>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerateContentResponse {
+    #[serde(default)]
+    candidates: Vec<Candidate>,
+    #[serde(default)]
+    usage_metadata: Option<UsageMetadata>,
+    #[serde(default)]
+    prompt_feedback: Option<PromptFeedback>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PromptFeedback {
+    #[serde(default)]
+    block_reason: Option<String>,
+    #[serde(default)]
+    safety_ratings: Vec<SafetyRating>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageMetadata {
+    #[serde(default)]
+    prompt_token_count: u32,
+    #[serde(default)]
+    candidates_token_count: u32,
+}
+
+impl GenerateContentResponse {
+    /// Concatenated text of the first candidate.
+    fn text(&self) -> String {
+        self.candidates
+            .first()
+            .and_then(|candidate| candidate.content.as_ref())
+            .map(|content| {
+                content.parts
+                    .iter()
+                    .filter_map(|part| part.text.as_deref())
+                    .collect::<String>()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Function calls in the first candidate.
+    fn function_calls(&self) -> impl Iterator<Item = &FunctionCall> {
+        self.candidates
+            .first()
+            .and_then(|candidate| candidate.content.as_ref())
+            .into_iter()
+            .flat_map(|content| content.parts.iter())
+            .filter_map(|part| part.function_call.as_ref())
+    }
+
+    /// Why the prompt or the first candidate was blocked, if it was.
+    fn safety_block(&self) -> Option<SafetyBlock> {
+        if let Some(feedback) = &self.prompt_feedback {
+            if let Some(reason) = &feedback.block_reason {
+                return Some(SafetyBlock {
+                    blocked: "prompt",
+                    reason: reason.clone(),
+                    ratings: feedback.safety_ratings.clone(),
+                });
+            }
+        }
+
+        let candidate = self.candidates.first()?;
+        let reason = candidate.finish_reason.as_deref()?;
+        BLOCKING_FINISH_REASONS.contains(&reason).then(|| SafetyBlock {
+            blocked: "response",
+            reason: reason.to_string(),
+            ratings: candidate.safety_ratings.clone(),
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Candidate {
+    #[serde(default)]
+    content: Option<ResponseContent>,
+    #[serde(default)]
+    finish_reason: Option<String>,
+    #[serde(default)]
+    safety_ratings: Vec<SafetyRating>,
+    #[serde(default)]
+    grounding_metadata: Option<GroundingMetadata>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GroundingMetadata {
+    #[serde(default)]
+    web_search_queries: Vec<String>,
+    #[serde(default)]
+    grounding_chunks: Vec<GroundingChunk>,
+}
+
+impl GroundingMetadata {
+    /// Add queries and sources not already present in `grounding`.
+    fn merge_into(&self, grounding: &mut Grounding) {
+        for query in &self.web_search_queries {
+            if !grounding.search_queries.contains(query) {
+                grounding.search_queries.push(query.clone());
+            }
+        }
+        for web in self.grounding_chunks.iter().filter_map(|chunk| chunk.web.as_ref()) {
+            if !grounding.citations.iter().any(|citation| citation.uri == web.uri) {
+                grounding.citations.push(Citation {
+                    title: web.title.clone(),
+                    uri: web.uri.clone(),
+                });
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct GroundingChunk {
+    #[serde(default)]
+    web: Option<WebChunk>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct WebChunk {
+    #[serde(default)]
+    uri: String,
+    #[serde(default)]
+    title: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ResponseContent {
+    #[serde(default)]
+    parts: Vec<ResponsePart>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ResponsePart {
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default, rename = "functionCall")]
+    function_call: Option<FunctionCall>,
+}
+
+#[derive(Debug, Serialize)]
+struct PredictRequest<'a> {
+    instances: Vec<PredictInstance<'a>>,
+    parameters: PredictParameters<'a>,
+}
+
+#[derive(Debug, Serialize)]
+struct PredictInstance<'a> {
+    prompt: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PredictParameters<'a> {
+    sample_count: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aspect_ratio: Option<&'a str>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PredictResponse {
+    /// Omitted entirely when every image was filtered out.
+    #[serde(default)]
+    predictions: Vec<Prediction>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Prediction {
+    bytes_base64_encoded: String,
+    #[serde(default)]
+    mime_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListModelsResponse {
+    #[serde(default)]
+    models: Vec<ApiModel>,
+    #[serde(default)]
+    next_page_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiModel {
+    name: String,
+    #[serde(default)]
+    display_name: Option<String>,
+    #[serde(default)]
+    input_token_limit: u32,
+    #[serde(default)]
+    output_token_limit: u32,
+}
+
+impl ApiModel {
+    fn into_info(self) -> ModelInfo {
+        let name = self.name.strip_prefix("models/").unwrap_or(&self.name).to_string();
+        let (input_modalities, output_modalities) = models::modalities_for(&name);
+
+        ModelInfo {
+            display_name: self.display_name.unwrap_or_else(|| name.clone()),
+            name,
+            input_token_limit: self.input_token_limit,
+            output_token_limit: self.output_token_limit,
+            input_modalities,
+            output_modalities,
+        }
+    }
+}
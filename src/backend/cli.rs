@@ -0,0 +1,884 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::Serialize;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Child;
+use tracing::Instrument;
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use super::pool::{PoolKey, WarmPool, WorkerExitedError};
+use super::version::{CliTooOldError, CliVersion, Feature};
+use super::{BinaryMissingError, CancelledError, ExecOptions, GenerateRequest, GenerateResponse, StalledError, TimeoutError, Usage};
+use crate::children;
+use crate::config::{Config, VertexConfig};
+use crate::files::Image;
+use crate::format::OutputFormat;
+use crate::launcher::Launcher;
+use crate::progress::ProgressReporter;
+use crate::tokens;
+
+/// stderr lines the CLI prints on every run, which say nothing about the request.
+const STDERR_NOISE: &[&str] = &[
+    "Loaded cached credentials.",
+    "Data collection is disabled.",
+    "Flushing log events to Clearcut.",
+];
+
+/// Most stderr lines attached to a result as warnings.
+const MAX_WARNINGS: usize = 20;
+
+/// Longest prompt passed with `--prompt`; longer ones are piped through stdin.
+///
+/// Linux caps a single argument at 128 KiB and Windows a whole command line
+/// at 32K characters, so stay under both.
+const ARGV_PROMPT_MAX_BYTES: usize = 30 * 1024;
+
+/// How long `gemini --version` may take at startup.
+const VERSION_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How long a stalled process has to exit after SIGTERM before it is killed.
+const TERM_GRACE: Duration = Duration::from_secs(5);
+
+/// Runs each request through a freshly spawned `gemini` CLI process.
+pub struct CliBackend {
+    launcher: Launcher,
+    /// Passed before the per-request arguments, e.g. the package name for `npx`.
+    prefix_args: Vec<String>,
+    /// Default for requests that don't choose whether to sandbox.
+    sandbox: bool,
+    /// Names of the server's environment variables the child may see.
+    env_allowlist: Vec<String>,
+    /// Set for every process, e.g. to select Vertex AI; request variables take precedence.
+    base_env: Vec<(String, String)>,
+    /// Warm processes to send prompts to instead of spawning, when enabled.
+    pool: Option<Arc<WarmPool>>,
+    /// What `gemini --version` reported; `None` inside when it could not be told.
+    version: OnceLock<Option<CliVersion>>,
+    /// How long a process may write nothing before it is stopped as hung.
+    stall_timeout: Option<Duration>,
+    /// Whether a timed-out request is answered with the output written so far.
+    partial_on_timeout: bool,
+}
+
+impl CliBackend {
+    pub fn new(config: &Config) -> Self {
+        let launcher = Launcher::from_config(config);
+        Self {
+            pool: (config.warm_pool_size > 0).then(|| WarmPool::new(config, launcher.clone())),
+            launcher,
+            prefix_args: config.gemini_args.clone(),
+            sandbox: config.sandbox,
+            env_allowlist: config.env_allowlist.clone(),
+            base_env: config.vertex.as_ref().map(VertexConfig::env).unwrap_or_default(),
+            version: OnceLock::new(),
+            stall_timeout: (config.stall_timeout_secs > 0).then(|| Duration::from_secs(config.stall_timeout_secs)),
+            partial_on_timeout: config.partial_on_timeout,
+        }
+    }
+
+    /// Ask the CLI for its version, once; later requests are checked against it.
+    ///
+    /// Returns `None` when the version could not be told, in which case no flag is gated.
+    pub async fn detect_version(&self) -> Option<CliVersion> {
+        if let Some(version) = self.version.get() {
+            return *version;
+        }
+        let mut cmd = self.command(&["--version".to_string()], &[], None);
+        cmd.kill_on_drop(true);
+        let version = match tokio::time::timeout(VERSION_TIMEOUT, cmd.output()).await {
+            Ok(Ok(output)) if output.status.success() => CliVersion::parse(&String::from_utf8_lossy(&output.stdout)),
+            Ok(Ok(output)) => {
+                tracing::warn!("`gemini --version` failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+                None
+            }
+            Ok(Err(e)) => {
+                tracing::warn!("Failed to run `gemini --version`: {}", e);
+                None
+            }
+            Err(_) => {
+                tracing::warn!("`gemini --version` did not finish within {}s", VERSION_TIMEOUT.as_secs());
+                None
+            }
+        };
+        *self.version.get_or_init(|| version)
+    }
+
+    /// The detected CLI version, if [`Self::detect_version`] could tell it.
+    pub fn version(&self) -> Option<CliVersion> {
+        self.version.get().copied().flatten()
+    }
+
+    /// Whether the CLI has `feature`, assuming it does when the version is unknown.
+    fn supports(&self, feature: Feature) -> bool {
+        self.version().map_or(true, |found| found >= feature.min_version())
+    }
+
+    fn require(&self, feature: Feature) -> Result<()> {
+        match self.version() {
+            Some(found) if !self.supports(feature) => Err(CliTooOldError { feature, found }.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Request parameters only the gemini CLI can honor.
+    pub fn cli_only_params(request: &GenerateRequest) -> Vec<&'static str> {
+        let mut params = Vec::new();
+        if request.all_files {
+            params.push("all_files");
+        }
+        if !request.include_directories.is_empty() {
+            params.push("include_directories");
+        }
+        if request.resume.is_some() {
+            params.push("resume");
+        }
+        params
+    }
+
+    /// Request parameters the gemini CLI has no flag for.
+    pub fn unsupported_params(request: &GenerateRequest) -> Vec<&'static str> {
+        let mut params = Vec::new();
+        if request.max_tokens.is_some() {
+            params.push("max_tokens");
+        }
+        if request.temperature.is_some() {
+            params.push("temperature");
+        }
+        if request.safety_settings.is_some() {
+            params.push("safety_settings");
+        }
+        if request.google_search {
+            params.push("google_search");
+        }
+        if !request.functions.is_empty() {
+            params.push("functions");
+        }
+        if !request.function_results.is_empty() {
+            params.push("function_results");
+        }
+        params
+    }
+
+    /// Sampling parameters the gemini CLI has no flag for, which it runs without
+    /// rather than failing.
+    pub fn ignored_params(request: &GenerateRequest) -> Vec<&'static str> {
+        let mut params = Vec::new();
+        if request.top_p.is_some() {
+            params.push("top_p");
+        }
+        if request.top_k.is_some() {
+            params.push("top_k");
+        }
+        if !request.stop_sequences.is_empty() {
+            params.push("stop_sequences");
+        }
+        if request.seed.is_some() {
+            params.push("seed");
+        }
+        params
+    }
+
+    pub async fn generate(&self, request: &GenerateRequest, options: ExecOptions<'_>) -> Result<GenerateResponse> {
+        let sandbox = request.sandbox.unwrap_or(self.sandbox);
+
+        if let Some(pool) = self.pool.as_ref().filter(|_| poolable(request) && self.supports(Feature::Acp)) {
+            let key = PoolKey {
+                model: request.model.clone(),
+                sandbox,
+                system_instruction: request.system_instruction.clone(),
+            };
+            match pool.generate(key, &request.prompt, request.cwd.clone(), &options).await {
+                Ok(text) => return Ok(estimated_response(request, text)),
+                Err(e) if e.downcast_ref::<WorkerExitedError>().is_some() => {
+                    tracing::warn!("{}, running the request on a new gemini process", e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let invocation = self.prepare(request, sandbox)?;
+        // JSON output arrives all at once at the end, so silence proves nothing
+        let stall_timeout = self.stall_timeout.filter(|_| request.output_format != OutputFormat::Json);
+        let run = self.run(&invocation.args, invocation.stdin, &invocation.env, request.cwd.as_deref(), stall_timeout, options);
+        let output = match run.await {
+            Ok(output) => output,
+            Err(e) => return self.partial_response(request, e),
+        };
+        let warnings = parse_warnings(&output.stderr);
+        let json_output = (request.output_format == OutputFormat::Json)
+            .then(|| parse_json_output(&output.stdout))
+            .flatten();
+        let response = match json_output {
+            Some((text, usage)) => GenerateResponse { text, usage, ..Default::default() },
+            None => estimated_response(request, output.stdout),
+        };
+        Ok(GenerateResponse { warnings, ..response })
+    }
+
+    /// `error`'s partial output as a truncated answer, when `partial_on_timeout`
+    /// allows it and `error` is a timeout after some plain-text output; `error` otherwise.
+    fn partial_response(&self, request: &GenerateRequest, error: anyhow::Error) -> Result<GenerateResponse> {
+        let partial = error.downcast_ref::<TimeoutError>().filter(|timeout| {
+            self.partial_on_timeout && !timeout.partial_output.is_empty() && request.output_format != OutputFormat::Json
+        });
+        let Some(timeout) = partial else {
+            return Err(error);
+        };
+        tracing::warn!("Answering with the output written before the {}s timeout", timeout.timeout.as_secs());
+        let warning = format!(
+            "Gemini timed out after {}s; this answer is truncated to what it wrote by then",
+            timeout.timeout.as_secs()
+        );
+        let response = estimated_response(request, timeout.partial_output.clone());
+        Ok(GenerateResponse { warnings: vec![warning], ..response })
+    }
+
+    /// The command `request` would run, without running it.
+    ///
+    /// Temporary files the command refers to, such as copied images, are
+    /// removed again before this returns.
+    pub fn plan(&self, request: &GenerateRequest) -> Result<CommandPlan> {
+        let sandbox = request.sandbox.unwrap_or(self.sandbox);
+        let invocation = self.prepare(request, sandbox)?;
+        let mut plan = self.command_plan(&invocation.args, &invocation.env, request.cwd.as_deref());
+        plan.stdin = invocation.stdin;
+        plan.warm_pool = self.pool.is_some() && poolable(request);
+        Ok(plan)
+    }
+
+    /// The command [`Self::manage_extensions`] would run with `args`, without running it.
+    pub fn plan_extensions(&self, args: &[String], cwd: Option<&std::path::Path>) -> Result<CommandPlan> {
+        self.require(Feature::Extensions)?;
+        Ok(self.command_plan(&extension_args(args), &[], cwd))
+    }
+
+    fn command_plan(&self, args: &[String], env: &[(String, String)], cwd: Option<&std::path::Path>) -> CommandPlan {
+        let cmd = self.command(args, env, cwd);
+        let cmd = cmd.as_std();
+        CommandPlan {
+            program: cmd.get_program().to_string_lossy().into_owned(),
+            args: cmd.get_args().map(|arg| arg.to_string_lossy().into_owned()).collect(),
+            env: cmd.get_envs()
+                .filter_map(|(name, value)| {
+                    let value = value?.to_string_lossy();
+                    Some((name.to_string_lossy().into_owned(), crate::redact::redact(&value).into_owned()))
+                })
+                .collect(),
+            cwd: cmd.get_current_dir().map(std::path::Path::to_path_buf),
+            stdin: None,
+            warm_pool: false,
+        }
+    }
+
+    /// Everything a fresh process needs for `request`.
+    fn prepare(&self, request: &GenerateRequest, sandbox: bool) -> Result<Invocation> {
+        // Fail with the version needed rather than the CLI's usage message
+        if request.output_format == OutputFormat::Json {
+            self.require(Feature::OutputFormat)?;
+        }
+        if request.resume.is_some() {
+            self.require(Feature::Resume)?;
+        }
+        if !request.include_directories.is_empty() || !request.images.is_empty() {
+            self.require(Feature::IncludeDirectories)?;
+        }
+
+        // Images are referenced with `@path`, which the CLI only resolves inside the
+        // workspace or an included directory; the directory has to outlive the child process
+        let image_dir = if request.images.is_empty() {
+            None
+        } else {
+            Some(write_image_dir(&request.images)?)
+        };
+        let (args, stdin) = build_prompt_args(request, image_dir.as_ref(), sandbox, &self.launcher);
+        let mut env = request.env.clone();
+
+        // The CLI reads a replacement system prompt from the file named by GEMINI_SYSTEM_MD;
+        // the file has to outlive the child process
+        let system_file = match &request.system_instruction {
+            Some(instruction) => {
+                let file = write_system_file(instruction)?;
+                env.push(("GEMINI_SYSTEM_MD".to_string(), file.path().display().to_string()));
+                Some(file)
+            }
+            None => None,
+        };
+
+        Ok(Invocation {
+            args,
+            stdin,
+            env,
+            _image_dir: image_dir,
+            _system_file: system_file,
+        })
+    }
+
+    /// The gemini command for `args`, with its environment filtered to the allowlist.
+    fn command(&self, args: &[String], env: &[(String, String)], cwd: Option<&std::path::Path>) -> tokio::process::Command {
+        let all_args = [self.prefix_args.as_slice(), args].concat();
+
+        // Gemini may execute tools, so it only sees allowlisted variables (including those from .env)
+        let base_env: Vec<_> = self.base_env.iter().filter(|(name, _)| !env.iter().any(|(set, _)| set == name)).cloned().collect();
+        let env = [base_env.as_slice(), env].concat();
+        let mut all_env = allowed_env(&self.env_allowlist);
+        all_env.retain(|(name, _)| !env.iter().any(|(set, _)| name == set.as_str()));
+        all_env.extend(env.iter().filter(|(_, value)| !value.is_empty()).map(|(name, value)| (name.into(), value.into())));
+        let mut cmd = self.launcher.command_in(&all_args, all_env, cwd);
+        children::isolate(&mut cmd);
+        cmd
+    }
+
+    /// The saved conversations the CLI can resume in `cwd`, newest last.
+    ///
+    /// Falls back to the CLI's raw listing when its format is not recognised.
+    pub async fn list_conversations(&self, cwd: Option<&std::path::Path>, options: ExecOptions<'_>) -> Result<Conversations> {
+        self.require(Feature::ListSessions)?;
+        let output = self.run(&["--list-sessions".to_string()], None, &[], cwd, self.stall_timeout, options).await?.stdout;
+        let conversations: Vec<Conversation> = output.lines().filter_map(parse_conversation).collect();
+        if conversations.is_empty() && !output.is_empty() && !output.starts_with("No ") {
+            return Ok(Conversations { conversations, raw: Some(output) });
+        }
+        Ok(Conversations { conversations, raw: None })
+    }
+
+    /// The extensions the CLI has installed, as `gemini extensions list` reports them.
+    ///
+    /// Falls back to the CLI's raw listing when its format is not recognised.
+    pub async fn list_extensions(&self, cwd: Option<&std::path::Path>, options: ExecOptions<'_>) -> Result<Extensions> {
+        let output = self.manage_extensions(&["list".to_string()], cwd, options).await?;
+        let extensions = parse_extensions(&output);
+        if extensions.is_empty() && !output.is_empty() && !output.starts_with("No ") {
+            return Ok(Extensions { extensions, raw: Some(output) });
+        }
+        Ok(Extensions { extensions, raw: None })
+    }
+
+    /// Run `gemini extensions <args>`, e.g. to install or enable one, and return what it printed.
+    pub async fn manage_extensions(&self, args: &[String], cwd: Option<&std::path::Path>, options: ExecOptions<'_>) -> Result<String> {
+        self.require(Feature::Extensions)?;
+        let output = self.run(&extension_args(args), None, &[], cwd, self.stall_timeout, options).await?;
+        // Some subcommands report through stderr only
+        let text = if output.stdout.is_empty() { output.stderr } else { output.stdout };
+        Ok(strip_ansi(&text).into_owned())
+    }
+
+    async fn run(
+        &self,
+        args: &[String],
+        stdin: Option<String>,
+        env: &[(String, String)],
+        cwd: Option<&std::path::Path>,
+        stall_timeout: Option<Duration>,
+        options: ExecOptions<'_>,
+    ) -> Result<Output> {
+        tracing::debug!("Running {} with args: {:?} {:?}", self.launcher.program().display(), self.prefix_args, args);
+
+        let child = self.command(args, env, cwd)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(BinaryMissingError { binary: self.launcher.program().display().to_string() }.into());
+            }
+            Err(e) => return Err(anyhow::Error::new(e).context("Failed to spawn gemini command")),
+        };
+        let tracked = children::track(&child, Some(options.timeout));
+
+        // Feed the prompt from a separate task so a child that writes before it finishes
+        // reading cannot deadlock against us; dropping stdin afterwards signals EOF
+        if let Some(mut pipe) = child.stdin.take() {
+            if let Some(input) = stdin {
+                tokio::spawn(async move {
+                    if let Err(e) = pipe.write_all(input.as_bytes()).await {
+                        tracing::debug!("Failed to write prompt to gemini stdin: {}", e);
+                    }
+                }.in_current_span());
+            }
+        }
+
+        // Filled as the output arrives, so a stalled call still has what came before
+        let captured = Arc::new(Captured::new());
+        let finished = tokio::select! {
+            collected = tokio::time::timeout(options.timeout, collect_output(&mut child, &captured, options.progress)) => {
+                collected.map_err(|_| Stop::TimedOut)
+            }
+            idle = stalled(&captured, stall_timeout) => Err(Stop::Stalled(idle)),
+            _ = options.ct.cancelled() => Err(Stop::Cancelled),
+        };
+
+        let status = match finished {
+            Ok(status) => {
+                let status = status?;
+                if let Some(tracked) = tracked {
+                    tracked.finish(&mut child);
+                }
+                status
+            }
+            Err(Stop::Cancelled) => {
+                tracing::info!("Gemini command cancelled by client, killing it");
+                child.kill().await
+                    .context("Failed to kill cancelled gemini command")?;
+                return Err(CancelledError.into());
+            }
+            Err(Stop::TimedOut) => {
+                tracing::warn!("Gemini command timed out after {:?}, killing it", options.timeout);
+                // kill() also waits for the process, so it is reaped here
+                child.kill().await
+                    .context("Failed to kill timed out gemini command")?;
+                let partial_output = String::from_utf8_lossy(&captured.stdout.lock().unwrap()).trim().to_owned();
+                return Err(TimeoutError { timeout: options.timeout, partial_output }.into());
+            }
+            Err(Stop::Stalled(idle)) => {
+                tracing::warn!("Gemini command wrote nothing for {:?}, stopping it", idle);
+                if let Some(pid) = child.id() {
+                    children::terminate_tree(pid);
+                }
+                if tokio::time::timeout(TERM_GRACE, child.wait()).await.is_err() {
+                    tracing::warn!("Gemini command ignored SIGTERM for {:?}, killing it", TERM_GRACE);
+                    child.kill().await
+                        .context("Failed to kill stalled gemini command")?;
+                }
+                let partial_output = String::from_utf8_lossy(&captured.stdout.lock().unwrap()).trim().to_owned();
+                return Err(StalledError { idle, partial_output }.into());
+            }
+        };
+        let stdout_buf = std::mem::take(&mut *captured.stdout.lock().unwrap());
+        let stderr_buf = std::mem::take(&mut *captured.stderr.lock().unwrap());
+
+        let stdout = String::from_utf8_lossy(&stdout_buf).trim().to_owned();
+        let stderr = String::from_utf8_lossy(&stderr_buf).trim().to_owned();
+
+        tracing::debug!("Command stdout: {}", stdout);
+        tracing::debug!("Command stderr: {}", stderr);
+
+        if status.success() {
+            Ok(Output { stdout, stderr })
+        } else if let Some(auth) = crate::auth::diagnose(&stderr) {
+            Err(auth.into())
+        } else {
+            anyhow::bail!(
+                "Gemini command failed: {}",
+                stderr
+            )
+        }
+    }
+}
+
+/// The exact command a request would run, as reported by dry runs.
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandPlan {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Variables the process would see, with secret values redacted.
+    pub env: BTreeMap<String, String>,
+    pub cwd: Option<PathBuf>,
+    /// The prompt as it would be piped through stdin when too long for `--prompt`.
+    pub stdin: Option<String>,
+    /// Whether a warm process would serve the request instead of this command.
+    pub warm_pool: bool,
+}
+
+/// Arguments, stdin and extra environment for one fresh gemini process,
+/// plus the temporary files they refer to.
+struct Invocation {
+    args: Vec<String>,
+    stdin: Option<String>,
+    env: Vec<(String, String)>,
+    _image_dir: Option<ImageDir>,
+    _system_file: Option<tempfile::NamedTempFile>,
+}
+
+/// Whether a warm process can serve `request`.
+///
+/// Warm processes are started without workspace flags such as `--include-directories`,
+/// which images also need, and each prompt gets a new ACP session, so they cannot
+/// resume a conversation either.
+fn poolable(request: &GenerateRequest) -> bool {
+    // Warm processes run as the server's own identity
+    request.images.is_empty() && request.env.is_empty() && CliBackend::cli_only_params(request).is_empty()
+}
+
+/// What a successful gemini run printed.
+struct Output {
+    stdout: String,
+    stderr: String,
+}
+
+/// The lines of `stderr` worth showing the client, without terminal escapes,
+/// per-run boilerplate and repeats.
+fn parse_warnings(stderr: &str) -> Vec<String> {
+    let mut warnings: Vec<String> = Vec::new();
+    for line in strip_ansi(stderr).lines() {
+        let line = line.trim();
+        // Node prints how to trace a deprecation right after the warning itself
+        if line.is_empty() || STDERR_NOISE.contains(&line) || line.starts_with("(Use `node --trace-") {
+            continue;
+        }
+        let line = crate::redact::redact(line).into_owned();
+        if !warnings.contains(&line) {
+            warnings.push(line);
+        }
+        if warnings.len() == MAX_WARNINGS {
+            break;
+        }
+    }
+    warnings
+}
+
+/// A conversation saved by the gemini CLI.
+#[derive(Debug, Clone, Serialize)]
+pub struct Conversation {
+    pub id: String,
+    /// Position in the CLI's listing, which `--resume` also accepts.
+    pub index: u32,
+    pub title: String,
+    /// When it was last used, as the CLI words it, e.g. "2 hours ago".
+    pub updated: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Conversations {
+    pub conversations: Vec<Conversation>,
+    /// The CLI's own output, when it could not be parsed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
+}
+
+/// The CLI arguments of `gemini extensions <args>`.
+fn extension_args(args: &[String]) -> Vec<String> {
+    let mut all_args = vec!["extensions".to_string()];
+    all_args.extend_from_slice(args);
+    all_args
+}
+
+/// An extension installed in the gemini CLI.
+#[derive(Debug, Clone, Serialize)]
+pub struct Extension {
+    pub name: String,
+    pub version: Option<String>,
+    /// Whether the CLI marks it as enabled, when it says.
+    pub enabled: Option<bool>,
+    /// The `Key: value` lines listed under it, such as its source and MCP servers.
+    pub details: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Extensions {
+    pub extensions: Vec<Extension>,
+    /// The CLI's own output, when it could not be parsed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
+}
+
+/// Parse `gemini extensions list`, where each extension starts with an unindented
+/// line such as `✓ security (1.2.0)` followed by indented `Key: value` lines.
+fn parse_extensions(output: &str) -> Vec<Extension> {
+    static HEADER: OnceLock<Regex> = OnceLock::new();
+    static DETAIL: OnceLock<Regex> = OnceLock::new();
+    let header = HEADER.get_or_init(|| {
+        Regex::new(r"^(?:([✓✗])\s+)?(\S+)\s+\(([^()]+)\)\s*$").expect("extension pattern is valid")
+    });
+    let detail = DETAIL.get_or_init(|| Regex::new(r"^\s+([^:]+):\s*(.*)$").expect("extension detail pattern is valid"));
+
+    let mut extensions: Vec<Extension> = Vec::new();
+    for line in output.lines() {
+        if let Some(captures) = header.captures(line) {
+            extensions.push(Extension {
+                enabled: captures.get(1).map(|mark| mark.as_str() == "✓"),
+                name: captures[2].to_string(),
+                version: Some(captures[3].to_string()),
+                details: BTreeMap::new(),
+            });
+        } else if let (Some(captures), Some(extension)) = (detail.captures(line), extensions.last_mut()) {
+            extension.details.insert(captures[1].trim().to_string(), captures[2].trim().to_string());
+        }
+    }
+    extensions
+}
+
+fn strip_ansi(text: &str) -> std::borrow::Cow<'_, str> {
+    static ANSI: OnceLock<Regex> = OnceLock::new();
+    let ansi = ANSI.get_or_init(|| Regex::new(r"\x1b\[[0-9;?]*[A-Za-z]").expect("ANSI pattern is valid"));
+    ansi.replace_all(text, "")
+}
+
+/// Parse one line of `--list-sessions`, e.g. `  1. Fix the parser (2 hours ago) [<uuid>]`.
+fn parse_conversation(line: &str) -> Option<Conversation> {
+    static LINE: OnceLock<Regex> = OnceLock::new();
+    let pattern = LINE.get_or_init(|| {
+        Regex::new(r"^\s*(\d+)\.\s+(.*?)\s*(?:\(([^()]*)\))?\s*\[([0-9A-Za-z-]+)\]\s*$")
+            .expect("conversation pattern is valid")
+    });
+    let captures = pattern.captures(line)?;
+    Some(Conversation {
+        index: captures[1].parse().ok()?,
+        title: captures[2].to_string(),
+        updated: captures.get(3).map(|updated| updated.as_str().to_string()),
+        id: captures[4].to_string(),
+    })
+}
+
+/// The answer and token counts from `--output-format json`, e.g.
+/// `{"response": "...", "stats": {"models": {"gemini-2.5-pro": {"tokens": {"prompt": 8, "candidates": 3}}}}}`.
+///
+/// `None` when stdout is not in that shape, e.g. from a CLI without the flag.
+fn parse_json_output(stdout: &str) -> Option<(String, Usage)> {
+    let output: serde_json::Value = serde_json::from_str(stdout).ok()?;
+    let text = output["response"].as_str()?.trim().to_string();
+
+    let mut usage = Usage::default();
+    if let Some(models) = output["stats"]["models"].as_object() {
+        for model in models.values() {
+            let count = |name: &str| model["tokens"][name].as_u64().unwrap_or(0) as u32;
+            usage.prompt_tokens += count("prompt");
+            usage.completion_tokens += count("candidates");
+        }
+    }
+    Some((text, usage))
+}
+
+/// The CLI does not report token counts, so estimate them.
+fn estimated_response(request: &GenerateRequest, text: String) -> GenerateResponse {
+    let usage = Usage {
+        prompt_tokens: tokens::estimate(&request.prompt) + tokens::IMAGE_TOKENS * request.images.len() as u32,
+        completion_tokens: tokens::estimate(&text),
+        estimated: true,
+    };
+
+    GenerateResponse {
+        text,
+        usage,
+        ..Default::default()
+    }
+}
+
+/// The server's environment variables matched by `allowlist`.
+pub fn allowed_env(allowlist: &[String]) -> Vec<(std::ffi::OsString, std::ffi::OsString)> {
+    std::env::vars_os()
+        .filter(|(name, _)| {
+            let Some(name) = name.to_str() else {
+                return false;
+            };
+            allowlist.iter().any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == pattern,
+            })
+        })
+        .collect()
+}
+
+/// The CLI arguments for `request`, plus the prompt to pipe through stdin when it is too long for argv.
+fn build_prompt_args(
+    request: &GenerateRequest,
+    image_dir: Option<&ImageDir>,
+    sandbox: bool,
+    launcher: &Launcher,
+) -> (Vec<String>, Option<String>) {
+    let mut cmd_args = vec![];
+    let mut stdin = None;
+
+    // Add prompt, followed by a reference to every image
+    let mut prompt = request.prompt.clone();
+    if let Some(images) = image_dir {
+        for path in &images.paths {
+            prompt.push_str(&format!(" @{}", launcher.map_path(path)));
+        }
+    }
+    // Without --prompt the CLI reads the prompt from stdin when it is not a terminal
+    if prompt.len() > ARGV_PROMPT_MAX_BYTES || !launcher.can_pass(&prompt) {
+        stdin = Some(prompt);
+    } else {
+        cmd_args.push("--prompt".to_string());
+        cmd_args.push(prompt);
+    }
+
+    // Continue a saved conversation
+    if let Some(id) = &request.resume {
+        cmd_args.push("--resume".to_string());
+        cmd_args.push(id.clone());
+    }
+
+    // Add optional model
+    if let Some(model_str) = &request.model {
+        cmd_args.push("--model".to_string());
+        cmd_args.push(model_str.clone());
+    }
+
+    // Run tools Gemini executes inside the container sandbox
+    if sandbox {
+        cmd_args.push("--sandbox".to_string());
+    }
+
+    // Have the CLI wrap its answer and token stats in JSON
+    if request.output_format == OutputFormat::Json {
+        cmd_args.push("--output-format".to_string());
+        cmd_args.push("json".to_string());
+    }
+
+    // Put every file in the workspace into the context
+    if request.all_files {
+        cmd_args.push("--all-files".to_string());
+    }
+
+    // Let the CLI read the requested directories and the images
+    let mut include: Vec<String> = request.include_directories.iter().map(|dir| launcher.map_path(dir)).collect();
+    if let Some(images) = image_dir {
+        include.push(launcher.map_path(images.dir.path()));
+    }
+    if !include.is_empty() {
+        cmd_args.push("--include-directories".to_string());
+        cmd_args.push(include.join(","));
+    }
+
+    (cmd_args, stdin)
+}
+
+/// Temporary directory holding a request's images.
+struct ImageDir {
+    dir: tempfile::TempDir,
+    paths: Vec<std::path::PathBuf>,
+}
+
+fn write_image_dir(images: &[Image]) -> Result<ImageDir> {
+    let dir = tempfile::Builder::new()
+        .prefix("gemini-images-")
+        .tempdir()
+        .context("Failed to create image directory")?;
+
+    let mut paths = Vec::with_capacity(images.len());
+    for (index, image) in images.iter().enumerate() {
+        let path = dir.path().join(format!("image-{}.{}", index + 1, image.extension()));
+        std::fs::write(&path, &image.data)
+            .context("Failed to write image file")?;
+        paths.push(path);
+    }
+
+    Ok(ImageDir { dir, paths })
+}
+
+pub(super) fn write_system_file(instruction: &str) -> Result<tempfile::NamedTempFile> {
+    use std::io::Write;
+
+    let mut file = tempfile::Builder::new()
+        .prefix("gemini-system-")
+        .suffix(".md")
+        .tempfile()
+        .context("Failed to create system instruction file")?;
+    file.write_all(instruction.as_bytes())
+        .context("Failed to write system instruction file")?;
+    Ok(file)
+}
+
+/// Why a command was stopped before it finished.
+enum Stop {
+    TimedOut,
+    /// It had written nothing for this long.
+    Stalled(Duration),
+    Cancelled,
+}
+
+/// A child's output so far, and when it last wrote any.
+struct Captured {
+    stdout: Mutex<Vec<u8>>,
+    stderr: Mutex<Vec<u8>>,
+    last_output: Mutex<Instant>,
+}
+
+impl Captured {
+    fn new() -> Self {
+        Self {
+            stdout: Mutex::new(Vec::new()),
+            stderr: Mutex::new(Vec::new()),
+            last_output: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn touch(&self) {
+        *self.last_output.lock().unwrap() = Instant::now();
+    }
+}
+
+/// Resolve with how long the child had been silent once it wrote nothing to
+/// stdout or stderr for `limit`; never without a limit.
+async fn stalled(captured: &Captured, limit: Option<Duration>) -> Duration {
+    let Some(limit) = limit else {
+        return std::future::pending().await;
+    };
+    loop {
+        let idle = captured.last_output.lock().unwrap().elapsed();
+        if idle >= limit {
+            return idle;
+        }
+        tokio::time::sleep(limit - idle).await;
+    }
+}
+
+/// Read the child's output to completion into `captured`, streaming stdout
+/// through `progress` line by line.
+async fn collect_output(
+    child: &mut Child,
+    captured: &Arc<Captured>,
+    progress: Option<&ProgressReporter>,
+) -> Result<std::process::ExitStatus> {
+    let mut stdout_reader = BufReader::new(child.stdout.take().context("Failed to capture gemini stdout")?);
+    let mut stderr_reader = child.stderr.take().context("Failed to capture gemini stderr")?;
+
+    // Drain stderr concurrently so the child never blocks on a full pipe
+    let stderr_captured = captured.clone();
+    let stderr_task = tokio::spawn(async move {
+        let mut chunk = [0u8; 8192];
+        loop {
+            let read = stderr_reader.read(&mut chunk).await?;
+            if read == 0 {
+                return Ok::<_, std::io::Error>(());
+            }
+            stderr_captured.stderr.lock().unwrap().extend_from_slice(&chunk[..read]);
+            stderr_captured.touch();
+        }
+    });
+
+    // Keep what arrives at once, so a line written slowly still counts as output,
+    // and forward each completed line
+    let mut line = Vec::new();
+    loop {
+        let chunk = stdout_reader.fill_buf().await
+            .context("Failed to read gemini stdout")?;
+        if chunk.is_empty() {
+            break;
+        }
+        let read = chunk.len();
+        captured.stdout.lock().unwrap().extend_from_slice(chunk);
+        captured.touch();
+        if progress.is_some() {
+            line.extend_from_slice(chunk);
+        }
+        stdout_reader.consume(read);
+
+        if let Some(progress) = progress {
+            while let Some(end) = line.iter().position(|&byte| byte == b'\n') {
+                let rest = line.split_off(end + 1);
+                progress.report(String::from_utf8_lossy(&line).into_owned()).await;
+                line = rest;
+            }
+        }
+    }
+    if let Some(progress) = progress.filter(|_| !line.is_empty()) {
+        progress.report(String::from_utf8_lossy(&line).into_owned()).await;
+    }
+
+    let status = child.wait().await
+        .context("Failed to wait for gemini command")?;
+    stderr_task.await
+        .context("Failed to join stderr reader")?
+        .context("Failed to read gemini stderr")?;
+
+    Ok(status)
+}
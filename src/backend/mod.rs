@@ -0,0 +1,379 @@
+mod api;
+mod cli;
+mod pool;
+mod version;
+
+pub use api::{ApiBackend, ImageRequest};
+pub use cli::{allowed_env, CliBackend};
+pub use version::{CliTooOldError, CliVersion, Feature};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::{BackendKind, Config};
+use crate::files::Image;
+use crate::format::OutputFormat;
+use crate::functions::{FunctionCall, FunctionDeclaration, FunctionResult};
+use crate::progress::ProgressReporter;
+use crate::safety::{SafetyBlock, SafetySettings};
+
+/// Model the API backend uses when neither the request nor the config names one.
+pub const DEFAULT_MODEL: &str = "gemini-2.5-pro";
+
+/// A single backend-agnostic generation request.
+#[derive(Debug, Clone, Default)]
+pub struct GenerateRequest {
+    pub prompt: String,
+    /// Images sent alongside the prompt.
+    pub images: Vec<Image>,
+    pub model: Option<String>,
+    /// Sent as a real system prompt rather than being concatenated into `prompt`.
+    pub system_instruction: Option<String>,
+    pub max_tokens: Option<u32>,
+    pub temperature: Option<f32>,
+    /// Only sample from the most likely tokens whose probabilities add up to this.
+    pub top_p: Option<f32>,
+    /// Only sample from this many of the most likely tokens.
+    pub top_k: Option<u32>,
+    /// Stop generating at the first of these, which is left out of the answer.
+    pub stop_sequences: Vec<String>,
+    /// Makes sampling repeatable, as far as the model allows.
+    pub seed: Option<i32>,
+    pub safety_settings: Option<SafetySettings>,
+    /// Let Gemini ground its answer with Google Search results.
+    pub google_search: bool,
+    /// Functions Gemini may ask the caller to call instead of answering.
+    pub functions: Vec<FunctionDeclaration>,
+    /// Results of the calls Gemini asked for after `prompt`, to continue from.
+    pub function_results: Vec<FunctionResult>,
+    /// Run the CLI in its sandbox; `None` uses the configured default. Has no effect
+    /// on the api backend, which never executes tools.
+    pub sandbox: Option<bool>,
+    /// Directory the CLI runs in. Has no effect on the api backend.
+    pub cwd: Option<PathBuf>,
+    /// Continue this saved CLI conversation instead of starting a new one. Only
+    /// the cli backend can resume; conversations are saved per project directory.
+    pub resume: Option<String>,
+    /// Have the CLI read every file in its workspace into the context.
+    pub all_files: bool,
+    /// Directories outside `cwd` the CLI may read.
+    pub include_directories: Vec<PathBuf>,
+    /// With `Json`, the API is asked for a JSON response and the CLI for its
+    /// JSON output, which also reports real token counts.
+    pub output_format: OutputFormat,
+    /// Variables of the request's auth profile, set for the CLI on top of the
+    /// allowlisted ones; an empty value withholds the variable. The api
+    /// backend takes its key from them.
+    pub env: Vec<(String, String)>,
+}
+
+impl GenerateRequest {
+    /// The API key the request's auth profile sets, if any.
+    pub fn api_key(&self) -> Option<&str> {
+        ["GEMINI_API_KEY", "GOOGLE_API_KEY"].iter().find_map(|key| {
+            self.env.iter().find(|(name, value)| name == key && !value.is_empty()).map(|(_, value)| value.as_str())
+        })
+    }
+}
+
+/// Token counts for one generation.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    /// True when the counts are local estimates rather than reported by Gemini.
+    pub estimated: bool,
+}
+
+impl std::ops::AddAssign for Usage {
+    /// Count `other` too; the sum is estimated if either part was.
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.estimated |= other.estimated;
+    }
+}
+
+/// A web page Gemini used to ground its answer.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Citation {
+    pub title: Option<String>,
+    pub uri: String,
+}
+
+/// How a search-grounded answer was produced.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Grounding {
+    /// Google Search queries Gemini ran.
+    pub search_queries: Vec<String>,
+    pub citations: Vec<Citation>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GenerateResponse {
+    pub text: String,
+    pub usage: Usage,
+    /// Set when Gemini refused to answer for safety reasons.
+    pub safety: Option<SafetyBlock>,
+    /// Set when the request used Google Search grounding.
+    pub grounding: Option<Grounding>,
+    /// How many times the request was retried after transient failures.
+    pub retries: u32,
+    /// The model that answered when the requested one was out of capacity.
+    pub fallback_model: Option<String>,
+    /// Notices the gemini CLI printed to stderr on a successful run, e.g. auth hints.
+    pub warnings: Vec<String>,
+    /// Calls of the request's `functions` Gemini made instead of, or before, answering.
+    pub function_calls: Vec<FunctionCall>,
+    /// How many matches of each kind `pii_filter` removed from the prompt.
+    pub redactions: BTreeMap<String, usize>,
+}
+
+/// Per-call execution controls shared by all backends.
+pub struct ExecOptions<'a> {
+    pub timeout: Duration,
+    pub ct: CancellationToken,
+    pub progress: Option<&'a ProgressReporter>,
+}
+
+/// Where generation requests are sent, selected with the `backend` setting.
+pub enum Backend {
+    /// Spawn the `gemini` CLI for every request.
+    ///
+    /// Requests using parameters the CLI cannot honor are routed through
+    /// `api` when an API key is configured.
+    Cli { cli: CliBackend, api: Option<ApiBackend> },
+    /// Call the Gemini REST API directly.
+    Api(ApiBackend),
+}
+
+impl Backend {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        match config.backend {
+            BackendKind::Api => Ok(Self::Api(ApiBackend::from_config(config)?)),
+            BackendKind::Cli => Ok(Self::Cli {
+                cli: CliBackend::new(config),
+                api: ApiBackend::from_config(config).ok(),
+            }),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Cli { .. } => "cli",
+            Self::Api(_) => "api",
+        }
+    }
+
+    /// The CLI runner, when requests go through the gemini CLI.
+    pub fn cli(&self) -> Option<&CliBackend> {
+        match self {
+            Self::Cli { cli, .. } => Some(cli),
+            Self::Api(_) => None,
+        }
+    }
+
+    /// The API client, when this backend has one (directly or for routing).
+    pub fn api(&self) -> Option<&ApiBackend> {
+        match self {
+            Self::Cli { api, .. } => api.as_ref(),
+            Self::Api(api) => Some(api),
+        }
+    }
+
+    /// What `generate` would do with `request`, without doing it: the exact gemini
+    /// command for the cli backend, or which API request it would make otherwise.
+    pub fn dry_run(&self, request: &GenerateRequest) -> Result<serde_json::Value> {
+        let routed_for = match self {
+            Self::Cli { cli, api } => match route(api.as_ref(), request)? {
+                Some((_, routed_for)) => routed_for,
+                None => {
+                    let plan = cli.plan(request)?;
+                    let ignored = CliBackend::ignored_params(request);
+                    return Ok(serde_json::json!({ "backend": "cli", "command": plan, "ignored": ignored }));
+                }
+            },
+            Self::Api(_) => {
+                let cli_only = CliBackend::cli_only_params(request);
+                if !cli_only.is_empty() {
+                    return Err(CliOnlyParamsError { params: cli_only, conflicts: Vec::new() }.into());
+                }
+                Vec::new()
+            }
+        };
+        Ok(serde_json::json!({
+            "backend": "api",
+            "routed_for": routed_for,
+            "model": request.model.as_deref().unwrap_or(DEFAULT_MODEL),
+        }))
+    }
+
+    pub async fn generate(&self, request: &GenerateRequest, options: ExecOptions<'_>) -> Result<GenerateResponse> {
+        match self {
+            Self::Cli { cli, api } => match route(api.as_ref(), request)? {
+                Some((api, routed_for)) => {
+                    tracing::info!("Routing request through the api backend for {:?}", routed_for);
+                    api.generate(request, options).await
+                }
+                None => {
+                    let mut response = cli.generate(request, options).await?;
+                    response.warnings.extend(
+                        CliBackend::ignored_params(request)
+                            .into_iter()
+                            .map(|param| format!("{} was ignored: the gemini CLI does not support it", param)),
+                    );
+                    Ok(response)
+                }
+            },
+            Self::Api(api) => {
+                let cli_only = CliBackend::cli_only_params(request);
+                if !cli_only.is_empty() {
+                    return Err(CliOnlyParamsError { params: cli_only, conflicts: Vec::new() }.into());
+                }
+                api.generate(request, options).await
+            }
+        }
+    }
+}
+
+/// Where the cli backend sends `request`: `None` when the CLI runs it, or the api
+/// and the parameters the request is routed there for.
+///
+/// Sampling parameters only the API honors never fail a request; when they can't
+/// be routed, the CLI runs without them and the result says they were ignored.
+fn route<'a>(api: Option<&'a ApiBackend>, request: &GenerateRequest) -> Result<Option<(&'a ApiBackend, Vec<&'static str>)>> {
+    let unsupported = CliBackend::unsupported_params(request);
+    let ignored = CliBackend::ignored_params(request);
+    let cli_only = CliBackend::cli_only_params(request);
+
+    if unsupported.is_empty() {
+        return Ok(api.filter(|_| !ignored.is_empty() && cli_only.is_empty()).map(|api| (api, ignored)));
+    }
+    // Neither backend can honor both kinds of parameter
+    if !cli_only.is_empty() {
+        return Err(CliOnlyParamsError { params: cli_only, conflicts: unsupported }.into());
+    }
+    match api {
+        Some(api) => Ok(Some((api, [unsupported, ignored].concat()))),
+        None => Err(UnsupportedParamsError { params: unsupported }.into()),
+    }
+}
+
+/// Returned when a generation was aborted because it ran past its timeout.
+#[derive(Debug)]
+pub struct TimeoutError {
+    pub timeout: Duration,
+    /// What the gemini CLI had written to stdout by then; empty for other backends.
+    pub partial_output: String,
+}
+
+impl TimeoutError {
+    /// A timeout with no output to show for it.
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout, partial_output: String::new() }
+    }
+}
+
+impl std::fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Gemini command timed out after {}s", self.timeout.as_secs())
+    }
+}
+
+impl std::error::Error for TimeoutError {}
+
+/// Returned when the gemini process wrote nothing for `stall_timeout_secs`
+/// and was stopped as hung.
+#[derive(Debug)]
+pub struct StalledError {
+    /// How long it had been silent.
+    pub idle: Duration,
+    /// What it had written to stdout by then.
+    pub partial_output: String,
+}
+
+impl std::fmt::Display for StalledError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Gemini command stalled: no output for {}s", self.idle.as_secs())
+    }
+}
+
+impl std::error::Error for StalledError {}
+
+/// Returned when the client cancelled the request and the generation was aborted.
+#[derive(Debug)]
+pub struct CancelledError;
+
+impl std::fmt::Display for CancelledError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Gemini command was cancelled by the client")
+    }
+}
+
+impl std::error::Error for CancelledError {}
+
+/// Returned when the gemini binary cannot be found or is not executable.
+#[derive(Debug)]
+pub struct BinaryMissingError {
+    pub binary: String,
+}
+
+impl std::fmt::Display for BinaryMissingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The gemini CLI '{}' was not found; install it with `npm install -g @google/gemini-cli` or set gemini_binary",
+            self.binary
+        )
+    }
+}
+
+impl std::error::Error for BinaryMissingError {}
+
+/// Returned when a request uses parameters that no available backend can honor.
+#[derive(Debug)]
+pub struct UnsupportedParamsError {
+    pub params: Vec<&'static str>,
+}
+
+impl std::fmt::Display for UnsupportedParamsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The gemini CLI does not support {}; set GEMINI_API_KEY (or GOOGLE_API_KEY) to route these requests through the API, or omit them",
+            self.params.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedParamsError {}
+
+/// Returned when a request uses parameters only the gemini CLI can honor but
+/// cannot be run by it.
+#[derive(Debug)]
+pub struct CliOnlyParamsError {
+    pub params: Vec<&'static str>,
+    /// Parameters in the same request that the CLI does not support.
+    pub conflicts: Vec<&'static str>,
+}
+
+impl std::fmt::Display for CliOnlyParamsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.conflicts.is_empty() {
+            write!(f, "{} need the cli backend", self.params.join(", "))
+        } else {
+            write!(
+                f,
+                "{} need the cli backend, which does not support {}; omit one or the other",
+                self.params.join(", "),
+                self.conflicts.join(", ")
+            )
+        }
+    }
+}
+
+impl std::error::Error for CliOnlyParamsError {}
@@ -0,0 +1,348 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout};
+
+use super::cli::{allowed_env, write_system_file};
+use super::{CancelledError, ExecOptions, TimeoutError};
+use crate::children::{self, ChildGuard};
+use crate::config::{Config, VertexConfig};
+use crate::launcher::Launcher;
+use crate::progress::ProgressReporter;
+
+/// How often idle processes are checked for expiry.
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Agent Client Protocol version spoken with `gemini --experimental-acp`.
+const ACP_PROTOCOL_VERSION: u64 = 1;
+
+/// What a warm process was started with; only requests that match it can use it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolKey {
+    pub model: Option<String>,
+    pub sandbox: bool,
+    pub system_instruction: Option<String>,
+}
+
+/// Returned when a warm process exited while serving a request; the request can
+/// still be run on a freshly spawned process.
+#[derive(Debug)]
+pub struct WorkerExitedError;
+
+impl std::fmt::Display for WorkerExitedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Warm gemini process exited unexpectedly")
+    }
+}
+
+impl std::error::Error for WorkerExitedError {}
+
+/// Long-lived `gemini --experimental-acp` processes that prompts are sent to
+/// over the Agent Client Protocol, saving the CLI's startup time per request.
+///
+/// Each process serves one prompt at a time, each in a fresh ACP session so
+/// no context leaks between requests. Processes that crash are replaced, and
+/// ones left idle for `idle_timeout` are stopped.
+pub struct WarmPool {
+    launcher: Launcher,
+    prefix_args: Vec<String>,
+    env_allowlist: Vec<String>,
+    base_env: Vec<(String, String)>,
+    size: usize,
+    idle_timeout: Duration,
+    idle: Mutex<Vec<Worker>>,
+}
+
+impl WarmPool {
+    pub fn new(config: &Config, launcher: Launcher) -> Arc<Self> {
+        let pool = Arc::new(Self {
+            launcher,
+            prefix_args: config.gemini_args.clone(),
+            env_allowlist: config.env_allowlist.clone(),
+            base_env: config.vertex.as_ref().map(VertexConfig::env).unwrap_or_default(),
+            size: config.warm_pool_size,
+            idle_timeout: Duration::from_secs(config.warm_pool_idle_secs),
+            idle: Mutex::new(Vec::new()),
+        });
+
+        let key = PoolKey {
+            model: config.resolve_model(None),
+            sandbox: config.sandbox,
+            system_instruction: config.system_instruction.clone(),
+        };
+        for _ in 0..pool.size {
+            pool.replenish(key.clone());
+        }
+        spawn_expiry(Arc::downgrade(&pool));
+
+        pool
+    }
+
+    /// Run `prompt` on a warm process started with `key`, starting one if none is idle.
+    pub async fn generate(
+        self: &Arc<Self>,
+        key: PoolKey,
+        prompt: &str,
+        cwd: Option<PathBuf>,
+        options: &ExecOptions<'_>,
+    ) -> Result<String> {
+        let cwd = match cwd {
+            Some(cwd) => cwd,
+            None => std::env::current_dir().context("Failed to get the current directory")?,
+        };
+
+        let run = async {
+            let mut worker = self.checkout(&key).await?;
+            let text = worker.prompt(prompt, &cwd, options.progress).await?;
+            Ok::<_, anyhow::Error>((worker, text))
+        };
+
+        // Timed out or cancelled workers are dropped mid-prompt, which kills them
+        let result = tokio::select! {
+            result = tokio::time::timeout(options.timeout, run) => result,
+            _ = options.ct.cancelled() => return Err(CancelledError.into()),
+        };
+        match result {
+            Ok(Ok((worker, text))) => {
+                self.checkin(worker);
+                Ok(text)
+            }
+            Ok(Err(e)) => {
+                if e.downcast_ref::<WorkerExitedError>().is_some() {
+                    self.replenish(key);
+                }
+                Err(e)
+            }
+            Err(_) => Err(TimeoutError::new(options.timeout).into()),
+        }
+    }
+
+    async fn checkout(&self, key: &PoolKey) -> Result<Worker> {
+        let idle = {
+            let mut idle = self.idle.lock().unwrap();
+            idle.iter().position(|worker| &worker.key == key).map(|index| idle.swap_remove(index))
+        };
+        if let Some(mut worker) = idle {
+            if worker.alive() {
+                return Ok(worker);
+            }
+            tracing::warn!("Warm gemini process exited while idle, starting a new one");
+        }
+        Worker::spawn(self, key.clone()).await
+    }
+
+    fn checkin(&self, mut worker: Worker) {
+        worker.idle_since = Instant::now();
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < self.size {
+            idle.push(worker);
+        }
+    }
+
+    /// Start a process for `key` in the background and add it to the idle set.
+    fn replenish(self: &Arc<Self>, key: PoolKey) {
+        let pool = self.clone();
+        tokio::spawn(async move {
+            match Worker::spawn(&pool, key).await {
+                Ok(worker) => pool.checkin(worker),
+                Err(e) => tracing::warn!("Failed to start warm gemini process: {:#}", e),
+            }
+        });
+    }
+}
+
+fn spawn_expiry(pool: Weak<WarmPool>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(EXPIRY_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let Some(pool) = pool.upgrade() else {
+                return;
+            };
+            let mut idle = pool.idle.lock().unwrap();
+            let before = idle.len();
+            idle.retain(|worker| worker.idle_since.elapsed() < pool.idle_timeout);
+            if idle.len() < before {
+                tracing::debug!("Stopped {} idle warm gemini processes", before - idle.len());
+            }
+        }
+    });
+}
+
+/// One `gemini --experimental-acp` process.
+struct Worker {
+    key: PoolKey,
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+    next_id: u64,
+    idle_since: Instant,
+    /// Kills the process tree when the worker is dropped.
+    _tracked: Option<ChildGuard>,
+    /// GEMINI_SYSTEM_MD file, which has to outlive the process.
+    _system_file: Option<tempfile::NamedTempFile>,
+}
+
+impl Worker {
+    async fn spawn(pool: &WarmPool, key: PoolKey) -> Result<Self> {
+        let mut args = pool.prefix_args.clone();
+        args.push("--experimental-acp".to_string());
+        if let Some(model) = &key.model {
+            args.push("--model".to_string());
+            args.push(model.clone());
+        }
+        if key.sandbox {
+            args.push("--sandbox".to_string());
+        }
+
+        let mut env = allowed_env(&pool.env_allowlist);
+        env.retain(|(name, _)| !pool.base_env.iter().any(|(set, _)| name == set.as_str()));
+        env.extend(pool.base_env.iter().map(|(name, value)| (name.into(), value.into())));
+        let system_file = match &key.system_instruction {
+            Some(instruction) => {
+                let file = write_system_file(instruction)?;
+                env.push(("GEMINI_SYSTEM_MD".into(), file.path().into()));
+                Some(file)
+            }
+            None => None,
+        };
+        let mut cmd = pool.launcher.command_in(&args, env, None);
+        children::isolate(&mut cmd);
+
+        let mut child = cmd
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .context("Failed to spawn warm gemini process")?;
+        let tracked = children::track(&child, None);
+        let stdin = child.stdin.take().context("Failed to capture gemini stdin")?;
+        let stdout = child.stdout.take().context("Failed to capture gemini stdout")?;
+
+        let mut worker = Self {
+            key,
+            child,
+            stdin,
+            stdout: BufReader::new(stdout).lines(),
+            next_id: 0,
+            idle_since: Instant::now(),
+            _tracked: tracked,
+            _system_file: system_file,
+        };
+        worker.request("initialize", json!({
+            "protocolVersion": ACP_PROTOCOL_VERSION,
+            "clientCapabilities": { "fs": { "readTextFile": false, "writeTextFile": false } },
+        }), None).await
+            .context("Failed to initialize warm gemini process")?;
+
+        tracing::debug!("Started warm gemini process for {:?}", worker.key.model);
+        Ok(worker)
+    }
+
+    fn alive(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    /// Send `prompt` in a new session and collect the reply.
+    async fn prompt(&mut self, prompt: &str, cwd: &std::path::Path, progress: Option<&ProgressReporter>) -> Result<String> {
+        let (session, _) = self.request("session/new", json!({ "cwd": cwd, "mcpServers": [] }), None).await?;
+        let session_id = session["sessionId"].as_str()
+            .context("gemini did not return a session id")?
+            .to_string();
+
+        let (_, text) = self.request("session/prompt", json!({
+            "sessionId": session_id,
+            "prompt": [{ "type": "text", "text": prompt }],
+        }), progress).await?;
+        Ok(text.trim().to_string())
+    }
+
+    /// Send a JSON-RPC request and wait for its result, answering the agent's
+    /// own requests and collecting streamed message text along the way.
+    async fn request(&mut self, method: &str, params: Value, progress: Option<&ProgressReporter>) -> Result<(Value, String)> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })).await?;
+
+        let mut text = String::new();
+        loop {
+            let line = self.stdout.next_line().await
+                .context("Failed to read from warm gemini process")?
+                .ok_or(WorkerExitedError)?;
+            let Ok(message) = serde_json::from_str::<Value>(&line) else {
+                tracing::debug!("Ignoring non-JSON output from gemini: {}", line);
+                continue;
+            };
+
+            if let Some(incoming) = message["method"].as_str() {
+                match message.get("id") {
+                    Some(request_id) => {
+                        let reply = reply_to(incoming, request_id.clone(), &message["params"]);
+                        self.send(reply).await?;
+                    }
+                    None if incoming == "session/update" => {
+                        let update = &message["params"]["update"];
+                        if update["sessionUpdate"] == "agent_message_chunk" {
+                            if let Some(chunk) = update["content"]["text"].as_str() {
+                                text.push_str(chunk);
+                                if let Some(progress) = progress {
+                                    progress.report(chunk.to_string()).await;
+                                }
+                            }
+                        }
+                    }
+                    None => {}
+                }
+                continue;
+            }
+
+            if message["id"] != id {
+                continue;
+            }
+            if let Some(error) = message.get("error") {
+                anyhow::bail!(
+                    "Gemini command failed: {}",
+                    error["message"].as_str().unwrap_or("unknown error")
+                );
+            }
+            return Ok((message["result"].clone(), text));
+        }
+    }
+
+    async fn send(&mut self, message: Value) -> Result<()> {
+        let mut line = message.to_string();
+        line.push('\n');
+        self.stdin.write_all(line.as_bytes()).await
+            .map_err(|_| WorkerExitedError)?;
+        self.stdin.flush().await
+            .map_err(|_| WorkerExitedError)?;
+        Ok(())
+    }
+}
+
+/// Answer a request from the agent.
+///
+/// Tool permission prompts are declined, as in the CLI's non-interactive
+/// mode; anything else is not supported by this client.
+fn reply_to(method: &str, id: Value, params: &Value) -> Value {
+    if method == "session/request_permission" {
+        let reject = params["options"]
+            .as_array()
+            .and_then(|options| options.iter().find(|option| option["kind"] == "reject_once"))
+            .and_then(|option| option["optionId"].as_str());
+        let outcome = match reject {
+            Some(option_id) => json!({ "outcome": "selected", "optionId": option_id }),
+            None => json!({ "outcome": "cancelled" }),
+        };
+        return json!({ "jsonrpc": "2.0", "id": id, "result": { "outcome": outcome } });
+    }
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": -32601, "message": format!("Method not found: {}", method) },
+    })
+}
@@ -0,0 +1,107 @@
+use regex::Regex;
+use serde::Serialize;
+use std::sync::OnceLock;
+
+/// A gemini CLI release, as `gemini --version` prints it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CliVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl CliVersion {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self { major, minor, patch }
+    }
+
+    /// The first `major.minor.patch` in `text`, ignoring prefixes such as `v`
+    /// and pre-release suffixes.
+    pub fn parse(text: &str) -> Option<Self> {
+        static VERSION: OnceLock<Regex> = OnceLock::new();
+        let version = VERSION.get_or_init(|| Regex::new(r"(\d+)\.(\d+)\.(\d+)").expect("version pattern is valid"));
+        let captures = version.captures(text)?;
+        let part = |index: usize| captures[index].parse().ok();
+        Some(Self::new(part(1)?, part(2)?, part(3)?))
+    }
+}
+
+impl std::fmt::Display for CliVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl Serialize for CliVersion {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// A flag or subcommand that older gemini CLI releases lack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    IncludeDirectories,
+    /// The protocol warm pool workers speak.
+    Acp,
+    Extensions,
+    OutputFormat,
+    Resume,
+    ListSessions,
+}
+
+impl Feature {
+    /// Every feature, oldest first.
+    pub const ALL: &'static [Feature] = &[
+        Self::IncludeDirectories,
+        Self::Acp,
+        Self::Extensions,
+        Self::OutputFormat,
+        Self::Resume,
+        Self::ListSessions,
+    ];
+
+    /// How the CLI spells the feature.
+    pub fn flag(self) -> &'static str {
+        match self {
+            Self::IncludeDirectories => "--include-directories",
+            Self::Acp => "--experimental-acp",
+            Self::Extensions => "gemini extensions",
+            Self::OutputFormat => "--output-format",
+            Self::Resume => "--resume",
+            Self::ListSessions => "--list-sessions",
+        }
+    }
+
+    /// The first release that has the feature.
+    pub fn min_version(self) -> CliVersion {
+        match self {
+            Self::IncludeDirectories => CliVersion::new(0, 1, 12),
+            Self::Acp => CliVersion::new(0, 2, 0),
+            Self::Extensions => CliVersion::new(0, 4, 0),
+            Self::OutputFormat => CliVersion::new(0, 6, 0),
+            Self::Resume | Self::ListSessions => CliVersion::new(0, 20, 0),
+        }
+    }
+}
+
+/// Returned when a request needs a flag the installed gemini CLI is too old to have.
+#[derive(Debug)]
+pub struct CliTooOldError {
+    pub feature: Feature,
+    pub found: CliVersion,
+}
+
+impl std::fmt::Display for CliTooOldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} requires gemini-cli >= {}, but {} is installed; upgrade with `npm install -g @google/gemini-cli@latest`",
+            self.feature.flag(),
+            self.feature.min_version(),
+            self.found
+        )
+    }
+}
+
+impl std::error::Error for CliTooOldError {}
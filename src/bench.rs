@@ -0,0 +1,200 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::tokens;
+
+/// What `bench` sends.
+#[derive(Debug, Clone)]
+pub struct Plan {
+    pub requests: usize,
+    /// Calls in flight at once; the server's own `max_concurrency` still applies.
+    pub concurrency: usize,
+    /// Approximate prompt sizes in tokens, taken in turn.
+    pub sizes: Vec<u32>,
+    /// Models taken in turn; `None` for the configured default.
+    pub models: Vec<Option<String>>,
+}
+
+impl Plan {
+    /// The model and size of the `index`th request, cycling through both so
+    /// every combination is sent about equally often.
+    pub fn case(&self, index: usize) -> (Option<String>, u32) {
+        let model = self.models[index % self.models.len()].clone();
+        let size = self.sizes[(index / self.models.len()) % self.sizes.len()];
+        (model, size)
+    }
+}
+
+/// How one request went.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub model: Option<String>,
+    pub size: u32,
+    pub elapsed: Duration,
+    /// `data.kind` of the error it failed with, if it did.
+    pub error: Option<String>,
+}
+
+/// Latencies and failures of a group of requests.
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub requests: usize,
+    pub failed: usize,
+    pub failure_rate: f64,
+    /// Percentiles of the successful requests' latency, in milliseconds; 0 without any.
+    pub p50_ms: u64,
+    pub p90_ms: u64,
+    pub p99_ms: u64,
+    pub max_ms: u64,
+    /// Failures by error kind.
+    pub errors: BTreeMap<String, usize>,
+}
+
+/// The statistics of a whole run, overall and per model and size.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub backend: String,
+    pub concurrency: usize,
+    pub wall_ms: u64,
+    /// Successful requests per second of the whole run.
+    pub throughput: f64,
+    pub total: Stats,
+    pub cases: Vec<Case>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Case {
+    /// `default` for the configured default model.
+    pub model: String,
+    pub size: u32,
+    #[serde(flatten)]
+    pub stats: Stats,
+}
+
+/// A prompt of about `size` tokens asking for a short answer, different for
+/// every `index` so no cache can serve it.
+pub fn prompt(index: usize, size: u32) -> String {
+    const FILLER: &str = "The quick brown fox jumps over the lazy dog while the benchmark measures latency. ";
+    let mut prompt = format!(
+        "Benchmark request {}. Ignore the filler text below and reply with the single word OK.\n\n",
+        index
+    );
+    while tokens::estimate(&prompt) < size {
+        prompt.push_str(FILLER);
+    }
+    prompt
+}
+
+impl Stats {
+    fn of<'a>(samples: impl IntoIterator<Item = &'a Sample>) -> Self {
+        let mut latencies = Vec::new();
+        let mut errors: BTreeMap<String, usize> = BTreeMap::new();
+        let mut requests = 0;
+        for sample in samples {
+            requests += 1;
+            match &sample.error {
+                Some(kind) => *errors.entry(kind.clone()).or_default() += 1,
+                None => latencies.push(sample.elapsed.as_millis() as u64),
+            }
+        }
+        latencies.sort_unstable();
+        let failed = requests - latencies.len();
+        Self {
+            requests,
+            failed,
+            failure_rate: if requests == 0 { 0.0 } else { failed as f64 / requests as f64 },
+            p50_ms: percentile(&latencies, 50),
+            p90_ms: percentile(&latencies, 90),
+            p99_ms: percentile(&latencies, 99),
+            max_ms: latencies.last().copied().unwrap_or(0),
+            errors,
+        }
+    }
+}
+
+/// The `percent`th percentile of `sorted` by the nearest-rank method.
+fn percentile(sorted: &[u64], percent: usize) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (percent * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+impl Report {
+    pub fn new(backend: &str, plan: &Plan, samples: &[Sample], wall: Duration) -> Self {
+        let mut cases = Vec::new();
+        for model in &plan.models {
+            for size in &plan.sizes {
+                let matching = samples.iter().filter(|sample| sample.model == *model && sample.size == *size);
+                let stats = Stats::of(matching);
+                if stats.requests > 0 {
+                    cases.push(Case {
+                        model: model.clone().unwrap_or_else(|| "default".to_string()),
+                        size: *size,
+                        stats,
+                    });
+                }
+            }
+        }
+        let total = Stats::of(samples);
+        let succeeded = total.requests - total.failed;
+        Self {
+            backend: backend.to_string(),
+            concurrency: plan.concurrency,
+            wall_ms: wall.as_millis() as u64,
+            throughput: succeeded as f64 / wall.as_secs_f64().max(0.001),
+            total,
+            cases,
+        }
+    }
+
+    /// The report as a table, for people rather than programs.
+    pub fn to_table(&self) -> String {
+        let mut out = format!(
+            "{} backend, {} requests, {} at once, {:.1}s, {:.2} answers/s\n\n",
+            self.backend,
+            self.total.requests,
+            self.concurrency,
+            self.wall_ms as f64 / 1000.0,
+            self.throughput
+        );
+        let width = self.cases.iter().map(|case| case.model.len()).chain([5]).max().unwrap_or(5);
+        let _ = writeln!(
+            out,
+            "{:<width$}  {:>6}  {:>8}  {:>7}  {:>8}  {:>8}  {:>8}  {:>8}",
+            "model", "tokens", "requests", "failed", "p50", "p90", "p99", "max"
+        );
+        let row = |out: &mut String, model: &str, size: String, stats: &Stats| {
+            let _ = writeln!(
+                out,
+                "{:<width$}  {:>6}  {:>8}  {:>6.1}%  {:>8}  {:>8}  {:>8}  {:>8}",
+                model,
+                size,
+                stats.requests,
+                stats.failure_rate * 100.0,
+                seconds(stats.p50_ms),
+                seconds(stats.p90_ms),
+                seconds(stats.p99_ms),
+                seconds(stats.max_ms)
+            );
+        };
+        for case in &self.cases {
+            row(&mut out, &case.model, case.size.to_string(), &case.stats);
+        }
+        if self.cases.len() > 1 {
+            row(&mut out, "all", String::new(), &self.total);
+        }
+        if !self.total.errors.is_empty() {
+            let errors: Vec<String> = self.total.errors.iter().map(|(kind, count)| format!("{} {}", kind, count)).collect();
+            let _ = writeln!(out, "\nFailures: {}", errors.join(", "));
+        }
+        out
+    }
+}
+
+fn seconds(ms: u64) -> String {
+    format!("{:.2}s", ms as f64 / 1000.0)
+}
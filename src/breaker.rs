@@ -0,0 +1,187 @@
+use chrono::{DateTime, Utc};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::auth::AuthError;
+use crate::backend::BinaryMissingError;
+
+/// Stops sending requests to a backend that keeps failing in ways retrying
+/// won't fix soon: the gemini binary not starting, rejected credentials or
+/// exhausted quota. After `threshold` such failures in a row, requests fail
+/// at once for `cooldown`; then the next one is tried, and another failure
+/// starts a new cooldown.
+pub struct CircuitBreaker {
+    /// Consecutive failures that open the breaker; 0 never does.
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    failures: u32,
+    last_error: String,
+    /// When the breaker opened, kept until a request succeeds again.
+    since: Option<DateTime<Utc>>,
+    /// When the current cooldown started.
+    opened: Option<Instant>,
+}
+
+/// Returned instead of running a request while the breaker is open.
+#[derive(Debug)]
+pub struct BackendUnhealthyError {
+    pub since: DateTime<Utc>,
+    pub failures: u32,
+    pub last_error: String,
+    pub retry_after: Duration,
+}
+
+impl std::fmt::Display for BackendUnhealthyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Gemini backend unhealthy since {} after {} failures in a row, last error: {}; retry in {}s",
+            self.since.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            self.failures,
+            self.last_error,
+            self.retry_after.as_secs().max(1)
+        )
+    }
+}
+
+impl std::error::Error for BackendUnhealthyError {}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self { threshold, cooldown, state: Mutex::new(State::default()) }
+    }
+
+    /// Fail when the breaker is open and its cooldown hasn't passed.
+    pub fn check(&self) -> Result<(), BackendUnhealthyError> {
+        let state = self.state.lock().unwrap();
+        let (Some(since), Some(opened)) = (state.since, state.opened) else {
+            return Ok(());
+        };
+        let elapsed = opened.elapsed();
+        if elapsed >= self.cooldown {
+            return Ok(());
+        }
+        Err(BackendUnhealthyError {
+            since,
+            failures: state.failures,
+            last_error: state.last_error.clone(),
+            retry_after: self.cooldown - elapsed,
+        })
+    }
+
+    /// A request succeeded, so the backend is healthy again.
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.since.is_some() {
+            tracing::info!("Gemini backend recovered after {} failures in a row", state.failures);
+        }
+        *state = State::default();
+    }
+
+    /// A request failed with `error`; only failures in [`trips`]'s sense count,
+    /// and others leave the streak as it is.
+    pub fn record_failure(&self, error: &anyhow::Error) {
+        if self.threshold == 0 || !trips(error) {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.failures += 1;
+        state.last_error = crate::redact::redact(&format!("{:#}", error)).into_owned();
+        if state.failures >= self.threshold {
+            if state.since.is_none() {
+                tracing::warn!(
+                    "Gemini backend failed {} times in a row, failing requests for {:?}: {}",
+                    state.failures, self.cooldown, state.last_error
+                );
+            }
+            state.since.get_or_insert_with(Utc::now);
+            state.opened = Some(Instant::now());
+        }
+    }
+}
+
+/// Whether `error` says the backend can't serve any request right now: the
+/// binary didn't start, or the credentials or quota are the problem.
+fn trips(error: &anyhow::Error) -> bool {
+    if error.downcast_ref::<BinaryMissingError>().is_some() || error.downcast_ref::<AuthError>().is_some() {
+        return true;
+    }
+    let message = format!("{:#}", error);
+    message.contains("Failed to spawn gemini command")
+        || crate::auth::diagnose(&message).is_some()
+        || crate::errors::is_quota(&message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn missing_binary() -> anyhow::Error {
+        BinaryMissingError { binary: "gemini".to_string() }.into()
+    }
+
+    #[test]
+    fn repeated_tripping_failures_open_the_breaker() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(60));
+        breaker.record_failure(&missing_binary());
+        breaker.record_failure(&anyhow::anyhow!("Failed to spawn gemini command: permission denied"));
+        assert!(breaker.check().is_ok());
+
+        breaker.record_failure(&missing_binary());
+        let error = breaker.check().unwrap_err();
+        assert_eq!(error.failures, 3);
+        assert!(error.retry_after <= Duration::from_secs(60));
+        assert!(error.to_string().contains("3 failures in a row"), "{}", error);
+    }
+
+    #[test]
+    fn other_failures_neither_count_nor_reset_the_streak() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+        breaker.record_failure(&missing_binary());
+        breaker.record_failure(&anyhow::anyhow!("Gemini command failed: invalid argument"));
+        assert!(breaker.check().is_ok());
+
+        breaker.record_failure(&missing_binary());
+        assert!(breaker.check().is_err());
+    }
+
+    #[test]
+    fn success_closes_the_breaker() {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(60));
+        breaker.record_failure(&missing_binary());
+        assert!(breaker.check().is_err());
+
+        breaker.record_success();
+        assert!(breaker.check().is_ok());
+        breaker.record_failure(&anyhow::anyhow!("Gemini command failed: invalid argument"));
+        assert!(breaker.check().is_ok());
+    }
+
+    #[test]
+    fn zero_threshold_never_opens() {
+        let breaker = CircuitBreaker::new(0, Duration::from_secs(60));
+        for _ in 0..10 {
+            breaker.record_failure(&missing_binary());
+        }
+        assert!(breaker.check().is_ok());
+    }
+
+    #[test]
+    fn failure_after_the_cooldown_reopens_since_the_first_time() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(20));
+        breaker.record_failure(&missing_binary());
+        let since = breaker.check().unwrap_err().since;
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.check().is_ok(), "the next request is let through");
+        breaker.record_failure(&missing_binary());
+        let error = breaker.check().unwrap_err();
+        assert_eq!(error.since, since);
+        assert_eq!(error.failures, 2);
+    }
+}
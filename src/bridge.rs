@@ -0,0 +1,207 @@
+use anyhow::{Context, Result};
+use rmcp::model::{CallToolRequestParam, CallToolResult, JsonObject, Tool};
+use rmcp::service::{RunningService, ServiceError, ServiceExt};
+use rmcp::transport::TokioChildProcess;
+use rmcp::{Error as McpError, RoleClient};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::errors::{self, ErrorKind};
+
+/// Prefix of every tool proxied from one of gemini's MCP servers.
+pub const TOOL_PREFIX: &str = "gemini_ext_";
+
+/// Longest tool name many MCP clients accept.
+const MAX_TOOL_NAME: usize = 64;
+
+/// How long a server gets to start and list its tools.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The part of a gemini `settings.json` the bridge reads.
+#[derive(Debug, Default, Deserialize)]
+struct Settings {
+    #[serde(default, rename = "mcpServers")]
+    mcp_servers: BTreeMap<String, ServerSettings>,
+}
+
+/// One entry of `mcpServers`, as the gemini CLI defines it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ServerSettings {
+    command: Option<String>,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    cwd: Option<PathBuf>,
+    url: Option<String>,
+    http_url: Option<String>,
+    #[serde(default)]
+    include_tools: Vec<String>,
+    #[serde(default)]
+    exclude_tools: Vec<String>,
+}
+
+impl ServerSettings {
+    /// Whether the server's settings let gemini use `tool`.
+    fn allows(&self, tool: &str) -> bool {
+        (self.include_tools.is_empty() || self.include_tools.iter().any(|name| name == tool))
+            && !self.exclude_tools.iter().any(|name| name == tool)
+    }
+}
+
+/// A connected MCP server from gemini's settings.
+struct Upstream {
+    name: String,
+    service: RunningService<RoleClient, ()>,
+}
+
+/// Re-exposes the MCP servers configured for the gemini CLI as tools of this
+/// server, named `gemini_ext_<server>_<tool>`.
+pub struct Bridge {
+    upstreams: Vec<Upstream>,
+    /// Exposed name to the upstream index and the tool's own name.
+    routes: BTreeMap<String, (usize, String)>,
+    tools: Vec<Tool>,
+}
+
+impl Bridge {
+    /// Start every stdio MCP server in gemini's user and workspace settings and
+    /// list their tools. Servers that fail to start are logged and left out.
+    pub async fn start(config: &Config) -> Self {
+        let mut servers = BTreeMap::new();
+        for path in settings_paths() {
+            // Workspace settings override user settings of the same name, as in the CLI
+            servers.extend(read_settings(&path).mcp_servers);
+        }
+
+        let connecting = servers.into_iter().filter_map(|(name, settings)| {
+            if settings.command.is_none() {
+                let url = settings.http_url.as_ref().or(settings.url.as_ref());
+                tracing::warn!("Not bridging MCP server {} at {:?}: only stdio servers are supported", name, url);
+                return None;
+            }
+            Some(async move {
+                let connected = tokio::time::timeout(CONNECT_TIMEOUT, connect(&settings, &config.env_allowlist)).await
+                    .unwrap_or_else(|_| Err(anyhow::anyhow!("no answer within {}s", CONNECT_TIMEOUT.as_secs())));
+                (name, settings, connected)
+            })
+        });
+        let connected = futures::future::join_all(connecting).await;
+
+        let mut bridge = Self {
+            upstreams: Vec::new(),
+            routes: BTreeMap::new(),
+            tools: Vec::new(),
+        };
+        for (name, settings, connected) in connected {
+            let (service, tools) = match connected {
+                Ok(connected) => connected,
+                Err(e) => {
+                    tracing::warn!("Not bridging MCP server {}: {:#}", name, e);
+                    continue;
+                }
+            };
+            let index = bridge.upstreams.len();
+            for mut tool in tools.into_iter().filter(|tool| settings.allows(&tool.name)) {
+                let exposed = exposed_name(&name, &tool.name);
+                if bridge.routes.contains_key(&exposed) {
+                    tracing::warn!("Skipping bridged tool {} of {}: the name {} is taken", tool.name, name, exposed);
+                    continue;
+                }
+                bridge.routes.insert(exposed.clone(), (index, tool.name.to_string()));
+                tool.name = exposed.into();
+                bridge.tools.push(tool);
+            }
+            tracing::info!("Bridging MCP server {}", name);
+            bridge.upstreams.push(Upstream { name, service });
+        }
+        bridge
+    }
+
+    /// The proxied tools, under their exposed names.
+    pub fn tools(&self) -> &[Tool] {
+        &self.tools
+    }
+
+    pub fn handles(&self, name: &str) -> bool {
+        self.routes.contains_key(name)
+    }
+
+    /// Forward a call of the exposed tool `name` to the server that provides it.
+    pub async fn call(&self, name: &str, arguments: Option<JsonObject>) -> Result<CallToolResult, McpError> {
+        let Some((index, tool)) = self.routes.get(name) else {
+            return Err(errors::invalid_argument(format!("Unknown tool {}", name)));
+        };
+        let upstream = &self.upstreams[*index];
+        let request = CallToolRequestParam {
+            name: tool.clone().into(),
+            arguments,
+        };
+        upstream.service.call_tool(request).await.map_err(|e| match e {
+            // Errors the upstream server itself returned pass through unchanged
+            ServiceError::McpError(error) => error,
+            other => errors::error(
+                ErrorKind::BridgeFailed,
+                format!("MCP server {} failed: {}", upstream.name, other),
+                serde_json::json!({ "server": upstream.name }),
+            ),
+        })
+    }
+}
+
+/// Settings files the gemini CLI reads MCP servers from, in increasing precedence.
+fn settings_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let Some(home) = std::env::var_os("HOME") {
+        paths.push(Path::new(&home).join(".gemini").join("settings.json"));
+    }
+    paths.push(PathBuf::from(".gemini").join("settings.json"));
+    paths
+}
+
+fn read_settings(path: &Path) -> Settings {
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return Settings::default();
+    };
+    serde_json::from_str(&contents).unwrap_or_else(|e| {
+        tracing::warn!("Ignoring gemini settings {}: {}", path.display(), e);
+        Settings::default()
+    })
+}
+
+async fn connect(settings: &ServerSettings, env_allowlist: &[String]) -> Result<(RunningService<RoleClient, ()>, Vec<Tool>)> {
+    let command = settings.command.as_deref().context("no command")?;
+    let mut cmd = tokio::process::Command::new(command);
+    cmd.args(&settings.args);
+    // Like gemini processes, servers only see allowlisted variables plus their own
+    cmd.env_clear();
+    cmd.envs(crate::backend::allowed_env(env_allowlist));
+    cmd.envs(settings.env.iter().map(|(name, value)| (name, crate::config::expand_env(value))));
+    if let Some(cwd) = &settings.cwd {
+        cmd.current_dir(cwd);
+    }
+    cmd.kill_on_drop(true);
+    crate::children::isolate(&mut cmd);
+
+    let transport = TokioChildProcess::new(cmd)
+        .with_context(|| format!("Failed to start {}", command))?;
+    let service = ().serve(transport).await
+        .context("MCP handshake failed")?;
+    let tools = service.list_all_tools().await
+        .context("Failed to list tools")?;
+    Ok((service, tools))
+}
+
+/// `gemini_ext_<server>_<tool>`, limited to the characters and length MCP clients accept.
+fn exposed_name(server: &str, tool: &str) -> String {
+    let mut name: String = format!("{}{}_{}", TOOL_PREFIX, server, tool)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+        .collect();
+    name.truncate(MAX_TOOL_NAME);
+    name
+}
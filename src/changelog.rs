@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+
+use crate::git::CommitInfo;
+
+/// Most commits sent to Gemini; older ones past this are left out with a warning.
+pub const MAX_COMMITS: usize = 1_000;
+
+/// Characters of a commit body kept for the prompt.
+const MAX_BODY_CHARS: usize = 500;
+
+/// How `gemini_changelog` lays out release notes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChangelogFormat {
+    /// A version heading with Added, Changed, Deprecated, Removed, Fixed and Security
+    /// sections, as in https://keepachangelog.com.
+    #[default]
+    KeepAChangelog,
+    /// GitHub release notes: highlights, then a "What's Changed" list.
+    Github,
+    /// Sections per Conventional Commits type, as conventional-changelog writes them.
+    Conventional,
+}
+
+impl std::str::FromStr for ChangelogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "keep-a-changelog" => Ok(Self::KeepAChangelog),
+            "github" => Ok(Self::Github),
+            "conventional" => Ok(Self::Conventional),
+            other => anyhow::bail!("Unknown changelog format '{}', expected 'keep-a-changelog', 'github' or 'conventional'", other),
+        }
+    }
+}
+
+impl ChangelogFormat {
+    fn layout(self, version: &str, date: &str) -> String {
+        match self {
+            Self::KeepAChangelog => format!(
+                "Follow Keep a Changelog: start with `## [{}] - {}`, then only the sections that have entries, in \
+                 this order: `### Added`, `### Changed`, `### Deprecated`, `### Removed`, `### Fixed`, `### Security`.",
+                version, date
+            ),
+            Self::Github => format!(
+                "Write GitHub release notes for {}: a `## Highlights` section with the few changes users care most \
+                 about in a sentence or two each, then `## What's Changed` listing every other notable change, and \
+                 `## Breaking Changes` first when there are any.",
+                version
+            ),
+            Self::Conventional => format!(
+                "Follow conventional-changelog: start with `## {} ({})`, then `### ⚠ BREAKING CHANGES`, `### Features`, \
+                 `### Bug Fixes`, `### Performance Improvements` and `### Reverts` as needed, each entry ending with \
+                 the short hash of its commit in parentheses.",
+                version, date
+            ),
+        }
+    }
+}
+
+/// The group a commit falls in, by the Conventional Commits type of its subject.
+pub fn group(commit: &CommitInfo) -> &'static str {
+    let subject = commit.subject.trim();
+    let kind = subject
+        .split_once(':')
+        .map(|(prefix, _)| prefix.split('(').next().unwrap_or(prefix).trim_end_matches('!').to_ascii_lowercase());
+    let breaking = subject.split_once(':').is_some_and(|(prefix, _)| prefix.ends_with('!'))
+        || commit.body.contains("BREAKING CHANGE");
+    if breaking {
+        return "breaking";
+    }
+    match kind.as_deref() {
+        Some("feat") => "features",
+        Some("fix") => "fixes",
+        Some("perf") => "performance",
+        Some("docs") => "documentation",
+        Some("refactor") => "refactoring",
+        Some("revert") => "reverts",
+        Some("test" | "build" | "ci" | "chore" | "style") => "maintenance",
+        _ => "other",
+    }
+}
+
+/// Prompt asking for the release notes of `version` from its commits, already grouped.
+pub fn prompt(
+    format: ChangelogFormat,
+    version: &str,
+    date: &str,
+    groups: &[(&str, Vec<&CommitInfo>)],
+    tags: &[String],
+    instructions: Option<&str>,
+) -> String {
+    let mut prompt = format!(
+        "Draft the release notes for the commits below. {} Write for the project's users: describe each change by \
+         its effect rather than its implementation, merge commits that belong to one change, and leave out \
+         maintenance that users don't notice, such as CI, formatting and test-only commits. Answer with the \
+         release notes in Markdown only.",
+        format.layout(version, date)
+    );
+    if let Some(instructions) = instructions.filter(|instructions| !instructions.trim().is_empty()) {
+        prompt.push_str(&format!("\n\n{}", instructions.trim()));
+    }
+    if !tags.is_empty() {
+        prompt.push_str(&format!("\n\nTags in this range, oldest first: {}", tags.join(", ")));
+    }
+    prompt.push_str("\n\nCommits by group, newest first:");
+    for (group, commits) in groups {
+        prompt.push_str(&format!("\n\n[{}]", group));
+        for commit in commits {
+            let short = &commit.hash[..commit.hash.len().min(7)];
+            prompt.push_str(&format!("\n- {} {}", short, commit.subject));
+            if !commit.body.is_empty() {
+                let body: String = commit.body.chars().take(MAX_BODY_CHARS).collect();
+                prompt.push_str(&format!("\n  {}", body.trim().replace('\n', "\n  ")));
+            }
+        }
+    }
+    prompt
+}
@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::process::Child;
+
+/// How often the reaper looks for gemini processes that outlived their request.
+const REAP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Extra time a process gets past its timeout before the reaper kills it.
+const REAP_GRACE: Duration = Duration::from_secs(30);
+
+/// Running gemini processes by pid, with the time after which they are considered escaped;
+/// long-lived processes have none.
+static CHILDREN: Mutex<BTreeMap<u32, Option<Instant>>> = Mutex::new(BTreeMap::new());
+
+/// Keeps a spawned gemini process registered; dropping it kills the process
+/// and anything it started, e.g. when the request is cancelled or its client
+/// disconnects mid-call.
+pub struct ChildGuard {
+    pid: u32,
+    armed: bool,
+}
+
+impl ChildGuard {
+    /// Stop tracking `child` once it has exited, so its pid, which may have
+    /// been reused by then, is never signalled; a child still running is
+    /// killed as usual when the guard drops.
+    pub fn finish(mut self, child: &mut Child) {
+        if matches!(child.try_wait(), Ok(Some(_))) {
+            self.armed = false;
+        }
+    }
+}
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        CHILDREN.lock().unwrap().remove(&self.pid);
+        // The CLI may have started node or tool processes that outlive it
+        if self.armed {
+            kill_tree(self.pid);
+        }
+    }
+}
+
+/// Register `child`, which is expected to finish within `timeout` if it has one.
+///
+/// The child must have been spawned with [`isolate`] so its whole process tree can be killed.
+pub fn track(child: &Child, timeout: Option<Duration>) -> Option<ChildGuard> {
+    let pid = child.id()?;
+    let deadline = timeout.map(|timeout| Instant::now() + timeout + REAP_GRACE);
+    CHILDREN.lock().unwrap().insert(pid, deadline);
+    Some(ChildGuard { pid, armed: true })
+}
+
+/// Start the child in a process group of its own, so it and its descendants can be killed together.
+pub fn isolate(cmd: &mut tokio::process::Command) {
+    #[cfg(unix)]
+    cmd.process_group(0);
+    #[cfg(not(unix))]
+    let _ = cmd;
+}
+
+/// Kill every tracked process; called when the server shuts down.
+pub fn kill_all() {
+    let children = std::mem::take(&mut *CHILDREN.lock().unwrap());
+    if !children.is_empty() {
+        tracing::info!("Killing {} running gemini processes", children.len());
+    }
+    for pid in children.into_keys() {
+        kill_tree(pid);
+    }
+}
+
+/// Periodically kill tracked processes that ran well past their timeout.
+pub fn spawn_reaper() {
+    tokio::spawn(async {
+        let mut ticker = tokio::time::interval(REAP_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let now = Instant::now();
+            let escaped: Vec<u32> = {
+                let mut children = CHILDREN.lock().unwrap();
+                let expired = |deadline: &Option<Instant>| deadline.is_some_and(|deadline| deadline <= now);
+                let escaped = children.iter().filter(|(_, deadline)| expired(deadline)).map(|(pid, _)| *pid).collect();
+                children.retain(|_, deadline| !expired(deadline));
+                escaped
+            };
+            for pid in escaped {
+                tracing::warn!("Reaping gemini process {} that outlived its request", pid);
+                kill_tree(pid);
+            }
+        }
+    });
+}
+
+/// Ask `pid` and everything it started to exit, giving them a chance to clean
+/// up; a [`ChildGuard`] still kills whatever is left when it is dropped.
+#[cfg(unix)]
+pub fn terminate_tree(pid: u32) {
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), libc::SIGTERM);
+    }
+}
+
+/// Ask `pid` and everything it started to exit, giving them a chance to clean
+/// up; a [`ChildGuard`] still kills whatever is left when it is dropped.
+#[cfg(windows)]
+pub fn terminate_tree(pid: u32) {
+    // Without /F, taskkill asks the processes to close
+    let result = std::process::Command::new("taskkill")
+        .args(["/T", "/PID", &pid.to_string()])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn();
+    if let Err(e) = result {
+        tracing::debug!("Failed to run taskkill for {}: {}", pid, e);
+    }
+}
+
+#[cfg(unix)]
+fn kill_tree(pid: u32) {
+    // The child leads its own process group, so this reaches its descendants too;
+    // ESRCH just means everything already exited
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+#[cfg(windows)]
+fn kill_tree(pid: u32) {
+    let result = std::process::Command::new("taskkill")
+        .args(["/T", "/F", "/PID", &pid.to_string()])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn();
+    if let Err(e) = result {
+        tracing::debug!("Failed to run taskkill for {}: {}", pid, e);
+    }
+}
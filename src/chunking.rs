@@ -0,0 +1,157 @@
+use crate::tokens;
+
+/// Fewest tokens a part may hold, below which a document is not worth splitting.
+pub const MIN_PART_TOKENS: u32 = 1_000;
+
+/// Most parts one request is split into.
+pub const MAX_PARTS: usize = 50;
+
+/// Cut `text` into parts of at most about `max_tokens` each, at line breaks.
+///
+/// Each part after the first starts with the last lines of the one before,
+/// up to `overlap_tokens`, so that a passage cut in two is whole in one of
+/// them. The overlap is capped at a quarter of a part.
+pub fn split(text: &str, max_tokens: u32, overlap_tokens: u32) -> Vec<String> {
+    let overlap_tokens = overlap_tokens.min(max_tokens / 4);
+    let mut parts = Vec::new();
+    // Lines of the current part with their estimates, kept for the overlap
+    let mut current: Vec<(&str, u32)> = Vec::new();
+    let mut current_tokens = 0;
+    for line in text.split_inclusive('\n') {
+        for segment in segments(line, max_tokens) {
+            let segment_tokens = tokens::estimate(segment);
+            if current_tokens + segment_tokens > max_tokens && !current.is_empty() {
+                parts.push(current.iter().map(|(segment, _)| *segment).collect::<String>());
+                let mut kept = 0;
+                let mut overlap = 0;
+                for (_, tokens) in current.iter().rev() {
+                    if overlap + tokens > overlap_tokens {
+                        break;
+                    }
+                    overlap += tokens;
+                    kept += 1;
+                }
+                current.drain(..current.len() - kept);
+                current_tokens = overlap;
+            }
+            // Drop an overlap the segment doesn't fit beside rather than let it
+            // overflow the part or end up in one of its own
+            if current_tokens + segment_tokens > max_tokens {
+                current.clear();
+                current_tokens = 0;
+            }
+            current.push((segment, segment_tokens));
+            current_tokens += segment_tokens;
+        }
+    }
+    let last: String = current.iter().map(|(segment, _)| *segment).collect();
+    if !last.trim().is_empty() {
+        parts.push(last);
+    }
+    parts
+}
+
+/// `line`, cut into pieces of at most `max_tokens` characters when it is longer
+/// than a part by itself, as in minified files.
+fn segments(line: &str, max_tokens: u32) -> Vec<&str> {
+    if tokens::estimate(line) <= max_tokens {
+        return vec![line];
+    }
+    let mut segments = Vec::new();
+    let mut rest = line;
+    while !rest.is_empty() {
+        let end = rest.char_indices().nth(max_tokens as usize).map_or(rest.len(), |(index, _)| index);
+        segments.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    segments
+}
+
+/// Prompt answering `question` from one part of a document, or following a
+/// long prompt's own instructions when there is no separate question.
+pub fn part_prompt(question: Option<&str>, part: &str, index: usize, total: usize) -> String {
+    match question {
+        Some(question) => format!(
+            "The input below is part {} of {} of a document too long to send at once, and consecutive parts \
+             overlap slightly. Using only this part, answer the request as far as the part allows, or say briefly \
+             that it has nothing relevant. Your answer will be combined with those for the other parts.\n\n\
+             Request:\n{}\n\n<part index=\"{}\" total=\"{}\">\n{}\n</part>",
+            index + 1, total, question.trim(), index + 1, total, part.trim_end()
+        ),
+        None => format!(
+            "The input below is part {} of {} of a prompt too long to send at once, and consecutive parts overlap \
+             slightly. Respond to what this part asks or contains as far as it allows. Your answer will be combined \
+             with those for the other parts.\n\n<part index=\"{}\" total=\"{}\">\n{}\n</part>",
+            index + 1, total, index + 1, total, part.trim_end()
+        ),
+    }
+}
+
+/// Prompt consolidating the answers for every part into the final answer.
+pub fn consolidation_prompt(question: Option<&str>, answers: &[String]) -> String {
+    let task = match question {
+        Some(question) => format!(
+            "A document too long to send at once was split into parts, and the request below was answered for \
+             each part on its own. Combine these partial answers into the single answer the request calls for \
+             over the whole document. Merge duplicates from the overlap between parts, resolve contradictions, \
+             and leave out parts that had nothing relevant.\n\nRequest:\n{}",
+            question.trim()
+        ),
+        None => "A prompt too long to send at once was split into parts, and each part was answered on its own. \
+                 Combine these partial answers into the single answer the whole prompt calls for. Merge duplicates \
+                 from the overlap between parts and resolve contradictions."
+            .to_string(),
+    };
+    let answers: Vec<String> = answers
+        .iter()
+        .enumerate()
+        .map(|(index, answer)| format!("<answer part=\"{}\">\n{}\n</answer>", index + 1, answer.trim()))
+        .collect();
+    format!("{}\n\n{}", task, answers.join("\n\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_within(parts: &[String], max_tokens: u32) {
+        for part in parts {
+            assert!(tokens::estimate(part) <= max_tokens, "{} tokens: {:?}", tokens::estimate(part), part);
+        }
+    }
+
+    #[test]
+    fn text_within_a_part_stays_whole() {
+        assert_eq!(split("one\ntwo\n", 100, 10), ["one\ntwo\n"]);
+    }
+
+    #[test]
+    fn parts_break_at_lines_and_overlap() {
+        // 2 tokens per line, so a part holds 10 lines and the next one repeats the last 2
+        let text: String = (0..20).map(|line| format!("line {:02}\n", line)).collect();
+        let parts = split(&text, 20, 5);
+
+        assert!(parts.len() > 1);
+        assert_within(&parts, 20);
+        assert!(parts.iter().all(|part| part.ends_with('\n')));
+        assert!(parts[0].ends_with("line 08\nline 09\n"), "{:?}", parts[0]);
+        assert!(parts[1].starts_with("line 08\nline 09\nline 10\n"), "{:?}", parts[1]);
+        assert!(parts.last().unwrap().ends_with("line 19\n"));
+    }
+
+    #[test]
+    fn overlong_line_is_cut_into_segments() {
+        let parts = split(&"x".repeat(200), 20, 0);
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts.concat(), "x".repeat(200));
+    }
+
+    #[test]
+    fn overlap_that_does_not_fit_beside_the_next_segment_is_dropped() {
+        let text = format!("abcdefg\n{}", "あ".repeat(40));
+        let parts = split(&text, 20, 5);
+
+        assert_eq!(parts, ["abcdefg\n".to_string(), "あ".repeat(20), "あ".repeat(20)]);
+        assert_within(&parts, 20);
+    }
+}
@@ -0,0 +1,159 @@
+use rmcp::{model::*, Peer, RoleServer};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::redact;
+
+/// Connections that asked for log messages with `logging/setLevel`.
+static CLIENTS: Mutex<Vec<Weak<ClientLog>>> = Mutex::new(Vec::new());
+/// Whether any connection has, so events are dropped cheaply until one does.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// One client connection's wish to receive the server's log as MCP
+/// `notifications/message`.
+#[derive(Default)]
+pub struct ClientLog {
+    target: Mutex<Option<(Peer<RoleServer>, LoggingLevel)>>,
+}
+
+impl ClientLog {
+    /// Track a new client connection; it receives nothing until it sets a level.
+    pub fn register() -> Arc<Self> {
+        let client = Arc::new(Self::default());
+        let mut clients = CLIENTS.lock().unwrap();
+        clients.retain(|client| client.strong_count() > 0);
+        clients.push(Arc::downgrade(&client));
+        client
+    }
+
+    /// Send events at `level` and above to `peer` from now on.
+    pub fn set_level(&self, peer: &Peer<RoleServer>, level: LoggingLevel) {
+        *self.target.lock().unwrap() = Some((peer.clone(), level));
+        ENABLED.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A tracing layer forwarding this server's own events to the clients that
+/// set a log level, redacted like the rest of the log, with the fields of the
+/// spans they happen in, such as the tool call's `request_id`. Events of
+/// libraries, including the MCP transport sending the notifications, are left out.
+pub struct ClientLogLayer;
+
+/// The fields a span was created with, kept for the events inside it.
+struct SpanFields(serde_json::Map<String, serde_json::Value>);
+
+impl<S> Layer<S> for ClientLogLayer
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attributes: &Attributes<'_>, id: &Id, context: Context<'_, S>) {
+        if !attributes.metadata().target().starts_with(crate_name()) {
+            return;
+        }
+        let mut fields = Fields::default();
+        attributes.record(&mut fields);
+        if let Some(span) = context.span(id) {
+            span.extensions_mut().insert(SpanFields(fields.data));
+        }
+    }
+
+    fn on_event(&self, event: &tracing::Event<'_>, context: Context<'_, S>) {
+        if !ENABLED.load(Ordering::Relaxed) || !event.metadata().target().starts_with(crate_name()) {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let level = level(event.metadata().level());
+        let peers: Vec<Peer<RoleServer>> = CLIENTS
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter_map(|client| client.target.lock().unwrap().clone())
+            .filter(|(_, min)| rank(level) >= rank(*min))
+            .map(|(peer, _)| peer)
+            .collect();
+        if peers.is_empty() {
+            return;
+        }
+
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        fields.data.insert("message".to_string(), serde_json::json!(redact::redact(&fields.message)));
+        for span in context.event_scope(event).into_iter().flatten() {
+            if let Some(SpanFields(span_fields)) = span.extensions().get::<SpanFields>() {
+                for (name, value) in span_fields {
+                    fields.data.entry(name.clone()).or_insert_with(|| value.clone());
+                }
+            }
+        }
+        let notification = LoggingMessageNotificationParam {
+            level,
+            logger: Some(event.metadata().target().to_string()),
+            data: serde_json::Value::Object(fields.data),
+        };
+        for peer in peers {
+            let notification = notification.clone();
+            // Failures aren't logged, which would only produce more events to send
+            runtime.spawn(async move {
+                let _ = peer.notify_logging_message(notification).await;
+            });
+        }
+    }
+}
+
+/// The name this crate's events are targeted under.
+fn crate_name() -> &'static str {
+    static NAME: OnceLock<&'static str> = OnceLock::new();
+    NAME.get_or_init(|| module_path!().split("::").next().unwrap_or_default())
+}
+
+fn level(level: &tracing::Level) -> LoggingLevel {
+    match *level {
+        tracing::Level::ERROR => LoggingLevel::Error,
+        tracing::Level::WARN => LoggingLevel::Warning,
+        tracing::Level::INFO => LoggingLevel::Info,
+        _ => LoggingLevel::Debug,
+    }
+}
+
+/// Severity of `level`, lowest first, as RFC 5424 orders them.
+fn rank(level: LoggingLevel) -> u8 {
+    match level {
+        LoggingLevel::Debug => 0,
+        LoggingLevel::Info => 1,
+        LoggingLevel::Notice => 2,
+        LoggingLevel::Warning => 3,
+        LoggingLevel::Error => 4,
+        LoggingLevel::Critical => 5,
+        LoggingLevel::Alert => 6,
+        LoggingLevel::Emergency => 7,
+    }
+}
+
+/// An event's message and its other fields, redacted.
+#[derive(Default)]
+struct Fields {
+    message: String,
+    data: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message = value.to_string(),
+            name => {
+                self.data.insert(name.to_string(), serde_json::json!(redact::redact(value)));
+            }
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value));
+    }
+}
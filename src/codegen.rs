@@ -0,0 +1,161 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::files::{self, Attachment};
+use crate::format::{self, InvalidOutputError};
+
+/// A file Gemini wrote, as `gemini_generate_tests` asks for them.
+#[derive(Debug, Deserialize)]
+pub struct GeneratedFile {
+    pub path: String,
+    pub contents: String,
+}
+
+#[derive(Deserialize)]
+struct GeneratedFiles {
+    files: Vec<GeneratedFile>,
+}
+
+/// Prompt asking for the complete contents of the file at `path`.
+pub fn prompt(path: &str, instructions: &str, existing: Option<&str>, references: &[Attachment]) -> String {
+    let mut prompt = match existing {
+        Some(_) => format!(
+            "Rewrite the file {} as follows, keeping everything the instructions don't ask to change.\n\n{}",
+            path,
+            instructions.trim()
+        ),
+        None => format!("Write the file {} as follows.\n\n{}", path, instructions.trim()),
+    };
+    prompt.push_str(
+        "\n\nAnswer with the complete contents of the file in a single code block and nothing else: \
+         no explanation before or after it, and no placeholders for parts left out.",
+    );
+    if let Some(existing) = existing {
+        prompt.push_str(&format!("\n\nCurrent contents:\n<file path=\"{}\">\n{}\n</file>", path, existing.trim_end()));
+    }
+    if !references.is_empty() {
+        prompt.push_str("\n\nFor reference:");
+        prompt = files::inline(&prompt, references);
+    }
+    prompt
+}
+
+/// The file's contents from Gemini's answer: the inside of its code block, or
+/// the whole answer when it has none. Always ends with a newline.
+pub fn extract(answer: &str) -> String {
+    static BLOCK: OnceLock<Regex> = OnceLock::new();
+    let block = BLOCK.get_or_init(|| Regex::new(r"(?s)^\s*(`{3,})[^\n]*\n(.*?)\n?\s*(`{3,})\s*$").expect("block pattern is valid"));
+
+    let contents = match block.captures(answer) {
+        // A longer closing fence belongs to a block nested inside the file
+        Some(captures) if captures[1].len() == captures[3].len() => captures[2].to_string(),
+        _ => answer.trim().to_string(),
+    };
+    format!("{}\n", contents.trim_end_matches('\n'))
+}
+
+/// Prompt asking for tests of `sources`, with `references` as further context.
+pub fn tests_prompt(framework: Option<&str>, instructions: Option<&str>, sources: &[Attachment], references: &[Attachment]) -> String {
+    let mut prompt = String::from(
+        "Write unit tests for the source files below. Cover each public function's main behavior, its edge cases \
+         and its error paths, with one focused test per behavior and descriptive test names. Test only behavior \
+         visible in the code; don't invent functions or modules that aren't there. Follow the conventions of the \
+         language and of any existing tests shown, including where test files go and how they are named.",
+    );
+    if let Some(framework) = framework {
+        prompt.push_str(&format!(" Use {}.", framework.trim()));
+    }
+    if let Some(instructions) = instructions.filter(|instructions| !instructions.trim().is_empty()) {
+        prompt.push_str(&format!("\n\n{}", instructions.trim()));
+    }
+    prompt.push_str(
+        "\n\nAnswer with a JSON object {\"files\": [{\"path\": ..., \"contents\": ...}]} listing every test file \
+         to write, with paths relative to the project and each file's complete contents. When a test file shown \
+         below already exists, return all of it with the new tests added.\n\nSource files:",
+    );
+    prompt = files::inline(&prompt, sources);
+    if !references.is_empty() {
+        prompt.push_str("\n\nFor reference:");
+        prompt = files::inline(&prompt, references);
+    }
+    prompt
+}
+
+/// The files in an answer to [`tests_prompt`].
+pub fn parse_files(answer: &str) -> Result<Vec<GeneratedFile>, InvalidOutputError> {
+    let value = format::parse_json(answer)?;
+    let parsed: GeneratedFiles = serde_json::from_value(value).map_err(|e| InvalidOutputError {
+        reason: format!("expected {{\"files\": [{{\"path\", \"contents\"}}]}}: {}", e),
+    })?;
+    Ok(parsed.files)
+}
+
+/// The test framework the project in `dir` appears to use, from its manifests.
+pub fn detect_framework(dir: &Path) -> Option<&'static str> {
+    if dir.join("Cargo.toml").is_file() {
+        return Some("Rust's built-in test harness, in #[cfg(test)] modules or the tests directory");
+    }
+    if dir.join("go.mod").is_file() {
+        return Some("Go's testing package, in _test.go files");
+    }
+    if let Ok(manifest) = std::fs::read_to_string(dir.join("package.json")) {
+        for (package, framework) in [("vitest", "Vitest"), ("jest", "Jest"), ("mocha", "Mocha"), ("@playwright/test", "Playwright Test")] {
+            if manifest.contains(&format!("\"{}\"", package)) {
+                return Some(framework);
+            }
+        }
+        return Some("the node:test module");
+    }
+    if ["pyproject.toml", "setup.py", "setup.cfg", "requirements.txt"].iter().any(|name| dir.join(name).is_file()) {
+        return Some("pytest");
+    }
+    None
+}
+
+/// Prompt asking for the changes `instructions` describe as a unified diff of `files`.
+pub fn patch_prompt(instructions: &str, files: &[Attachment]) -> String {
+    let prompt = format!(
+        "Make the following change to the files below.\n\n{}\n\nAnswer with the change as a unified diff, as \
+         `git diff` writes it, in a single ```diff code block and nothing else. Use paths exactly as the files \
+         are named below, with a/ and b/ prefixes, /dev/null for files created or deleted, and three lines of \
+         unchanged context around each hunk, copied exactly.",
+        instructions.trim()
+    );
+    files::inline(&prompt, files)
+}
+
+/// Write `contents` to `path`, creating missing directories and first copying a
+/// file already there next to it as `<name>.<timestamp>.bak`, which is returned.
+pub fn write(path: &Path, contents: &str) -> Result<Option<PathBuf>> {
+    let backup = if path.exists() {
+        let backup = backup_path(path)?;
+        std::fs::copy(path, &backup)
+            .with_context(|| format!("Failed to back up {} to {}", path.display(), backup.display()))?;
+        Some(backup)
+    } else {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        None
+    };
+    std::fs::write(path, contents)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(backup)
+}
+
+/// Delete the file at `path` by moving it to its backup name, which is returned.
+pub fn remove(path: &Path) -> Result<PathBuf> {
+    let backup = backup_path(path)?;
+    std::fs::rename(path, &backup)
+        .with_context(|| format!("Failed to move {} to {}", path.display(), backup.display()))?;
+    Ok(backup)
+}
+
+fn backup_path(path: &Path) -> Result<PathBuf> {
+    let name = path.file_name().context("The path names no file")?.to_string_lossy();
+    Ok(path.with_file_name(format!("{}.{}.bak", name, chrono::Local::now().format("%Y%m%d-%H%M%S"))))
+}
@@ -0,0 +1,192 @@
+use anyhow::Result;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::OnceLock;
+use tokio::process::Command;
+
+/// Most of the staged diff sent to Gemini, in estimated tokens; the middle of a
+/// larger diff is cut out.
+pub const MAX_DIFF_TOKENS: u32 = 60_000;
+
+/// How many recent subjects are shown to Gemini as examples of the repository's style.
+const RECENT_SUBJECTS: usize = 10;
+
+/// Types Conventional Commits subjects may start with.
+const CONVENTIONAL_TYPES: &[&str] = &[
+    "feat", "fix", "docs", "style", "refactor", "perf", "test", "build", "ci", "chore", "revert",
+];
+
+/// The form commit messages written by `gemini_commit_message` take.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Convention {
+    /// `type(scope): summary`, as in https://www.conventionalcommits.org.
+    #[default]
+    Conventional,
+    /// A gitmoji such as ✨ or `:bug:` followed by the summary, as in https://gitmoji.dev.
+    Gitmoji,
+    /// An imperative summary with no prefix.
+    Plain,
+}
+
+impl std::str::FromStr for Convention {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "conventional" => Ok(Self::Conventional),
+            "gitmoji" => Ok(Self::Gitmoji),
+            "plain" => Ok(Self::Plain),
+            other => anyhow::bail!("Unknown commit convention '{}', expected 'conventional', 'gitmoji' or 'plain'", other),
+        }
+    }
+}
+
+impl Convention {
+    fn rules(self) -> String {
+        match self {
+            Self::Conventional => format!(
+                "Follow Conventional Commits: the subject is `type(scope): summary` or `type: summary`, where type is \
+                 one of {}, the optional scope names the part of the code changed, and `!` after the type or scope \
+                 marks a breaking change, which the body then explains in a `BREAKING CHANGE:` footer.",
+                CONVENTIONAL_TYPES.join(", ")
+            ),
+            Self::Gitmoji => "Follow gitmoji: the subject starts with the one emoji that best describes the change \
+                 (e.g. ✨ new feature, 🐛 bug fix, ♻️ refactor, 📝 documentation, ✅ tests, ⚡️ performance, 🔥 removal), \
+                 then a space and the summary."
+                .to_string(),
+            Self::Plain => "The subject is the summary alone, with no type, tag or emoji in front.".to_string(),
+        }
+    }
+}
+
+/// A commit message split into its parts.
+#[derive(Debug, Serialize)]
+pub struct Message {
+    pub subject: String,
+    /// Everything after the blank line following the subject; empty for a subject-only message.
+    pub body: String,
+}
+
+impl Message {
+    /// The message as `git commit -F` reads it.
+    pub fn to_text(&self) -> String {
+        if self.body.is_empty() {
+            format!("{}\n", self.subject)
+        } else {
+            format!("{}\n\n{}\n", self.subject, self.body)
+        }
+    }
+}
+
+/// Subjects of the latest commits in `repo`, newest first; empty for a repository
+/// without commits.
+pub async fn recent_subjects(repo: &Path) -> Vec<String> {
+    let output = Command::new("git")
+        .arg("-C").arg(repo)
+        .args(["log", "--no-color", "--format=%s"])
+        .arg(format!("-n{}", RECENT_SUBJECTS))
+        .output()
+        .await;
+    match output {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(str::to_string)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Prompt asking for the commit message of `diff`.
+pub fn prompt(diff: &str, convention: Convention, max_subject_chars: usize, guidelines: Option<&str>, recent: &[String]) -> String {
+    let mut prompt = format!(
+        "Write the commit message for the staged changes below.\n\n\
+         The first line is the subject: at most {} characters, in the imperative mood (\"Add\", not \"Added\"), with no \
+         trailing period. {}\n\n\
+         If the change needs explaining, add a blank line and a body wrapped at 72 columns that says what changed and \
+         why, not how; leave it out for a change the subject fully describes.",
+        max_subject_chars,
+        convention.rules()
+    );
+    if let Some(guidelines) = guidelines.filter(|guidelines| !guidelines.trim().is_empty()) {
+        prompt.push_str(&format!("\n\nAlso follow these guidelines of the project:\n{}", guidelines.trim()));
+    }
+    if !recent.is_empty() {
+        prompt.push_str(&format!(
+            "\n\nRecent commit subjects in this repository, for its vocabulary and scopes:\n{}",
+            recent.iter().map(|subject| format!("- {}", subject)).collect::<Vec<_>>().join("\n")
+        ));
+    }
+    prompt.push_str(&format!(
+        "\n\nAnswer with the commit message only, without code fences or anything around it.\n\n```diff\n{}\n```",
+        diff.trim_end()
+    ));
+    prompt
+}
+
+/// Prompt asking Gemini to fix `message` for the `problems` found in it.
+pub fn revision_prompt(message: &Message, problems: &[String]) -> String {
+    format!(
+        "This commit message breaks its rules:\n{}\n\nRewrite it to fix that, keeping its meaning. Answer with the \
+         commit message only.\n\n{}",
+        problems.iter().map(|problem| format!("- {}", problem)).collect::<Vec<_>>().join("\n"),
+        message.to_text()
+    )
+}
+
+/// The message in Gemini's answer, without fences or a leading `Subject:` label.
+pub fn parse(answer: &str) -> Message {
+    let text = crate::codegen::extract(answer);
+    let mut lines = text.trim().lines();
+    let subject = lines.next().unwrap_or_default().trim();
+    let subject = subject.strip_prefix("Subject:").unwrap_or(subject).trim().to_string();
+    let body = lines.collect::<Vec<_>>().join("\n").trim().to_string();
+    Message { subject, body }
+}
+
+/// How `message` breaks the rules of `convention`; empty when it follows them.
+pub fn problems(message: &Message, convention: Convention, max_subject_chars: usize) -> Vec<String> {
+    static CONVENTIONAL: OnceLock<Regex> = OnceLock::new();
+    static GITMOJI: OnceLock<Regex> = OnceLock::new();
+
+    let subject = &message.subject;
+    let mut problems = Vec::new();
+    if subject.is_empty() {
+        problems.push("The subject is empty".to_string());
+        return problems;
+    }
+    let length = subject.chars().count();
+    if length > max_subject_chars {
+        problems.push(format!("The subject is {} characters, more than {}", length, max_subject_chars));
+    }
+    if subject.ends_with('.') {
+        problems.push("The subject ends with a period".to_string());
+    }
+    match convention {
+        Convention::Conventional => {
+            let pattern = CONVENTIONAL.get_or_init(|| {
+                Regex::new(&format!(r"^(?:{})(?:\([^()\s]+\))?!?: \S", CONVENTIONAL_TYPES.join("|")))
+                    .expect("conventional commit pattern is valid")
+            });
+            if !pattern.is_match(subject) {
+                problems.push(format!(
+                    "The subject is not `type(scope): summary` with a type of {}",
+                    CONVENTIONAL_TYPES.join(", ")
+                ));
+            }
+        }
+        Convention::Gitmoji => {
+            let pattern = GITMOJI.get_or_init(|| {
+                Regex::new(r"^(?::[a-z0-9_+\-]+:|\p{Extended_Pictographic}[\u{FE0F}\u{200D}\p{Extended_Pictographic}]*) \S")
+                    .expect("gitmoji pattern is valid")
+            });
+            if !pattern.is_match(subject) {
+                problems.push("The subject doesn't start with a gitmoji followed by a space".to_string());
+            }
+        }
+        Convention::Plain => {}
+    }
+    problems
+}
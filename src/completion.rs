@@ -0,0 +1,47 @@
+use rmcp::model::CompletionInfo;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Most values one completion returns, as MCP allows.
+const MAX_VALUES: usize = 100;
+
+/// How long the live model list is reused; clients ask on every keystroke.
+const MODELS_TTL: Duration = Duration::from_secs(300);
+
+/// The candidates starting with `value`, then those containing it elsewhere,
+/// ignoring case.
+pub fn matching(candidates: Vec<String>, value: &str) -> CompletionInfo {
+    let needle = value.to_lowercase();
+    let (mut values, others): (Vec<String>, Vec<String>) = candidates
+        .into_iter()
+        .filter(|candidate| candidate.to_lowercase().contains(&needle))
+        .partition(|candidate| candidate.to_lowercase().starts_with(&needle));
+    values.extend(others);
+    let total = values.len();
+    values.truncate(MAX_VALUES);
+    CompletionInfo {
+        values,
+        total: Some(total as u32),
+        has_more: Some(total > MAX_VALUES),
+    }
+}
+
+/// Model names offered for `model` arguments, kept for [`MODELS_TTL`].
+#[derive(Default)]
+pub struct ModelNames {
+    cached: Mutex<Option<(Instant, Vec<String>)>>,
+}
+
+impl ModelNames {
+    /// The names stored within the last [`MODELS_TTL`].
+    pub fn get(&self) -> Option<Vec<String>> {
+        match &*self.cached.lock().unwrap() {
+            Some((stored, names)) if stored.elapsed() < MODELS_TTL => Some(names.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn set(&self, names: Vec<String>) {
+        *self.cached.lock().unwrap() = Some((Instant::now(), names));
+    }
+}
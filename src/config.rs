@@ -0,0 +1,1005 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::changelog::ChangelogFormat;
+use crate::commit::Convention;
+use crate::policy::ModelPolicy;
+use crate::postprocess::PostProcessor;
+use crate::ratelimit::{RateLimit, RateLimitPolicy};
+use crate::scheduler::Priority;
+
+/// Which backend serves generation requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    #[default]
+    Cli,
+    Api,
+}
+
+impl std::str::FromStr for BackendKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "cli" => Ok(Self::Cli),
+            "api" => Ok(Self::Api),
+            other => anyhow::bail!("Unknown backend '{}', expected 'cli' or 'api'", other),
+        }
+    }
+}
+
+/// Language of the server instructions and tool descriptions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    Ja,
+}
+
+impl std::str::FromStr for Locale {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "en" => Ok(Self::En),
+            "ja" => Ok(Self::Ja),
+            other => anyhow::bail!("Unknown locale '{}', expected 'en' or 'ja'", other),
+        }
+    }
+}
+
+/// What happens to a prompt too large for the model's context window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PromptOverflow {
+    /// Fail with `prompt_too_large`, reporting the measured size.
+    #[default]
+    Reject,
+    /// Cut the middle of the prompt out until it fits, with a warning.
+    Truncate,
+    /// Answer the input in overlapping parts and consolidate the answers with one
+    /// more call; tools that can't split their input reject it instead.
+    Chunk,
+}
+
+impl std::str::FromStr for PromptOverflow {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "reject" => Ok(Self::Reject),
+            "truncate" => Ok(Self::Truncate),
+            "chunk" => Ok(Self::Chunk),
+            other => anyhow::bail!("Unknown prompt overflow '{}', expected 'reject', 'truncate' or 'chunk'", other),
+        }
+    }
+}
+
+/// What happens to a `gemini_chat` session's oldest turns once its history
+/// nears the model's context window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatHistory {
+    /// Replace them with a summary Gemini writes of them, in one more call.
+    #[default]
+    Summarize,
+    /// Stop replaying them.
+    Evict,
+    /// Replay every turn, leaving an oversized history to `prompt_overflow`.
+    Keep,
+}
+
+impl std::str::FromStr for ChatHistory {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "summarize" => Ok(Self::Summarize),
+            "evict" => Ok(Self::Evict),
+            "keep" => Ok(Self::Keep),
+            other => anyhow::bail!("Unknown chat history '{}', expected 'summarize', 'evict' or 'keep'", other),
+        }
+    }
+}
+
+/// Which transport the server listens on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    #[default]
+    Stdio,
+    Http,
+}
+
+impl std::str::FromStr for TransportKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "stdio" => Ok(Self::Stdio),
+            "http" => Ok(Self::Http),
+            other => anyhow::bail!("Unknown transport '{}', expected 'stdio' or 'http'", other),
+        }
+    }
+}
+
+/// Whether Gemini's answers are recorded to, or replayed from, `recordings_dir`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Recording {
+    #[default]
+    Off,
+    /// Ask Gemini and save every answer.
+    Record,
+    /// Answer from the saved answers without running the CLI or calling the API.
+    Replay,
+}
+
+impl std::str::FromStr for Recording {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "off" => Ok(Self::Off),
+            "record" => Ok(Self::Record),
+            "replay" => Ok(Self::Replay),
+            other => anyhow::bail!("Unknown recording '{}', expected 'off', 'record' or 'replay'", other),
+        }
+    }
+}
+
+/// Settings that replace the server-wide ones for a single tool.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ToolOverrides {
+    /// Replaces `timeout_secs`; a `timeout_secs` argument in the call still wins.
+    pub timeout_secs: Option<u64>,
+    /// Replaces `max_retries`.
+    pub max_retries: Option<u32>,
+    /// How many calls of this tool may run at once, on top of `max_concurrency`.
+    pub max_concurrency: Option<usize>,
+    /// Replaces the tool's place in the queue; a `priority` argument in the call still wins.
+    pub priority: Option<Priority>,
+    /// Set to `false` to hide the tool from clients and refuse its calls.
+    /// Unlike the other overrides it is re-read when the config file changes.
+    pub enabled: Option<bool>,
+}
+
+/// Where the gemini CLI runs when it runs in Docker rather than on the host.
+///
+/// Exactly one of `container` and `image` is set.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DockerConfig {
+    /// Running container to `docker exec` the CLI in (`GEMINI_DOCKER_CONTAINER`).
+    pub container: Option<String>,
+    /// Image to `docker run --rm` for every call (`GEMINI_DOCKER_IMAGE`).
+    pub image: Option<String>,
+    /// The docker CLI, or a compatible one such as `podman`.
+    pub binary: String,
+    /// Extra `docker run` arguments, e.g. `["-v", "/data:/data:ro"]` or `["--network", "host"]`.
+    pub run_args: Vec<String>,
+}
+
+impl Default for DockerConfig {
+    fn default() -> Self {
+        Self {
+            container: None,
+            image: None,
+            binary: "docker".to_string(),
+            run_args: Vec::new(),
+        }
+    }
+}
+
+impl DockerConfig {
+    fn validate(&self) -> Result<()> {
+        match (&self.container, &self.image) {
+            (Some(_), Some(_)) => anyhow::bail!("docker.container and docker.image are mutually exclusive"),
+            (None, None) => anyhow::bail!("docker needs a container to exec in or an image to run"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// An identity requests can run as, chosen with a tool's `profile` argument.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    /// Variables set for the gemini CLI on top of the allowlisted ones, e.g.
+    /// `GEMINI_API_KEY`, or `GOOGLE_GENAI_USE_VERTEXAI` and `GOOGLE_CLOUD_PROJECT`.
+    /// `$VAR` and `${VAR}` are replaced from the server's environment, and an
+    /// empty value withholds the variable.
+    pub env: BTreeMap<String, String>,
+}
+
+impl Profile {
+    /// `env` with variables expanded.
+    pub fn resolved_env(&self) -> Vec<(String, String)> {
+        self.env.iter().map(|(name, value)| (name.clone(), expand_env(value))).collect()
+    }
+}
+
+/// Send requests through Vertex AI, billed to a Google Cloud project, instead of the Gemini API.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VertexConfig {
+    /// Project to bill (default: `GOOGLE_CLOUD_PROJECT`). Without one, requests use
+    /// Vertex AI express mode with `GOOGLE_API_KEY`.
+    pub project: Option<String>,
+    /// Region to serve requests from (default: `GOOGLE_CLOUD_LOCATION`, else `global`).
+    pub location: Option<String>,
+}
+
+impl VertexConfig {
+    pub fn location(&self) -> &str {
+        self.location.as_deref().unwrap_or("global")
+    }
+
+    /// Check that requests can be billed: to a project, or to an API key in express mode.
+    pub fn validate(&self) -> Result<()> {
+        if self.project.is_none() && !std::env::var("GOOGLE_API_KEY").is_ok_and(|key| !key.is_empty()) {
+            anyhow::bail!("vertex needs a project (vertex.project or GOOGLE_CLOUD_PROJECT), or GOOGLE_API_KEY for express mode");
+        }
+        if self.project.as_deref() == Some("") || self.location().is_empty() {
+            anyhow::bail!("vertex.project and vertex.location must not be empty");
+        }
+        Ok(())
+    }
+
+    /// The variables that make the gemini CLI use Vertex AI.
+    pub fn env(&self) -> Vec<(String, String)> {
+        let mut env = vec![
+            ("GOOGLE_GENAI_USE_VERTEXAI".to_string(), "true".to_string()),
+            ("GOOGLE_CLOUD_LOCATION".to_string(), self.location().to_string()),
+        ];
+        if let Some(project) = &self.project {
+            env.push(("GOOGLE_CLOUD_PROJECT".to_string(), project.clone()));
+        }
+        env
+    }
+}
+
+/// A remote host to run the gemini CLI on over SSH.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SshConfig {
+    /// `host`, `user@host` or a `Host` from `~/.ssh/config` (`GEMINI_SSH_HOST`).
+    pub host: String,
+    pub port: Option<u16>,
+    /// Private key to log in with, instead of ssh's defaults and agent.
+    pub identity_file: Option<PathBuf>,
+    /// The ssh client to run.
+    pub binary: String,
+    /// Extra ssh arguments, e.g. `["-o", "ConnectTimeout=10"]`.
+    pub options: Vec<String>,
+    /// Local directory prefixes and the remote paths they correspond to, e.g.
+    /// `"/Users/me/src" = "/home/me/src"`; other paths are used unchanged.
+    pub path_map: BTreeMap<PathBuf, String>,
+    /// Variables forwarded to the remote CLI with their values, which the
+    /// remote host's process list shows; it uses its own environment otherwise.
+    pub forward_env: Vec<String>,
+}
+
+impl Default for SshConfig {
+    fn default() -> Self {
+        Self {
+            host: String::new(),
+            port: None,
+            identity_file: None,
+            binary: "ssh".to_string(),
+            options: Vec::new(),
+            path_map: BTreeMap::new(),
+            forward_env: Vec::new(),
+        }
+    }
+}
+
+/// Variables the gemini CLI needs to run, authenticate and reach the network.
+const DEFAULT_ENV_ALLOWLIST: &[&str] = &[
+    // Process basics
+    "PATH", "HOME", "USER", "LOGNAME", "SHELL", "TERM", "TMPDIR", "LANG", "LC_*", "TZ",
+    "XDG_CONFIG_HOME", "XDG_CACHE_HOME", "XDG_DATA_HOME",
+    // Windows equivalents
+    "SYSTEMROOT", "SystemRoot", "COMSPEC", "PATHEXT", "USERPROFILE", "APPDATA", "LOCALAPPDATA", "TEMP", "TMP",
+    // Gemini and Google Cloud authentication
+    "GEMINI_API_KEY", "GOOGLE_API_KEY", "GOOGLE_CLOUD_PROJECT", "GOOGLE_CLOUD_LOCATION",
+    "GOOGLE_APPLICATION_CREDENTIALS", "GOOGLE_GENAI_USE_VERTEXAI", "GOOGLE_GENAI_USE_GCA",
+    // CLI behavior
+    "GEMINI_SANDBOX", "SANDBOX_FLAGS", "SEATBELT_PROFILE", "NO_COLOR", "DEBUG",
+    // Networking
+    "HTTP_PROXY", "HTTPS_PROXY", "NO_PROXY", "http_proxy", "https_proxy", "no_proxy",
+    "NODE_EXTRA_CA_CERTS", "SSL_CERT_FILE", "SSL_CERT_DIR",
+];
+
+/// Server configuration.
+///
+/// Values are layered, later sources winning: built-in defaults, the TOML
+/// config file, environment variables, then command-line flags.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Model used when a request does not name one (`GEMINI_MODEL`, `--model`); may be an alias.
+    pub default_model: Option<String>,
+    /// Short names for models, e.g. `fast = "gemini-2.5-flash"`, accepted wherever a model is.
+    pub model_aliases: BTreeMap<String, String>,
+    /// Models to try in order when a model is out of quota or overloaded,
+    /// e.g. `"gemini-2.5-pro" = ["gemini-2.5-flash"]`.
+    pub model_fallbacks: BTreeMap<String, Vec<String>>,
+    /// Models requests may and may not use.
+    pub model_policy: ModelPolicy,
+    /// System prompt used when a request does not supply one.
+    pub system_instruction: Option<String>,
+    /// `cli` or `api` (`GEMINI_BACKEND`, `--backend`).
+    pub backend: BackendKind,
+    /// Seconds a generation may run before it is aborted (`GEMINI_TIMEOUT_SECS`, `--timeout`).
+    pub timeout_secs: u64,
+    /// Seconds a gemini process may go without writing to stdout or stderr before it
+    /// is considered hung and stopped; 0 turns the watchdog off (`GEMINI_STALL_TIMEOUT_SECS`).
+    /// Requests for JSON output, which is only written at the end, and warm pool
+    /// processes are not watched.
+    pub stall_timeout_secs: u64,
+    /// Answer a timed-out call with the output the gemini CLI wrote before it was
+    /// killed, marked as truncated, instead of failing it (`GEMINI_PARTIAL_ON_TIMEOUT`).
+    pub partial_on_timeout: bool,
+    /// While a generation runs without other progress, tell the client every this many
+    /// seconds that it still does; 0 turns it off (`GEMINI_KEEPALIVE_SECS`).
+    pub keepalive_secs: u64,
+    /// Tokens of a model's context window kept free for the answer when checking a
+    /// prompt's size; a request's `max_tokens` takes its place (`GEMINI_OUTPUT_TOKEN_RESERVE`).
+    pub output_token_reserve: u32,
+    /// Whether prompts too large for the model are rejected (`reject`), have their
+    /// middle cut out (`truncate`) or are answered in parts (`chunk`) (`GEMINI_PROMPT_OVERFLOW`).
+    pub prompt_overflow: PromptOverflow,
+    /// Tokens each part repeats from the end of the one before when `prompt_overflow`
+    /// is `chunk` (`GEMINI_CHUNK_OVERLAP_TOKENS`).
+    pub chunk_overlap_tokens: u32,
+    /// Whether `gemini_chat` summarizes (`summarize`), drops (`evict`) or keeps (`keep`)
+    /// a session's oldest turns once its history nears the context window (`GEMINI_CHAT_HISTORY`).
+    pub chat_history: ChatHistory,
+    /// Tokens of replayed chat history that trigger trimming; 0 for 80% of the
+    /// model's context window (`GEMINI_CHAT_HISTORY_TOKENS`).
+    pub chat_history_tokens: u32,
+    /// How many generations may run at once; the rest wait in a FIFO queue
+    /// (`GEMINI_MAX_CONCURRENCY`, `--max-concurrency`).
+    pub max_concurrency: usize,
+    /// Retries after a transient (quota, 5xx, connection reset) failure (`GEMINI_MAX_RETRIES`).
+    pub max_retries: u32,
+    /// First retry delay in milliseconds; doubled on every further retry.
+    pub retry_base_delay_ms: u64,
+    /// Upper bound for a single retry delay in milliseconds.
+    pub retry_max_delay_ms: u64,
+    /// Failures in a row of the binary, credentials or quota after which requests fail
+    /// at once for `circuit_breaker_cooldown_secs`; 0 turns this off (`GEMINI_CIRCUIT_BREAKER_FAILURES`).
+    pub circuit_breaker_failures: u32,
+    /// How long requests fail at once before the backend is tried again (`GEMINI_CIRCUIT_BREAKER_COOLDOWN_SECS`).
+    pub circuit_breaker_cooldown_secs: u64,
+    /// Requests and tokens per minute allowed per model; the `"*"` entry applies to
+    /// models without one of their own.
+    pub rate_limits: BTreeMap<String, RateLimit>,
+    /// Whether requests over the limit wait (`queue`) or fail (`reject`).
+    pub rate_limit_policy: RateLimitPolicy,
+    /// `rate_limit_policy` overrides keyed by the MCP client's name, e.g. `claude-code`.
+    pub client_rate_limit_policies: BTreeMap<String, RateLimitPolicy>,
+    /// Timeout, retry and concurrency overrides keyed by tool name, e.g. `gemini_review_diff`.
+    pub tools: BTreeMap<String, ToolOverrides>,
+    /// Steps applied in order to the answers of a tool, keyed by tool name; the `"*"`
+    /// entry applies to tools without one of their own.
+    pub postprocess: BTreeMap<String, Vec<PostProcessor>>,
+    /// Path or name of the gemini executable (`GEMINI_BINARY`, `--gemini-binary`).
+    pub gemini_binary: String,
+    /// Extra regular expressions whose matches are redacted from logs and error messages.
+    pub redact_patterns: Vec<String>,
+    /// Replace email addresses, phone numbers and AWS keys in prompts with placeholders
+    /// before they are sent to Gemini (`GEMINI_PII_FILTER`).
+    pub pii_filter: bool,
+    /// Further regular expressions `pii_filter` scrubs, keyed by the kind reported
+    /// for their matches, e.g. `employee_id = "EMP-[0-9]{6}"`.
+    pub pii_patterns: BTreeMap<String, String>,
+    /// Environment variables passed to the gemini process; everything else is withheld.
+    /// A trailing `*` matches any suffix, e.g. `LC_*`.
+    pub env_allowlist: Vec<String>,
+    /// Run the gemini CLI in its container sandbox (`--sandbox`) unless a request says otherwise.
+    pub sandbox: bool,
+    /// Long-lived gemini processes kept ready for prompts; 0 spawns one per request
+    /// (`GEMINI_WARM_POOL_SIZE`).
+    pub warm_pool_size: usize,
+    /// Seconds an idle warm process is kept before it is stopped.
+    pub warm_pool_idle_secs: u64,
+    /// Arguments placed before every gemini invocation's own, e.g. `["-y", "@google/gemini-cli"]`
+    /// when `gemini_binary` is `npx` (`GEMINI_ARGS`, whitespace-separated).
+    pub gemini_args: Vec<String>,
+    /// Run the gemini CLI inside Docker; `gemini_binary` is then looked up in the container.
+    pub docker: Option<DockerConfig>,
+    /// Run the gemini CLI on another host over SSH; `gemini_binary` is then looked up there.
+    pub ssh: Option<SshConfig>,
+    /// Use Vertex AI instead of the Gemini API (`GOOGLE_GENAI_USE_VERTEXAI`).
+    pub vertex: Option<VertexConfig>,
+    /// Named identities, e.g. a personal API key and a work Vertex AI project.
+    pub profiles: BTreeMap<String, Profile>,
+    /// Profile used when a call does not choose one (`GEMINI_PROFILE`).
+    pub default_profile: Option<String>,
+    /// tracing filter directive (`RUST_LOG`, `--log-level`).
+    pub log_level: String,
+    /// Also write JSON logs to this file, rotated by size and date (`GEMINI_LOG_FILE`).
+    pub log_file: Option<PathBuf>,
+    /// Size in bytes at which the log file is rotated.
+    pub log_max_bytes: u64,
+    /// How many rotated log files to keep.
+    pub log_max_files: usize,
+    /// `stdio` or `http` (`--transport`).
+    pub transport: TransportKind,
+    /// Listen address for the http transport (`--bind`).
+    pub bind: String,
+    /// Gemini REST API endpoint (`GEMINI_API_BASE_URL`).
+    pub api_base_url: String,
+    /// Where usage totals are persisted (default: `<data dir>/usage.json`).
+    pub usage_file: Option<PathBuf>,
+    /// SQLite database chat sessions are kept in (`GEMINI_SESSIONS_DB`, default: `<data dir>/sessions.db`).
+    pub sessions_db: Option<PathBuf>,
+    /// Seconds a chat session may go unused before it is deleted; 0 keeps sessions
+    /// forever (`GEMINI_SESSION_TTL_SECS`).
+    pub session_ttl_secs: u64,
+    /// Most bytes of messages all chat sessions may hold together; the least
+    /// recently used are deleted beyond it, 0 for no limit (`GEMINI_MAX_SESSION_BYTES`).
+    pub max_session_bytes: u64,
+    /// Longest response text, in bytes, returned in one tool result; the rest is
+    /// fetched with `gemini_fetch_continuation`; 0 disables paging (`GEMINI_MAX_RESPONSE_BYTES`).
+    pub max_response_bytes: usize,
+    /// Largest file, in bytes, that may be attached through `files` (`GEMINI_MAX_FILE_BYTES`).
+    pub max_file_bytes: u64,
+    /// Largest image, in bytes, that may be sent through `images` (`GEMINI_MAX_IMAGE_BYTES`).
+    pub max_image_bytes: u64,
+    /// Most files the glob patterns in one request's `files` may match.
+    pub max_glob_files: usize,
+    /// Most bytes the files matched by one request's globs may add up to.
+    pub max_glob_bytes: u64,
+    /// Largest page, in bytes, `gemini_summarize_url` downloads (`GEMINI_MAX_FETCH_BYTES`).
+    pub max_fetch_bytes: u64,
+    /// Most of a log file, in bytes, `gemini_summarize_logs` reads, from its end (`GEMINI_MAX_LOG_BYTES`).
+    pub max_log_bytes: u64,
+    /// Let `gemini_summarize_url` fetch from loopback and private network addresses.
+    pub fetch_private_hosts: bool,
+    /// Imagen model used by `gemini_generate_image` (`GEMINI_IMAGE_MODEL`).
+    pub image_model: String,
+    /// Where generated images are written (`GEMINI_IMAGE_DIR`, default: `<data dir>/images`).
+    pub image_output_dir: Option<PathBuf>,
+    /// Directory of prompt templates exposed as MCP prompts (default: `<config dir>/prompts`).
+    pub prompts_dir: Option<PathBuf>,
+    /// Markdown file whose contents replace the built-in instructions clients show
+    /// their model, e.g. to name the models a team may use (`GEMINI_INSTRUCTIONS_FILE`).
+    pub instructions_file: Option<PathBuf>,
+    /// `en` or `ja`, for the built-in instructions and tool descriptions (`GEMINI_LOCALE`).
+    pub locale: Locale,
+    /// Let workflows ask the client's own model for help through MCP sampling,
+    /// when the client supports it (`GEMINI_CLIENT_SAMPLING`).
+    pub client_sampling: bool,
+    /// Append a JSONL record of every tool call to this file (`GEMINI_AUDIT_LOG`).
+    pub audit_log: Option<PathBuf>,
+    /// Let clients install, enable and disable gemini CLI extensions
+    /// (`GEMINI_EXTENSION_MANAGEMENT`).
+    pub extension_management: bool,
+    /// Re-expose the MCP servers in gemini's settings as `gemini_ext_*` tools
+    /// (`GEMINI_BRIDGE`, `--bridge`).
+    pub bridge: bool,
+    /// Form of the messages `gemini_commit_message` writes: `conventional`, `gitmoji`
+    /// or `plain` (`GEMINI_COMMIT_CONVENTION`).
+    pub commit_convention: Convention,
+    /// Longest subject line `gemini_commit_message` accepts (`GEMINI_COMMIT_SUBJECT_MAX_CHARS`).
+    pub commit_subject_max_chars: usize,
+    /// The project's own commit message rules, added to the convention's, e.g.
+    /// "reference the issue as #123 in the body".
+    pub commit_guidelines: Option<String>,
+    /// Layout of the release notes `gemini_changelog` drafts: `keep-a-changelog`,
+    /// `github` or `conventional` (`GEMINI_CHANGELOG_FORMAT`).
+    pub changelog_format: ChangelogFormat,
+    /// Describe the gemini command of every generation instead of running it
+    /// (`GEMINI_DRY_RUN`, `--dry-run`).
+    pub dry_run: bool,
+    /// `record` saves every answer under `recordings_dir`, keyed by a hash of the
+    /// request; `replay` serves them instead of asking Gemini (`GEMINI_RECORDING`).
+    pub recording: Recording,
+    /// Where recorded answers are kept (`GEMINI_RECORDINGS_DIR`, default: `<data dir>/recordings`).
+    pub recordings_dir: Option<PathBuf>,
+
+    /// File this configuration was read from, if any.
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            default_model: None,
+            model_aliases: BTreeMap::new(),
+            model_fallbacks: BTreeMap::new(),
+            model_policy: ModelPolicy::default(),
+            system_instruction: None,
+            backend: BackendKind::Cli,
+            timeout_secs: 300,
+            stall_timeout_secs: 300,
+            partial_on_timeout: false,
+            keepalive_secs: 15,
+            output_token_reserve: 8192,
+            prompt_overflow: PromptOverflow::Reject,
+            chunk_overlap_tokens: 500,
+            chat_history: ChatHistory::Summarize,
+            chat_history_tokens: 0,
+            max_concurrency: 4,
+            max_retries: 2,
+            retry_base_delay_ms: 1_000,
+            retry_max_delay_ms: 30_000,
+            circuit_breaker_failures: 5,
+            circuit_breaker_cooldown_secs: 60,
+            rate_limits: BTreeMap::new(),
+            rate_limit_policy: RateLimitPolicy::Queue,
+            client_rate_limit_policies: BTreeMap::new(),
+            tools: BTreeMap::new(),
+            postprocess: BTreeMap::new(),
+            gemini_binary: "gemini".to_string(),
+            gemini_args: Vec::new(),
+            redact_patterns: Vec::new(),
+            pii_filter: false,
+            pii_patterns: BTreeMap::new(),
+            env_allowlist: DEFAULT_ENV_ALLOWLIST.iter().map(|name| name.to_string()).collect(),
+            sandbox: false,
+            warm_pool_size: 0,
+            warm_pool_idle_secs: 600,
+            log_level: "info".to_string(),
+            log_file: None,
+            log_max_bytes: 10_485_760,
+            log_max_files: 5,
+            transport: TransportKind::Stdio,
+            bind: "127.0.0.1:8080".to_string(),
+            api_base_url: "https://generativelanguage.googleapis.com/v1beta".to_string(),
+            usage_file: None,
+            sessions_db: None,
+            session_ttl_secs: 2_592_000,
+            max_session_bytes: 104_857_600,
+            max_response_bytes: 100_000,
+            max_file_bytes: 1_048_576,
+            max_image_bytes: 7_340_032,
+            max_glob_files: 200,
+            max_glob_bytes: 4_194_304,
+            max_fetch_bytes: 5_242_880,
+            max_log_bytes: 67_108_864,
+            fetch_private_hosts: false,
+            image_model: "imagen-4.0-generate-001".to_string(),
+            image_output_dir: None,
+            prompts_dir: None,
+            instructions_file: None,
+            locale: Locale::En,
+            client_sampling: true,
+            extension_management: false,
+            bridge: false,
+            commit_convention: Convention::Conventional,
+            commit_subject_max_chars: 72,
+            commit_guidelines: None,
+            changelog_format: ChangelogFormat::KeepAChangelog,
+            audit_log: None,
+            dry_run: false,
+            recording: Recording::Off,
+            recordings_dir: None,
+            docker: None,
+            ssh: None,
+            vertex: None,
+            profiles: BTreeMap::new(),
+            default_profile: None,
+            source: None,
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration from all sources, with `flags` overriding the rest.
+    pub fn load(flags: Flags) -> Result<Self> {
+        let explicit_path = flags.config.clone()
+            .or_else(|| std::env::var_os("GEMINI_MCP_CONFIG").map(PathBuf::from));
+
+        let mut config = match &explicit_path {
+            Some(path) => Self::from_file(path)?,
+            None => match default_path() {
+                Some(path) if path.exists() => Self::from_file(&path)?,
+                _ => Self::default(),
+            },
+        };
+
+        config.apply_env()?;
+        config.apply_flags(flags)?;
+        if let Some(docker) = &config.docker {
+            docker.validate()?;
+        }
+        if let Some(profile) = &config.default_profile {
+            config.profile(profile)?;
+        }
+        if let Some(vertex) = &config.vertex {
+            vertex.validate()?;
+        }
+        crate::pii::PiiFilter::from_config(&config)?;
+        crate::postprocess::PostProcessing::from_config(&config)?;
+        crate::recording::Recorder::from_config(&config)?;
+        if config.model_policy.is_restricted() {
+            let model = config.resolve_model(None).unwrap_or_else(|| crate::backend::DEFAULT_MODEL.to_string());
+            config.model_policy.check(&model).context("Set default_model to a model model_policy allows")?;
+        }
+        if let Some(ssh) = &config.ssh {
+            if ssh.host.is_empty() {
+                anyhow::bail!("ssh needs a host");
+            }
+            if config.docker.is_some() {
+                anyhow::bail!("docker and ssh are mutually exclusive");
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let mut config: Self = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))?;
+        config.source = Some(path.to_path_buf());
+        Ok(config)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+
+    /// How often silent generations send a keepalive progress notification, if at all.
+    pub fn keepalive(&self) -> Option<Duration> {
+        (self.keepalive_secs > 0).then(|| Duration::from_secs(self.keepalive_secs))
+    }
+
+    /// The overrides configured for `tool`, empty when it has none.
+    pub fn tool(&self, tool: &str) -> ToolOverrides {
+        self.tools.get(tool).copied().unwrap_or_default()
+    }
+
+    /// Where calls of `tool` wait in the queue, honoring its override.
+    pub fn priority(&self, tool: &str) -> Priority {
+        self.tool(tool).priority.unwrap_or_else(|| Priority::default_for(tool))
+    }
+
+    /// Tools switched off with `enabled = false` under `[tools]`.
+    pub fn disabled_tools(&self) -> BTreeSet<String> {
+        self.tools.iter()
+            .filter(|(_, overrides)| overrides.enabled == Some(false))
+            .map(|(tool, _)| tool.clone())
+            .collect()
+    }
+
+    /// How long a call of `tool` may run, honoring its override.
+    pub fn tool_timeout(&self, tool: &str) -> Duration {
+        self.tool(tool).timeout_secs.map(Duration::from_secs).unwrap_or_else(|| self.timeout())
+    }
+
+    /// `model`, or the default model when there is none, with an alias replaced by its model.
+    pub fn resolve_model(&self, model: Option<String>) -> Option<String> {
+        model.or_else(|| self.default_model.clone()).map(|model| self.resolve_alias(model))
+    }
+
+    /// The profile named `name`.
+    pub fn profile(&self, name: &str) -> Result<&Profile> {
+        self.profiles.get(name).with_context(|| {
+            let known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            format!("Unknown profile '{}', expected one of {:?}", name, known)
+        })
+    }
+
+    pub fn resolve_alias(&self, model: String) -> String {
+        self.model_aliases.get(&model).cloned().unwrap_or(model)
+    }
+
+    /// The fallback models for `model`, with aliases resolved on both sides.
+    pub fn fallbacks(&self, model: &str) -> Vec<String> {
+        self.model_fallbacks
+            .iter()
+            .find(|(key, _)| self.resolve_alias(key.to_string()) == model)
+            .map(|(_, fallbacks)| fallbacks.iter().map(|fallback| self.resolve_alias(fallback.clone())).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn usage_path(&self) -> Option<PathBuf> {
+        self.usage_file.clone().or_else(|| data_dir().map(|dir| dir.join("usage.json")))
+    }
+
+    pub fn sessions_path(&self) -> Option<PathBuf> {
+        self.sessions_db.clone().or_else(|| data_dir().map(|dir| dir.join("sessions.db")))
+    }
+
+    pub fn image_dir(&self) -> Option<PathBuf> {
+        self.image_output_dir.clone().or_else(|| data_dir().map(|dir| dir.join("images")))
+    }
+
+    pub fn recordings_path(&self) -> Option<PathBuf> {
+        self.recordings_dir.clone().or_else(|| data_dir().map(|dir| dir.join("recordings")))
+    }
+
+    pub fn prompts_path(&self) -> Option<PathBuf> {
+        self.prompts_dir.clone().or_else(|| config_dir().map(|dir| dir.join("prompts")))
+    }
+
+    fn apply_env(&mut self) -> Result<()> {
+        if let Ok(model) = std::env::var("GEMINI_MODEL") {
+            self.default_model = Some(model);
+        }
+        if let Ok(backend) = std::env::var("GEMINI_BACKEND") {
+            self.backend = backend.parse().context("Invalid GEMINI_BACKEND")?;
+        }
+        if let Ok(models) = std::env::var("GEMINI_ALLOWED_MODELS") {
+            self.model_policy.allow = split_list(&models);
+        }
+        if let Ok(models) = std::env::var("GEMINI_DENIED_MODELS") {
+            self.model_policy.deny = split_list(&models);
+        }
+        if let Ok(timeout) = std::env::var("GEMINI_TIMEOUT_SECS") {
+            self.timeout_secs = timeout.parse().context("Invalid GEMINI_TIMEOUT_SECS")?;
+        }
+        if let Ok(stall) = std::env::var("GEMINI_STALL_TIMEOUT_SECS") {
+            self.stall_timeout_secs = stall.parse().context("Invalid GEMINI_STALL_TIMEOUT_SECS")?;
+        }
+        if let Ok(enabled) = std::env::var("GEMINI_PARTIAL_ON_TIMEOUT") {
+            self.partial_on_timeout = enabled.parse().context("Invalid GEMINI_PARTIAL_ON_TIMEOUT")?;
+        }
+        if let Ok(locale) = std::env::var("GEMINI_LOCALE") {
+            self.locale = locale.parse().context("Invalid GEMINI_LOCALE")?;
+        }
+        if let Ok(path) = std::env::var("GEMINI_INSTRUCTIONS_FILE") {
+            self.instructions_file = Some(path.into());
+        }
+        if let Ok(keepalive) = std::env::var("GEMINI_KEEPALIVE_SECS") {
+            self.keepalive_secs = keepalive.parse().context("Invalid GEMINI_KEEPALIVE_SECS")?;
+        }
+        if let Ok(reserve) = std::env::var("GEMINI_OUTPUT_TOKEN_RESERVE") {
+            self.output_token_reserve = reserve.parse().context("Invalid GEMINI_OUTPUT_TOKEN_RESERVE")?;
+        }
+        if let Ok(overflow) = std::env::var("GEMINI_PROMPT_OVERFLOW") {
+            self.prompt_overflow = overflow.parse().context("Invalid GEMINI_PROMPT_OVERFLOW")?;
+        }
+        if let Ok(overlap) = std::env::var("GEMINI_CHUNK_OVERLAP_TOKENS") {
+            self.chunk_overlap_tokens = overlap.parse().context("Invalid GEMINI_CHUNK_OVERLAP_TOKENS")?;
+        }
+        if let Ok(history) = std::env::var("GEMINI_CHAT_HISTORY") {
+            self.chat_history = history.parse().context("Invalid GEMINI_CHAT_HISTORY")?;
+        }
+        if let Ok(tokens) = std::env::var("GEMINI_CHAT_HISTORY_TOKENS") {
+            self.chat_history_tokens = tokens.parse().context("Invalid GEMINI_CHAT_HISTORY_TOKENS")?;
+        }
+        if let Ok(max) = std::env::var("GEMINI_MAX_CONCURRENCY") {
+            self.max_concurrency = max.parse().context("Invalid GEMINI_MAX_CONCURRENCY")?;
+        }
+        if let Ok(retries) = std::env::var("GEMINI_MAX_RETRIES") {
+            self.max_retries = retries.parse().context("Invalid GEMINI_MAX_RETRIES")?;
+        }
+        if let Ok(failures) = std::env::var("GEMINI_CIRCUIT_BREAKER_FAILURES") {
+            self.circuit_breaker_failures = failures.parse().context("Invalid GEMINI_CIRCUIT_BREAKER_FAILURES")?;
+        }
+        if let Ok(cooldown) = std::env::var("GEMINI_CIRCUIT_BREAKER_COOLDOWN_SECS") {
+            self.circuit_breaker_cooldown_secs = cooldown.parse().context("Invalid GEMINI_CIRCUIT_BREAKER_COOLDOWN_SECS")?;
+        }
+        if let Ok(binary) = std::env::var("GEMINI_BINARY") {
+            self.gemini_binary = binary;
+        }
+        if let Ok(size) = std::env::var("GEMINI_WARM_POOL_SIZE") {
+            self.warm_pool_size = size.parse().context("Invalid GEMINI_WARM_POOL_SIZE")?;
+        }
+        if let Ok(args) = std::env::var("GEMINI_ARGS") {
+            self.gemini_args = args.split_whitespace().map(str::to_string).collect();
+        }
+        if let Ok(container) = std::env::var("GEMINI_DOCKER_CONTAINER") {
+            self.docker.get_or_insert_with(DockerConfig::default).container = Some(container);
+        }
+        if let Ok(image) = std::env::var("GEMINI_DOCKER_IMAGE") {
+            self.docker.get_or_insert_with(DockerConfig::default).image = Some(image);
+        }
+        if std::env::var("GOOGLE_GENAI_USE_VERTEXAI").is_ok_and(|value| value == "true" || value == "1") {
+            self.vertex.get_or_insert_with(VertexConfig::default);
+        }
+        // Only fill in what the config file leaves open, as the CLI reads the same variables
+        if let Some(vertex) = &mut self.vertex {
+            if vertex.project.is_none() {
+                vertex.project = std::env::var("GOOGLE_CLOUD_PROJECT").ok().filter(|project| !project.is_empty());
+            }
+            if vertex.location.is_none() {
+                vertex.location = std::env::var("GOOGLE_CLOUD_LOCATION").ok().filter(|location| !location.is_empty());
+            }
+        }
+        if let Ok(filter) = std::env::var("GEMINI_PII_FILTER") {
+            self.pii_filter = filter.parse().context("Invalid GEMINI_PII_FILTER")?;
+        }
+        if let Ok(profile) = std::env::var("GEMINI_PROFILE") {
+            self.default_profile = Some(profile);
+        }
+        if let Ok(host) = std::env::var("GEMINI_SSH_HOST") {
+            self.ssh.get_or_insert_with(SshConfig::default).host = host;
+        }
+        if let Ok(level) = std::env::var("RUST_LOG") {
+            self.log_level = level;
+        }
+        if let Ok(path) = std::env::var("GEMINI_LOG_FILE") {
+            self.log_file = Some(path.into());
+        }
+        if let Ok(url) = std::env::var("GEMINI_API_BASE_URL") {
+            self.api_base_url = url;
+        }
+        if let Ok(path) = std::env::var("GEMINI_SESSIONS_DB") {
+            self.sessions_db = Some(path.into());
+        }
+        if let Ok(ttl) = std::env::var("GEMINI_SESSION_TTL_SECS") {
+            self.session_ttl_secs = ttl.parse().context("Invalid GEMINI_SESSION_TTL_SECS")?;
+        }
+        if let Ok(max) = std::env::var("GEMINI_MAX_SESSION_BYTES") {
+            self.max_session_bytes = max.parse().context("Invalid GEMINI_MAX_SESSION_BYTES")?;
+        }
+        if let Ok(max) = std::env::var("GEMINI_MAX_RESPONSE_BYTES") {
+            self.max_response_bytes = max.parse().context("Invalid GEMINI_MAX_RESPONSE_BYTES")?;
+        }
+        if let Ok(max) = std::env::var("GEMINI_MAX_FILE_BYTES") {
+            self.max_file_bytes = max.parse().context("Invalid GEMINI_MAX_FILE_BYTES")?;
+        }
+        if let Ok(max) = std::env::var("GEMINI_MAX_IMAGE_BYTES") {
+            self.max_image_bytes = max.parse().context("Invalid GEMINI_MAX_IMAGE_BYTES")?;
+        }
+        if let Ok(max) = std::env::var("GEMINI_MAX_FETCH_BYTES") {
+            self.max_fetch_bytes = max.parse().context("Invalid GEMINI_MAX_FETCH_BYTES")?;
+        }
+        if let Ok(max) = std::env::var("GEMINI_MAX_LOG_BYTES") {
+            self.max_log_bytes = max.parse().context("Invalid GEMINI_MAX_LOG_BYTES")?;
+        }
+        if let Ok(model) = std::env::var("GEMINI_IMAGE_MODEL") {
+            self.image_model = model;
+        }
+        if let Ok(enabled) = std::env::var("GEMINI_CLIENT_SAMPLING") {
+            self.client_sampling = enabled.parse().context("Invalid GEMINI_CLIENT_SAMPLING")?;
+        }
+        if let Ok(enabled) = std::env::var("GEMINI_BRIDGE") {
+            self.bridge = enabled.parse().context("Invalid GEMINI_BRIDGE")?;
+        }
+        if let Ok(enabled) = std::env::var("GEMINI_EXTENSION_MANAGEMENT") {
+            self.extension_management = enabled.parse().context("Invalid GEMINI_EXTENSION_MANAGEMENT")?;
+        }
+        if let Ok(convention) = std::env::var("GEMINI_COMMIT_CONVENTION") {
+            self.commit_convention = convention.parse().context("Invalid GEMINI_COMMIT_CONVENTION")?;
+        }
+        if let Ok(max) = std::env::var("GEMINI_COMMIT_SUBJECT_MAX_CHARS") {
+            self.commit_subject_max_chars = max.parse().context("Invalid GEMINI_COMMIT_SUBJECT_MAX_CHARS")?;
+        }
+        if let Ok(format) = std::env::var("GEMINI_CHANGELOG_FORMAT") {
+            self.changelog_format = format.parse().context("Invalid GEMINI_CHANGELOG_FORMAT")?;
+        }
+        if let Ok(path) = std::env::var("GEMINI_AUDIT_LOG") {
+            self.audit_log = Some(path.into());
+        }
+        if let Ok(enabled) = std::env::var("GEMINI_DRY_RUN") {
+            self.dry_run = enabled.parse().context("Invalid GEMINI_DRY_RUN")?;
+        }
+        if let Ok(dir) = std::env::var("GEMINI_IMAGE_DIR") {
+            self.image_output_dir = Some(dir.into());
+        }
+        if let Ok(recording) = std::env::var("GEMINI_RECORDING") {
+            self.recording = recording.parse().context("Invalid GEMINI_RECORDING")?;
+        }
+        if let Ok(dir) = std::env::var("GEMINI_RECORDINGS_DIR") {
+            self.recordings_dir = Some(dir.into());
+        }
+        Ok(())
+    }
+
+    fn apply_flags(&mut self, flags: Flags) -> Result<()> {
+        if let Some(model) = flags.model {
+            self.default_model = Some(model);
+        }
+        if let Some(backend) = flags.backend {
+            self.backend = backend.parse()?;
+        }
+        if let Some(timeout) = flags.timeout {
+            self.timeout_secs = timeout.parse().context("Invalid --timeout")?;
+        }
+        if let Some(max) = flags.max_concurrency {
+            self.max_concurrency = max.parse().context("Invalid --max-concurrency")?;
+        }
+        if let Some(binary) = flags.gemini_binary {
+            self.gemini_binary = binary;
+        }
+        if let Some(level) = flags.log_level {
+            self.log_level = level;
+        }
+        if let Some(transport) = flags.transport {
+            self.transport = transport.parse()?;
+        }
+        if let Some(bind) = flags.bind {
+            self.bind = bind;
+        }
+        if flags.dry_run {
+            self.dry_run = true;
+        }
+        if flags.bridge {
+            self.bridge = true;
+        }
+        Ok(())
+    }
+}
+
+/// `$XDG_CONFIG_HOME/gemini-mcp/config.toml`, falling back to `~/.config/gemini-mcp/config.toml`.
+pub fn default_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config.toml"))
+}
+
+/// `$XDG_CONFIG_HOME/gemini-mcp`, falling back to `~/.config/gemini-mcp`.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(base.join("gemini-mcp"))
+}
+
+/// `$XDG_DATA_HOME/gemini-mcp`, falling back to `~/.local/share/gemini-mcp`.
+pub fn data_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("share")))?;
+    Some(base.join("gemini-mcp"))
+}
+
+/// Command-line overrides, shared by every subcommand that loads the config.
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct Flags {
+    /// Config file to read instead of the default one (GEMINI_MCP_CONFIG)
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Default model or alias
+    #[arg(long)]
+    model: Option<String>,
+    /// `cli` or `api`
+    #[arg(long)]
+    backend: Option<String>,
+    /// Seconds a generation may run
+    #[arg(long, value_name = "SECS")]
+    timeout: Option<String>,
+    /// How many generations may run at once
+    #[arg(long, value_name = "N")]
+    max_concurrency: Option<String>,
+    /// gemini binary or wrapper to run
+    #[arg(long, value_name = "PATH")]
+    gemini_binary: Option<String>,
+    /// tracing filter, e.g. `debug` or `gemini_cli_mcp=trace`
+    #[arg(long, value_name = "FILTER")]
+    log_level: Option<String>,
+    /// `stdio` or `http`
+    #[arg(long)]
+    transport: Option<String>,
+    /// Address the http transport listens on
+    #[arg(long, value_name = "ADDR")]
+    bind: Option<String>,
+    /// Report the gemini command or API request each call would make instead of running it
+    #[arg(long)]
+    dry_run: bool,
+    /// Expose the tools of gemini's own MCP servers as gemini_ext_* tools
+    #[arg(long)]
+    bridge: bool,
+}
+
+/// Replace `$VAR` and `${VAR}` in `value` with the server's environment, as the
+/// gemini CLI does in its settings.
+pub fn expand_env(value: &str) -> String {
+    static VAR: OnceLock<Regex> = OnceLock::new();
+    let var = VAR.get_or_init(|| Regex::new(r"\$\{(\w+)\}|\$(\w+)").expect("variable pattern is valid"));
+    var.replace_all(value, |captures: &regex::Captures| {
+        let name = captures.get(1).or_else(|| captures.get(2)).map_or("", |name| name.as_str());
+        std::env::var(name).unwrap_or_default()
+    }).into_owned()
+}
+
+/// The non-empty entries of a comma-separated environment variable.
+fn split_list(value: &str) -> Vec<String> {
+    value.split(',').map(str::trim).filter(|entry| !entry.is_empty()).map(str::to_string).collect()
+}
@@ -0,0 +1,171 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long the rest of a paged response stays available.
+const TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Where to pick up a response that did not fit in one tool result.
+#[derive(Debug, Clone, Serialize)]
+pub struct Continuation {
+    pub token: String,
+    pub remaining_bytes: usize,
+}
+
+struct Entry {
+    text: String,
+    offset: usize,
+    expires: Instant,
+}
+
+/// The unsent remainder of long responses, fetched page by page with `gemini_fetch_continuation`.
+#[derive(Default)]
+pub struct ContinuationStore {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl ContinuationStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Split `text` into a first page of at most `page_bytes` and, when more is
+    /// left, store the rest under a new token.
+    pub fn paginate(&self, text: String, page_bytes: usize) -> (String, Option<Continuation>) {
+        if page_bytes == 0 || text.len() <= page_bytes {
+            return (text, None);
+        }
+
+        let end = page_end(&text, 0, page_bytes);
+        let page = text[..end].to_string();
+        let token = uuid::Uuid::new_v4().to_string();
+        let continuation = Continuation { token: token.clone(), remaining_bytes: text.len() - end };
+
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        entries.retain(|_, entry| entry.expires > now);
+        entries.insert(token, Entry { text, offset: end, expires: now + TTL });
+
+        (page, Some(continuation))
+    }
+
+    /// The next page for `token`, plus a continuation while more is left.
+    ///
+    /// `None` means the token is unknown, already exhausted or expired.
+    pub fn next(&self, token: &str, page_bytes: usize) -> Option<(String, Option<Continuation>)> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(token).filter(|entry| entry.expires > Instant::now())?;
+
+        let start = entry.offset;
+        let end = if page_bytes == 0 {
+            entry.text.len()
+        } else {
+            page_end(&entry.text, start, page_bytes)
+        };
+        let page = entry.text[start..end].to_string();
+
+        if end >= entry.text.len() {
+            entries.remove(token);
+            return Some((page, None));
+        }
+        entry.offset = end;
+        let continuation = Continuation { token: token.to_string(), remaining_bytes: entry.text.len() - end };
+        Some((page, Some(continuation)))
+    }
+}
+
+/// End of a page starting at `start`: at most `page_bytes` on, preferably
+/// after a line break, and never inside a character.
+fn page_end(text: &str, start: usize, page_bytes: usize) -> usize {
+    let mut end = (start + page_bytes).min(text.len());
+    if end == text.len() {
+        return end;
+    }
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    // Break at a line only when that keeps at least half the page
+    match text[start..end].rfind('\n') {
+        Some(newline) if newline >= page_bytes / 2 => start + newline + 1,
+        _ if end > start => end,
+        // A page too small for even one character still has to make progress
+        _ => start + text[start..].chars().next().map_or(0, char::len_utf8),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Concatenate every page of `text` fetched `page_bytes` at a time.
+    fn all_pages(store: &ContinuationStore, text: &str, page_bytes: usize) -> Vec<String> {
+        let (page, mut continuation) = store.paginate(text.to_string(), page_bytes);
+        let mut pages = vec![page];
+        while let Some(Continuation { token, remaining_bytes }) = continuation {
+            let (page, next) = store.next(&token, page_bytes).expect("token is live");
+            assert_eq!(page.len() + next.as_ref().map_or(0, |next| next.remaining_bytes), remaining_bytes);
+            pages.push(page);
+            continuation = next;
+        }
+        pages
+    }
+
+    #[test]
+    fn short_text_fits_in_one_page() {
+        let store = ContinuationStore::new();
+        let (page, continuation) = store.paginate("hello".to_string(), 100);
+        assert_eq!(page, "hello");
+        assert!(continuation.is_none());
+    }
+
+    #[test]
+    fn pages_break_after_lines_and_add_up_to_the_text() {
+        let text: String = (0..20).map(|line| format!("line {}\n", line)).collect();
+        let pages = all_pages(&ContinuationStore::new(), &text, 30);
+
+        assert!(pages.len() > 1);
+        assert_eq!(pages.concat(), text);
+        for page in &pages {
+            assert!(page.len() <= 30, "{:?}", page);
+            assert!(page.ends_with('\n'), "{:?}", page);
+        }
+    }
+
+    #[test]
+    fn pages_never_split_a_character() {
+        let text = "日本語のテキスト".repeat(10);
+        let pages = all_pages(&ContinuationStore::new(), &text, 10);
+
+        assert_eq!(pages.concat(), text);
+        assert!(pages.iter().all(|page| !page.is_empty() && page.len() <= 10));
+    }
+
+    #[test]
+    fn page_smaller_than_a_character_still_makes_progress() {
+        let pages = all_pages(&ContinuationStore::new(), "日本", 1);
+        assert_eq!(pages, ["日", "本"]);
+    }
+
+    #[test]
+    fn exhausted_and_unknown_tokens_are_gone() {
+        let store = ContinuationStore::new();
+        let (_, continuation) = store.paginate("a".repeat(20), 10);
+        let token = continuation.unwrap().token;
+
+        let (page, next) = store.next(&token, 10).unwrap();
+        assert_eq!(page, "a".repeat(10));
+        assert!(next.is_none());
+        assert!(store.next(&token, 10).is_none());
+        assert!(store.next("unknown", 10).is_none());
+    }
+
+    #[test]
+    fn zero_page_size_returns_the_rest_at_once() {
+        let store = ContinuationStore::new();
+        let (_, continuation) = store.paginate("a".repeat(20), 5);
+        let (page, next) = store.next(&continuation.unwrap().token, 0).unwrap();
+        assert_eq!(page, "a".repeat(15));
+        assert!(next.is_none());
+    }
+}
@@ -0,0 +1,32 @@
+/// Most critique and revision rounds `gemini_cross_check` runs.
+pub const MAX_ROUNDS: u32 = 3;
+
+/// What the client's model replies with when an answer needs no changes.
+const APPROVAL: &str = "LGTM";
+
+pub const REVIEWER_INSTRUCTION: &str = r#"You review another model's answer to a question.
+Point out factual errors, bugs, missing cases and unclear explanations, as a short list of concrete problems.
+Do not rewrite the answer and do not comment on style alone.
+If the answer is correct and complete, reply with exactly LGTM."#;
+
+/// Prompt asking the client's model to critique Gemini's `answer`.
+pub fn critique_prompt(question: &str, answer: &str) -> String {
+    format!("Question:\n{}\n\nAnswer to review:\n{}", question.trim(), answer.trim())
+}
+
+/// Prompt asking Gemini to improve its `answer` given the client's `critique`.
+pub fn revision_prompt(question: &str, answer: &str, critique: &str) -> String {
+    format!(
+        "{}\n\nYour previous answer was:\n{}\n\nA reviewer raised these points:\n{}\n\n\
+         Write an improved answer. Address the points that are right and keep what was already correct. \
+         Reply with the answer only.",
+        question.trim(),
+        answer.trim(),
+        critique.trim()
+    )
+}
+
+/// Whether the critique found nothing to change.
+pub fn approves(critique: &str) -> bool {
+    critique.trim().trim_end_matches('.').eq_ignore_ascii_case(APPROVAL)
+}
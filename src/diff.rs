@@ -0,0 +1,331 @@
+use similar::TextDiff;
+
+/// Lines of unchanged context around each hunk, as `diff -u` and git use.
+const CONTEXT_LINES: usize = 3;
+
+/// Unified diff turning `old` into `new` for the file at `path`, empty when they
+/// are the same. A new file is diffed against `/dev/null`, as git does.
+pub fn unified(path: &str, old: Option<&str>, new: &str) -> String {
+    let original = if old.is_some() { format!("a/{}", path) } else { "/dev/null".to_string() };
+    TextDiff::from_lines(old.unwrap_or_default(), new)
+        .unified_diff()
+        .context_radius(CONTEXT_LINES)
+        .header(&original, &format!("b/{}", path))
+        .to_string()
+}
+
+/// One file's changes in a unified diff.
+#[derive(Debug)]
+pub struct FilePatch {
+    /// The file before the change, `None` when the patch creates it.
+    pub old_path: Option<String>,
+    /// The file after the change, `None` when the patch deletes it.
+    pub new_path: Option<String>,
+    hunks: Vec<Hunk>,
+}
+
+#[derive(Debug)]
+struct Hunk {
+    /// 1-based first line of the hunk in the old file, 0 for an empty file.
+    old_start: usize,
+    lines: Vec<HunkLine>,
+    /// The hunk's last old or new line is the end of a file without a final newline.
+    old_missing_newline: bool,
+    new_missing_newline: bool,
+}
+
+#[derive(Debug)]
+enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+impl FilePatch {
+    /// The path the patch is about, preferring the new name of a renamed file.
+    pub fn path(&self) -> &str {
+        self.new_path.as_deref().or(self.old_path.as_deref()).unwrap_or_default()
+    }
+
+    /// Lines added and removed.
+    pub fn stats(&self) -> (usize, usize) {
+        let lines = self.hunks.iter().flat_map(|hunk| &hunk.lines);
+        lines.fold((0, 0), |(added, removed), line| match line {
+            HunkLine::Add(_) => (added + 1, removed),
+            HunkLine::Remove(_) => (added, removed + 1),
+            HunkLine::Context(_) => (added, removed),
+        })
+    }
+
+    /// The file after the patch, given its contents before (`None` when it doesn't
+    /// exist), or `None` when the patch deletes it. Hunks may have moved from the
+    /// lines their headers name, and lines match ignoring trailing whitespace, but
+    /// every context and removed line has to be there. The file keeps its line
+    /// endings, `\r\n` or `\n`.
+    pub fn apply(&self, original: Option<&str>) -> Result<Option<String>, String> {
+        match (original, &self.old_path) {
+            (Some(_), None) => return Err(format!("{} already exists", self.path())),
+            (None, Some(path)) => return Err(format!("{} doesn't exist", path)),
+            _ => {}
+        }
+        let original = original.unwrap_or_default();
+        let lines: Vec<&str> = original.lines().collect();
+        let line_ending = match original.find('\n') {
+            Some(end) if original[..end].ends_with('\r') => "\r\n",
+            _ => "\n",
+        };
+        let original_missing_newline = !original.is_empty() && !original.ends_with('\n');
+
+        let mut patched: Vec<&str> = Vec::with_capacity(lines.len());
+        let mut cursor = 0;
+        let mut offset: isize = 0;
+        let mut missing_newline = original_missing_newline;
+        for (index, hunk) in self.hunks.iter().enumerate() {
+            let old: Vec<&str> = hunk.lines.iter()
+                .filter_map(|line| match line {
+                    HunkLine::Context(text) | HunkLine::Remove(text) => Some(text.as_str()),
+                    HunkLine::Add(_) => None,
+                })
+                .collect();
+            let stated = if old.is_empty() { hunk.old_start } else { hunk.old_start.saturating_sub(1) };
+            let position = find(&lines, &old, cursor, stated.saturating_add_signed(offset)).ok_or_else(|| {
+                format!("Hunk {} (@@ -{}) doesn't match {}", index + 1, hunk.old_start, self.path())
+            })?;
+
+            patched.extend(&lines[cursor..position]);
+            let mut old_lines = lines[position..position + old.len()].iter();
+            for line in &hunk.lines {
+                match line {
+                    // Context keeps the file's own lines, whitespace and all
+                    HunkLine::Context(_) => patched.extend(old_lines.next()),
+                    HunkLine::Remove(_) => {
+                        old_lines.next();
+                    }
+                    HunkLine::Add(text) => patched.push(text),
+                }
+            }
+            cursor = position + old.len();
+            offset = position as isize - stated as isize;
+            if cursor == lines.len() {
+                // Only a hunk whose old lines reach the end can say how the file ends
+                if !old.is_empty() && hunk.old_missing_newline != original_missing_newline {
+                    return Err(format!(
+                        "Hunk {} (@@ -{}) says {} {} a final newline, but it {}",
+                        index + 1,
+                        hunk.old_start,
+                        self.path(),
+                        if hunk.old_missing_newline { "lacks" } else { "has" },
+                        if original_missing_newline { "doesn't" } else { "does" },
+                    ));
+                }
+                missing_newline = hunk.new_missing_newline;
+            }
+        }
+        patched.extend(&lines[cursor..]);
+
+        if self.new_path.is_none() {
+            if patched.iter().any(|line| !line.trim().is_empty()) {
+                return Err(format!("The diff deletes {} but doesn't remove all of it", self.path()));
+            }
+            return Ok(None);
+        }
+        let mut text = patched.join(line_ending);
+        if !patched.is_empty() && !missing_newline {
+            text.push_str(line_ending);
+        }
+        Ok(Some(text))
+    }
+}
+
+/// Where `old` appears in `lines` at or after `cursor`, nearest to `expected`.
+fn find(lines: &[&str], old: &[&str], cursor: usize, expected: usize) -> Option<usize> {
+    let last = lines.len().checked_sub(old.len())?;
+    (cursor..=last)
+        .filter(|position| {
+            lines[*position..*position + old.len()].iter().zip(old).all(|(line, old)| line.trim_end() == old.trim_end())
+        })
+        .min_by_key(|position| position.abs_diff(expected))
+}
+
+/// Parse a unified diff of one or more files, as `diff -u` or `git diff` write them.
+///
+/// Hunk line counts are not trusted, since models often get them wrong: a hunk
+/// runs until the next hunk or file header.
+pub fn parse(diff: &str) -> Result<Vec<FilePatch>, String> {
+    let lines: Vec<&str> = diff.lines().collect();
+    let mut patches: Vec<FilePatch> = Vec::new();
+    let mut index = 0;
+    while index < lines.len() {
+        let line = lines[index];
+        if let (Some(old), Some(new)) = (line.strip_prefix("--- "), lines.get(index + 1).and_then(|next| next.strip_prefix("+++ "))) {
+            patches.push(FilePatch {
+                old_path: header_path(old, "a/"),
+                new_path: header_path(new, "b/"),
+                hunks: Vec::new(),
+            });
+            index += 2;
+            continue;
+        }
+        if let Some(header) = line.strip_prefix("@@ ") {
+            let patch = patches.last_mut().ok_or("The diff has a hunk before any file header")?;
+            let old_start = header.split_whitespace().next()
+                .and_then(|range| range.strip_prefix('-'))
+                .and_then(|range| range.split(',').next())
+                .and_then(|start| start.parse().ok())
+                .ok_or_else(|| format!("Invalid hunk header '{}'", line))?;
+            let mut hunk = Hunk { old_start, lines: Vec::new(), old_missing_newline: false, new_missing_newline: false };
+            index += 1;
+            while let Some(line) = lines.get(index) {
+                if line.starts_with("@@ ") || line.starts_with("diff --git ") || (line.starts_with("--- ") && lines.get(index + 1).is_some_and(|next| next.starts_with("+++ "))) {
+                    break;
+                }
+                if line.starts_with('\\') {
+                    // "\ No newline at end of file" refers to the line before it
+                    match hunk.lines.last() {
+                        Some(HunkLine::Remove(_)) => hunk.old_missing_newline = true,
+                        Some(HunkLine::Add(_)) => hunk.new_missing_newline = true,
+                        _ => {
+                            hunk.old_missing_newline = true;
+                            hunk.new_missing_newline = true;
+                        }
+                    }
+                } else if let Some(text) = line.strip_prefix('+') {
+                    hunk.lines.push(HunkLine::Add(text.to_string()));
+                } else if let Some(text) = line.strip_prefix('-') {
+                    hunk.lines.push(HunkLine::Remove(text.to_string()));
+                } else {
+                    // Blank context lines often lose their leading space
+                    hunk.lines.push(HunkLine::Context(line.strip_prefix(' ').unwrap_or(line).to_string()));
+                }
+                index += 1;
+            }
+            // Trailing blank lines are the end of the answer rather than context
+            while matches!(hunk.lines.last(), Some(HunkLine::Context(text)) if text.is_empty()) {
+                hunk.lines.pop();
+            }
+            patch.hunks.push(hunk);
+            continue;
+        }
+        // Anything else, such as `diff --git` and `index` lines, carries nothing needed
+        index += 1;
+    }
+
+    if patches.is_empty() {
+        return Err("The answer contains no unified diff".to_string());
+    }
+    if let Some(empty) = patches.iter().find(|patch| patch.hunks.is_empty()) {
+        return Err(format!("The diff of {} has no hunks", empty.path()));
+    }
+    Ok(patches)
+}
+
+/// The path in a `---` or `+++` header, without its `a/` or `b/` prefix and any
+/// timestamp; `None` for `/dev/null`.
+fn header_path(header: &str, prefix: &str) -> Option<String> {
+    let path = header.split('\t').next().unwrap_or_default().trim();
+    if path == "/dev/null" {
+        return None;
+    }
+    Some(path.strip_prefix(prefix).unwrap_or(path).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unchanged_file_has_an_empty_diff() {
+        assert_eq!(unified("notes.txt", Some("same\n"), "same\n"), "");
+    }
+
+    #[test]
+    fn changed_lines_are_diffed_with_git_headers() {
+        let diff = unified("src/lib.rs", Some("one\ntwo\nthree\n"), "one\n2\nthree\n");
+        assert!(diff.starts_with("--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ "), "{}", diff);
+        assert!(diff.contains("\n-two\n+2\n"), "{}", diff);
+    }
+
+    #[test]
+    fn new_file_is_diffed_against_dev_null() {
+        let diff = unified("new.txt", None, "hello\n");
+        assert!(diff.starts_with("--- /dev/null\n+++ b/new.txt\n"), "{}", diff);
+        assert!(diff.contains("\n+hello\n"), "{}", diff);
+    }
+
+    /// The single file patch in `diff`.
+    fn patch(diff: &str) -> FilePatch {
+        let mut patches = parse(diff).expect("diff parses");
+        assert_eq!(patches.len(), 1);
+        patches.remove(0)
+    }
+
+    const NOTES: &str = "one\ntwo\nthree\nfour\nfive\n";
+
+    #[test]
+    fn parsed_patch_reports_its_path_and_stats() {
+        let patch = patch("diff --git a/notes.txt b/notes.txt\n--- a/notes.txt\n+++ b/notes.txt\n@@ -1,2 +1,3 @@\n one\n-two\n+2\n+2b\n");
+        assert_eq!(patch.path(), "notes.txt");
+        assert_eq!(patch.stats(), (2, 1));
+    }
+
+    #[test]
+    fn answer_without_a_diff_or_hunks_is_an_error() {
+        assert!(parse("Here is the change you asked for.").is_err());
+        assert!(parse("--- a/notes.txt\n+++ b/notes.txt\n").is_err());
+    }
+
+    #[test]
+    fn unified_diff_applies_back_to_the_new_text() {
+        let new = "one\n2\nthree\nfour\nfive\nsix\n";
+        let patch = patch(&unified("notes.txt", Some(NOTES), new));
+        assert_eq!(patch.apply(Some(NOTES)).unwrap().as_deref(), Some(new));
+    }
+
+    #[test]
+    fn hunk_applies_where_its_lines_are_despite_a_wrong_header() {
+        let patch = patch("--- a/notes.txt\n+++ b/notes.txt\n@@ -1,2 +1,2 @@\n three\n-four\n+4\n");
+        assert_eq!(patch.apply(Some(NOTES)).unwrap().as_deref(), Some("one\ntwo\nthree\n4\nfive\n"));
+    }
+
+    #[test]
+    fn hunk_that_does_not_match_is_rejected() {
+        let patch = patch("--- a/notes.txt\n+++ b/notes.txt\n@@ -2,1 +2,1 @@\n-deux\n+2\n");
+        assert!(patch.apply(Some(NOTES)).unwrap_err().contains("doesn't match"));
+    }
+
+    #[test]
+    fn new_and_deleted_files_need_the_right_original() {
+        let create = patch("--- /dev/null\n+++ b/new.txt\n@@ -0,0 +1,1 @@\n+hello\n");
+        assert_eq!(create.apply(None).unwrap().as_deref(), Some("hello\n"));
+        assert!(create.apply(Some("hello\n")).is_err());
+
+        let delete = patch("--- a/notes.txt\n+++ /dev/null\n@@ -1,2 +0,0 @@\n-one\n-two\n");
+        assert_eq!(delete.apply(Some("one\ntwo\n")).unwrap(), None);
+        assert!(delete.apply(Some("one\ntwo\nthree\n")).is_err());
+        assert!(delete.apply(None).is_err());
+    }
+
+    #[test]
+    fn missing_final_newline_is_kept_or_added_as_the_diff_says() {
+        let keep = patch("--- a/notes.txt\n+++ b/notes.txt\n@@ -1,2 +1,2 @@\n one\n-two\n\\ No newline at end of file\n+2\n\\ No newline at end of file\n");
+        assert_eq!(keep.apply(Some("one\ntwo")).unwrap().as_deref(), Some("one\n2"));
+
+        let add = patch("--- a/notes.txt\n+++ b/notes.txt\n@@ -1,2 +1,2 @@\n one\n-two\n\\ No newline at end of file\n+2\n");
+        assert_eq!(add.apply(Some("one\ntwo")).unwrap().as_deref(), Some("one\n2\n"));
+    }
+
+    #[test]
+    fn final_newline_marker_must_agree_with_the_file() {
+        let lacks = patch("--- a/notes.txt\n+++ b/notes.txt\n@@ -1,2 +1,2 @@\n one\n-two\n\\ No newline at end of file\n+2\n");
+        assert!(lacks.apply(Some("one\ntwo\n")).unwrap_err().contains("final newline"));
+
+        let has = patch("--- a/notes.txt\n+++ b/notes.txt\n@@ -1,2 +1,2 @@\n one\n-two\n+2\n");
+        assert!(has.apply(Some("one\ntwo")).unwrap_err().contains("final newline"));
+    }
+
+    #[test]
+    fn crlf_line_endings_are_kept() {
+        let patch = patch("--- a/notes.txt\n+++ b/notes.txt\n@@ -1,3 +1,3 @@\n one\n-two\n+2\n three\n");
+        assert_eq!(patch.apply(Some("one\r\ntwo\r\nthree\r\n")).unwrap().as_deref(), Some("one\r\n2\r\nthree\r\n"));
+    }
+}
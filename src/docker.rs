@@ -0,0 +1,73 @@
+use std::path::Path;
+
+use crate::config::DockerConfig;
+
+/// Variables describing the host rather than the container, which are never
+/// forwarded into it.
+const HOST_ONLY_ENV: &[&str] = &[
+    "PATH", "HOME", "USER", "LOGNAME", "SHELL", "TMPDIR",
+    "XDG_CONFIG_HOME", "XDG_CACHE_HOME", "XDG_DATA_HOME",
+    "SYSTEMROOT", "SystemRoot", "COMSPEC", "PATHEXT", "USERPROFILE", "APPDATA", "LOCALAPPDATA", "TEMP", "TMP",
+];
+
+/// Runs the gemini CLI in a container, with `docker exec` or `docker run`.
+#[derive(Debug)]
+pub struct Docker {
+    config: DockerConfig,
+    /// The gemini executable inside the container.
+    gemini: String,
+}
+
+impl Docker {
+    pub fn new(config: DockerConfig, gemini: String) -> Self {
+        Self { config, gemini }
+    }
+
+    /// The docker arguments that run `gemini <args>` in the container, working
+    /// in `cwd` (the server's own directory when `None`) and forwarding the
+    /// variables named in `env`.
+    ///
+    /// `docker run` mounts the working directory and the temporary directory,
+    /// where images and system prompts are written for the CLI, at their host
+    /// paths, so paths in the arguments mean the same inside. A container used
+    /// with `docker exec` has to mount them the same way.
+    pub fn args<'a>(&self, args: &[String], env: impl IntoIterator<Item = &'a str>, cwd: Option<&Path>) -> Vec<String> {
+        let cwd = cwd.map(Path::to_path_buf).or_else(|| std::env::current_dir().ok());
+
+        let mut docker_args: Vec<String> = match &self.config.container {
+            Some(_) => vec!["exec".into(), "-i".into()],
+            // --init forwards signals to the CLI and reaps what it spawns
+            None => vec!["run".into(), "--rm".into(), "-i".into(), "--init".into()],
+        };
+        for name in env.into_iter().filter(|name| !HOST_ONLY_ENV.contains(name)) {
+            // Without a value docker takes the variable from its own environment
+            docker_args.push("-e".into());
+            docker_args.push(name.to_string());
+        }
+        if self.config.image.is_some() {
+            let temp = std::env::temp_dir();
+            docker_args.push("-v".into());
+            docker_args.push(format!("{}:ro", same_path_mount(&temp)));
+            if let Some(cwd) = &cwd {
+                docker_args.push("-v".into());
+                docker_args.push(same_path_mount(cwd));
+            }
+            docker_args.extend(self.config.run_args.iter().cloned());
+        }
+        if let Some(cwd) = &cwd {
+            docker_args.push("-w".into());
+            docker_args.push(cwd.display().to_string());
+        }
+
+        let target = self.config.container.as_ref().or(self.config.image.as_ref());
+        docker_args.extend(target.cloned());
+        docker_args.push(self.gemini.clone());
+        docker_args.extend_from_slice(args);
+        docker_args
+    }
+}
+
+/// A `-v` value mounting `path` at the same path in the container.
+fn same_path_mount(path: &Path) -> String {
+    format!("{}:{}", path.display(), path.display())
+}
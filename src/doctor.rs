@@ -0,0 +1,262 @@
+use serde::Serialize;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::backend::{Backend, CliVersion, ExecOptions, Feature, GenerateRequest};
+use crate::config::{BackendKind, Config};
+use crate::launcher::{self, Launcher};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Warning,
+    Error,
+}
+
+/// The outcome of one diagnostic, with a hint on how to fix it when it failed.
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl Check {
+    pub fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        Self { name, status: Status::Ok, detail: detail.into(), hint: None }
+    }
+
+    pub fn warning(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self { name, status: Status::Warning, detail: detail.into(), hint: Some(hint.into()) }
+    }
+
+    pub fn error(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self { name, status: Status::Error, detail: detail.into(), hint: Some(hint.into()) }
+    }
+}
+
+/// The prompt the test prompt check sends.
+pub const TEST_PROMPT: &str = "Reply with the single word: ok";
+
+/// Locate the gemini binary and ask it for its version.
+///
+/// Missing binaries are only a warning for the api backend, which does not need one.
+pub async fn check_binary(config: &Config) -> Vec<Check> {
+    let severity = match config.backend {
+        BackendKind::Cli => Status::Error,
+        BackendKind::Api => Status::Warning,
+    };
+
+    // In Docker or over SSH only the docker or ssh client has to be on this machine
+    let (binary, hint) = match (&config.docker, &config.ssh) {
+        (Some(docker), _) => (&docker.binary, "Install Docker, or point `docker.binary` at a compatible CLI such as podman"),
+        (None, Some(ssh)) => (&ssh.binary, "Install an OpenSSH client, or point `ssh.binary` at one"),
+        (None, None) => (&config.gemini_binary, "Install the CLI with `npm install -g @google/gemini-cli`, or point `gemini_binary` (GEMINI_BINARY) at it"),
+    };
+    let Some(path) = launcher::find_executable(binary) else {
+        return vec![Check {
+            name: "binary",
+            status: severity,
+            detail: format!("'{}' was not found on PATH", binary),
+            hint: Some(hint.to_string()),
+        }];
+    };
+    let mut checks = vec![Check::ok("binary", path.display().to_string())];
+
+    let mut args = config.gemini_args.clone();
+    args.push("--version".to_string());
+    let version = tokio::time::timeout(
+        Duration::from_secs(15),
+        Launcher::from_config(config).command(&args).kill_on_drop(true).output(),
+    ).await;
+    checks.push(match version {
+        Ok(Ok(output)) if output.status.success() => version_check(String::from_utf8_lossy(&output.stdout).trim()),
+        Ok(Ok(output)) => Check::warning(
+            "version",
+            format!("`{} --version` failed: {}", path.display(), String::from_utf8_lossy(&output.stderr).trim()),
+            "Reinstall the gemini CLI",
+        ),
+        Ok(Err(e)) => Check::error("version", format!("Failed to run {}: {}", path.display(), e), "Check that the binary is executable"),
+        Err(_) => Check::warning("version", "`gemini --version` did not finish within 15s", "Reinstall the gemini CLI"),
+    });
+
+    checks
+}
+
+/// The version check, warning about flags the installed release lacks.
+fn version_check(output: &str) -> Check {
+    let Some(version) = CliVersion::parse(output) else {
+        return Check::ok("version", output);
+    };
+    let missing: Vec<String> = Feature::ALL
+        .iter()
+        .filter(|feature| version < feature.min_version())
+        .map(|feature| format!("{} (>= {})", feature.flag(), feature.min_version()))
+        .collect();
+    if missing.is_empty() {
+        Check::ok("version", output)
+    } else {
+        Check::warning(
+            "version",
+            format!("{} lacks {}", version, missing.join(", ")),
+            "Upgrade with `npm install -g @google/gemini-cli@latest`; tools that need these flags fail until then",
+        )
+    }
+}
+
+/// Report which credentials Gemini can authenticate with.
+pub fn check_auth(config: &Config) -> Vec<Check> {
+    let api_key = ["GEMINI_API_KEY", "GOOGLE_API_KEY"]
+        .into_iter()
+        .find(|var| std::env::var(var).is_ok_and(|value| !value.is_empty()));
+    let adc = adc_path().filter(|path| path.exists());
+    let oauth = home().map(|home| home.join(".gemini").join("oauth_creds.json")).filter(|path| path.exists());
+
+    let mut checks = vec![match api_key {
+        Some(var) => Check::ok("api_key", format!("{} is set", var)),
+        None if config.vertex.is_some() => Check::ok("api_key", "Not needed with Vertex AI"),
+        None if config.backend == BackendKind::Api => Check::error(
+            "api_key",
+            "Neither GEMINI_API_KEY nor GOOGLE_API_KEY is set",
+            "The api backend needs an API key from https://aistudio.google.com/apikey",
+        ),
+        None => Check::warning(
+            "api_key",
+            "Neither GEMINI_API_KEY nor GOOGLE_API_KEY is set",
+            "Without an API key, requests using max_tokens, temperature, safety settings or search grounding fail on the cli backend",
+        ),
+    }];
+
+    if config.backend == BackendKind::Cli {
+        checks.push(match (&adc, &oauth) {
+            (_, Some(path)) => Check::ok("cli_auth", format!("Google login found at {}", path.display())),
+            (Some(path), None) => Check::ok("cli_auth", format!("Application default credentials found at {}", path.display())),
+            (None, None) if api_key.is_some() => Check::ok("cli_auth", "The CLI will use the API key"),
+            (None, None) => Check::error(
+                "cli_auth",
+                "No API key, application default credentials or Google login found",
+                "Run `gemini` once interactively to log in, run `gcloud auth application-default login`, or set GEMINI_API_KEY",
+            ),
+        });
+    }
+
+    if adc.is_some() && config.vertex.is_none() && std::env::var("GOOGLE_CLOUD_PROJECT").is_err() {
+        checks.push(Check::warning(
+            "project",
+            "GOOGLE_CLOUD_PROJECT is not set",
+            "Vertex AI and Code Assist logins need GOOGLE_CLOUD_PROJECT",
+        ));
+    }
+
+    checks
+}
+
+/// With `vertex` configured, check its project and the credentials Vertex AI needs.
+pub fn check_vertex(config: &Config) -> Vec<Check> {
+    let Some(vertex) = &config.vertex else {
+        return Vec::new();
+    };
+
+    let Some(project) = &vertex.project else {
+        return vec![Check::ok("vertex", "Express mode with GOOGLE_API_KEY")];
+    };
+    let mut checks = vec![Check::ok("vertex", format!("Project {} in {}", project, vertex.location()))];
+
+    checks.push(match adc_path().filter(|path| path.exists()) {
+        Some(path) => Check::ok("vertex_credentials", format!("Application default credentials found at {}", path.display())),
+        None => Check::error(
+            "vertex_credentials",
+            "No application default credentials found",
+            "Run `gcloud auth application-default login`, or point GOOGLE_APPLICATION_CREDENTIALS at a service account key",
+        ),
+    });
+    // The api backend gets its access tokens from gcloud
+    if config.backend == BackendKind::Api && launcher::find_executable("gcloud").is_none() {
+        checks.push(Check::error(
+            "gcloud",
+            "'gcloud' was not found on PATH",
+            "Install the Google Cloud CLI; the api backend uses it for Vertex AI access tokens",
+        ));
+    }
+
+    // An auth type chosen in the CLI's settings wins over GOOGLE_GENAI_USE_VERTEXAI
+    if config.backend == BackendKind::Cli {
+        if let Some(selected) = selected_auth_type().filter(|selected| selected != "vertex-ai") {
+            checks.push(Check::warning(
+                "cli_auth_type",
+                format!("~/.gemini/settings.json selects the {} auth type", selected),
+                "Choose Vertex AI with /auth in an interactive gemini session, or remove the setting",
+            ));
+        }
+    }
+
+    checks
+}
+
+/// The auth type the gemini CLI's user settings select, in either the old or the nested layout.
+fn selected_auth_type() -> Option<String> {
+    let settings = std::fs::read_to_string(home()?.join(".gemini").join("settings.json")).ok()?;
+    let settings: serde_json::Value = serde_json::from_str(&settings).ok()?;
+    settings
+        .pointer("/security/auth/selectedType")
+        .or_else(|| settings.get("selectedAuthType"))
+        .and_then(|selected| selected.as_str())
+        .map(str::to_string)
+}
+
+/// Send [`TEST_PROMPT`] straight to `backend`, outside any MCP call.
+pub async fn check_prompt(config: &Config, backend: &Backend) -> Check {
+    let request = GenerateRequest {
+        prompt: TEST_PROMPT.to_string(),
+        model: config.resolve_model(None),
+        ..Default::default()
+    };
+    let options = ExecOptions {
+        timeout: Duration::from_secs(60),
+        ct: tokio_util::sync::CancellationToken::new(),
+        progress: None,
+    };
+
+    let started = std::time::Instant::now();
+    match backend.generate(&request, options).await {
+        Ok(response) => Check::ok(
+            "test_prompt",
+            format!("{} backend answered {:?} in {}ms", backend.name(), response.text, started.elapsed().as_millis()),
+        ),
+        Err(e) => Check::error(
+            "test_prompt",
+            crate::redact::redact(&format!("{:#}", e)),
+            "Run the same prompt with the gemini CLI directly to see its full output",
+        ),
+    }
+}
+
+/// Whether every check passed without errors.
+pub fn healthy(checks: &[Check]) -> bool {
+    checks.iter().all(|check| check.status != Status::Error)
+}
+
+/// One line per check, for people rather than programs.
+pub fn summary(checks: &[Check]) -> String {
+    checks
+        .iter()
+        .map(|check| match &check.hint {
+            Some(hint) if check.status != Status::Ok => format!("[{:?}] {}: {} ({})", check.status, check.name, check.detail, hint),
+            _ => format!("[{:?}] {}: {}", check.status, check.name, check.detail),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn adc_path() -> Option<PathBuf> {
+    std::env::var_os("GOOGLE_APPLICATION_CREDENTIALS")
+        .map(PathBuf::from)
+        .or_else(|| home().map(|home| home.join(".config").join("gcloud").join("application_default_credentials.json")))
+}
+
+fn home() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(PathBuf::from)
+}
@@ -0,0 +1,243 @@
+use rmcp::model::ErrorCode;
+use rmcp::Error as McpError;
+use serde::Serialize;
+
+use crate::auth::AuthError;
+use crate::breaker::BackendUnhealthyError;
+use crate::backend::{CancelledError, CliOnlyParamsError, CliTooOldError, StalledError, TimeoutError, UnsupportedParamsError};
+use crate::fetch::FetchError;
+use crate::files::AttachmentError;
+use crate::format::InvalidOutputError;
+use crate::policy::ModelNotAllowedError;
+use crate::ratelimit::RateLimitedError;
+use crate::recording::NotRecordedError;
+use crate::roots::OutsideRootsError;
+use crate::sampling::SamplingUnavailableError;
+use crate::tokens::PromptTooLargeError;
+
+/// Failure markers, matched case-insensitively, that mean the account's quota is used up.
+const QUOTA_MARKERS: &[&str] = &["quota", "resource_exhausted", "429", "too many requests"];
+
+/// What went wrong, reported as `data.kind` of every error the server returns.
+///
+/// Argument problems keep the standard invalid-params code so clients show
+/// them as such; every other kind has its own code in the JSON-RPC server range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// A tool argument is missing, malformed or out of range.
+    InvalidArgument,
+    UnsupportedParameter,
+    InvalidFile,
+    OutsideRoots,
+    SamplingUnavailable,
+    /// The prompt does not fit the model's context window.
+    PromptTooLarge,
+    /// The gemini CLI failed for a reason not covered below.
+    BackendFailed,
+    /// The gemini binary could not be found or started.
+    BinaryMissing,
+    AuthFailed,
+    QuotaExceeded,
+    /// This server's own `rate_limits` rejected the request.
+    RateLimited,
+    Timeout,
+    /// The gemini process stopped producing output and was killed as hung.
+    Stalled,
+    Cancelled,
+    /// Gemini refused to answer; reported in tool error results rather than as an MCP error.
+    SafetyBlocked,
+    InvalidOutput,
+    FetchFailed,
+    /// A server proxied in bridge mode failed.
+    BridgeFailed,
+    /// The installed gemini CLI is too old for a flag the call needs.
+    CliTooOld,
+    /// The server's `model_policy` forbids the requested model.
+    PolicyDenied,
+    /// Requests kept failing, so the backend is not tried until a cooldown ends.
+    BackendUnhealthy,
+    /// Replay mode has no recorded answer for the request.
+    NotRecorded,
+    Internal,
+}
+
+impl ErrorKind {
+    pub fn code(self) -> ErrorCode {
+        let code = match self {
+            Self::InvalidArgument
+            | Self::UnsupportedParameter
+            | Self::InvalidFile
+            | Self::OutsideRoots
+            | Self::SamplingUnavailable
+            | Self::PromptTooLarge => ErrorCode::INVALID_PARAMS.0,
+            Self::BackendFailed => -32000,
+            Self::BinaryMissing => -32001,
+            Self::AuthFailed => -32002,
+            Self::QuotaExceeded => -32003,
+            Self::RateLimited => -32004,
+            Self::Timeout => -32005,
+            Self::Cancelled => -32006,
+            Self::SafetyBlocked => -32007,
+            Self::InvalidOutput => -32008,
+            Self::FetchFailed => -32009,
+            Self::BridgeFailed => -32010,
+            Self::CliTooOld => -32011,
+            Self::PolicyDenied => -32012,
+            Self::Stalled => -32013,
+            Self::BackendUnhealthy => -32014,
+            Self::NotRecorded => -32015,
+            Self::Internal => ErrorCode::INTERNAL_ERROR.0,
+        };
+        ErrorCode(code)
+    }
+
+    /// Whether the same call may succeed if simply tried again later.
+    pub fn retryable(self) -> bool {
+        matches!(self, Self::QuotaExceeded | Self::RateLimited | Self::Timeout | Self::Stalled | Self::BackendUnhealthy | Self::BackendFailed)
+    }
+}
+
+/// An MCP error of `kind`, whose `data` holds the kind, whether it is
+/// retryable and the fields of `details`, which must be a JSON object or null.
+pub fn error(kind: ErrorKind, message: impl Into<String>, details: serde_json::Value) -> McpError {
+    let mut data = serde_json::json!({
+        "kind": kind,
+        "retryable": kind.retryable(),
+    });
+    if let serde_json::Value::Object(details) = details {
+        data.as_object_mut().expect("data is an object").extend(details);
+    }
+    McpError::new(kind.code(), message.into(), Some(data))
+}
+
+/// A tool argument that is missing, malformed or out of range.
+pub fn invalid_argument(message: impl Into<String>) -> McpError {
+    error(ErrorKind::InvalidArgument, message, serde_json::Value::Null)
+}
+
+/// A failure of the server itself, e.g. writing a file.
+pub fn internal(message: impl Into<String>) -> McpError {
+    error(ErrorKind::Internal, message, serde_json::Value::Null)
+}
+
+/// Convert a backend failure into a typed MCP error.
+///
+/// Secrets are redacted from the message, which often quotes gemini's stderr.
+pub fn command_error(e: anyhow::Error) -> McpError {
+    let mut error = classify(e);
+    error.message = crate::redact::redact(&error.message).into_owned().into();
+    error
+}
+
+fn classify(e: anyhow::Error) -> McpError {
+    use serde_json::json;
+
+    if let Some(timeout) = e.downcast_ref::<TimeoutError>() {
+        return error(ErrorKind::Timeout, timeout.to_string(), json!({
+                "timeout_secs": timeout.timeout.as_secs(),
+                "partial_output": crate::redact::redact(&timeout.partial_output),
+            }));
+    }
+    if let Some(stalled) = e.downcast_ref::<StalledError>() {
+        return error(
+            ErrorKind::Stalled,
+            stalled.to_string(),
+            json!({
+                "idle_secs": stalled.idle.as_secs(),
+                "partial_output": crate::redact::redact(&stalled.partial_output),
+            }),
+        );
+    }
+    if e.downcast_ref::<CancelledError>().is_some() {
+        return error(ErrorKind::Cancelled, e.to_string(), json!(null));
+    }
+    if let Some(unsupported) = e.downcast_ref::<UnsupportedParamsError>() {
+        return error(ErrorKind::UnsupportedParameter, unsupported.to_string(), json!({ "parameters": unsupported.params }));
+    }
+    if let Some(cli_only) = e.downcast_ref::<CliOnlyParamsError>() {
+        return error(ErrorKind::UnsupportedParameter, cli_only.to_string(), json!({ "parameters": cli_only.params }));
+    }
+    if let Some(attachment) = e.downcast_ref::<AttachmentError>() {
+        return error(ErrorKind::InvalidFile, attachment.to_string(), json!({ "path": attachment.path }));
+    }
+    if let Some(outside) = e.downcast_ref::<OutsideRootsError>() {
+        return error(ErrorKind::OutsideRoots, outside.to_string(), json!({ "path": outside.path, "roots": outside.roots }));
+    }
+    if let Some(fetch) = e.downcast_ref::<FetchError>() {
+        return error(ErrorKind::FetchFailed, fetch.to_string(), json!({ "url": fetch.url }));
+    }
+    if let Some(limited) = e.downcast_ref::<RateLimitedError>() {
+        return error(ErrorKind::RateLimited, limited.to_string(), json!({ "retry_after_secs": limited.retry_after.as_secs_f64() }));
+    }
+    if e.downcast_ref::<SamplingUnavailableError>().is_some() {
+        return error(ErrorKind::SamplingUnavailable, e.to_string(), json!(null));
+    }
+    if let Some(invalid) = e.downcast_ref::<InvalidOutputError>() {
+        return error(ErrorKind::InvalidOutput, invalid.to_string(), json!({ "reason": invalid.reason }));
+    }
+    if let Some(too_old) = e.downcast_ref::<CliTooOldError>() {
+        let details = json!({
+            "feature": too_old.feature.flag(),
+            "required_version": too_old.feature.min_version(),
+            "found_version": too_old.found,
+        });
+        return error(ErrorKind::CliTooOld, too_old.to_string(), details);
+    }
+    if let Some(too_large) = e.downcast_ref::<PromptTooLargeError>() {
+        let details = json!({
+            "model": too_large.model,
+            "prompt_tokens": too_large.prompt_tokens,
+            "max_prompt_tokens": too_large.max_prompt_tokens,
+            "input_token_limit": too_large.input_token_limit,
+            "reserved_tokens": too_large.reserved_tokens,
+        });
+        return error(ErrorKind::PromptTooLarge, too_large.to_string(), details);
+    }
+    if let Some(denied) = e.downcast_ref::<ModelNotAllowedError>() {
+        return error(ErrorKind::PolicyDenied, denied.to_string(), json!({ "model": denied.model, "allowed_models": denied.allowed }));
+    }
+    if let Some(unhealthy) = e.downcast_ref::<BackendUnhealthyError>() {
+        let details = json!({
+            "since": unhealthy.since.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            "failures": unhealthy.failures,
+            "last_error": unhealthy.last_error,
+            "retry_after_secs": unhealthy.retry_after.as_secs().max(1),
+        });
+        return error(ErrorKind::BackendUnhealthy, unhealthy.to_string(), details);
+    }
+    if let Some(missing) = e.downcast_ref::<NotRecordedError>() {
+        return error(ErrorKind::NotRecorded, missing.to_string(), json!({ "key": missing.key, "dir": missing.dir }));
+    }
+    if let Some(auth) = e.downcast_ref::<AuthError>() {
+        return auth_failed(auth);
+    }
+    if let Some(missing) = e.downcast_ref::<crate::backend::BinaryMissingError>() {
+        return error(ErrorKind::BinaryMissing, missing.to_string(), json!({ "binary": missing.binary }));
+    }
+
+    // The CLI reports everything else on stderr, so go by what it said
+    let message = format!("{:#}", e);
+    if let Some(auth) = crate::auth::diagnose(&message) {
+        return auth_failed(&auth);
+    }
+    if is_quota(&message) {
+        return error(ErrorKind::QuotaExceeded, message, json!(null));
+    }
+    error(ErrorKind::BackendFailed, message, json!(null))
+}
+
+/// Whether a failure message says the Gemini account is out of quota.
+pub fn is_quota(message: &str) -> bool {
+    let lower = message.to_lowercase();
+    QUOTA_MARKERS.iter().any(|marker| lower.contains(marker))
+}
+
+fn auth_failed(auth: &AuthError) -> McpError {
+    let details = serde_json::json!({
+        "problem": auth.problem,
+        "remediation": auth.problem.remediation(),
+        "env_var": auth.problem.env_var(),
+    });
+    error(ErrorKind::AuthFailed, auth.to_string(), details)
+}
@@ -0,0 +1,407 @@
+use anyhow::Result;
+use futures::StreamExt;
+use regex::Regex;
+use reqwest::Url;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::config::Config;
+
+/// How long one page may take to download, redirects included.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Most redirects followed before giving up.
+const MAX_REDIRECTS: usize = 5;
+
+/// Elements whose contents are navigation, scripts or other page chrome rather than text.
+const BOILERPLATE_TAGS: &[&str] = &[
+    "script", "style", "noscript", "template", "svg", "iframe", "nav", "header", "footer", "aside", "form",
+];
+
+pub const SUMMARY_INSTRUCTION: &str = r#"You summarize web pages for someone who has not read them.
+Start with one sentence saying what the page is, then give the key points as a short list.
+Keep names, numbers, versions and dates exact, and do not add anything the page does not say.
+Ignore leftover navigation, cookie notices and advertising text."#;
+
+/// Returned when a URL cannot be fetched or its content cannot be summarized.
+#[derive(Debug)]
+pub struct FetchError {
+    pub url: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Cannot fetch {}: {}", self.url, self.reason)
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+/// A fetched page, reduced to its readable text.
+pub struct Page {
+    /// Where the page was found, after redirects.
+    pub url: String,
+    pub title: Option<String>,
+    pub content_type: String,
+    /// Size of the downloaded body.
+    pub bytes: usize,
+    pub text: String,
+}
+
+/// Downloads pages for `gemini_summarize_url`.
+pub struct Fetcher {
+    client: reqwest::Client,
+    max_bytes: u64,
+    private_hosts: bool,
+}
+
+impl Fetcher {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            client: client_builder().build().unwrap_or_default(),
+            max_bytes: config.max_fetch_bytes,
+            private_hosts: config.fetch_private_hosts,
+        }
+    }
+
+    /// Download `url` and extract its text, rejecting non-text content, bodies
+    /// over `max_fetch_bytes` and, unless `fetch_private_hosts` is set, hosts on
+    /// loopback or private networks.
+    pub async fn fetch(&self, url: &str) -> Result<Page> {
+        let error = |reason: String| FetchError {
+            url: url.to_string(),
+            reason,
+        };
+        let mut current = Url::parse(url).map_err(|e| error(e.to_string()))?;
+
+        let response = tokio::time::timeout(FETCH_TIMEOUT, async {
+            for _ in 0..=MAX_REDIRECTS {
+                let addresses = self.check_host(&current).await.map_err(error)?;
+                // Connect to the addresses just checked, so a second lookup
+                // can't swap in a private one
+                let client = match current.domain() {
+                    Some(domain) if !addresses.is_empty() => client_builder()
+                        .resolve_to_addrs(domain, &addresses)
+                        .build()
+                        .map_err(|e| error(e.to_string()))?,
+                    _ => self.client.clone(),
+                };
+                let response = client.get(current.clone()).send().await
+                    .map_err(|e| error(e.to_string()))?;
+                if !response.status().is_redirection() {
+                    return Ok(response);
+                }
+                let location = response.headers().get(reqwest::header::LOCATION)
+                    .and_then(|location| location.to_str().ok())
+                    .ok_or_else(|| error(format!("{} without a Location header", response.status())))?;
+                current = current.join(location).map_err(|e| error(e.to_string()))?;
+            }
+            Err(error(format!("more than {} redirects", MAX_REDIRECTS)))
+        }).await.map_err(|_| error(format!("no response within {}s", FETCH_TIMEOUT.as_secs())))??;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(error(format!("server answered {}", status)).into());
+        }
+        let content_type = response.headers().get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(|value| value.trim().to_ascii_lowercase())
+            .unwrap_or_else(|| "text/html".to_string());
+        let html = matches!(content_type.as_str(), "text/html" | "application/xhtml+xml");
+        let text_like = content_type.starts_with("text/")
+            || matches!(content_type.as_str(), "application/json" | "application/xml" | "application/rss+xml" | "application/atom+xml");
+        if !html && !text_like {
+            return Err(error(format!("{} is not a text format", content_type)).into());
+        }
+        if response.content_length().is_some_and(|length| length > self.max_bytes) {
+            return Err(error(format!(
+                "page is {} bytes, more than the max_fetch_bytes limit of {}",
+                response.content_length().unwrap_or_default(), self.max_bytes
+            )).into());
+        }
+
+        let final_url = response.url().to_string();
+        let mut body = Vec::new();
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = tokio::time::timeout(FETCH_TIMEOUT, stream.next()).await
+            .map_err(|_| error(format!("download stalled for {}s", FETCH_TIMEOUT.as_secs())))?
+        {
+            let chunk = chunk.map_err(|e| error(e.to_string()))?;
+            body.extend_from_slice(&chunk);
+            // Servers may omit or misstate Content-Length
+            if body.len() as u64 > self.max_bytes {
+                return Err(error(format!("page is more than the max_fetch_bytes limit of {} bytes", self.max_bytes)).into());
+            }
+        }
+
+        let raw = String::from_utf8_lossy(&body);
+        let (title, text) = if html {
+            (title(&raw), readable_text(&raw))
+        } else {
+            (None, raw.trim().to_string())
+        };
+        if text.is_empty() {
+            return Err(error("page has no readable text".to_string()).into());
+        }
+
+        Ok(Page {
+            url: final_url,
+            title,
+            content_type,
+            bytes: body.len(),
+            text,
+        })
+    }
+
+    /// Fail unless `url` is http(s) and, without `fetch_private_hosts`, every
+    /// address its host resolves to is public; returns those addresses, or
+    /// none when `fetch_private_hosts` lets any host through.
+    async fn check_host(&self, url: &Url) -> Result<Vec<SocketAddr>, String> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("only http and https URLs can be fetched, not {}", url.scheme()));
+        }
+        if self.private_hosts {
+            return Ok(Vec::new());
+        }
+        let host = url.host_str().ok_or("URL has no host")?;
+        let port = url.port_or_known_default().unwrap_or(80);
+        let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host.trim_matches(|c| c == '[' || c == ']'), port)).await
+            .map_err(|e| format!("cannot resolve {}: {}", host, e))?
+            .collect();
+        for address in &addresses {
+            if !is_public(address.ip()) {
+                return Err(format!(
+                    "{} resolves to the non-public address {}; set fetch_private_hosts to allow it",
+                    host, address.ip()
+                ));
+            }
+        }
+        Ok(addresses)
+    }
+}
+
+fn client_builder() -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        .user_agent(concat!("gemini-cli-mcp/", env!("CARGO_PKG_VERSION")))
+        // Redirects are followed by hand so every hop gets the host check
+        .redirect(reqwest::redirect::Policy::none())
+}
+
+/// Whether `ip` is reachable on the public internet, as opposed to loopback,
+/// private, link-local (cloud metadata), multicast and similar ranges.
+///
+/// IPv6 addresses that embed an IPv4 one are judged by that address, since
+/// that is what they reach.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified()
+                || ip.is_broadcast() || ip.is_documentation() || ip.is_multicast()
+                // "This network" 0.0.0.0/8, which Linux routes to the local host
+                || first == 0
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (first == 100 && second & 0xc0 == 64)
+                // Benchmarking, 198.18.0.0/15
+                || (first == 198 && second & 0xfe == 18)
+                // Reserved, 240.0.0.0/4
+                || first >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if ip.is_loopback() || ip.is_unspecified() {
+                return false;
+            }
+            // IPv4-mapped ::ffff:a.b.c.d and IPv4-compatible ::a.b.c.d
+            if let Some(ip) = ip.to_ipv4() {
+                return is_public(IpAddr::V4(ip));
+            }
+            let segments = ip.segments();
+            let embedded = |high: u16, low: u16| IpAddr::V4(Ipv4Addr::from((u32::from(high) << 16) | u32::from(low)));
+            // NAT64, 64:ff9b::/96
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                return is_public(embedded(segments[6], segments[7]));
+            }
+            // 6to4, 2002::/16
+            if segments[0] == 0x2002 {
+                return is_public(embedded(segments[1], segments[2]));
+            }
+            let first = segments[0];
+            !(ip.is_multicast()
+                // Unique local fc00::/7 and link-local fe80::/10
+                || first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80
+                // Documentation, 2001:db8::/32
+                || (first == 0x2001 && segments[1] == 0xdb8))
+        }
+    }
+}
+
+/// Prompt asking Gemini to summarize `page`, with an optional `focus`.
+pub fn summary_prompt(page: &Page, focus: Option<&str>) -> String {
+    let mut prompt = String::from("Summarize this web page.");
+    if let Some(focus) = focus.map(str::trim).filter(|focus| !focus.is_empty()) {
+        prompt.push_str(&format!(" Concentrate on: {}", focus));
+    }
+    prompt.push_str(&format!("\n\n<page url=\"{}\"", page.url));
+    if let Some(title) = &page.title {
+        prompt.push_str(&format!(" title=\"{}\"", title.replace('"', "'")));
+    }
+    prompt.push_str(&format!(">\n{}\n</page>", page.text));
+    prompt
+}
+
+fn title(html: &str) -> Option<String> {
+    static TITLE: OnceLock<Regex> = OnceLock::new();
+    let title = TITLE.get_or_init(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>").expect("title pattern is valid"));
+    let title = collapse_spaces(&decode_entities(title.captures(html)?.get(1)?.as_str()));
+    (!title.is_empty()).then_some(title)
+}
+
+/// The text of an HTML page without its markup and page chrome, keeping
+/// paragraph and list structure as line breaks.
+fn readable_text(html: &str) -> String {
+    static COMMENT: OnceLock<Regex> = OnceLock::new();
+    static BOILERPLATE: OnceLock<Vec<Regex>> = OnceLock::new();
+    static MAIN: OnceLock<Regex> = OnceLock::new();
+    static LIST_ITEM: OnceLock<Regex> = OnceLock::new();
+    static BLOCK: OnceLock<Regex> = OnceLock::new();
+    static TAG: OnceLock<Regex> = OnceLock::new();
+
+    let comment = COMMENT.get_or_init(|| Regex::new(r"(?s)<!--.*?-->").expect("comment pattern is valid"));
+    let mut html = comment.replace_all(html, "").into_owned();
+    // The regex crate has no backreferences, so each element gets its own pattern
+    let boilerplate = BOILERPLATE.get_or_init(|| {
+        BOILERPLATE_TAGS.iter()
+            .map(|tag| {
+                Regex::new(&format!(r"(?is)<{tag}\b[^>]*>.*?</{tag}\s*>")).expect("boilerplate pattern is valid")
+            })
+            .collect()
+    });
+    for element in boilerplate {
+        html = element.replace_all(&html, "\n").into_owned();
+    }
+
+    // Most article pages mark up their content; prefer it over the whole body
+    let main = MAIN.get_or_init(|| {
+        Regex::new(r"(?is)<(?:main|article)\b[^>]*>(.*)</(?:main|article)\s*>").expect("main content pattern is valid")
+    });
+    if let Some(content) = main.captures(&html).and_then(|captures| captures.get(1)) {
+        html = content.as_str().to_string();
+    }
+
+    let list_item = LIST_ITEM.get_or_init(|| Regex::new(r"(?i)<li\b[^>]*>").expect("list item pattern is valid"));
+    let html = list_item.replace_all(&html, "\n- ");
+    let block = BLOCK.get_or_init(|| {
+        Regex::new(r"(?i)</?(?:p|div|br|hr|h[1-6]|ul|ol|tr|table|section|blockquote|pre|dt|dd)\b[^>]*>").expect("block pattern is valid")
+    });
+    let html = block.replace_all(&html, "\n");
+    let tag = TAG.get_or_init(|| Regex::new(r"(?s)<[^>]*>").expect("tag pattern is valid"));
+    let text = decode_entities(&tag.replace_all(&html, ""));
+
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines().map(collapse_spaces) {
+        // Keep single blank lines between paragraphs only
+        if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    lines.join("\n").trim().to_string()
+}
+
+fn collapse_spaces(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Decode named entities common in page text, and numeric ones.
+fn decode_entities(text: &str) -> String {
+    static ENTITY: OnceLock<Regex> = OnceLock::new();
+    let entity = ENTITY.get_or_init(|| Regex::new(r"&(#[0-9]+|#[xX][0-9a-fA-F]+|[a-zA-Z]+);").expect("entity pattern is valid"));
+    entity.replace_all(text, |captures: &regex::Captures| {
+        let name = &captures[1];
+        let decoded = match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            "mdash" => Some('—'),
+            "ndash" => Some('–'),
+            "hellip" => Some('…'),
+            "rsquo" => Some('’'),
+            "lsquo" => Some('‘'),
+            "rdquo" => Some('”'),
+            "ldquo" => Some('“'),
+            _ => {
+                let code = if let Some(hex) = name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+                    u32::from_str_radix(hex, 16).ok()
+                } else {
+                    name.strip_prefix('#').and_then(|decimal| decimal.parse().ok())
+                };
+                code.and_then(char::from_u32)
+            }
+        };
+        decoded.map(String::from).unwrap_or_else(|| captures[0].to_string())
+    }).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_public_addresses_pass() {
+        let public = [
+            "8.8.8.8",
+            "1.1.1.1",
+            "100.128.0.1",
+            "198.20.0.1",
+            "223.255.255.255",
+            "2606:4700::1111",
+            "::ffff:8.8.8.8",
+            "64:ff9b::808:808",
+            "2002:808:808::1",
+        ];
+        let private = [
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "0.1.2.3",
+            "100.64.0.1",
+            "192.0.2.1",
+            "198.18.0.1",
+            "198.19.255.255",
+            "224.0.0.1",
+            "239.255.255.250",
+            "240.0.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "fc00::1",
+            "fd12:3456::1",
+            "fe80::1",
+            "ff02::1",
+            "2001:db8::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+            "::127.0.0.1",
+            "::10.0.0.1",
+            "64:ff9b::7f00:1",
+            "64:ff9b::a9fe:a9fe",
+            "2002:7f00:1::",
+            "2002:a9fe:a9fe::1",
+        ];
+        for address in public {
+            assert!(is_public(address.parse().unwrap()), "{} should be public", address);
+        }
+        for address in private {
+            assert!(!is_public(address.parse().unwrap()), "{} should not be public", address);
+        }
+    }
+}
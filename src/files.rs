@@ -0,0 +1,305 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use std::path::{Path, PathBuf};
+
+use crate::roots::Scope;
+
+/// A text file read by the server and inlined into the prompt.
+pub struct Attachment {
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+/// An image for a multimodal prompt, validated and loaded into memory.
+#[derive(Debug, Clone)]
+pub struct Image {
+    /// The file path, or `images[N]` for inline base64 data.
+    pub source: String,
+    pub mime_type: &'static str,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    /// File extension matching the image's format.
+    pub fn extension(&self) -> &'static str {
+        self.mime_type.strip_prefix("image/").unwrap_or("bin")
+    }
+}
+
+/// Characters that make an entry of `files` a glob pattern.
+const GLOB_CHARS: &[char] = &['*', '?', '[', '{'];
+
+/// Returned when an entry of `files` or `images` cannot be attached.
+#[derive(Debug)]
+pub struct AttachmentError {
+    pub path: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for AttachmentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Cannot attach {}: {}", self.path, self.reason)
+    }
+}
+
+impl std::error::Error for AttachmentError {}
+
+/// Read every file in `paths`, rejecting missing, non-text and oversized files
+/// and files outside `scope`.
+///
+/// Relative paths are resolved against `base`, or the server's working directory without one.
+pub async fn read_all(paths: &[String], base: Option<&Path>, max_bytes: u64, scope: &Scope) -> Result<Vec<Attachment>> {
+    let mut attachments = Vec::with_capacity(paths.len());
+
+    for path in paths {
+        let error = |reason: String| AttachmentError {
+            path: path.clone(),
+            reason,
+        };
+        let resolved = resolve(base, path);
+        scope.check(&resolved)?;
+
+        let metadata = tokio::fs::metadata(&resolved).await
+            .map_err(|e| error(e.to_string()))?;
+        if !metadata.is_file() {
+            return Err(error("not a regular file".to_string()).into());
+        }
+        if metadata.len() > max_bytes {
+            return Err(error(format!(
+                "file is {} bytes, more than the max_file_bytes limit of {}",
+                metadata.len(), max_bytes
+            )).into());
+        }
+
+        let bytes = tokio::fs::read(&resolved).await
+            .map_err(|e| error(e.to_string()))?;
+        let contents = String::from_utf8(bytes)
+            .map_err(|_| error("not a UTF-8 text file".to_string()))?;
+
+        attachments.push(Attachment {
+            path: PathBuf::from(path),
+            contents,
+        });
+    }
+
+    Ok(attachments)
+}
+
+/// How much a glob in `files` may expand to.
+pub struct GlobLimits {
+    /// Most files all globs in one request may match together.
+    pub max_files: usize,
+    /// Most bytes the matched files may add up to.
+    pub max_bytes: u64,
+}
+
+/// Replace every glob pattern in `entries`, such as `src/**/*.rs`, with the files it
+/// matches, skipping hidden files and those ignored by `.gitignore`.
+///
+/// Entries without glob characters are kept as they are. Relative patterns are
+/// matched against `base`, or the server's working directory without one, and
+/// expand to paths relative to it.
+pub fn expand_globs(entries: &[String], base: Option<&Path>, limits: &GlobLimits) -> Result<Vec<String>> {
+    if !entries.iter().any(|entry| entry.contains(GLOB_CHARS)) {
+        return Ok(entries.to_vec());
+    }
+    let root = match base {
+        Some(base) => base.to_path_buf(),
+        None => std::env::current_dir().context("Failed to get the current directory")?,
+    };
+
+    let mut expanded = Vec::new();
+    let mut matched_files = 0;
+    let mut matched_bytes = 0;
+    for entry in entries {
+        if !entry.contains(GLOB_CHARS) {
+            expanded.push(entry.clone());
+            continue;
+        }
+        let error = |reason: String| AttachmentError {
+            path: entry.clone(),
+            reason,
+        };
+        let matcher = globset::GlobBuilder::new(entry)
+            .literal_separator(true)
+            .build()
+            .map_err(|e| error(format!("invalid glob: {}", e)))?
+            .compile_matcher();
+
+        // Relative patterns are matched against paths relative to the root, absolute ones as they are
+        let absolute = Path::new(entry).is_absolute();
+        let walk_from = root.join(literal_prefix(entry));
+        let mut matches = Vec::new();
+        for found in ignore::WalkBuilder::new(&walk_from).build() {
+            // Unreadable directories are skipped rather than failing the whole pattern
+            let Ok(found) = found else {
+                continue;
+            };
+            if !found.file_type().is_some_and(|kind| kind.is_file()) {
+                continue;
+            }
+            let candidate = if absolute {
+                found.path()
+            } else {
+                found.path().strip_prefix(&root).unwrap_or(found.path())
+            };
+            if !matcher.is_match(candidate) {
+                continue;
+            }
+
+            matched_files += 1;
+            if matched_files > limits.max_files {
+                return Err(error(format!("globs matched more than the max_glob_files limit of {} files", limits.max_files)).into());
+            }
+            matched_bytes += found.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+            if matched_bytes > limits.max_bytes {
+                return Err(error(format!("matched files add up to more than the max_glob_bytes limit of {}", limits.max_bytes)).into());
+            }
+            matches.push(candidate.display().to_string());
+        }
+
+        if matches.is_empty() {
+            return Err(error("glob matched no files".to_string()).into());
+        }
+        matches.sort();
+        for path in matches {
+            if !expanded.contains(&path) {
+                expanded.push(path);
+            }
+        }
+    }
+
+    Ok(expanded)
+}
+
+/// The leading components of `pattern` that contain no glob characters, where
+/// walking for its matches can start.
+fn literal_prefix(pattern: &str) -> PathBuf {
+    let mut prefix = PathBuf::new();
+    let components: Vec<_> = Path::new(pattern).components().collect();
+    // The last component is matched against file names, never walked into
+    for component in &components[..components.len().saturating_sub(1)] {
+        if component.as_os_str().to_string_lossy().contains(GLOB_CHARS) {
+            break;
+        }
+        prefix.push(component);
+    }
+    prefix
+}
+
+/// Append the attachments to `prompt`, each wrapped in a `<file>` block naming its path.
+pub fn inline(prompt: &str, attachments: &[Attachment]) -> String {
+    let mut inlined = prompt.to_string();
+    for attachment in attachments {
+        inlined.push_str(&format!(
+            "\n\n<file path=\"{}\">\n{}\n</file>",
+            attachment.path.display(),
+            attachment.contents.trim_end()
+        ));
+    }
+    inlined
+}
+
+/// Load every image in `inputs`, each either a file path, a `data:` URL or raw base64.
+///
+/// Only formats Gemini accepts (PNG, JPEG, WebP, HEIC, HEIF) are allowed, detected
+/// from the data itself rather than the file name. Relative paths are resolved, and
+/// files checked against `scope`, like [`read_all`].
+pub async fn read_images(inputs: &[String], base: Option<&Path>, max_bytes: u64, scope: &Scope) -> Result<Vec<Image>> {
+    let mut images = Vec::with_capacity(inputs.len());
+
+    for (index, input) in inputs.iter().enumerate() {
+        let resolved = resolve(base, input);
+        // Anything that is not an existing file is treated as base64 data
+        let file_len = if input.starts_with("data:") {
+            None
+        } else {
+            tokio::fs::metadata(&resolved).await.ok().filter(|m| m.is_file()).map(|m| m.len())
+        };
+        let source = match file_len {
+            Some(_) => input.clone(),
+            None => format!("images[{}]", index),
+        };
+        if file_len.is_some() {
+            scope.check(&resolved)?;
+        }
+        let error = |reason: String| AttachmentError {
+            path: source.clone(),
+            reason,
+        };
+        let too_large = |len: u64| error(format!(
+            "image is {} bytes, more than the max_image_bytes limit of {}",
+            len, max_bytes
+        ));
+
+        let data = match file_len {
+            Some(len) if len > max_bytes => return Err(too_large(len).into()),
+            Some(_) => tokio::fs::read(&resolved).await
+                .map_err(|e| error(e.to_string()))?,
+            None => {
+                let data = decode_base64(input)
+                    .ok_or_else(|| error("neither an existing file nor valid base64 image data".to_string()))?;
+                if data.len() as u64 > max_bytes {
+                    return Err(too_large(data.len() as u64).into());
+                }
+                data
+            }
+        };
+
+        let mime_type = sniff_image(&data)
+            .ok_or_else(|| error("unsupported image format, expected PNG, JPEG, WebP, HEIC or HEIF".to_string()))?;
+
+        images.push(Image {
+            source,
+            mime_type,
+            data,
+        });
+    }
+
+    Ok(images)
+}
+
+/// `path` relative to `base`, or as given without one.
+pub fn resolve(base: Option<&Path>, path: &str) -> PathBuf {
+    match base {
+        Some(base) => base.join(path),
+        None => PathBuf::from(path),
+    }
+}
+
+/// Decode raw base64 or the payload of a `data:<mime>;base64,` URL.
+fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    let payload = match input.strip_prefix("data:") {
+        Some(url) => {
+            let (header, payload) = url.split_once(',')?;
+            if !header.ends_with(";base64") {
+                return None;
+            }
+            payload
+        }
+        None => input,
+    };
+    let payload: String = payload.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    base64::engine::general_purpose::STANDARD.decode(payload).ok()
+}
+
+/// Detect an image format Gemini accepts from its magic bytes.
+fn sniff_image(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some("image/png");
+    }
+    if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        return Some("image/jpeg");
+    }
+    if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    if data.len() >= 12 && &data[4..8] == b"ftyp" {
+        return match &data[8..12] {
+            b"heic" | b"heix" | b"heim" | b"heis" => Some("image/heic"),
+            b"mif1" | b"msf1" | b"heif" => Some("image/heif"),
+            _ => None,
+        };
+    }
+    None
+}
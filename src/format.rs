@@ -0,0 +1,118 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+/// How a tool returns Gemini's answer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Plain text, with Markdown syntax removed.
+    Text,
+    /// Gemini's answer as written, which is usually Markdown.
+    #[default]
+    Markdown,
+    /// A JSON value, parsed and validated by the server.
+    Json,
+}
+
+/// Appended to prompts that ask for JSON, since the CLI has no way to force it.
+const JSON_INSTRUCTION: &str = "Reply with a single valid JSON value only, without code fences or any text around it.";
+
+/// Returned when an answer requested as JSON is not valid JSON.
+#[derive(Debug)]
+pub struct InvalidOutputError {
+    pub reason: String,
+}
+
+impl std::fmt::Display for InvalidOutputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Gemini's answer is not valid JSON: {}", self.reason)
+    }
+}
+
+impl std::error::Error for InvalidOutputError {}
+
+/// An answer converted to its [`OutputFormat`].
+pub enum Formatted {
+    Text(String),
+    Json(serde_json::Value),
+}
+
+/// Convert Gemini's `answer` to `format`.
+pub fn apply(format: OutputFormat, answer: String) -> Result<Formatted, InvalidOutputError> {
+    match format {
+        OutputFormat::Markdown => Ok(Formatted::Text(answer)),
+        OutputFormat::Text => Ok(Formatted::Text(plain_text(&answer))),
+        OutputFormat::Json => parse_json(&answer).map(Formatted::Json),
+    }
+}
+
+/// `prompt` with whatever `format` needs Gemini to be told.
+pub fn prompt(prompt: String, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Json => format!("{}\n\n{}", prompt, JSON_INSTRUCTION),
+        OutputFormat::Text | OutputFormat::Markdown => prompt,
+    }
+}
+
+/// Parse an answer requested as JSON, tolerating the code fences and short
+/// preambles Gemini sometimes adds anyway.
+pub fn parse_json(answer: &str) -> Result<serde_json::Value, InvalidOutputError> {
+    static FENCE: OnceLock<Regex> = OnceLock::new();
+    let fence = FENCE.get_or_init(|| Regex::new(r"(?s)```(?:json)?\s*\n(.*?)\n\s*```").expect("fence pattern is valid"));
+
+    let answer = answer.trim();
+    let first_error = match serde_json::from_str(answer) {
+        Ok(value) => return Ok(value),
+        Err(e) => e,
+    };
+    if let Some(fenced) = fence.captures(answer).and_then(|captures| captures.get(1)) {
+        if let Ok(value) = serde_json::from_str(fenced.as_str()) {
+            return Ok(value);
+        }
+    }
+    // Text around a single object or array
+    let start = answer.find(['{', '[']);
+    let end = answer.rfind(['}', ']']);
+    if let (Some(start), Some(end)) = (start, end) {
+        if start < end {
+            if let Ok(value) = serde_json::from_str(&answer[start..=end]) {
+                return Ok(value);
+            }
+        }
+    }
+    Err(InvalidOutputError { reason: first_error.to_string() })
+}
+
+/// Strip Markdown syntax from `answer`, keeping its text, line structure and link targets.
+pub fn plain_text(answer: &str) -> String {
+    static FENCE_LINE: OnceLock<Regex> = OnceLock::new();
+    static HEADING: OnceLock<Regex> = OnceLock::new();
+    static EMPHASIS: OnceLock<Regex> = OnceLock::new();
+    static CODE: OnceLock<Regex> = OnceLock::new();
+    static IMAGE: OnceLock<Regex> = OnceLock::new();
+    static LINK: OnceLock<Regex> = OnceLock::new();
+    static BULLET: OnceLock<Regex> = OnceLock::new();
+
+    let fence_line = FENCE_LINE.get_or_init(|| Regex::new(r"(?m)^\s*```.*\n?").expect("fence line pattern is valid"));
+    let heading = HEADING.get_or_init(|| Regex::new(r"(?m)^\s{0,3}#{1,6}\s+").expect("heading pattern is valid"));
+    // No backreferences in the regex crate, so each marker is its own alternative
+    let emphasis = EMPHASIS.get_or_init(|| {
+        Regex::new(r"\*\*(.+?)\*\*|__(.+?)__|~~(.+?)~~|\*([^*\s](?:[^*]*[^*\s])?)\*").expect("emphasis pattern is valid")
+    });
+    let code = CODE.get_or_init(|| Regex::new(r"`([^`]+)`").expect("inline code pattern is valid"));
+    let image = IMAGE.get_or_init(|| Regex::new(r"!\[([^\]]*)\]\([^)]*\)").expect("image pattern is valid"));
+    let link = LINK.get_or_init(|| Regex::new(r"\[([^\]]+)\]\(([^)\s]+)\)").expect("link pattern is valid"));
+    let bullet = BULLET.get_or_init(|| Regex::new(r"(?m)^(\s*)[*+]\s+").expect("bullet pattern is valid"));
+
+    let text = fence_line.replace_all(answer, "");
+    let text = heading.replace_all(&text, "");
+    let text = image.replace_all(&text, "$1");
+    let text = link.replace_all(&text, "$1 ($2)");
+    let text = code.replace_all(&text, "$1");
+    let text = bullet.replace_all(&text, "$1- ");
+    let text = emphasis.replace_all(&text, |captures: &regex::Captures| {
+        (1..=4).find_map(|group| captures.get(group)).map_or("", |inner| inner.as_str()).to_string()
+    });
+    text.trim().to_string()
+}
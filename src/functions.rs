@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+/// Longest function name Gemini accepts.
+const MAX_NAME_LEN: usize = 64;
+
+/// A function the caller offers Gemini, which only the caller can execute.
+#[derive(Debug, Clone, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct FunctionDeclaration {
+    #[schemars(description = "Name Gemini calls the function by: letters, digits, underscores, dots and dashes, at most 64 characters")]
+    pub name: String,
+    #[schemars(description = "What the function does and when to call it")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[schemars(description = "JSON Schema of the arguments, an object schema (optional for functions without arguments)")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<serde_json::Value>,
+}
+
+/// A call Gemini asks the caller to make instead of answering.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub args: serde_json::Value,
+}
+
+/// What a [`FunctionCall`] returned, sent back so Gemini can finish its answer.
+#[derive(Debug, Clone, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct FunctionResult {
+    #[schemars(description = "Name of the function Gemini called")]
+    pub name: String,
+    #[schemars(description = "The arguments Gemini called it with, as returned in function_calls")]
+    #[serde(default)]
+    pub args: serde_json::Value,
+    #[schemars(description = "What the function returned; values other than objects are sent as {\"result\": value}")]
+    pub response: serde_json::Value,
+}
+
+/// Check that `declarations` have names Gemini accepts, without repeats.
+pub fn validate(declarations: &[FunctionDeclaration]) -> Result<(), String> {
+    for (index, declaration) in declarations.iter().enumerate() {
+        let name = &declaration.name;
+        let valid = !name.is_empty()
+            && name.len() <= MAX_NAME_LEN
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+        if !valid {
+            return Err(format!("Invalid function name '{}'", name));
+        }
+        if declarations[..index].iter().any(|earlier| earlier.name == *name) {
+            return Err(format!("Function '{}' is declared twice", name));
+        }
+        if declaration.parameters.as_ref().is_some_and(|parameters| !parameters.is_object()) {
+            return Err(format!("The parameters of function '{}' must be a JSON Schema object", name));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::Path;
+use tokio::process::Command;
+
+/// Separates the fields of one commit in `git log` output.
+const FIELD: char = '\u{1f}';
+/// Ends each commit in `git log` output.
+const RECORD: char = '\u{1e}';
+
+/// One commit, as `git log` reports it.
+#[derive(Debug, Clone, Serialize)]
+pub struct CommitInfo {
+    pub hash: String,
+    pub author: String,
+    /// Committer date in ISO 8601.
+    pub date: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Fail unless `name` can be passed to git as a revision rather than read as an option.
+pub fn check_ref(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.starts_with('-') || name.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(format!("'{}' is not a valid git revision", name));
+    }
+    Ok(())
+}
+
+async fn run(repo: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C").arg(repo)
+        .args(args)
+        .output()
+        .await
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        anyhow::bail!("git {} failed: {}", args[0], String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The commits in `range` (e.g. `main..HEAD`), newest first, leaving out merges.
+pub async fn log(repo: &Path, range: &str) -> Result<Vec<CommitInfo>> {
+    let format = format!("--format=%H{f}%an{f}%cI{f}%s{f}%b{r}", f = FIELD, r = RECORD);
+    let output = run(repo, &["log", "--no-color", "--no-merges", &format, range, "--"]).await?;
+    Ok(output
+        .split(RECORD)
+        .filter_map(|record| {
+            let mut fields = record.trim_start_matches('\n').split(FIELD);
+            Some(CommitInfo {
+                hash: fields.next().filter(|hash| !hash.is_empty())?.to_string(),
+                author: fields.next()?.to_string(),
+                date: fields.next()?.to_string(),
+                subject: fields.next()?.to_string(),
+                body: fields.next().unwrap_or_default().trim().to_string(),
+            })
+        })
+        .collect())
+}
+
+/// `git diff --stat` of `range`, e.g. `main...HEAD`.
+pub async fn diffstat(repo: &Path, range: &str) -> Result<String> {
+    run(repo, &["diff", "--no-color", "--stat", range, "--"]).await
+}
+
+/// Tags reachable from `to` but not from `from`, oldest first, e.g. the releases made between them.
+pub async fn tags_between(repo: &Path, from: &str, to: &str) -> Result<Vec<String>> {
+    let output = run(repo, &["tag", "--merged", to, "--no-merged", from, "--sort=creatordate"]).await?;
+    Ok(output.lines().map(str::trim).filter(|tag| !tag.is_empty()).map(str::to_string).collect())
+}
+
+/// The date of the commit `revision` points at, as YYYY-MM-DD.
+pub async fn date(repo: &Path, revision: &str) -> Result<String> {
+    let output = run(repo, &["log", "-n1", "--format=%cs", revision, "--"]).await?;
+    Ok(output.trim().to_string())
+}
@@ -0,0 +1,49 @@
+use crate::config::Locale;
+
+/// Japanese descriptions of the built-in tools, keyed by tool name.
+const JA_TOOL_DESCRIPTIONS: &[(&str, &str)] = &[
+    ("gemini_prompt", "Gemini にプロンプトを送信します"),
+    ("gemini_chat", "呼び出しをまたいでコンテキストを保持するセッションで Gemini とチャットします"),
+    ("gemini_batch_prompt", "互いに独立した多数のプロンプトを並列数を制限して 1 回の呼び出しで Gemini に送り、プロンプトごとに結果またはエラーを返します"),
+    ("gemini_compare_models", "同じプロンプトを 2〜4 個のモデルに同時に送り、回答、レイテンシ、トークン数を並べて比較します"),
+    ("gemini_review_diff", "git リポジトリの変更を Gemini でレビューし、指摘をファイルごとにまとめて返します"),
+    ("gemini_search_grounded", "Google 検索の結果に基づいて Gemini が質問に答え、回答と出典の URL を返します"),
+    ("gemini_generate_image", "Imagen で画像を生成してファイルに保存し、画像コンテンツとして返します"),
+    ("gemini_list_models", "利用できる Gemini モデルを、コンテキストウィンドウのサイズと対応するモダリティとともに一覧表示します"),
+    ("gemini_count_tokens", "プロンプトのトークン数と、モデルのコンテキストウィンドウに収まるかどうかを調べます"),
+    ("gemini_usage_report", "トークン使用量と推定コストを日ごと、モデルごと、チャットセッションごとに報告します"),
+    ("gemini_doctor", "gemini バイナリ、バージョン、認証、テストプロンプトからサーバーの設定を診断し、問題の直し方を示します"),
+    ("gemini_config", "Gemini CLI の設定を行います"),
+    ("gemini_fetch_continuation", "長すぎて途中で切られた応答の続きを取得します"),
+    ("gemini_resume", "ターミナルなどで gemini CLI で始めた会話を再開します。conversation_id を省略すると再開できる会話を一覧表示します"),
+    ("gemini_cross_check", "Gemini が回答したあと、MCP サンプリングで呼び出し元のクライアント自身のモデルが批評し、Gemini が修正します (最大 3 回)"),
+    ("gemini_audit_search", "過去のツール呼び出しの監査ログをテキスト、ツール、状態、期間で検索します"),
+    ("gemini_summarize_url", "サーバー上で Web ページを取得して Gemini に要約させます。自分で Web を閲覧できないクライアント向けです"),
+    ("gemini_web_search", "gemini CLI の google_web_search ツールで Web を検索して結果から回答し、回答と参照したページのタイトルと URL を返します"),
+    ("gemini_list_extensions", "gemini CLI にインストールされた拡張機能と、それぞれが有効かどうかを一覧表示します"),
+    ("gemini_install_extension", "git リポジトリまたはローカルパスから gemini CLI の拡張機能をインストールし、後のプロンプトに必要な機能を追加します。extension_management が必要です"),
+    ("gemini_enable_extension", "インストール済みの gemini CLI 拡張機能を、すべてのプロジェクトまたは 1 つのワークスペースで有効化または無効化します。extension_management が必要です"),
+    ("gemini_pipeline", "各ステップのテンプレートに前のステップの出力を埋め込みながら、Gemini へのプロンプトを順に実行します (例: 抽出、批評、書き直し)。ステップごとにモデルを選べます"),
+    ("gemini_summarize_files", "ファイルやチャンクごとに高速なモデルで要約し、その要約をより強力なモデルでまとめることで、コンテキストウィンドウに収まらないリポジトリ全体でも多数のファイルを要約します"),
+    ("gemini_generate_file", "指示に従って Gemini にファイルを書かせるか既存のファイルを書き直させ、現在の内容との差分を返します。write を指定すると、古いファイルをバックアップしてから結果を保存します"),
+    ("gemini_propose_patch", "変更内容を unified diff として Gemini に提案させ、きれいに適用できるかを確認します。apply を指定した場合にのみ適用するので、差分を確認してから patch として渡し直して適用できます"),
+    ("gemini_generate_tests", "プロジェクトのテストフレームワークを使って、ソースファイルの単体テストを Gemini に書かせます。テストファイルを差分付きで返し、write を指定すると保存します"),
+    ("gemini_commit_message", "git リポジトリのステージされた変更のコミットメッセージを、Conventional Commits や gitmoji などの規約に沿って書きます。git commit -F にそのまま渡せる形で返します"),
+    ("gemini_pr_description", "ベースブランチと head の間のコミット、diffstat、差分から、プルリクエストの説明 (タイトル、概要、変更点、テスト計画) を下書きします"),
+    ("gemini_changelog", "2 つの git ref の間のコミットとタグから、変更の種類ごとにまとめたリリースノートを Keep a Changelog などの設定可能な形式で下書きします"),
+    ("gemini_explain_error", "スタックトレース、パニック、コンパイルエラーの根本原因を説明し、修正案を示します。エラーが参照するソースの行は自動的にプロンプトに含まれます"),
+    ("gemini_summarize_logs", "ログファイルを要約し、異常を見つけてタイムラインを再構成します。コンテキストウィンドウに収まるよう、サーバー側で grep、exclude、重複除去、tail によって絞り込みます"),
+    ("gemini_sessions_list", "gemini_chat のセッションを最近使った順に、タイトル、モデル、ターン数、トークン使用量とともに一覧表示します"),
+    ("gemini_session_get", "gemini_chat のセッションのメタデータ、トークン使用量、メッセージを表示します"),
+    ("gemini_session_delete", "gemini_chat のセッションとそのすべてのメッセージを削除します"),
+    ("gemini_session_rename", "gemini_chat のセッションに gemini_sessions_list で表示されるタイトルを付けるか、削除します"),
+];
+
+/// The description of the built-in tool `name` in `locale`, when it differs from the English one.
+pub fn tool_description(locale: Locale, name: &str) -> Option<&'static str> {
+    let descriptions = match locale {
+        Locale::En => return None,
+        Locale::Ja => JA_TOOL_DESCRIPTIONS,
+    };
+    descriptions.iter().find(|(tool, _)| *tool == name).map(|(_, description)| *description)
+}
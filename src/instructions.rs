@@ -0,0 +1,26 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+
+use crate::config::Locale;
+
+/// Instructions sent to clients when `instructions_file` is not set.
+pub fn builtin(locale: Locale) -> &'static str {
+    match locale {
+        Locale::En => include_str!("instructions.md"),
+        Locale::Ja => include_str!("instructions.ja.md"),
+    }
+}
+
+/// The server instructions clients show their model: the contents of `path`, or
+/// the built-in ones in `locale`.
+pub fn load(path: Option<&Path>, locale: Locale) -> Result<String> {
+    let Some(path) = path else {
+        return Ok(builtin(locale).to_string());
+    };
+    let instructions = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read instructions file {}", path.display()))?;
+    if instructions.trim().is_empty() {
+        anyhow::bail!("Instructions file {} is empty", path.display());
+    }
+    Ok(instructions)
+}
@@ -0,0 +1,255 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::process::Command;
+
+use crate::config::Config;
+use crate::docker::Docker;
+use crate::ssh::Ssh;
+
+/// Used when PATHEXT is unset.
+const DEFAULT_PATHEXT: &str = ".COM;.EXE;.BAT;.CMD";
+
+/// cmd.exe refuses command lines over 8191 characters; leave room for quoting.
+const CMD_LINE_MAX_BYTES: usize = 6 * 1024;
+
+#[derive(Debug, Clone)]
+enum Kind {
+    /// A native executable, or a script with a shebang on Unix.
+    Direct,
+    /// A `.cmd`/`.bat` file, which Windows can only run through cmd.exe.
+    Batch,
+    /// A `.ps1` script, run through PowerShell.
+    PowerShell,
+    /// The gemini CLI in a container; `program` is the docker CLI.
+    Docker(Arc<Docker>),
+    /// The gemini CLI on another host; `program` is the ssh client.
+    Ssh(Arc<Ssh>),
+}
+
+/// How to start a program on this platform.
+///
+/// npm installs the gemini CLI on Windows as `gemini.cmd` and `gemini.ps1`
+/// shims next to a Unix shell script named `gemini`, none of which
+/// `Command::new("gemini")` can run directly.
+#[derive(Debug, Clone)]
+pub struct Launcher {
+    program: PathBuf,
+    kind: Kind,
+}
+
+impl Launcher {
+    /// The launcher for the configured gemini CLI, on this host, in Docker or over SSH.
+    pub fn from_config(config: &Config) -> Self {
+        let gemini = config.gemini_binary.clone();
+        if let Some(docker) = &config.docker {
+            return Self {
+                program: find_executable(&docker.binary).unwrap_or_else(|| PathBuf::from(&docker.binary)),
+                kind: Kind::Docker(Arc::new(Docker::new(docker.clone(), gemini))),
+            };
+        }
+        if let Some(ssh) = &config.ssh {
+            return Self {
+                program: find_executable(&ssh.binary).unwrap_or_else(|| PathBuf::from(&ssh.binary)),
+                kind: Kind::Ssh(Arc::new(Ssh::new(ssh.clone(), gemini))),
+            };
+        }
+        Self::resolve(&gemini)
+    }
+
+    /// Resolve `binary` on PATH, falling back to the bare name so spawning reports it missing.
+    pub fn resolve(binary: &str) -> Self {
+        let program = find_executable(binary).unwrap_or_else(|| PathBuf::from(binary));
+        let kind = match extension(&program).as_deref() {
+            Some("cmd" | "bat") if cfg!(windows) => Kind::Batch,
+            Some("ps1") if cfg!(windows) => Kind::PowerShell,
+            _ => Kind::Direct,
+        };
+        Self { program, kind }
+    }
+
+    pub fn program(&self) -> &Path {
+        &self.program
+    }
+
+    /// Whether `arg` can be passed on the command line intact.
+    ///
+    /// cmd.exe cannot carry line breaks in arguments and has a much shorter
+    /// line limit, so such arguments have to go another way, e.g. stdin.
+    pub fn can_pass(&self, arg: &str) -> bool {
+        match self.kind {
+            Kind::Batch => arg.len() <= CMD_LINE_MAX_BYTES && !arg.contains(['\n', '\r']),
+            Kind::Direct | Kind::PowerShell | Kind::Docker(_) | Kind::Ssh(_) => true,
+        }
+    }
+
+    /// How the CLI refers to local `path`, which differs on a remote host.
+    pub fn map_path(&self, path: &Path) -> String {
+        match &self.kind {
+            Kind::Ssh(ssh) => ssh.map_path(path),
+            Kind::Direct | Kind::Batch | Kind::PowerShell | Kind::Docker(_) => path.display().to_string(),
+        }
+    }
+
+    /// A command that runs the program with `args`.
+    pub fn command(&self, args: &[String]) -> Command {
+        match &self.kind {
+            Kind::Direct => {
+                let mut cmd = Command::new(&self.program);
+                cmd.args(args);
+                cmd
+            }
+            Kind::PowerShell => {
+                let mut cmd = Command::new("powershell.exe");
+                cmd.args(["-NoLogo", "-NoProfile", "-NonInteractive", "-ExecutionPolicy", "Bypass", "-File"])
+                    .arg(&self.program)
+                    .args(args);
+                cmd
+            }
+            Kind::Batch => batch_command(&self.program, args),
+            Kind::Docker(docker) => {
+                let mut cmd = Command::new(&self.program);
+                cmd.args(docker.args(args, [], None));
+                cmd
+            }
+            Kind::Ssh(ssh) => {
+                let mut cmd = Command::new(&self.program);
+                cmd.args(ssh.args(args, &[], None));
+                cmd
+            }
+        }
+    }
+
+    /// A command that runs the program with `args` in `cwd`, seeing only `env`.
+    ///
+    /// In Docker the variables are forwarded into the container, while the
+    /// docker CLI itself also keeps its `DOCKER_*` settings. Over SSH the ssh
+    /// client keeps its `SSH_*` settings, such as the agent socket, and
+    /// [`Ssh::args`] decides what reaches the remote CLI.
+    pub fn command_in(&self, args: &[String], env: Vec<(OsString, OsString)>, cwd: Option<&Path>) -> Command {
+        let mut cmd = match &self.kind {
+            Kind::Docker(docker) => {
+                let names = env.iter().filter_map(|(name, _)| name.to_str());
+                let mut cmd = Command::new(&self.program);
+                cmd.args(docker.args(args, names, cwd));
+                cmd.env_clear();
+                cmd.envs(std::env::vars_os().filter(|(name, _)| name.to_string_lossy().starts_with("DOCKER_")));
+                cmd
+            }
+            Kind::Ssh(ssh) => {
+                let mut cmd = Command::new(&self.program);
+                cmd.args(ssh.args(args, &env, cwd));
+                cmd.env_clear();
+                cmd.envs(std::env::vars_os().filter(|(name, _)| name.to_string_lossy().starts_with("SSH_")));
+                cmd
+            }
+            Kind::Direct | Kind::Batch | Kind::PowerShell => {
+                let mut cmd = self.command(args);
+                cmd.env_clear();
+                if let Some(cwd) = cwd {
+                    cmd.current_dir(cwd);
+                }
+                cmd
+            }
+        };
+        cmd.envs(env);
+        cmd
+    }
+}
+
+/// Find `binary` the way the platform's shell would: on PATH unless it
+/// contains a path separator, trying each PATHEXT extension on Windows.
+pub fn find_executable(binary: &str) -> Option<PathBuf> {
+    if binary.contains(['/', std::path::MAIN_SEPARATOR]) {
+        return candidates(PathBuf::from(binary)).into_iter().find(|path| path.is_file());
+    }
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .flat_map(|dir| candidates(dir.join(binary)))
+        .find(|path| path.is_file())
+}
+
+/// The files `path` may refer to.
+///
+/// On Windows a name without an extension gets each PATHEXT extension, then
+/// `.ps1`; the extensionless npm shim is a shell script Windows cannot run.
+fn candidates(path: PathBuf) -> Vec<PathBuf> {
+    if !cfg!(windows) || path.extension().is_some() {
+        return vec![path];
+    }
+    let pathext = std::env::var("PATHEXT").unwrap_or_else(|_| DEFAULT_PATHEXT.to_string());
+    pathext
+        .split(';')
+        .filter(|ext| !ext.is_empty())
+        .chain([".PS1"])
+        .map(|ext| {
+            let mut name = path.clone().into_os_string();
+            name.push(ext.to_lowercase());
+            PathBuf::from(name)
+        })
+        .collect()
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension().and_then(|ext| ext.to_str()).map(str::to_lowercase)
+}
+
+#[cfg(windows)]
+fn batch_command(program: &Path, args: &[String]) -> Command {
+    let mut line = escape_meta(&program.display().to_string());
+    for arg in args {
+        line.push(' ');
+        // npm shims forward their arguments with `%*`, which cmd parses a second time
+        line.push_str(&escape_meta(&escape_meta(&quote(arg))));
+    }
+
+    let shell = std::env::var_os("COMSPEC").unwrap_or_else(|| "cmd.exe".into());
+    let mut cmd = Command::new(shell);
+    // /s keeps cmd from stripping quotes inside the line, which is quoted once more as a whole
+    cmd.args(["/d", "/s", "/c"]).raw_arg(format!("\"{}\"", line));
+    cmd
+}
+
+#[cfg(not(windows))]
+fn batch_command(program: &Path, args: &[String]) -> Command {
+    let mut cmd = Command::new(program);
+    cmd.args(args);
+    cmd
+}
+
+/// Quote `arg` so CommandLineToArgvW, which node uses, reads it back unchanged.
+#[cfg(windows)]
+fn quote(arg: &str) -> String {
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        if c == '\\' {
+            backslashes += 1;
+            continue;
+        }
+        if c == '"' {
+            // Backslashes before a quote are escapes, so double them and escape the quote
+            quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+        } else {
+            quoted.push_str(&"\\".repeat(backslashes));
+        }
+        quoted.push(c);
+        backslashes = 0;
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+/// Escape every character cmd.exe treats specially with `^`, quotes included,
+/// so cmd never enters a quoted section where `^` stops working.
+#[cfg(windows)]
+fn escape_meta(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "()[]%!^\"`<>&|;, *?".contains(c) {
+            escaped.push('^');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -0,0 +1,123 @@
+use anyhow::{Context, Result};
+use chrono::{Local, NaiveDate};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::redact;
+
+/// A log file that is rotated when it grows past `max_bytes` or the local date changes.
+///
+/// Rotated files are renamed `<path>.1` (newest) through `<path>.<max_files>`;
+/// older ones are deleted.
+pub struct RotatingFile {
+    state: Mutex<State>,
+}
+
+struct State {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+    date: NaiveDate,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, max_bytes: u64, max_files: usize) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create log directory {}", parent.display()))?;
+        }
+        let file = open(path)?;
+        let metadata = file.metadata()
+            .with_context(|| format!("Failed to read log file {}", path.display()))?;
+        // Continue today's file, but rotate one left over from an earlier day
+        let date = metadata.modified()
+            .map(|modified| chrono::DateTime::<Local>::from(modified).date_naive())
+            .unwrap_or_else(|_| Local::now().date_naive());
+
+        Ok(Self {
+            state: Mutex::new(State {
+                path: path.to_path_buf(),
+                max_bytes,
+                max_files,
+                file,
+                size: metadata.len(),
+                date,
+            }),
+        })
+    }
+}
+
+impl State {
+    fn rotate_if_needed(&mut self, incoming: usize) -> std::io::Result<()> {
+        let today = Local::now().date_naive();
+        let full = self.size > 0 && self.size + incoming as u64 > self.max_bytes;
+        if !full && today == self.date {
+            return Ok(());
+        }
+
+        for n in (1..self.max_files).rev() {
+            let from = numbered(&self.path, n);
+            if from.exists() {
+                std::fs::rename(&from, numbered(&self.path, n + 1))?;
+            }
+        }
+        if self.max_files > 0 {
+            std::fs::rename(&self.path, numbered(&self.path, 1))?;
+        } else {
+            std::fs::remove_file(&self.path)?;
+        }
+
+        self.file = open(&self.path).map_err(std::io::Error::other)?;
+        self.size = 0;
+        self.date = today;
+        Ok(())
+    }
+}
+
+impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for RotatingFile {
+    type Writer = RotatingWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RotatingWriter { state: &self.state }
+    }
+}
+
+pub struct RotatingWriter<'a> {
+    state: &'a Mutex<State>,
+}
+
+impl Write for RotatingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // The formatter hands over one complete event per write
+        let text = String::from_utf8_lossy(buf);
+        let redacted = redact::redact(&text);
+
+        let mut state = self.state.lock().unwrap();
+        state.rotate_if_needed(redacted.len())?;
+        state.file.write_all(redacted.as_bytes())?;
+        state.size += redacted.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.state.lock().unwrap().file.flush()
+    }
+}
+
+fn open(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open log file {}", path.display()))
+}
+
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
@@ -0,0 +1,235 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::OnceLock;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::format::{self, InvalidOutputError};
+use crate::tokens;
+
+/// Most of the filtered log sent to Gemini, in estimated tokens, when the
+/// model's context window is unknown.
+pub const DEFAULT_MAX_LOG_TOKENS: u32 = 100_000;
+
+/// Which lines of a log `gemini_summarize_logs` keeps, applied in field order.
+pub struct Filters {
+    /// Keep only lines matching this.
+    pub grep: Option<Regex>,
+    /// Drop lines matching this.
+    pub exclude: Option<Regex>,
+    /// Keep one of each group of lines that differ only in numbers, such as
+    /// timestamps and ids, noting how often it repeated.
+    pub dedup: bool,
+    /// Keep only the last this many lines.
+    pub tail: Option<usize>,
+}
+
+/// A log after [`filter`], with how many lines each step removed.
+#[derive(Debug, Serialize)]
+pub struct Filtered {
+    #[serde(skip)]
+    pub text: String,
+    pub total_lines: usize,
+    /// Lines removed by `grep` and `exclude`.
+    pub filtered_lines: usize,
+    /// Repeats folded into the first line like them.
+    pub duplicate_lines: usize,
+    /// Lines before the last `tail`, or cut to fit the context window.
+    pub dropped_lines: usize,
+    pub kept_lines: usize,
+}
+
+/// What Gemini found in a log.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LogSummary {
+    /// What the log shows overall, in a short paragraph.
+    pub summary: String,
+    #[serde(default)]
+    pub anomalies: Vec<Anomaly>,
+    #[serde(default)]
+    pub timeline: Vec<Event>,
+}
+
+/// An error, warning or unusual pattern in a log.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Anomaly {
+    pub description: String,
+    /// `critical`, `error`, `warning` or `info`.
+    #[serde(default)]
+    pub severity: String,
+    /// The log line or lines showing it, quoted.
+    #[serde(default)]
+    pub evidence: String,
+}
+
+/// One notable moment in a log.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Event {
+    /// The timestamp as the log writes it; empty for a log without them.
+    #[serde(default)]
+    pub time: String,
+    pub event: String,
+}
+
+impl LogSummary {
+    /// The summary as Markdown.
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = format!("## Summary\n\n{}\n", self.summary.trim());
+        if !self.anomalies.is_empty() {
+            markdown.push_str("\n## Anomalies\n\n");
+            for anomaly in &self.anomalies {
+                match anomaly.severity.trim() {
+                    "" => markdown.push_str(&format!("- {}\n", anomaly.description.trim())),
+                    severity => markdown.push_str(&format!("- **{}** {}\n", severity, anomaly.description.trim())),
+                }
+                if !anomaly.evidence.trim().is_empty() {
+                    markdown.push_str(&format!("  `{}`\n", anomaly.evidence.trim().replace('\n', " ⏎ ")));
+                }
+            }
+        }
+        if !self.timeline.is_empty() {
+            markdown.push_str("\n## Timeline\n\n");
+            for event in &self.timeline {
+                match event.time.trim() {
+                    "" => markdown.push_str(&format!("- {}\n", event.event.trim())),
+                    time => markdown.push_str(&format!("- `{}` {}\n", time, event.event.trim())),
+                }
+            }
+        }
+        markdown
+    }
+}
+
+/// The last `max_bytes` of the log at `path`, starting at a whole line, and
+/// whether anything before them was left out. Invalid UTF-8 is replaced.
+pub async fn read_tail(path: &Path, max_bytes: u64) -> Result<(String, bool)> {
+    let mut file = tokio::fs::File::open(path).await
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let metadata = file.metadata().await
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    if !metadata.is_file() {
+        anyhow::bail!("{} is not a regular file", path.display());
+    }
+    let cut = metadata.len() > max_bytes;
+    if cut {
+        file.seek(std::io::SeekFrom::Start(metadata.len() - max_bytes)).await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+    }
+    let mut bytes = Vec::new();
+    file.take(max_bytes).read_to_end(&mut bytes).await
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let text = String::from_utf8_lossy(&bytes);
+    let text = match text.split_once('\n') {
+        Some((_, rest)) if cut => rest.to_string(),
+        _ => text.into_owned(),
+    };
+    Ok((text, cut))
+}
+
+/// `log` with `filters` applied, then cut from the front to about `max_tokens`.
+pub fn filter(log: &str, filters: &Filters, max_tokens: u32) -> Filtered {
+    let lines: Vec<&str> = log.lines().filter(|line| !line.trim().is_empty()).collect();
+    let total_lines = lines.len();
+    let lines: Vec<&str> = lines
+        .into_iter()
+        .filter(|line| filters.grep.as_ref().is_none_or(|grep| grep.is_match(line)))
+        .filter(|line| !filters.exclude.as_ref().is_some_and(|exclude| exclude.is_match(line)))
+        .collect();
+    let filtered_lines = total_lines - lines.len();
+
+    let mut kept: Vec<String> = Vec::with_capacity(lines.len());
+    let mut duplicate_lines = 0;
+    if filters.dedup {
+        let mut first: HashMap<String, usize> = HashMap::new();
+        let mut repeats: Vec<usize> = Vec::new();
+        for line in &lines {
+            match first.get(&shape(line)) {
+                Some(index) => {
+                    repeats[*index] += 1;
+                    duplicate_lines += 1;
+                }
+                None => {
+                    first.insert(shape(line), kept.len());
+                    kept.push(line.to_string());
+                    repeats.push(0);
+                }
+            }
+        }
+        for (line, repeats) in kept.iter_mut().zip(repeats) {
+            if repeats > 0 {
+                line.push_str(&format!("  [repeated {} more times]", repeats));
+            }
+        }
+    } else {
+        kept = lines.iter().map(|line| line.to_string()).collect();
+    }
+
+    let after_dedup = kept.len();
+    if let Some(tail) = filters.tail {
+        kept.drain(..kept.len().saturating_sub(tail));
+    }
+    // Keep the end of the log, where whatever is being investigated usually is
+    let mut spent = 0;
+    let start = kept
+        .iter()
+        .rposition(|line| {
+            spent += tokens::estimate(line) + 1;
+            spent > max_tokens
+        })
+        .map_or(0, |index| index + 1);
+    kept.drain(..start);
+
+    Filtered {
+        text: kept.join("\n"),
+        total_lines,
+        filtered_lines,
+        duplicate_lines,
+        dropped_lines: after_dedup - kept.len(),
+        kept_lines: kept.len(),
+    }
+}
+
+/// `line` with every run of digits, and of hex digits containing one, replaced by `#`.
+fn shape(line: &str) -> String {
+    static NUMBER: OnceLock<Regex> = OnceLock::new();
+    let number = NUMBER.get_or_init(|| Regex::new(r"[0-9A-Fa-f]*[0-9][0-9A-Fa-f]*").expect("number pattern is valid"));
+    number.replace_all(line, "#").into_owned()
+}
+
+/// Prompt asking for the anomalies and timeline of `log`, the filtered lines of the file at `path`.
+pub fn prompt(path: &str, question: Option<&str>, log: &Filtered) -> String {
+    let mut prompt = format!(
+        "Analyze the log below, from {}. Find the errors, warnings and unusual patterns in it, such as bursts of \
+         failures, retries, slowdowns and gaps, and reconstruct the timeline of what happened. Lines marked \
+         `[repeated N more times]` stand for that many further lines like them, differing only in numbers.",
+        path
+    );
+    if log.filtered_lines + log.duplicate_lines + log.dropped_lines > 0 {
+        prompt.push_str(&format!(
+            " The log was filtered before you see it: {} of its {} lines are shown.",
+            log.kept_lines, log.total_lines
+        ));
+    }
+    if let Some(question) = question.filter(|question| !question.trim().is_empty()) {
+        prompt.push_str(&format!("\n\n{}", question.trim()));
+    }
+    prompt.push_str(&format!(
+        "\n\nAnswer with a JSON object {{\"summary\": ..., \"anomalies\": [{{\"description\": ..., \"severity\": ..., \
+         \"evidence\": ...}}], \"timeline\": [{{\"time\": ..., \"event\": ...}}]}}: a summary of one short paragraph; \
+         the anomalies, most severe first, each with a severity of critical, error, warning or info and the log \
+         line showing it, quoted exactly; and the notable events in order, with their timestamps as the log \
+         writes them.\n\n<log>\n{}\n</log>",
+        log.text
+    ));
+    prompt
+}
+
+/// The summary in Gemini's answer to [`prompt`].
+pub fn parse(answer: &str) -> Result<LogSummary, InvalidOutputError> {
+    let value = format::parse_json(answer)?;
+    serde_json::from_value(value).map_err(|e| InvalidOutputError {
+        reason: format!("expected {{\"summary\", \"anomalies\", \"timeline\"}}: {}", e),
+    })
+}
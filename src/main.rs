@@ -1,184 +1,4069 @@
-use anyhow::{Context, Result};
+mod annotations;
+mod args;
+mod audit;
+mod auth;
+mod backend;
+mod bench;
+mod breaker;
+mod bridge;
+mod changelog;
+mod children;
+mod chunking;
+mod clientlog;
+mod codegen;
+mod commit;
+mod completion;
+mod config;
+mod continuation;
+mod crosscheck;
+mod diff;
+mod docker;
+mod doctor;
+mod errors;
+mod fetch;
+mod files;
+mod format;
+mod functions;
+mod git;
+mod i18n;
+mod instructions;
+mod launcher;
+mod logfile;
+mod logs;
+mod mapreduce;
+mod memory;
+mod metrics;
+mod models;
+mod pii;
+mod pipeline;
+mod policy;
+mod postprocess;
+mod pr;
+mod progress;
+mod prompts;
+mod ratelimit;
+mod recording;
+mod redact;
+mod reload;
+mod request_id;
+mod resources;
+mod retry;
+mod roots;
+mod review;
+mod safety;
+mod sampling;
+mod scheduler;
+mod session;
+mod ssh;
+mod toggles;
+mod tokens;
+mod traces;
+mod usage;
+mod transport;
+mod websearch;
+
+use anyhow::Result;
+use base64::Engine;
 use rmcp::{
-    tool, tool_handler, tool_router,
-    ServerHandler, ServiceExt,
-    handler::server::{router::tool::ToolRouter, tool::Parameters},
+    tool, tool_router,
+    RoleServer, ServerHandler,
+    handler::server::{router::tool::ToolRouter, tool::{Parameters, ToolCallContext}},
     model::*,
+    service::RequestContext,
     Error as McpError,
 };
+use futures::StreamExt;
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::future::Future;
-use tracing_subscriber::EnvFilter;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::Instrument;
+use tracing_subscriber::{prelude::*, EnvFilter};
+
+use audit::{AuditEntry, AuditLog, AuditQuery};
+use breaker::CircuitBreaker;
+use bridge::Bridge;
+use backend::{
+    Backend, CancelledError, CliBackend, ExecOptions, GenerateRequest, GenerateResponse, ImageRequest,
+    CliOnlyParamsError, UnsupportedParamsError, Usage,
+};
+use args::{Command, ConfigCommand};
+use clap::Parser;
+use config::{ChatHistory, Config, Flags, PromptOverflow, Recording};
+use clientlog::{ClientLog, ClientLogLayer};
+use continuation::{Continuation, ContinuationStore};
+use errors::{command_error, ErrorKind};
+use fetch::Fetcher;
+use format::{Formatted, OutputFormat};
+use functions::{FunctionDeclaration, FunctionResult};
+use progress::ProgressReporter;
+use metrics::Metrics;
+use pii::PiiFilter;
+use pipeline::{Pipeline, PipelineStep};
+use postprocess::PostProcessing;
+use prompts::PromptLibrary;
+use ratelimit::{RateLimitPolicy, RateLimiter};
+use recording::Recorder;
+use resources::{ResourceNotifier, Subscriber};
+use retry::RetryPolicy;
+use roots::Scope;
+use safety::{SafetyBlock, SafetySettings};
+use scheduler::{Priority, Scheduler};
+use session::{Session, SessionStore};
+use reload::{LiveSettings, Reloadable};
+use toggles::ToolToggles;
+use tokens::PromptTooLargeError;
+use transport::Transport;
+use usage::UsageTracker;
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiPromptArgs {
+    #[schemars(description = "The prompt to send to Gemini")]
+    prompt: String,
+    #[schemars(description = "The model to use (optional)")]
+    #[serde(default)]
+    model: Option<String>,
+    #[schemars(description = "Maximum number of tokens (optional)")]
+    #[serde(default)]
+    max_tokens: Option<u32>,
+    #[schemars(description = "Temperature for sampling (optional)")]
+    #[serde(default)]
+    temperature: Option<f32>,
+    #[schemars(description = "Only sample from the most likely tokens whose probabilities add up to this, between 0 and 1 (optional)")]
+    #[serde(default)]
+    top_p: Option<f32>,
+    #[schemars(description = "Only sample from this many of the most likely tokens (optional)")]
+    #[serde(default)]
+    top_k: Option<u32>,
+    #[schemars(description = "Stop generating at the first of these strings, which is left out of the answer (optional, at most 5)")]
+    #[serde(default)]
+    stop_sequences: Vec<String>,
+    #[schemars(description = "Seed for sampling, to make answers repeatable as far as the model allows (optional)")]
+    #[serde(default)]
+    seed: Option<i32>,
+    #[schemars(description = "Functions Gemini may call instead of answering; its calls are returned as function_calls for the client to execute (optional, needs the Gemini API)")]
+    #[serde(default)]
+    functions: Vec<FunctionDeclaration>,
+    #[schemars(description = "Results of the function_calls a previous call with the same prompt and functions returned, to let Gemini finish its answer (optional)")]
+    #[serde(default)]
+    function_results: Vec<FunctionResult>,
+    #[schemars(description = "Timeout in seconds for this request (optional, overrides the configured timeout)")]
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    #[schemars(description = "Auth profile from the config to run the request as (optional, overrides default_profile)")]
+    #[serde(default)]
+    profile: Option<String>,
+    #[schemars(description = "Place in the queue when the server is busy: low, normal or high (optional, default normal)")]
+    #[serde(default)]
+    priority: Option<Priority>,
+    #[schemars(description = "System instruction passed to Gemini as a system prompt (optional, overrides the configured default)")]
+    #[serde(default)]
+    system_instruction: Option<String>,
+    #[schemars(description = "Per-category safety thresholds (optional, api backend only)")]
+    #[serde(default)]
+    safety_settings: Option<SafetySettings>,
+    #[schemars(description = "Paths of text files the server reads and attaches to the prompt; glob patterns such as \"src/**/*.rs\" are expanded, honoring .gitignore (optional)")]
+    #[serde(default)]
+    files: Vec<String>,
+    #[schemars(description = "Images to send with the prompt, each a file path, a data: URL or raw base64 (optional; PNG, JPEG, WebP, HEIC or HEIF)")]
+    #[serde(default)]
+    images: Vec<String>,
+    #[schemars(description = "Run the gemini CLI in its container sandbox (optional, overrides the configured sandbox setting)")]
+    #[serde(default)]
+    sandbox: Option<bool>,
+    #[schemars(description = "Directory the gemini CLI runs in, usually the project the prompt is about; relative files and images are resolved against it (optional)")]
+    #[serde(default)]
+    cwd: Option<String>,
+    #[schemars(description = "Have the gemini CLI read every file in cwd into the context, for questions about a whole repository (optional, default false, cli backend only)")]
+    #[serde(default)]
+    all_files: bool,
+    #[schemars(description = "Directories outside cwd the gemini CLI may read, resolved against cwd (optional, cli backend only)")]
+    #[serde(default)]
+    include_directories: Vec<String>,
+    #[schemars(description = "Return the exact gemini command (binary, args, env, cwd) instead of running it (optional, default false)")]
+    #[serde(default)]
+    dry_run: bool,
+    #[schemars(description = "How to return the answer: markdown as Gemini writes it, text with Markdown removed, or json, parsed and validated by the server (optional, default markdown)")]
+    #[serde(default)]
+    output_format: OutputFormat,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiChatArgs {
+    #[schemars(description = "The message to send to Gemini")]
+    message: String,
+    #[schemars(description = "Session id returned by a previous gemini_chat call (optional, starts a new session when omitted)")]
+    #[serde(default)]
+    session_id: Option<String>,
+    #[schemars(description = "The model to use (optional, defaults to the session's model)")]
+    #[serde(default)]
+    model: Option<String>,
+    #[schemars(description = "Timeout in seconds for this request (optional, overrides the configured timeout)")]
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    #[schemars(description = "Auth profile from the config to run the request as (optional, overrides default_profile)")]
+    #[serde(default)]
+    profile: Option<String>,
+    #[schemars(description = "Place in the queue when the server is busy: low, normal or high (optional, default high)")]
+    #[serde(default)]
+    priority: Option<Priority>,
+    #[schemars(description = "Directory the gemini CLI runs in for this turn (optional)")]
+    #[serde(default)]
+    cwd: Option<String>,
+    #[schemars(description = "Return the exact gemini command instead of running it; the session is left unchanged (optional, default false)")]
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiBatchPromptArgs {
+    #[schemars(description = "The prompts to send to Gemini, each answered independently")]
+    prompts: Vec<String>,
+    #[schemars(description = "The model to use for every prompt (optional)")]
+    #[serde(default)]
+    model: Option<String>,
+    #[schemars(description = "System instruction applied to every prompt (optional, overrides the configured default)")]
+    #[serde(default)]
+    system_instruction: Option<String>,
+    #[schemars(description = "How many prompts may run at once (optional, defaults to and is capped at the server's max_concurrency)")]
+    #[serde(default)]
+    max_parallel: Option<usize>,
+    #[schemars(description = "Timeout in seconds for each prompt (optional, overrides the configured timeout)")]
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    #[schemars(description = "Auth profile from the config to run the request as (optional, overrides default_profile)")]
+    #[serde(default)]
+    profile: Option<String>,
+    #[schemars(description = "Place in the queue of every prompt when the server is busy: low, normal or high (optional, default low)")]
+    #[serde(default)]
+    priority: Option<Priority>,
+    #[schemars(description = "How to return each answer: markdown, text or json, as in gemini_prompt (optional, default markdown)")]
+    #[serde(default)]
+    output_format: OutputFormat,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiCompareModelsArgs {
+    #[schemars(description = "The prompt to send to every model")]
+    prompt: String,
+    #[schemars(description = "The models to compare, 2 to 4 (e.g. [\"gemini-2.5-pro\", \"gemini-2.5-flash\"])")]
+    models: Vec<String>,
+    #[schemars(description = "System instruction sent to every model (optional, overrides the configured default)")]
+    #[serde(default)]
+    system_instruction: Option<String>,
+    #[schemars(description = "Timeout in seconds for each model (optional, overrides the configured timeout)")]
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    #[schemars(description = "Auth profile from the config to run the request as (optional, overrides default_profile)")]
+    #[serde(default)]
+    profile: Option<String>,
+    #[schemars(description = "Place in the queue when the server is busy: low, normal or high (optional, default normal)")]
+    #[serde(default)]
+    priority: Option<Priority>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiReviewDiffArgs {
+    #[schemars(description = "Path of the git repository to review")]
+    repo_path: String,
+    #[schemars(description = "Review staged changes instead of unstaged ones (optional, default false)")]
+    #[serde(default)]
+    staged: bool,
+    #[schemars(description = "Revision range to diff instead, e.g. \"main...HEAD\" (optional, takes precedence over staged)")]
+    #[serde(default)]
+    range: Option<String>,
+    #[schemars(description = "Only review changes to these paths (optional)")]
+    #[serde(default)]
+    paths: Vec<String>,
+    #[schemars(description = "The model to use (optional)")]
+    #[serde(default)]
+    model: Option<String>,
+    #[schemars(description = "Timeout in seconds for each review request (optional, overrides the configured timeout)")]
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    #[schemars(description = "Auth profile from the config to run the request as (optional, overrides default_profile)")]
+    #[serde(default)]
+    profile: Option<String>,
+    #[schemars(description = "Place in the queue when the server is busy: low, normal or high (optional, default normal)")]
+    #[serde(default)]
+    priority: Option<Priority>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiSearchGroundedArgs {
+    #[schemars(description = "The question to answer using Google Search results")]
+    query: String,
+    #[schemars(description = "The model to use (optional)")]
+    #[serde(default)]
+    model: Option<String>,
+    #[schemars(description = "Timeout in seconds for this request (optional, overrides the configured timeout)")]
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    #[schemars(description = "Auth profile from the config to run the request as (optional, overrides default_profile)")]
+    #[serde(default)]
+    profile: Option<String>,
+    #[schemars(description = "Place in the queue when the server is busy: low, normal or high (optional, default normal)")]
+    #[serde(default)]
+    priority: Option<Priority>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiGenerateImageArgs {
+    #[schemars(description = "Description of the image to generate")]
+    prompt: String,
+    #[schemars(description = "The Imagen model to use (optional, defaults to the configured image_model)")]
+    #[serde(default)]
+    model: Option<String>,
+    #[schemars(description = "How many images to generate, 1 to 4 (optional, default 1)")]
+    #[serde(default)]
+    number_of_images: Option<u32>,
+    #[schemars(description = "Aspect ratio: 1:1, 3:4, 4:3, 9:16 or 16:9 (optional)")]
+    #[serde(default)]
+    aspect_ratio: Option<String>,
+    #[schemars(description = "Place in the queue when the server is busy: low, normal or high (optional, default normal)")]
+    #[serde(default)]
+    priority: Option<Priority>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiCountTokensArgs {
+    #[schemars(description = "The text to count tokens for (prompt plus any file context)")]
+    text: String,
+    #[schemars(description = "The model whose tokenizer and context window to use (optional)")]
+    #[serde(default)]
+    model: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiUsageReportArgs {
+    #[schemars(description = "How many days of history to include, counting today (optional, default 7)")]
+    #[serde(default)]
+    days: Option<u32>,
+    #[schemars(description = "Include totals for this gemini_chat session (optional)")]
+    #[serde(default)]
+    session_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiDoctorArgs {
+    #[schemars(description = "Skip sending a test prompt to Gemini (optional, default false)")]
+    #[serde(default)]
+    skip_prompt: bool,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiConfigArgs {
+    #[schemars(description = "API key for Gemini (optional)")]
+    #[serde(default)]
+    api_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiFetchContinuationArgs {
+    #[schemars(description = "The continuation token from a truncated result")]
+    token: String,
+    #[schemars(description = "Most bytes to return (optional, defaults to the configured max_response_bytes)")]
+    #[serde(default)]
+    max_bytes: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiResumeArgs {
+    #[schemars(description = "Id or index of the CLI conversation to continue, or \"latest\" (optional; lists the resumable conversations when omitted)")]
+    #[serde(default)]
+    conversation_id: Option<String>,
+    #[schemars(description = "The message to continue the conversation with (required with conversation_id)")]
+    #[serde(default)]
+    message: Option<String>,
+    #[schemars(description = "Project directory the conversation was started in; the CLI keeps conversations per directory (optional, defaults to the server's working directory)")]
+    #[serde(default)]
+    cwd: Option<String>,
+    #[schemars(description = "The model to use (optional)")]
+    #[serde(default)]
+    model: Option<String>,
+    #[schemars(description = "Timeout in seconds for this request (optional, overrides the configured timeout)")]
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    #[schemars(description = "Auth profile from the config to run the request as (optional, overrides default_profile)")]
+    #[serde(default)]
+    profile: Option<String>,
+    #[schemars(description = "Place in the queue when the server is busy: low, normal or high (optional, default normal)")]
+    #[serde(default)]
+    priority: Option<Priority>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiAuditSearchArgs {
+    #[schemars(description = "Text to find in the recorded prompts and responses, case-insensitive (optional)")]
+    #[serde(default)]
+    query: Option<String>,
+    #[schemars(description = "Only calls of this tool, e.g. \"gemini_prompt\" (optional)")]
+    #[serde(default)]
+    tool: Option<String>,
+    #[schemars(description = "Only the call with this request_id, as its result reported (optional)")]
+    #[serde(default)]
+    request_id: Option<String>,
+    #[schemars(description = "Only calls with this status: ok, tool_error or error (optional)")]
+    #[serde(default)]
+    status: Option<String>,
+    #[schemars(description = "Only calls at or after this time, as an RFC 3339 timestamp or a YYYY-MM-DD date in UTC (optional)")]
+    #[serde(default)]
+    since: Option<String>,
+    #[schemars(description = "Most entries to return, newest first (optional, default 50, at most 500)")]
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiCrossCheckArgs {
+    #[schemars(description = "The question or task for Gemini")]
+    prompt: String,
+    #[schemars(description = "How many times the client's model may critique and Gemini revise, 1 to 3 (optional, default 1)")]
+    #[serde(default)]
+    rounds: Option<u32>,
+    #[schemars(description = "The model to use (optional)")]
+    #[serde(default)]
+    model: Option<String>,
+    #[schemars(description = "Timeout in seconds for each Gemini request (optional, overrides the configured timeout)")]
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    #[schemars(description = "Auth profile from the config to run the request as (optional, overrides default_profile)")]
+    #[serde(default)]
+    profile: Option<String>,
+    #[schemars(description = "Place in the queue when the server is busy: low, normal or high (optional, default normal)")]
+    #[serde(default)]
+    priority: Option<Priority>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiSummarizeUrlArgs {
+    #[schemars(description = "The http or https URL of the page to summarize")]
+    url: String,
+    #[schemars(description = "What the summary should concentrate on, e.g. \"breaking changes\" (optional)")]
+    #[serde(default)]
+    focus: Option<String>,
+    #[schemars(description = "The model to use (optional)")]
+    #[serde(default)]
+    model: Option<String>,
+    #[schemars(description = "Timeout in seconds for the Gemini request (optional, overrides the configured timeout)")]
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    #[schemars(description = "Auth profile from the config to run the request as (optional, overrides default_profile)")]
+    #[serde(default)]
+    profile: Option<String>,
+    #[schemars(description = "Place in the queue when the server is busy: low, normal or high (optional, default normal)")]
+    #[serde(default)]
+    priority: Option<Priority>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiWebSearchArgs {
+    #[schemars(description = "The question to search the web for")]
+    query: String,
+    #[schemars(description = "The model to use (optional)")]
+    #[serde(default)]
+    model: Option<String>,
+    #[schemars(description = "Timeout in seconds for this request (optional, overrides the configured timeout)")]
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    #[schemars(description = "Auth profile from the config to run the request as (optional, overrides default_profile)")]
+    #[serde(default)]
+    profile: Option<String>,
+    #[schemars(description = "Place in the queue when the server is busy: low, normal or high (optional, default normal)")]
+    #[serde(default)]
+    priority: Option<Priority>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiListExtensionsArgs {
+    #[schemars(description = "Project directory whose workspace extension settings apply (optional, defaults to the server's working directory)")]
+    #[serde(default)]
+    cwd: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiInstallExtensionArgs {
+    #[schemars(description = "Git repository URL or local path of the extension to install")]
+    source: String,
+    #[schemars(description = "Git branch, tag or commit to install (optional)")]
+    #[serde(default, rename = "ref")]
+    git_ref: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiEnableExtensionArgs {
+    #[schemars(description = "Name of the installed extension")]
+    name: String,
+    #[schemars(description = "Enable the extension, or disable it with false (optional, default true)")]
+    #[serde(default)]
+    enabled: Option<bool>,
+    #[schemars(description = "Where the setting applies: \"user\" for every project or \"workspace\" for cwd only (optional, default user)")]
+    #[serde(default)]
+    scope: Option<String>,
+    #[schemars(description = "Project directory for the workspace scope (optional, defaults to the server's working directory)")]
+    #[serde(default)]
+    cwd: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiPipelineArgs {
+    #[schemars(description = "The input the steps work on, available to every step's template as {{input}}")]
+    input: String,
+    #[schemars(description = "The steps to run in order, each with a prompt template rendered from the outputs before it (at most 10)")]
+    steps: Vec<PipelineStep>,
+    #[schemars(description = "The model for steps that don't name their own (optional)")]
+    #[serde(default)]
+    model: Option<String>,
+    #[schemars(description = "Timeout in seconds for each step (optional, overrides the configured timeout)")]
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    #[schemars(description = "Auth profile from the config to run the requests as (optional, overrides default_profile)")]
+    #[serde(default)]
+    profile: Option<String>,
+    #[schemars(description = "Place in the queue when the server is busy: low, normal or high (optional, default normal)")]
+    #[serde(default)]
+    priority: Option<Priority>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiSummarizeFilesArgs {
+    #[schemars(description = "Paths or glob patterns of the text files to summarize, e.g. [\"src/**/*.rs\", \"README.md\"]")]
+    files: Vec<String>,
+    #[schemars(description = "What the summary should concentrate on, e.g. \"the public API\" (optional)")]
+    #[serde(default)]
+    focus: Option<String>,
+    #[schemars(description = "Directory relative paths and globs are resolved against (optional)")]
+    #[serde(default)]
+    cwd: Option<String>,
+    #[schemars(description = "Model summarizing each file or chunk (optional, default gemini-2.5-flash)")]
+    #[serde(default)]
+    map_model: Option<String>,
+    #[schemars(description = "Model combining the partial summaries (optional, default gemini-2.5-pro)")]
+    #[serde(default)]
+    reduce_model: Option<String>,
+    #[schemars(description = "Estimated tokens per chunk; larger files are split at line breaks (optional, default 100000)")]
+    #[serde(default)]
+    chunk_tokens: Option<u32>,
+    #[schemars(description = "How many chunks are summarized at once (optional, defaults to and is capped at the server's max_concurrency)")]
+    #[serde(default)]
+    max_parallel: Option<usize>,
+    #[schemars(description = "Timeout in seconds for each Gemini request (optional, overrides the configured timeout)")]
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    #[schemars(description = "Auth profile from the config to run the requests as (optional, overrides default_profile)")]
+    #[serde(default)]
+    profile: Option<String>,
+    #[schemars(description = "Place in the queue when the server is busy: low, normal or high (optional, default normal)")]
+    #[serde(default)]
+    priority: Option<Priority>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiGenerateFileArgs {
+    #[schemars(description = "Path of the file to generate or rewrite")]
+    path: String,
+    #[schemars(description = "What the file should contain, or how to change it when it exists")]
+    instructions: String,
+    #[schemars(description = "Paths or glob patterns of files Gemini should see for reference, e.g. [\"src/lib.rs\"] (optional)")]
+    #[serde(default)]
+    files: Vec<String>,
+    #[schemars(description = "Write the result to path, backing up a file already there (optional, default false: only return it)")]
+    #[serde(default)]
+    write: bool,
+    #[schemars(description = "Directory path and files are resolved against (optional)")]
+    #[serde(default)]
+    cwd: Option<String>,
+    #[schemars(description = "The Gemini model to use (optional)")]
+    #[serde(default)]
+    model: Option<String>,
+    #[schemars(description = "Timeout in seconds for this call (optional, overrides the configured timeout)")]
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    #[schemars(description = "Auth profile from the config to run as (optional, overrides default_profile)")]
+    #[serde(default)]
+    profile: Option<String>,
+    #[schemars(description = "Place in the queue when the server is busy: low, normal or high (optional, default normal)")]
+    #[serde(default)]
+    priority: Option<Priority>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiProposePatchArgs {
+    #[schemars(description = "The change to make, e.g. \"rename Config::load to Config::read\" (required unless patch is given)")]
+    #[serde(default)]
+    instructions: Option<String>,
+    #[schemars(description = "Paths or glob patterns of the files Gemini should change or needs to see (required unless patch is given)")]
+    #[serde(default)]
+    files: Vec<String>,
+    #[schemars(description = "A diff returned by an earlier call, to check or apply instead of asking Gemini again (optional)")]
+    #[serde(default)]
+    patch: Option<String>,
+    #[schemars(description = "Apply the patch when every file applies cleanly, backing up the changed files (optional, default false: only check it)")]
+    #[serde(default)]
+    apply: bool,
+    #[schemars(description = "Directory the files and the diff's paths are resolved against (optional)")]
+    #[serde(default)]
+    cwd: Option<String>,
+    #[schemars(description = "The Gemini model to use (optional)")]
+    #[serde(default)]
+    model: Option<String>,
+    #[schemars(description = "Timeout in seconds for this call (optional, overrides the configured timeout)")]
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    #[schemars(description = "Auth profile from the config to run as (optional, overrides default_profile)")]
+    #[serde(default)]
+    profile: Option<String>,
+    #[schemars(description = "Place in the queue when the server is busy: low, normal or high (optional, default normal)")]
+    #[serde(default)]
+    priority: Option<Priority>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiGenerateTestsArgs {
+    #[schemars(description = "Paths or glob patterns of the source files to test")]
+    files: Vec<String>,
+    #[schemars(description = "Test framework to use, e.g. \"pytest\" or \"vitest\" (optional, detected from the project's manifests)")]
+    #[serde(default)]
+    framework: Option<String>,
+    #[schemars(description = "Paths or glob patterns of related files Gemini should see, such as existing tests or the types the sources use (optional)")]
+    #[serde(default)]
+    context_files: Vec<String>,
+    #[schemars(description = "Further instructions, e.g. \"only test the parser\" (optional)")]
+    #[serde(default)]
+    instructions: Option<String>,
+    #[schemars(description = "Write the test files, backing up any already there (optional, default false: only return them)")]
+    #[serde(default)]
+    write: bool,
+    #[schemars(description = "Project directory the files and the test paths are resolved against (optional)")]
+    #[serde(default)]
+    cwd: Option<String>,
+    #[schemars(description = "The Gemini model to use (optional)")]
+    #[serde(default)]
+    model: Option<String>,
+    #[schemars(description = "Timeout in seconds for this call (optional, overrides the configured timeout)")]
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    #[schemars(description = "Auth profile from the config to run as (optional, overrides default_profile)")]
+    #[serde(default)]
+    profile: Option<String>,
+    #[schemars(description = "Place in the queue when the server is busy: low, normal or high (optional, default normal)")]
+    #[serde(default)]
+    priority: Option<Priority>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiCommitMessageArgs {
+    #[schemars(description = "Path of the git repository whose staged changes to describe")]
+    repo_path: String,
+    #[schemars(description = "Message convention: conventional, gitmoji or plain (optional, defaults to the configured commit_convention)")]
+    #[serde(default)]
+    convention: Option<commit::Convention>,
+    #[schemars(description = "The project's own commit message rules, e.g. \"reference the issue in a Refs: footer\" (optional, defaults to the configured commit_guidelines)")]
+    #[serde(default)]
+    guidelines: Option<String>,
+    #[schemars(description = "The Gemini model to use (optional)")]
+    #[serde(default)]
+    model: Option<String>,
+    #[schemars(description = "Timeout in seconds for each request (optional, overrides the configured timeout)")]
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    #[schemars(description = "Auth profile from the config to run as (optional, overrides default_profile)")]
+    #[serde(default)]
+    profile: Option<String>,
+    #[schemars(description = "Place in the queue when the server is busy: low, normal or high (optional, default normal)")]
+    #[serde(default)]
+    priority: Option<Priority>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiPrDescriptionArgs {
+    #[schemars(description = "Path of the git repository")]
+    repo_path: String,
+    #[schemars(description = "Branch the pull request merges into, e.g. \"main\"")]
+    base: String,
+    #[schemars(description = "Branch or commit with the changes (optional, default HEAD)")]
+    #[serde(default)]
+    head: Option<String>,
+    #[schemars(description = "Further context, e.g. the issue the change fixes (optional)")]
+    #[serde(default)]
+    instructions: Option<String>,
+    #[schemars(description = "The Gemini model to use (optional)")]
+    #[serde(default)]
+    model: Option<String>,
+    #[schemars(description = "Timeout in seconds for this call (optional, overrides the configured timeout)")]
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    #[schemars(description = "Auth profile from the config to run as (optional, overrides default_profile)")]
+    #[serde(default)]
+    profile: Option<String>,
+    #[schemars(description = "Place in the queue when the server is busy: low, normal or high (optional, default normal)")]
+    #[serde(default)]
+    priority: Option<Priority>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiChangelogArgs {
+    #[schemars(description = "Path of the git repository")]
+    repo_path: String,
+    #[schemars(description = "The last release, usually its tag, e.g. \"v1.2.0\"; its commits are left out")]
+    from: String,
+    #[schemars(description = "The revision being released (optional, default HEAD)")]
+    #[serde(default)]
+    to: Option<String>,
+    #[schemars(description = "Version for the heading, e.g. \"1.3.0\" (optional, defaults to to when it is a tag, otherwise Unreleased)")]
+    #[serde(default)]
+    version: Option<String>,
+    #[schemars(description = "Layout: keep-a-changelog, github or conventional (optional, defaults to the configured changelog_format)")]
+    #[serde(default)]
+    format: Option<changelog::ChangelogFormat>,
+    #[schemars(description = "Further instructions, e.g. \"mention the minimum supported Rust version\" (optional)")]
+    #[serde(default)]
+    instructions: Option<String>,
+    #[schemars(description = "The Gemini model to use (optional)")]
+    #[serde(default)]
+    model: Option<String>,
+    #[schemars(description = "Timeout in seconds for this call (optional, overrides the configured timeout)")]
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    #[schemars(description = "Auth profile from the config to run as (optional, overrides default_profile)")]
+    #[serde(default)]
+    profile: Option<String>,
+    #[schemars(description = "Place in the queue when the server is busy: low, normal or high (optional, default normal)")]
+    #[serde(default)]
+    priority: Option<Priority>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiExplainErrorArgs {
+    #[schemars(description = "The stack trace, panic message or compiler error, as printed")]
+    error: String,
+    #[schemars(description = "Paths or glob patterns of further source files to include in full (optional; the lines the error refers to are included automatically)")]
+    #[serde(default)]
+    files: Vec<String>,
+    #[schemars(description = "What else to know, e.g. \"this started after upgrading tokio\" (optional)")]
+    #[serde(default)]
+    question: Option<String>,
+    #[schemars(description = "Directory the paths in the error and files are resolved against (optional)")]
+    #[serde(default)]
+    cwd: Option<String>,
+    #[schemars(description = "The Gemini model to use (optional)")]
+    #[serde(default)]
+    model: Option<String>,
+    #[schemars(description = "Timeout in seconds for this call (optional, overrides the configured timeout)")]
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    #[schemars(description = "Auth profile from the config to run as (optional, overrides default_profile)")]
+    #[serde(default)]
+    profile: Option<String>,
+    #[schemars(description = "Place in the queue when the server is busy: low, normal or high (optional, default normal)")]
+    #[serde(default)]
+    priority: Option<Priority>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiSummarizeLogsArgs {
+    #[schemars(description = "Path to the log file")]
+    path: String,
+    #[schemars(description = "Regular expression; only lines matching it are kept (optional)")]
+    #[serde(default)]
+    grep: Option<String>,
+    #[schemars(description = "Regular expression; lines matching it are dropped, e.g. \"DEBUG|health check\" (optional)")]
+    #[serde(default)]
+    exclude: Option<String>,
+    #[schemars(description = "Fold lines that differ only in numbers such as timestamps and ids into one (optional, default: true)")]
+    #[serde(default)]
+    dedup: Option<bool>,
+    #[schemars(description = "Keep only the last N lines after the other filters (optional)")]
+    #[serde(default)]
+    tail: Option<usize>,
+    #[schemars(description = "What to look for, e.g. \"why did the 14:00 deploy fail\" (optional)")]
+    #[serde(default)]
+    question: Option<String>,
+    #[schemars(description = "Directory a relative path is resolved against (optional)")]
+    #[serde(default)]
+    cwd: Option<String>,
+    #[schemars(description = "The Gemini model to use (optional)")]
+    #[serde(default)]
+    model: Option<String>,
+    #[schemars(description = "Timeout in seconds for this call (optional, overrides the configured timeout)")]
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    #[schemars(description = "Auth profile from the config to run as (optional, overrides default_profile)")]
+    #[serde(default)]
+    profile: Option<String>,
+    #[schemars(description = "Place in the queue when the server is busy: low, normal or high (optional, default normal)")]
+    #[serde(default)]
+    priority: Option<Priority>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiSessionsListArgs {
+    #[schemars(description = "Most sessions to return, most recently used first (optional, default: all)")]
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiSessionGetArgs {
+    #[schemars(description = "Session id returned by gemini_chat")]
+    session_id: String,
+    #[schemars(description = "Include the session's messages, not only its metadata (optional, default true)")]
+    #[serde(default)]
+    include_messages: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiSessionDeleteArgs {
+    #[schemars(description = "Session id returned by gemini_chat")]
+    session_id: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GeminiSessionRenameArgs {
+    #[schemars(description = "Session id returned by gemini_chat")]
+    session_id: String,
+    #[schemars(description = "The session's new title (optional, removes the title when omitted or empty)")]
+    #[serde(default)]
+    title: Option<String>,
+}
+
+/// Most prompts `gemini_batch_prompt` accepts in one call.
+const MAX_BATCH_SIZE: usize = 100;
+
+/// Most entries `gemini_audit_search` returns.
+const MAX_AUDIT_RESULTS: usize = 500;
+
+/// Most `stop_sequences` Gemini accepts.
+const MAX_STOP_SEQUENCES: usize = 5;
+
+/// Per-call settings for [`GeminiServer::run_generation`].
+#[derive(Debug, Default, Clone, Copy)]
+struct CallOptions<'a> {
+    /// The tool making the call, whose `tools` overrides apply.
+    tool: &'a str,
+    timeout_secs: Option<u64>,
+    /// Chat session the call belongs to, for usage accounting.
+    session_id: Option<&'a str>,
+    /// Where to report progress instead of the request's own progress token.
+    progress: Option<&'a ProgressReporter>,
+    /// Never fall back to another model, e.g. when comparing models.
+    pin_model: bool,
+    /// Describe the command instead of running it; also forced by the `dry_run` setting.
+    dry_run: bool,
+    /// Auth profile to run as instead of `default_profile`.
+    profile: Option<&'a str>,
+    /// Place in the queue instead of the tool's priority.
+    priority: Option<Priority>,
+}
+
+/// How much of its model's context window a request may use.
+struct PromptBudget {
+    input_token_limit: u32,
+    /// Tokens kept free for the answer.
+    reserved_tokens: u32,
+    /// The most the prompt, system instruction and images may take together.
+    max_prompt_tokens: u32,
+    /// What the system instruction and images take.
+    other_tokens: u32,
+}
+
+#[derive(Clone)]
+struct GeminiServer {
+    tool_router: ToolRouter<Self>,
+    config: Arc<Config>,
+    backend: Arc<Backend>,
+    scheduler: Arc<Scheduler>,
+    /// Concurrency limits of tools with a `max_concurrency` override.
+    tool_limits: Arc<BTreeMap<String, Scheduler>>,
+    rate_limiter: Arc<RateLimiter>,
+    /// Fails requests at once while the backend keeps failing.
+    breaker: Arc<CircuitBreaker>,
+    sessions: Arc<SessionStore>,
+    continuations: Arc<ContinuationStore>,
+    model_names: Arc<completion::ModelNames>,
+    usage: Arc<UsageTracker>,
+    metrics: Arc<Metrics>,
+    /// Record of every tool call, when `audit_log` is set.
+    audit: Option<Arc<AuditLog>>,
+    /// Tools proxied from gemini's own MCP servers, when `bridge` is on.
+    bridge: Option<Arc<Bridge>>,
+    fetcher: Arc<Fetcher>,
+    /// Replaced when the prompts directory changes; see [`Self::prompts`].
+    prompts: Arc<RwLock<Arc<PromptLibrary>>>,
+    /// Timeouts, default model and model aliases, kept current with the config file.
+    live: Arc<LiveSettings>,
+    /// Sent to clients in `initialize`, from `instructions_file` or built in.
+    instructions: Arc<str>,
+    resources: Arc<ResourceNotifier>,
+    /// The `postprocess` steps answers go through.
+    postprocessing: Arc<PostProcessing>,
+    /// Scrubs personal data from prompts, when `pii_filter` is on.
+    pii: Option<Arc<PiiFilter>>,
+    /// Records answers or replays them, when `recording` is on.
+    recorder: Option<Arc<Recorder>>,
+    /// Tools switched off under `[tools]`, kept current with the config file.
+    toggles: Arc<ToolToggles>,
+    /// This connection's resource subscriptions.
+    subscriber: Arc<Subscriber>,
+    /// The log level this connection set, if any.
+    client_log: Arc<ClientLog>,
+}
+
+#[tool_router]
+impl GeminiServer {
+    fn new(config: Config, backend: Backend, instructions: String, audit: Option<AuditLog>, bridge: Option<Bridge>) -> Self {
+        let resources = Arc::new(ResourceNotifier::new());
+        Self {
+            subscriber: resources.register(),
+            client_log: ClientLog::register(),
+            resources,
+            tool_router: Self::tool_router(),
+            backend: Arc::new(backend),
+            scheduler: Arc::new(Scheduler::new(config.max_concurrency)),
+            tool_limits: Arc::new(config.tools.iter()
+                .filter_map(|(tool, overrides)| Some((tool.clone(), Scheduler::new(overrides.max_concurrency?))))
+                .collect()),
+            rate_limiter: Arc::new(RateLimiter::new(config.rate_limits.clone())),
+            breaker: Arc::new(CircuitBreaker::new(
+                config.circuit_breaker_failures,
+                Duration::from_secs(config.circuit_breaker_cooldown_secs),
+            )),
+            usage: Arc::new(UsageTracker::open(config.usage_path())),
+            metrics: Arc::new(Metrics::new()),
+            audit: audit.map(Arc::new),
+            bridge: bridge.map(Arc::new),
+            fetcher: Arc::new(Fetcher::new(&config)),
+            prompts: Arc::new(RwLock::new(Arc::new(PromptLibrary::load(config.prompts_path().as_deref())))),
+            live: Arc::new(LiveSettings::new(&config)),
+            instructions: instructions.into(),
+            toggles: Arc::new(ToolToggles::new(&config)),
+            postprocessing: Arc::new(PostProcessing::from_config(&config).expect("postprocess patterns were checked when loading the config")),
+            pii: PiiFilter::from_config(&config).expect("pii_patterns were checked when loading the config").map(Arc::new),
+            recorder: Recorder::from_config(&config).expect("recordings_dir was checked when loading the config").map(Arc::new),
+            sessions: Arc::new(SessionStore::open(config.sessions_path(), session::Limits {
+                ttl: Some(Duration::from_secs(config.session_ttl_secs)).filter(|ttl| !ttl.is_zero()),
+                max_bytes: Some(config.max_session_bytes).filter(|max| *max > 0),
+            })),
+            config: Arc::new(config),
+            continuations: Arc::new(ContinuationStore::new()),
+            model_names: Arc::new(completion::ModelNames::default()),
+        }
+    }
+
+    /// Have the backend answer `request`, or the recordings when `recording` is on.
+    async fn generate(&self, request: &GenerateRequest, options: ExecOptions<'_>) -> Result<GenerateResponse> {
+        match &self.recorder {
+            Some(recorder) => recorder.generate(&self.backend, request, options).await,
+            None => self.backend.generate(request, options).await,
+        }
+    }
+
+    /// Fail in replay mode for `tool`, whose results aren't recorded.
+    fn check_live(&self, tool: &str) -> Result<(), McpError> {
+        match &self.recorder {
+            Some(recorder) => recorder.check_live(tool).map_err(command_error),
+            None => Ok(()),
+        }
+    }
+
+    /// The prompt templates as currently loaded.
+    fn prompts(&self) -> Arc<PromptLibrary> {
+        self.prompts.read().unwrap().clone()
+    }
+
+    /// A handle for a new client connection, sharing all state but its resource
+    /// subscriptions and log level.
+    fn connection(&self) -> Self {
+        Self {
+            subscriber: self.resources.register(),
+            client_log: ClientLog::register(),
+            ..self.clone()
+        }
+    }
+
+    /// Cut `text` to `max_response_bytes`, keeping the rest for `gemini_fetch_continuation`.
+    fn paginate(&self, text: String) -> (String, Option<Continuation>) {
+        let (mut page, continuation) = self.continuations.paginate(text, self.config.max_response_bytes);
+        if let Some(continuation) = &continuation {
+            page.push_str(&continuation_note(continuation));
+        }
+        (page, continuation)
+    }
+
+    /// Whether workflows may ask the calling client's model for help.
+    fn sampling_available(&self, context: &RequestContext<RoleServer>) -> bool {
+        self.config.client_sampling && sampling::supported(context)
+    }
+
+    /// The client's roots, which paths in a request must lie in; only asked for
+    /// when the request names any.
+    async fn scope(&self, context: &RequestContext<RoleServer>, has_paths: bool) -> Result<Scope, McpError> {
+        if !has_paths {
+            return Ok(Scope::unrestricted());
+        }
+        Scope::of(context).await
+            .map_err(command_error)
+    }
+
+    /// `text` with personal data replaced when the PII filter is on, for tools
+    /// that send Gemini something other than a [`GenerateRequest`], and what was removed.
+    fn scrub(&self, text: String) -> (String, BTreeMap<String, usize>) {
+        let mut redactions = BTreeMap::new();
+        let Some(pii) = &self.pii else {
+            return (text, redactions);
+        };
+        let text = pii.scrub(&text, &mut redactions).into_owned();
+        if !redactions.is_empty() {
+            tracing::info!("Scrubbed personal data from the prompt: {:?}", redactions);
+        }
+        (text, redactions)
+    }
+
+    /// The files `patterns` name, globs expanded, read within `scope` and the
+    /// `max_glob_*` and `max_file_bytes` limits.
+    async fn attach(&self, patterns: &[String], cwd: Option<&std::path::Path>, scope: &Scope) -> Result<Vec<files::Attachment>, McpError> {
+        let limits = files::GlobLimits {
+            max_files: self.config.max_glob_files,
+            max_bytes: self.config.max_glob_bytes,
+        };
+        // Walking a large tree blocks, so keep it off the other requests' threads
+        let (entries, base) = (patterns.to_vec(), cwd.map(std::path::Path::to_path_buf));
+        let paths = tokio::task::spawn_blocking(move || files::expand_globs(&entries, base.as_deref(), &limits))
+            .await
+            .map_err(|e| errors::internal(e.to_string()))?
+            .map_err(command_error)?;
+        files::read_all(&paths, cwd, self.config.max_file_bytes, scope).await
+            .map_err(command_error)
+    }
+
+    /// The CLI runner, for tools that drive the gemini CLI itself rather than a model.
+    fn cli_backend(&self, param: &'static str) -> Result<&CliBackend, McpError> {
+        self.backend.cli()
+            .ok_or_else(|| command_error(CliOnlyParamsError { params: vec![param], conflicts: Vec::new() }.into()))
+    }
+
+    /// A failed CLI command, counted against the breaker. Successes aren't
+    /// counted: an extension command working says nothing about auth or quota.
+    fn cli_error(&self, e: anyhow::Error) -> McpError {
+        self.breaker.record_failure(&e);
+        command_error(e)
+    }
+
+    /// Fail unless `extension_management` allows changing the CLI's extensions.
+    fn check_extension_management(&self) -> Result<(), McpError> {
+        if self.config.extension_management {
+            return Ok(());
+        }
+        Err(errors::invalid_argument(
+            "Extension management is disabled; set extension_management to let clients change gemini CLI extensions",
+        ))
+    }
+
+    /// Execution controls for a CLI command run outside `run_generation`.
+    fn exec_options<'a>(&self, tool: &str, timeout_secs: Option<u64>, context: &RequestContext<RoleServer>) -> ExecOptions<'a> {
+        ExecOptions {
+            timeout: timeout_secs.map(Duration::from_secs).unwrap_or_else(|| self.live.tool_timeout(tool)),
+            ct: context.ct.clone(),
+            progress: None,
+        }
+    }
+
+    /// Run the tool call `request`, recording it in the audit log.
+    async fn handle_call(
+        &self,
+        request: CallToolRequestParam,
+        request_id: &str,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        if !self.toggles.enabled(&request.name) {
+            return Err(errors::invalid_argument(format!("Tool {} is disabled by the server's configuration", request.name)));
+        }
+
+        // Tools with their own max_concurrency queue here, on top of the shared scheduler,
+        // in the place the call's priority argument asks for
+        let _permit = match self.tool_limits.get(request.name.as_ref()) {
+            Some(limit) => {
+                let progress = ProgressReporter::new(&context);
+                let priority = request.arguments.as_ref()
+                    .and_then(|arguments| arguments.get("priority"))
+                    .and_then(|priority| serde_json::from_value(priority.clone()).ok())
+                    .unwrap_or_else(|| self.config.priority(&request.name));
+                Some(limit.acquire(priority, Some(&progress), &context.ct).await
+                    .map_err(command_error)?)
+            }
+            None => None,
+        };
+
+        let Some(audit) = &self.audit else {
+            return self.dispatch(request, context).await;
+        };
+
+        let started = std::time::Instant::now();
+        let tool = request.name.to_string();
+        let args = serde_json::Value::Object(request.arguments.clone().unwrap_or_default());
+        let client = context.peer.peer_info().map(|info| info.client_info.name.clone());
+        let model = match &args["model"] {
+            serde_json::Value::String(model) => self.live.resolve_model(Some(model.clone())),
+            _ => self.live.resolve_model(None),
+        };
+
+        let result = self.dispatch(request, context).await;
+        audit.record(&AuditEntry::new(tool, request_id.to_string(), client, &args, model, &result, started.elapsed()));
+        result
+    }
+
+    /// Run the tool `request` names, whether one of ours or a bridged one.
+    async fn dispatch(&self, request: CallToolRequestParam, context: RequestContext<RoleServer>) -> Result<CallToolResult, McpError> {
+        let Some(bridge) = self.bridge.as_ref().filter(|bridge| bridge.handles(&request.name)) else {
+            return self.tool_router.call(ToolCallContext::new(self, request, context)).await;
+        };
+        let timeout = self.live.tool_timeout(&request.name);
+        tokio::select! {
+            result = tokio::time::timeout(timeout, bridge.call(&request.name, request.arguments)) => {
+                result.map_err(|_| command_error(backend::TimeoutError::new(timeout).into()))?
+            }
+            _ = context.ct.cancelled() => Err(command_error(CancelledError.into())),
+        }
+    }
+
+    /// Wait for quota for `prompt_tokens` to `model` and then for a slot, failing
+    /// at once while the backend is unhealthy; requests turned away are counted
+    /// in the metrics.
+    async fn admit(
+        &self,
+        model: &str,
+        prompt_tokens: u32,
+        priority: Priority,
+        progress: &ProgressReporter,
+        context: &RequestContext<RoleServer>,
+    ) -> Result<tokio::sync::OwnedSemaphorePermit, McpError> {
+        let started = std::time::Instant::now();
+        // Wait for quota before taking a slot so rate-limited requests don't block others,
+        // and don't queue at all while the backend is unhealthy
+        let admitted = async {
+            self.breaker.check()?;
+            self.rate_limiter
+                .acquire(model, prompt_tokens, self.rate_limit_policy(context), Some(progress), &context.ct)
+                .await?;
+            let permit = self.scheduler.acquire(priority, Some(progress), &context.ct).await?;
+            // The breaker may have opened while this request waited
+            self.breaker.check()?;
+            Ok::<_, anyhow::Error>(permit)
+        };
+        admitted.await.map_err(|e| {
+            self.metrics.record_request(self.backend.name(), model, metrics::failure_kind(&e), started.elapsed());
+            command_error(e)
+        })
+    }
+
+    /// The rate limit policy for the calling client, by its MCP client name.
+    fn rate_limit_policy(&self, context: &RequestContext<RoleServer>) -> RateLimitPolicy {
+        context.peer.peer_info()
+            .and_then(|info| self.config.client_rate_limit_policies.get(info.client_info.name.as_str()))
+            .copied()
+            .unwrap_or(self.config.rate_limit_policy)
+    }
+
+    /// How much of its model's context window `request` may use; `None` for models
+    /// the registry doesn't know, which are left for Gemini to judge.
+    fn prompt_budget(&self, request: &GenerateRequest) -> Option<PromptBudget> {
+        let info = models::lookup(request.model.as_deref().unwrap_or(backend::DEFAULT_MODEL))?;
+        let reserved_tokens = request.max_tokens.unwrap_or(self.config.output_token_reserve);
+        Some(PromptBudget {
+            input_token_limit: info.input_token_limit,
+            reserved_tokens,
+            max_prompt_tokens: info.input_token_limit.saturating_sub(reserved_tokens),
+            other_tokens: request.system_instruction.as_deref().map(tokens::estimate).unwrap_or(0)
+                + request.images.len() as u32 * tokens::IMAGE_TOKENS,
+        })
+    }
+
+    /// Models and aliases the model policy permits, for completing `model`
+    /// arguments: the live listing when the API is reachable, else the registry.
+    async fn model_names(&self) -> Vec<String> {
+        if let Some(names) = self.model_names.get() {
+            return names;
+        }
+        let models = match self.backend.api() {
+            Some(api) => api.list_models().await.unwrap_or_else(|e| {
+                tracing::debug!("Failed to list models from the API, completing from the built-in registry: {}", e);
+                models::registry()
+            }),
+            None => models::registry(),
+        };
+        let mut names: Vec<String> = self.live.model_aliases().into_keys().collect();
+        names.extend(models.into_iter().map(|model| model.name).filter(|name| !names.contains(name)));
+        names.retain(|name| self.config.model_policy.permits(name));
+        self.model_names.set(names.clone());
+        names
+    }
+
+    /// Trim the oldest turns of `session` as `chat_history` says once the prompt
+    /// for `message` nears the context window of `request`'s model, saving the
+    /// session. `None` when nothing was trimmed.
+    async fn trim_history(
+        &self,
+        session: &mut Session,
+        message: &str,
+        request: &GenerateRequest,
+        profile: Option<&str>,
+        priority: Option<Priority>,
+        context: &RequestContext<RoleServer>,
+    ) -> Result<Option<memory::Trimmed>, McpError> {
+        let window = self.prompt_budget(request)
+            .map(|budget| budget.max_prompt_tokens.saturating_sub(budget.other_tokens) / 100 * memory::WINDOW_PERCENT);
+        let limit = match (self.config.chat_history_tokens, window) {
+            (0, None) => return Ok(None),
+            (0, Some(window)) => window,
+            (max, window) => window.map_or(max, |window| window.min(max)),
+        };
+        let count = memory::to_trim(session, message, limit, self.config.chat_history);
+        if count == 0 {
+            return Ok(None);
+        }
+
+        let tokens_before = tokens::estimate(&request.prompt);
+        let mut strategy = "evict";
+        let mut summary = session.summary.clone();
+        let mut usage = None;
+        if self.config.chat_history == ChatHistory::Summarize {
+            let summarize = GenerateRequest {
+                prompt: memory::summary_prompt(session.summary.as_deref(), &session.history()[..count]),
+                model: request.model.clone(),
+                ..Default::default()
+            };
+            let call = CallOptions {
+                tool: "gemini_chat",
+                profile,
+                priority,
+                session_id: Some(&session.id),
+                ..Default::default()
+            };
+            // Without a summary the turns are still evicted, so the chat can go on
+            match self.run_generation(&summarize, call, context).await {
+                Ok(response) if response.safety.is_none() && !response.text.trim().is_empty() => {
+                    summary = Some(response.text.trim().to_string());
+                    usage = Some(response.usage);
+                    strategy = "summarize";
+                }
+                Ok(_) => tracing::warn!("Evicting the oldest turns of chat session {}: the summary came back empty", session.id),
+                Err(e) => tracing::warn!("Evicting the oldest turns of chat session {}: summarizing failed: {}", session.id, e.message),
+            }
+        }
+        *session = self.sessions
+            .trim(&session.id, session.trimmed + count, summary)
+            .map_err(|e| errors::internal(e.to_string()))?;
+        Ok(Some(memory::Trimmed {
+            strategy,
+            messages: count,
+            tokens_before,
+            tokens_after: tokens::estimate(&session.transcript_with(message)),
+            usage,
+        }))
+    }
+
+    /// `request` with its prompt cut down to fit the model's context window and a
+    /// warning saying so, `None` when it fits as it is, or a `prompt_too_large`
+    /// error when `prompt_overflow` is `reject` or cutting cannot help.
+    fn fit_prompt(&self, request: &GenerateRequest) -> Result<Option<(GenerateRequest, String)>, McpError> {
+        let model = request.model.as_deref().unwrap_or(backend::DEFAULT_MODEL);
+        let Some(PromptBudget { input_token_limit, reserved_tokens, max_prompt_tokens, other_tokens }) = self.prompt_budget(request) else {
+            return Ok(None);
+        };
+        let prompt_tokens = tokens::estimate(&request.prompt) + other_tokens;
+        if prompt_tokens <= max_prompt_tokens {
+            return Ok(None);
+        }
+
+        if self.config.prompt_overflow == PromptOverflow::Truncate {
+            let truncated = max_prompt_tokens.checked_sub(other_tokens)
+                .filter(|budget| *budget > 0)
+                .and_then(|budget| tokens::truncate_middle(&request.prompt, budget));
+            if let Some(prompt) = truncated {
+                let warning = format!(
+                    "The prompt was truncated from about {} to {} tokens to fit the context window of {}",
+                    prompt_tokens, tokens::estimate(&prompt) + other_tokens, model
+                );
+                tracing::warn!("{}", warning);
+                return Ok(Some((GenerateRequest { prompt, ..request.clone() }, warning)));
+            }
+        }
+        Err(command_error(PromptTooLargeError {
+            model: model.to_string(),
+            prompt_tokens,
+            max_prompt_tokens,
+            input_token_limit,
+            reserved_tokens,
+        }.into()))
+    }
+
+    /// Answer a `request` too large for its model's context window in parts, when
+    /// `prompt_overflow` is `chunk`: `document` is split into overlapping parts, each
+    /// sent with `question` (or alone when there is none), and one more call
+    /// consolidates the partial answers. `None` when the request fits or can't be
+    /// split, leaving it to [`Self::run_generation`] to reject.
+    async fn run_chunked(
+        &self,
+        request: &GenerateRequest,
+        question: Option<&str>,
+        document: &str,
+        call: CallOptions<'_>,
+        context: &RequestContext<RoleServer>,
+    ) -> Result<Option<GenerateResponse>, McpError> {
+        // Function calling continues one conversation, which parts can't share
+        if self.config.prompt_overflow != PromptOverflow::Chunk
+            || call.dry_run
+            || self.config.dry_run
+            || !request.functions.is_empty()
+            || !request.function_results.is_empty()
+        {
+            return Ok(None);
+        }
+        let Some(budget) = self.prompt_budget(request) else {
+            return Ok(None);
+        };
+        if tokens::estimate(&request.prompt) + budget.other_tokens <= budget.max_prompt_tokens {
+            return Ok(None);
+        }
+        let instructions = tokens::estimate(&chunking::part_prompt(question, "", chunking::MAX_PARTS, chunking::MAX_PARTS));
+        let part_tokens = budget.max_prompt_tokens.saturating_sub(budget.other_tokens + instructions);
+        if part_tokens < chunking::MIN_PART_TOKENS {
+            return Ok(None);
+        }
+        let parts = chunking::split(document, part_tokens, self.config.chunk_overlap_tokens);
+        if parts.len() > chunking::MAX_PARTS {
+            return Ok(None);
+        }
+
+        let total = parts.len();
+        let model = request.model.as_deref().unwrap_or(backend::DEFAULT_MODEL);
+        tracing::info!("Prompt too large for {}, answering it in {} parts", model, total);
+
+        let own_progress;
+        let progress = match call.progress {
+            Some(progress) => progress,
+            None => {
+                own_progress = ProgressReporter::new(context);
+                &own_progress
+            }
+        };
+        let muted = ProgressReporter::muted(context);
+        let part_call = CallOptions { progress: Some(&muted), ..call };
+        let mut pending = futures::stream::iter(parts.iter().enumerate())
+            .map(|(index, part)| {
+                let request = GenerateRequest {
+                    prompt: chunking::part_prompt(question, part, index, total),
+                    // Only the consolidated answer has to be in the requested format
+                    output_format: OutputFormat::Text,
+                    ..request.clone()
+                };
+                async move { self.run_generation(&request, part_call, context).await }
+            })
+            .buffered(self.config.max_concurrency.max(1));
+
+        let mut usage = Usage::default();
+        let mut retries = 0;
+        let mut warnings = vec![format!("The prompt was too large for {} and was answered in {} parts", model, total)];
+        let mut redactions: BTreeMap<String, usize> = BTreeMap::new();
+        let mut answers = Vec::with_capacity(total);
+        while let Some(outcome) = pending.next().await {
+            let response = outcome?;
+            usage += response.usage;
+            if response.safety.is_some() {
+                return Ok(Some(GenerateResponse { usage, ..response }));
+            }
+            retries += response.retries;
+            warnings.extend(response.warnings);
+            for (kind, count) in response.redactions {
+                *redactions.entry(kind).or_default() += count;
+            }
+            answers.push(response.text);
+            progress.report(format!("Answered part {} of {}", answers.len(), total)).await;
+        }
+        drop(pending);
+
+        progress.report("Consolidating the answers".to_string()).await;
+        let consolidation = GenerateRequest {
+            prompt: format::prompt(chunking::consolidation_prompt(question, &answers), request.output_format),
+            ..request.clone()
+        };
+        let mut response = self.run_generation(&consolidation, CallOptions { progress: Some(progress), ..call }, context).await?;
+        response.usage += usage;
+        response.retries += retries;
+        warnings.append(&mut response.warnings);
+        response.warnings = warnings;
+        for (kind, count) in std::mem::take(&mut response.redactions) {
+            *redactions.entry(kind).or_default() += count;
+        }
+        response.redactions = redactions;
+        Ok(Some(response))
+    }
+
+    /// Run a generation once a scheduler slot is free, streaming progress to the client,
+    /// retrying transient failures with backoff, falling back to the configured
+    /// `model_fallbacks` when the model is out of capacity, and recording usage.
+    async fn run_generation(
+        &self,
+        request: &GenerateRequest,
+        call: CallOptions<'_>,
+        context: &RequestContext<RoleServer>,
+    ) -> Result<GenerateResponse, McpError> {
+        let own_progress;
+        let progress = match call.progress {
+            Some(progress) => progress,
+            None => {
+                own_progress = ProgressReporter::new(context);
+                &own_progress
+            }
+        };
+
+        let profiled;
+        let request = match call.profile.or(self.config.default_profile.as_deref()) {
+            Some(name) => {
+                let profile = self.config.profile(name)
+                    .map_err(|e| errors::invalid_argument(format!("{:#}", e)))?;
+                profiled = GenerateRequest { env: profile.resolved_env(), ..request.clone() };
+                &profiled
+            }
+            None => request,
+        };
+
+        let scrubbed;
+        let (request, redactions) = match &self.pii {
+            Some(pii) => {
+                let (request, redactions) = pii.scrub_request(request);
+                if !redactions.is_empty() {
+                    tracing::info!("Scrubbed personal data from the prompt: {:?}", redactions);
+                }
+                scrubbed = request;
+                (&scrubbed, redactions)
+            }
+            None => (request, BTreeMap::new()),
+        };
+
+        self.config.model_policy.check(request.model.as_deref().unwrap_or(backend::DEFAULT_MODEL))
+            .map_err(|e| command_error(e.into()))?;
+
+        let fitted;
+        let (request, truncated) = match self.fit_prompt(request)? {
+            Some((request, warning)) => {
+                fitted = request;
+                (&fitted, Some(warning))
+            }
+            None => (request, None),
+        };
+
+        if call.dry_run || self.config.dry_run {
+            let plan = self.backend.dry_run(request)
+                .map_err(command_error)?;
+            let text = serde_json::to_string_pretty(&plan)
+                .map_err(|e| errors::internal(e.to_string()))?;
+            return Ok(GenerateResponse { text, ..Default::default() });
+        }
+
+        let started = std::time::Instant::now();
+        let backend_name = self.backend.name();
+        let model = request.model.as_deref().unwrap_or(backend::DEFAULT_MODEL);
+
+        let prompt_tokens = tokens::estimate(&request.prompt)
+            + request.system_instruction.as_deref().map(tokens::estimate).unwrap_or(0)
+            + request.images.len() as u32 * tokens::IMAGE_TOKENS;
+        let priority = call.priority.unwrap_or_else(|| self.config.priority(call.tool));
+        let _permit = self.admit(model, prompt_tokens, priority, progress, context).await?;
+
+        let mut policy = RetryPolicy::from_config(&self.config);
+        if let Some(max_retries) = self.config.tool(call.tool).max_retries {
+            policy.max_retries = max_retries;
+        }
+        let mut retries = 0;
+        let mut fallbacks = if call.pin_model {
+            Vec::new()
+        } else {
+            self.config.fallbacks(model)
+        }.into_iter().filter(|fallback| self.config.model_policy.permits(fallback));
+        // The request with its model replaced by the current fallback, once one is used
+        let mut fallback: Option<GenerateRequest> = None;
+
+        loop {
+            let request = fallback.as_ref().unwrap_or(request);
+            let model = request.model.as_deref().unwrap_or(backend::DEFAULT_MODEL);
+            let options = ExecOptions {
+                timeout: call.timeout_secs.map(Duration::from_secs).unwrap_or_else(|| self.live.tool_timeout(call.tool)),
+                ct: context.ct.clone(),
+                progress: Some(progress),
+            };
+
+            let generated = progress.keepalive(self.config.keepalive(), self.generate(request, options)).await;
+            let error = match generated {
+                Ok(mut response) => {
+                    response.retries = retries;
+                    response.warnings.extend(truncated.clone());
+                    response.redactions = redactions.clone();
+                    response.text = self.postprocessing.apply(call.tool, response.text);
+                    response.fallback_model = fallback.as_ref().and_then(|fallback| fallback.model.clone());
+                    self.usage.record(model, call.session_id, &response.usage);
+                    self.metrics.record_tokens(model, &response.usage);
+                    self.rate_limiter.consume(model, response.usage.completion_tokens);
+                    self.metrics.record_request(backend_name, model, "success", started.elapsed());
+                    self.breaker.record_success();
+                    self.resources.updated(resources::USAGE_TODAY_URI).await;
+                    return Ok(response);
+                }
+                Err(e) => e,
+            };
+            self.metrics.record_failure(backend_name, metrics::failure_kind(&error));
+
+            if retries >= policy.max_retries || !retry::is_transient(&error) {
+                if retry::is_capacity(&error) {
+                    if let Some(next) = fallbacks.next() {
+                        tracing::warn!("{} is out of capacity, falling back to {}: {:#}", model, next, error);
+                        progress.report(format!("{} is out of capacity, falling back to {}", model, next)).await;
+                        fallback = Some(GenerateRequest {
+                            model: Some(next),
+                            ..request.clone()
+                        });
+                        retries = 0;
+                        continue;
+                    }
+                }
+                self.metrics.record_request(backend_name, model, metrics::failure_kind(&error), started.elapsed());
+                self.breaker.record_failure(&error);
+                let error = if retries > 0 {
+                    error.context(format!("Gemini request failed after {} retries", retries))
+                } else {
+                    error
+                };
+                return Err(command_error(error));
+            }
+
+            retries += 1;
+            self.metrics.record_retry();
+            let delay = policy.backoff(retries);
+            tracing::warn!("Transient gemini failure, retry {} of {} in {:?}: {:#}", retries, policy.max_retries, delay, error);
+            progress.report(format!(
+                "Transient failure, retrying in {:.1}s (retry {} of {})",
+                delay.as_secs_f64(), retries, policy.max_retries
+            )).await;
+
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = context.ct.cancelled() => {
+                    self.metrics.record_request(backend_name, model, "cancelled", started.elapsed());
+                    return Err(command_error(CancelledError.into()));
+                }
+            }
+        }
+    }
+
+    #[tool(description = "Send a prompt to Gemini")]
+    async fn gemini_prompt(
+        &self,
+        Parameters(GeminiPromptArgs {
+            prompt, model, max_tokens, temperature, top_p, top_k, stop_sequences, seed, functions, function_results, timeout_secs,
+            profile, priority, system_instruction, safety_settings, files, images, sandbox, cwd, all_files, include_directories,
+            dry_run, output_format,
+        }): Parameters<GeminiPromptArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        if top_p.is_some_and(|top_p| !(0.0..=1.0).contains(&top_p)) {
+            return Err(errors::invalid_argument("top_p must be between 0 and 1"));
+        }
+        if stop_sequences.len() > MAX_STOP_SEQUENCES {
+            return Err(errors::invalid_argument(
+                format!("At most {} stop_sequences are allowed", MAX_STOP_SEQUENCES),
+            ));
+        }
+        functions::validate(&functions).map_err(errors::invalid_argument)?;
+        if !function_results.is_empty() && functions.is_empty() {
+            return Err(errors::invalid_argument("function_results need the functions Gemini called"));
+        }
+
+        let has_paths = cwd.is_some() || !files.is_empty() || !images.is_empty() || !include_directories.is_empty();
+        let scope = self.scope(&context, has_paths).await?;
+        let cwd = working_dir(cwd, &scope)?;
+        let include_directories = included_dirs(&include_directories, cwd.as_deref(), &scope)?;
+        let attachments = self.attach(&files, cwd.as_deref(), &scope).await?;
+        let images = files::read_images(&images, cwd.as_deref(), self.config.max_image_bytes, &scope).await
+            .map_err(command_error)?;
+
+        let request = GenerateRequest {
+            prompt: format::prompt(files::inline(&prompt, &attachments), output_format),
+            images,
+            model: self.live.resolve_model(model),
+            system_instruction: system_instruction.or_else(|| self.config.system_instruction.clone()),
+            max_tokens,
+            temperature,
+            top_p,
+            top_k,
+            stop_sequences,
+            seed,
+            functions,
+            function_results,
+            safety_settings,
+            sandbox,
+            cwd,
+            all_files,
+            include_directories,
+            output_format,
+            ..Default::default()
+        };
+
+        tracing::info!("Calling gemini with prompt via {} backend", self.backend.name());
+
+        let call = CallOptions {
+            tool: "gemini_prompt",
+            profile: profile.as_deref(),
+            priority,
+            timeout_secs,
+            dry_run,
+            ..Default::default()
+        };
+        // Attached files are what gets split; the prompt is asked of every part
+        let (question, document) = if attachments.is_empty() {
+            (None, prompt.clone())
+        } else {
+            (Some(prompt.as_str()), files::inline("", &attachments).trim_start().to_string())
+        };
+        let response = match self.run_chunked(&request, question, &document, call, &context).await? {
+            Some(response) => response,
+            None => self.run_generation(&request, call, &context).await?,
+        };
+
+        if let Some(block) = response.safety {
+            return safety_blocked(block, serde_json::json!({
+                "backend": self.backend.name(),
+                "model": request.model,
+                "usage": response.usage,
+            }));
+        }
+
+        // Gemini wants functions run first; the client continues with their function_results
+        if !response.function_calls.is_empty() {
+            return Ok(CallToolResult::success(vec![
+                Content::json(serde_json::json!({
+                    "function_calls": response.function_calls,
+                    "text": response.text,
+                }))?,
+                Content::json(with_fields(generation_metadata(&response, request.model.as_deref()), serde_json::json!({
+                    "backend": self.backend.name(),
+                })))?,
+            ]));
+        }
+
+        let metadata = generation_metadata(&response, request.model.as_deref());
+        // A dry run's text is the command, which is left as it is
+        let formatted = if dry_run || self.config.dry_run {
+            Formatted::Text(response.text)
+        } else {
+            format::apply(output_format, response.text)
+                .map_err(|e| command_error(e.into()))?
+        };
+        // JSON is returned whole, since a page of it would not parse
+        let (answer, continuation) = match formatted {
+            Formatted::Text(text) => {
+                let (text, continuation) = self.paginate(text);
+                (Content::text(text), continuation)
+            }
+            Formatted::Json(value) => (Content::json(value)?, None),
+        };
+        Ok(CallToolResult::success(vec![
+            answer,
+            Content::json(with_fields(metadata, serde_json::json!({
+                "backend": self.backend.name(),
+                "output_format": output_format,
+                "continuation": continuation,
+                "files": attachments.iter().map(|a| a.path.display().to_string()).collect::<Vec<_>>(),
+                "images": request.images.iter().map(|i| &i.source).collect::<Vec<_>>(),
+            })))?,
+        ]))
+    }
+
+    #[tool(description = "Chat with Gemini in a persistent session that keeps context across calls")]
+    async fn gemini_chat(
+        &self,
+        Parameters(GeminiChatArgs { message, session_id, model, timeout_secs, profile, priority, cwd, dry_run }): Parameters<GeminiChatArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let scope = self.scope(&context, cwd.is_some()).await?;
+        let cwd = working_dir(cwd, &scope)?;
+        // A dry run must not leave a new session behind
+        let mut session = if session_id.is_none() && (dry_run || self.config.dry_run) {
+            Session::unsaved(model.clone())
+        } else {
+            let session = self.sessions
+                .get_or_create(session_id.as_deref(), model.clone())
+                .map_err(|e| errors::invalid_argument(e.to_string()))?;
+            if session_id.is_none() {
+                self.resources.list_changed().await;
+            }
+            session
+        };
+
+        let mut request = GenerateRequest {
+            prompt: session.transcript_with(&message),
+            model: self.live.resolve_model(model.or_else(|| session.model.clone())),
+            system_instruction: self.config.system_instruction.clone(),
+            cwd,
+            ..Default::default()
+        };
+        let trimmed = if dry_run || self.config.dry_run {
+            None
+        } else {
+            self.trim_history(&mut session, &message, &request, profile.as_deref(), priority, &context).await?
+        };
+        if trimmed.is_some() {
+            request.prompt = session.transcript_with(&message);
+        }
+
+        tracing::info!("Calling gemini for chat session {}", session.id);
+
+        let call = CallOptions {
+            tool: "gemini_chat",
+            profile: profile.as_deref(),
+            priority,
+            timeout_secs,
+            session_id: Some(&session.id),
+            dry_run,
+            ..Default::default()
+        };
+        let response = self.run_generation(&request, call, &context).await?;
+
+        if dry_run || self.config.dry_run {
+            return Ok(CallToolResult::success(vec![Content::text(response.text)]));
+        }
+
+        // A blocked reply is not recorded, so the session can continue with another message
+        if let Some(block) = response.safety {
+            return safety_blocked(block, serde_json::json!({
+                "session_id": session.id,
+                "usage": response.usage,
+            }));
+        }
+
+        let session = self.sessions
+            .record_turn(&session.id, message, response.text.clone(), response.usage)
+            .map_err(|e| errors::internal(e.to_string()))?;
+        self.resources.updated(&resources::session_uri(&session.id)).await;
+
+        let metadata = generation_metadata(&response, request.model.as_deref());
+        let mut warnings = response.warnings;
+        if let Some(trimmed) = &trimmed {
+            warnings.push(format!(
+                "The {} oldest messages of the session were {} to keep its history within the context window",
+                trimmed.messages,
+                if trimmed.strategy == "summarize" { "summarized" } else { "dropped" }
+            ));
+        }
+        let (text, continuation) = self.paginate(response.text);
+        Ok(CallToolResult::success(vec![
+            Content::text(text),
+            Content::json(with_fields(metadata, serde_json::json!({
+                "session_id": session.id,
+                "turn_count": session.messages.len() / 2,
+                "history_tokens": tokens::estimate(&request.prompt),
+                "trimmed": trimmed,
+                "continuation": continuation,
+                "warnings": warnings,
+            })))?,
+        ]))
+    }
+
+    #[tool(description = "List gemini_chat sessions, most recently used first, with their title, model, turn count and token usage")]
+    async fn gemini_sessions_list(
+        &self,
+        Parameters(GeminiSessionsListArgs { limit }): Parameters<GeminiSessionsListArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let mut sessions: Vec<_> = self.sessions.list().iter().map(Session::info).collect();
+        sessions.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+        let total = sessions.len();
+        sessions.truncate(limit.unwrap_or(total));
+        Ok(CallToolResult::success(vec![Content::json(serde_json::json!({
+            "sessions": sessions,
+            "total": total,
+        }))?]))
+    }
+
+    #[tool(description = "Show a gemini_chat session: its metadata, token usage and messages")]
+    async fn gemini_session_get(
+        &self,
+        Parameters(GeminiSessionGetArgs { session_id, include_messages }): Parameters<GeminiSessionGetArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let session = self.sessions
+            .get(&session_id)
+            .ok_or_else(|| errors::invalid_argument(format!("Unknown session_id: {}", session_id)))?;
+        let mut result = serde_json::to_value(session.info())
+            .map_err(|e| errors::internal(e.to_string()))?;
+        if include_messages.unwrap_or(true) {
+            result["summary"] = serde_json::json!(session.summary);
+            result["messages"] = serde_json::json!(session.messages);
+        }
+        Ok(CallToolResult::success(vec![Content::json(result)?]))
+    }
+
+    #[tool(description = "Delete a gemini_chat session and all of its messages")]
+    async fn gemini_session_delete(
+        &self,
+        Parameters(GeminiSessionDeleteArgs { session_id }): Parameters<GeminiSessionDeleteArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let deleted = self.sessions
+            .delete(&session_id)
+            .map_err(|e| errors::internal(e.to_string()))?;
+        if !deleted {
+            return Err(errors::invalid_argument(format!("Unknown session_id: {}", session_id)));
+        }
+        self.resources.list_changed().await;
+        Ok(CallToolResult::success(vec![Content::text(format!("Deleted session {}", session_id))]))
+    }
+
+    #[tool(description = "Give a gemini_chat session a title, shown by gemini_sessions_list, or remove it")]
+    async fn gemini_session_rename(
+        &self,
+        Parameters(GeminiSessionRenameArgs { session_id, title }): Parameters<GeminiSessionRenameArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let title = title.map(|title| title.trim().to_string()).filter(|title| !title.is_empty());
+        if self.sessions.get(&session_id).is_none() {
+            return Err(errors::invalid_argument(format!("Unknown session_id: {}", session_id)));
+        }
+        let session = self.sessions
+            .rename(&session_id, title)
+            .map_err(|e| errors::internal(e.to_string()))?;
+        self.resources.list_changed().await;
+        Ok(CallToolResult::success(vec![Content::json(session.info())?]))
+    }
+
+    #[tool(description = "Send many independent prompts to Gemini in one call with bounded parallelism, returning a result or error per prompt")]
+    async fn gemini_batch_prompt(
+        &self,
+        Parameters(GeminiBatchPromptArgs {
+            prompts, model, system_instruction, max_parallel, timeout_secs, profile, priority, output_format,
+        }): Parameters<GeminiBatchPromptArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        if prompts.is_empty() {
+            return Err(errors::invalid_argument("prompts must not be empty"));
+        }
+        if prompts.len() > MAX_BATCH_SIZE {
+            return Err(errors::invalid_argument(
+                format!("At most {} prompts can be sent in one batch", MAX_BATCH_SIZE),
+            ));
+        }
+
+        let total = prompts.len();
+        let parallel = max_parallel.unwrap_or(self.config.max_concurrency).clamp(1, self.config.max_concurrency.max(1));
+        let model = self.live.resolve_model(model);
+        let system_instruction = system_instruction.or_else(|| self.config.system_instruction.clone());
+
+        tracing::info!("Running batch of {} prompts, {} at a time", total, parallel);
+
+        // Interleaved output from several prompts would be unreadable, so only completions are reported
+        let progress = ProgressReporter::new(&context);
+        let muted = ProgressReporter::muted(&context);
+
+        let mut pending = futures::stream::iter(prompts.into_iter().enumerate())
+            .map(|(index, prompt)| {
+                let request = GenerateRequest {
+                    prompt: format::prompt(prompt, output_format),
+                    model: model.clone(),
+                    system_instruction: system_instruction.clone(),
+                    output_format,
+                    ..Default::default()
+                };
+                let call = CallOptions {
+                    tool: "gemini_batch_prompt",
+                    profile: profile.as_deref(),
+                    priority,
+                    timeout_secs,
+                    progress: Some(&muted),
+                    ..Default::default()
+                };
+                let context = &context;
+                async move { (index, self.run_generation(&request, call, context).await) }
+            })
+            .buffer_unordered(parallel);
+
+        let mut results = Vec::with_capacity(total);
+        let mut failed = 0;
+        while let Some((index, outcome)) = pending.next().await {
+            let outcome = outcome.and_then(|mut response| {
+                let text = std::mem::take(&mut response.text);
+                let answer = if self.config.dry_run || response.safety.is_some() {
+                    Formatted::Text(text)
+                } else {
+                    format::apply(output_format, text)
+                        .map_err(|e| command_error(e.into()))?
+                };
+                Ok((answer, response))
+            });
+            let result = match outcome {
+                Ok((_, GenerateResponse { safety: Some(block), usage, .. })) => serde_json::json!({
+                    "index": index,
+                    "ok": false,
+                    "error": format!("Gemini blocked the {} for safety reasons ({})", block.blocked, block.reason),
+                    "safety_block": block,
+                    "usage": usage,
+                }),
+                Ok((Formatted::Text(text), response)) => with_fields(generation_metadata(&response, model.as_deref()), serde_json::json!({
+                    "index": index,
+                    "ok": true,
+                    "text": text,
+                })),
+                Ok((Formatted::Json(value), response)) => with_fields(generation_metadata(&response, model.as_deref()), serde_json::json!({
+                    "index": index,
+                    "ok": true,
+                    "json": value,
+                })),
+                Err(error) => serde_json::json!({
+                    "index": index,
+                    "ok": false,
+                    "error": error.message,
+                    "data": error.data,
+                }),
+            };
+            if result["ok"] != true {
+                failed += 1;
+            }
+            results.push(result);
+            progress.report(format!("Completed {} of {} prompts", results.len(), total)).await;
+        }
+
+        results.sort_by_key(|result| result["index"].as_u64());
+
+        Ok(CallToolResult::success(vec![Content::json(serde_json::json!({
+            "model": model,
+            "output_format": output_format,
+            "succeeded": total - failed,
+            "failed": failed,
+            "results": results,
+        }))?]))
+    }
+
+    #[tool(description = "Send the same prompt to 2-4 models concurrently and compare their answers, latency and token counts side by side")]
+    async fn gemini_compare_models(
+        &self,
+        Parameters(GeminiCompareModelsArgs { prompt, models, system_instruction, timeout_secs, profile, priority }): Parameters<GeminiCompareModelsArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        if !(2..=4).contains(&models.len()) {
+            return Err(errors::invalid_argument("Specify between 2 and 4 models to compare"));
+        }
+
+        let system_instruction = system_instruction.or_else(|| self.config.system_instruction.clone());
+        let models: Vec<String> = models.into_iter().map(|model| self.live.resolve_alias(model)).collect();
+
+        tracing::info!("Comparing models {:?}", models);
+
+        let progress = ProgressReporter::new(&context);
+        let muted = ProgressReporter::muted(&context);
+
+        let runs = models.iter().map(|model| {
+            let request = GenerateRequest {
+                prompt: prompt.clone(),
+                model: Some(model.clone()),
+                system_instruction: system_instruction.clone(),
+                ..Default::default()
+            };
+            let call = CallOptions {
+                tool: "gemini_compare_models",
+                profile: profile.as_deref(),
+                timeout_secs,
+                priority,
+                progress: Some(&muted),
+                pin_model: true,
+                ..Default::default()
+            };
+            let (context, progress) = (&context, &progress);
+            async move {
+                let started = std::time::Instant::now();
+                let outcome = self.run_generation(&request, call, context).await;
+                let latency_ms = started.elapsed().as_millis() as u64;
+                progress.report(format!("{} finished in {}ms", model, latency_ms)).await;
+
+                match outcome {
+                    Ok(GenerateResponse { safety: Some(block), usage, .. }) => serde_json::json!({
+                        "model": model,
+                        "ok": false,
+                        "error": format!("Gemini blocked the {} for safety reasons ({})", block.blocked, block.reason),
+                        "safety_block": block,
+                        "latency_ms": latency_ms,
+                        "usage": usage,
+                    }),
+                    Ok(response) => serde_json::json!({
+                        "model": model,
+                        "ok": true,
+                        "text": response.text,
+                        "latency_ms": latency_ms,
+                        "retries": response.retries,
+                        "warnings": response.warnings,
+                        "redactions": response.redactions,
+                        "usage": response.usage,
+                    }),
+                    Err(error) => serde_json::json!({
+                        "model": model,
+                        "ok": false,
+                        "error": error.message,
+                        "data": error.data,
+                        "latency_ms": latency_ms,
+                    }),
+                }
+            }
+        });
+        let results = futures::future::join_all(runs).await;
+
+        Ok(CallToolResult::success(vec![Content::json(serde_json::json!({
+            "prompt": prompt,
+            "results": results,
+        }))?]))
+    }
+
+    #[tool(description = "Review the changes in a git repository with Gemini and return findings grouped by file")]
+    async fn gemini_review_diff(
+        &self,
+        Parameters(GeminiReviewDiffArgs {
+            repo_path, staged, range, paths, model, timeout_secs, profile, priority,
+        }): Parameters<GeminiReviewDiffArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        self.scope(&context, true).await?
+            .check(std::path::Path::new(&repo_path))
+            .map_err(command_error)?;
+        let spec = review::DiffSpec {
+            staged,
+            range: range.as_deref(),
+            paths: &paths,
+        };
+        let diff = review::git_diff(std::path::Path::new(&repo_path), &spec).await
+            .map_err(|e| errors::invalid_argument(format!("{:#}", e)))?;
+        if diff.trim().is_empty() {
+            return Ok(CallToolResult::success(vec![
+                Content::text("No changes to review"),
+                Content::json(serde_json::json!({ "files": {}, "chunks": 0 }))?,
+            ]));
+        }
+
+        let chunks = review::chunk(&diff);
+        let model = self.live.resolve_model(model);
+
+        tracing::info!("Reviewing diff of {} bytes in {} chunk(s)", diff.len(), chunks.len());
+
+        let progress = ProgressReporter::new(&context);
+        let muted = ProgressReporter::muted(&context);
+
+        let reviews = chunks.iter().enumerate().map(|(index, chunk)| {
+            let request = GenerateRequest {
+                prompt: review::prompt(chunk),
+                model: model.clone(),
+                system_instruction: Some(review::SYSTEM_INSTRUCTION.to_string()),
+                ..Default::default()
+            };
+            let call = CallOptions {
+                tool: "gemini_review_diff",
+                profile: profile.as_deref(),
+                timeout_secs,
+                priority,
+                progress: Some(&muted),
+                ..Default::default()
+            };
+            let (context, progress, total) = (&context, &progress, chunks.len());
+            async move {
+                let outcome = self.run_generation(&request, call, context).await;
+                progress.report(format!("Reviewed chunk {} of {}", index + 1, total)).await;
+                outcome
+            }
+        });
+        let reviews = futures::future::join_all(reviews).await;
+
+        let mut findings = Vec::new();
+        let mut unparsed = Vec::new();
+        let mut repaired = 0;
+        let mut usage = backend::Usage::default();
+        for outcome in reviews {
+            let response = outcome?;
+            if let Some(block) = response.safety {
+                return safety_blocked(block, serde_json::json!({ "model": model }));
+            }
+            if let Some(parsed) = review::parse_findings(&response.text) {
+                findings.extend(parsed);
+            } else if let Some(parsed) = self.repair_review(&response.text, &context).await {
+                repaired += 1;
+                findings.extend(parsed);
+            } else {
+                // Keep replies that ignored the requested format rather than losing them
+                unparsed.push(response.text);
+            }
+            usage += response.usage;
+        }
+
+        let count = findings.len();
+        let grouped = review::group_by_file(findings);
+        let summary = format!("{} finding(s) in {} file(s)", count, grouped.len());
+
+        Ok(CallToolResult::success(vec![
+            Content::text(summary),
+            Content::json(serde_json::json!({
+                "model": model,
+                "chunks": chunks.len(),
+                "files": grouped,
+                "unparsed": unparsed,
+                "repaired": repaired,
+                "usage": usage,
+            }))?,
+        ]))
+    }
+
+    /// Ask the client's model to convert a review reply that is not valid findings JSON.
+    async fn repair_review(&self, reply: &str, context: &RequestContext<RoleServer>) -> Option<Vec<review::Finding>> {
+        if !self.sampling_available(context) {
+            return None;
+        }
+        match sampling::ask(context, review::REPAIR_INSTRUCTION, review::repair_prompt(reply)).await {
+            Ok(repaired) => review::parse_findings(&repaired),
+            Err(e) => {
+                tracing::warn!("Failed to repair review reply through client sampling: {:#}", e);
+                None
+            }
+        }
+    }
+
+    #[tool(description = "Answer a question with Gemini grounded in Google Search results, returning the answer with its citation URLs")]
+    async fn gemini_search_grounded(
+        &self,
+        Parameters(GeminiSearchGroundedArgs { query, model, timeout_secs, profile, priority }): Parameters<GeminiSearchGroundedArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let request = GenerateRequest {
+            prompt: query,
+            model: self.live.resolve_model(model),
+            system_instruction: self.config.system_instruction.clone(),
+            google_search: true,
+            ..Default::default()
+        };
+
+        tracing::info!("Calling gemini with Google Search grounding via {} backend", self.backend.name());
+
+        let call = CallOptions {
+            tool: "gemini_search_grounded",
+            profile: profile.as_deref(),
+            timeout_secs,
+            priority,
+            ..Default::default()
+        };
+        let response = self.run_generation(&request, call, &context).await?;
+
+        if let Some(block) = response.safety {
+            return safety_blocked(block, serde_json::json!({
+                "model": request.model,
+                "usage": response.usage,
+            }));
+        }
+
+        let metadata = generation_metadata(&response, request.model.as_deref());
+        // Gemini answers from its own knowledge when it decides no search is needed
+        let grounding = response.grounding.unwrap_or_default();
+
+        let (text, continuation) = self.paginate(response.text);
+        Ok(CallToolResult::success(vec![
+            Content::text(text),
+            Content::json(with_fields(metadata, serde_json::json!({
+                "citations": grounding.citations,
+                "search_queries": grounding.search_queries,
+                "continuation": continuation,
+            })))?,
+        ]))
+    }
+
+    #[tool(description = "Generate images with Imagen, saving them as files and returning them as image content")]
+    async fn gemini_generate_image(
+        &self,
+        Parameters(GeminiGenerateImageArgs { prompt, model, number_of_images, aspect_ratio, priority }): Parameters<GeminiGenerateImageArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let Some(api) = self.backend.api() else {
+            return Err(command_error(UnsupportedParamsError { params: vec!["image generation"] }.into()));
+        };
+
+        let count = number_of_images.unwrap_or(1);
+        if !(1..=4).contains(&count) {
+            return Err(errors::invalid_argument("number_of_images must be between 1 and 4"));
+        }
+        if let Some(ratio) = aspect_ratio.as_deref() {
+            if !["1:1", "3:4", "4:3", "9:16", "16:9"].contains(&ratio) {
+                return Err(errors::invalid_argument(format!("Unsupported aspect_ratio '{}'", ratio)));
+            }
+        }
+        let Some(dir) = self.config.image_dir() else {
+            return Err(errors::internal("No image_output_dir configured and no data directory found"));
+        };
+
+        let model = model
+            .map(|model| self.live.resolve_alias(model))
+            .unwrap_or_else(|| self.config.image_model.clone());
+        self.config.model_policy.check(&model)
+            .map_err(|e| command_error(e.into()))?;
+        let (prompt, redactions) = self.scrub(prompt);
+        let request = ImageRequest {
+            model: &model,
+            prompt: &prompt,
+            count,
+            aspect_ratio: aspect_ratio.as_deref(),
+        };
+
+        if self.config.dry_run {
+            let plan = serde_json::json!({
+                "backend": "api",
+                "model": model,
+                "prompt": prompt,
+                "number_of_images": count,
+                "aspect_ratio": aspect_ratio,
+                "output_dir": dir,
+                "redactions": redactions,
+            });
+            let text = serde_json::to_string_pretty(&plan)
+                .map_err(|e| errors::internal(e.to_string()))?;
+            return Ok(CallToolResult::success(vec![Content::text(text)]));
+        }
+        self.check_live("gemini_generate_image")?;
+
+        tracing::info!("Generating {} image(s) with {}", count, model);
+
+        let progress = ProgressReporter::new(&context);
+        let started = std::time::Instant::now();
+        let backend_name = self.backend.name();
+        let prompt_tokens = tokens::estimate(&prompt);
+        let priority = priority.unwrap_or_else(|| self.config.priority("gemini_generate_image"));
+        let _permit = self.admit(&model, prompt_tokens, priority, &progress, &context).await?;
+
+        let options = ExecOptions {
+            timeout: self.live.tool_timeout("gemini_generate_image"),
+            ct: context.ct.clone(),
+            progress: Some(&progress),
+        };
+        let images = match api.generate_image(&request, options).await {
+            Ok(images) => images,
+            Err(e) => {
+                let kind = metrics::failure_kind(&e);
+                self.metrics.record_failure(backend_name, kind);
+                self.metrics.record_request(backend_name, &model, kind, started.elapsed());
+                self.breaker.record_failure(&e);
+                return Err(command_error(e));
+            }
+        };
+        // Imagen reports no token counts, and its answer is images rather than text
+        let usage = Usage { prompt_tokens, completion_tokens: 0, estimated: true };
+        self.usage.record(&model, None, &usage);
+        self.metrics.record_tokens(&model, &usage);
+        self.metrics.record_request(backend_name, &model, "success", started.elapsed());
+        self.breaker.record_success();
+        self.resources.updated(resources::USAGE_TODAY_URI).await;
+        if images.is_empty() {
+            return Err(errors::internal("Imagen returned no images; the prompt may have been filtered"));
+        }
+
+        tokio::fs::create_dir_all(&dir).await
+            .map_err(|e| errors::internal(format!("Failed to create {}: {}", dir.display(), e)))?;
+
+        // Unique per call, so concurrent calls never overwrite each other's files
+        let stamp = format!(
+            "{}-{}",
+            chrono::Utc::now().format("%Y%m%d-%H%M%S"),
+            &uuid::Uuid::new_v4().simple().to_string()[..8]
+        );
+        let mut contents = Vec::with_capacity(images.len() + 1);
+        let mut paths = Vec::with_capacity(images.len());
+        for (index, image) in images.into_iter().enumerate() {
+            let extension = image.mime_type.strip_prefix("image/").unwrap_or("png");
+            let path = dir.join(format!("{}-{}.{}", stamp, index + 1, extension));
+            tokio::fs::write(&path, &image.data).await
+                .map_err(|e| errors::internal(format!("Failed to write {}: {}", path.display(), e)))?;
+
+            contents.push(Content::image(
+                base64::engine::general_purpose::STANDARD.encode(&image.data),
+                image.mime_type,
+            ));
+            paths.push(path.display().to_string());
+        }
+
+        contents.push(Content::json(serde_json::json!({
+            "model": model,
+            "paths": paths,
+            "redactions": redactions,
+            "usage": usage,
+        }))?);
+        Ok(CallToolResult::success(contents))
+    }
+
+    #[tool(description = "List available Gemini models with their context window sizes and supported modalities")]
+    async fn gemini_list_models(&self) -> Result<CallToolResult, McpError> {
+        // Prefer the live listing, but fall back to the built-in registry when it is unavailable
+        let (source, models) = match self.backend.api() {
+            Some(api) => match api.list_models().await {
+                Ok(models) => ("api", models),
+                Err(e) => {
+                    tracing::warn!("Failed to list models from the API, using built-in registry: {}", e);
+                    ("registry", models::registry())
+                }
+            },
+            None => ("registry", models::registry()),
+        };
+        let models: Vec<_> = models.into_iter().filter(|model| self.config.model_policy.permits(&model.name)).collect();
+
+        Ok(CallToolResult::success(vec![Content::json(serde_json::json!({
+            "source": source,
+            "default_model": self.live.resolve_model(None).as_deref().unwrap_or(backend::DEFAULT_MODEL),
+            "aliases": self.live.model_aliases(),
+            "models": models,
+        }))?]))
+    }
+
+    #[tool(description = "Count how many tokens a prompt occupies and whether it fits the model's context window")]
+    async fn gemini_count_tokens(
+        &self,
+        Parameters(GeminiCountTokensArgs { text, model }): Parameters<GeminiCountTokensArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let model = self.live.resolve_model(model)
+            .unwrap_or_else(|| backend::DEFAULT_MODEL.to_string());
+        // Counted as it would be sent, which is also all the API gets to see
+        let (text, redactions) = self.scrub(text);
+
+        // Use the exact count when the API is reachable, otherwise estimate locally
+        let (method, total_tokens) = match self.backend.api() {
+            Some(api) => match api.count_tokens(&model, &text).await {
+                Ok(count) => ("api", count),
+                Err(e) => {
+                    tracing::warn!("countTokens failed, falling back to an estimate: {:#}", e);
+                    ("estimate", tokens::estimate(&text))
+                }
+            },
+            None => ("estimate", tokens::estimate(&text)),
+        };
+
+        let input_token_limit = models::lookup(&model).map(|info| info.input_token_limit);
+
+        Ok(CallToolResult::success(vec![Content::json(serde_json::json!({
+            "model": model,
+            "total_tokens": total_tokens,
+            "method": method,
+            "input_token_limit": input_token_limit,
+            "fits": input_token_limit.map(|limit| total_tokens <= limit),
+            "redactions": redactions,
+        }))?]))
+    }
+
+    #[tool(description = "Report token usage and estimated cost per day, per model and per chat session")]
+    async fn gemini_usage_report(
+        &self,
+        Parameters(GeminiUsageReportArgs { days, session_id }): Parameters<GeminiUsageReportArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let report = self.usage.report(days.unwrap_or(7), session_id.as_deref());
+        Ok(CallToolResult::success(vec![Content::json(report)?]))
+    }
+
+    #[tool(description = "Diagnose the server's setup: gemini binary, version, authentication and a test prompt, with hints for fixing problems")]
+    async fn gemini_doctor(
+        &self,
+        Parameters(GeminiDoctorArgs { skip_prompt }): Parameters<GeminiDoctorArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let mut checks = doctor::check_binary(&self.config).await;
+        checks.extend(doctor::check_auth(&self.config));
+        checks.extend(doctor::check_vertex(&self.config));
+
+        // Only worth trying when nothing above is already known to be broken
+        if !skip_prompt && doctor::healthy(&checks) {
+            let request = GenerateRequest {
+                prompt: doctor::TEST_PROMPT.to_string(),
+                model: self.live.resolve_model(None),
+                ..Default::default()
+            };
+            let muted = ProgressReporter::muted(&context);
+            let call = CallOptions {
+                tool: "gemini_doctor",
+                timeout_secs: Some(60),
+                progress: Some(&muted),
+                ..Default::default()
+            };
+
+            let started = std::time::Instant::now();
+            checks.push(match self.run_generation(&request, call, &context).await {
+                Ok(response) => doctor::Check::ok(
+                    "test_prompt",
+                    format!("{} backend answered {:?} in {}ms", self.backend.name(), response.text, started.elapsed().as_millis()),
+                ),
+                Err(error) => doctor::Check::error(
+                    "test_prompt",
+                    error.message.to_string(),
+                    "Run the same prompt with the gemini CLI directly to see its full output",
+                ),
+            });
+        }
+
+        let healthy = doctor::healthy(&checks);
+        Ok(CallToolResult::success(vec![
+            Content::text(doctor::summary(&checks)),
+            Content::json(serde_json::json!({
+                "healthy": healthy,
+                "backend": self.backend.name(),
+                "checks": checks,
+            }))?,
+        ]))
+    }
+
+    #[tool(description = "Configure Gemini CLI settings")]
+    async fn gemini_config(
+        &self,
+        Parameters(GeminiConfigArgs { api_key }): Parameters<GeminiConfigArgs>,
+    ) -> Result<String, McpError> {
+        // Note: gemini CLI configuration is typically done through environment variables
+        if let Some(_key) = api_key {
+            Ok("Note: Gemini API key should be set via GOOGLE_API_KEY environment variable".to_string())
+        } else {
+            Ok(format!(
+                "Gemini configuration:\n- Config file: {}\n- Backend: {} (set via `backend` in config.toml or GEMINI_BACKEND: cli or api)\n- API key: Set via GOOGLE_API_KEY environment variable\n- Model: {} (set via `default_model` in config.toml, GEMINI_MODEL or --model)\n- Timeout: {}s",
+                self.config.source.as_ref().map(|p| p.display().to_string()).unwrap_or_else(|| "none".to_string()),
+                self.backend.name(),
+                self.live.resolve_model(None).as_deref().unwrap_or(backend::DEFAULT_MODEL),
+                self.live.timeout().as_secs(),
+            ))
+        }
+    }
+
+    #[tool(description = "Fetch the next part of a response that was truncated because it was too long")]
+    async fn gemini_fetch_continuation(
+        &self,
+        Parameters(GeminiFetchContinuationArgs { token, max_bytes }): Parameters<GeminiFetchContinuationArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let page_bytes = max_bytes.unwrap_or(self.config.max_response_bytes);
+        let Some((mut text, continuation)) = self.continuations.next(&token, page_bytes) else {
+            return Err(errors::invalid_argument(
+                format!("Unknown or expired continuation token '{}'", token),
+            ));
+        };
+        if let Some(continuation) = &continuation {
+            text.push_str(&continuation_note(continuation));
+        }
+
+        Ok(CallToolResult::success(vec![
+            Content::text(text),
+            Content::json(serde_json::json!({ "continuation": continuation }))?,
+        ]))
+    }
+
+    #[tool(description = "Continue a conversation started in the gemini CLI, e.g. in a terminal; call without conversation_id to list the ones that can be resumed")]
+    async fn gemini_resume(
+        &self,
+        Parameters(GeminiResumeArgs { conversation_id, message, cwd, model, timeout_secs, profile, priority }): Parameters<GeminiResumeArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let cli = self.cli_backend("resume")?;
+        let scope = self.scope(&context, cwd.is_some()).await?;
+        let cwd = working_dir(cwd, &scope)?;
+
+        let Some(conversation_id) = conversation_id else {
+            self.check_live("gemini_resume")?;
+            let progress = ProgressReporter::new(&context);
+            let _permit = self.scheduler.acquire(priority.unwrap_or_else(|| self.config.priority("gemini_resume")), Some(&progress), &context.ct).await
+                .map_err(command_error)?;
+            let options = self.exec_options("gemini_resume", timeout_secs, &context);
+            let listing = cli.list_conversations(cwd.as_deref(), options).await
+                .map_err(command_error)?;
+            return Ok(CallToolResult::success(vec![Content::json(listing)?]));
+        };
+        let Some(message) = message else {
+            return Err(errors::invalid_argument("message is required to resume a conversation"));
+        };
+
+        let request = GenerateRequest {
+            prompt: message,
+            model: self.live.resolve_model(model),
+            cwd,
+            resume: Some(conversation_id),
+            ..Default::default()
+        };
+
+        tracing::info!("Resuming gemini CLI conversation {:?}", request.resume);
+
+        let call = CallOptions {
+            tool: "gemini_resume",
+            profile: profile.as_deref(),
+            timeout_secs,
+            priority,
+            // Another model's context window may not fit the conversation
+            pin_model: true,
+            ..Default::default()
+        };
+        let response = self.run_generation(&request, call, &context).await?;
+
+        let (text, continuation) = self.paginate(response.text);
+        Ok(CallToolResult::success(vec![
+            Content::text(text),
+            Content::json(serde_json::json!({
+                "conversation_id": request.resume,
+                "model": request.model,
+                "continuation": continuation,
+                "retries": response.retries,
+                "warnings": response.warnings,
+                "redactions": response.redactions,
+                "usage": response.usage,
+            }))?,
+        ]))
+    }
+
+    #[tool(description = "Answer with Gemini, then have the calling client's own model critique the answer through MCP sampling and Gemini revise it, for up to 3 rounds")]
+    async fn gemini_cross_check(
+        &self,
+        Parameters(GeminiCrossCheckArgs { prompt, rounds, model, timeout_secs, profile, priority }): Parameters<GeminiCrossCheckArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        if !self.sampling_available(&context) {
+            return Err(command_error(sampling::SamplingUnavailableError.into()));
+        }
+        let rounds = rounds.unwrap_or(1);
+        if !(1..=crosscheck::MAX_ROUNDS).contains(&rounds) {
+            return Err(errors::invalid_argument(
+                format!("rounds must be between 1 and {}", crosscheck::MAX_ROUNDS),
+            ));
+        }
+
+        let model = self.live.resolve_model(model);
+        let system_instruction = self.config.system_instruction.clone();
+        let progress = ProgressReporter::new(&context);
+
+        tracing::info!("Cross-checking a Gemini answer with the client's model, up to {} round(s)", rounds);
+
+        let generate = |prompt: String| {
+            let request = GenerateRequest {
+                prompt,
+                model: model.clone(),
+                system_instruction: system_instruction.clone(),
+                ..Default::default()
+            };
+            let call = CallOptions {
+                tool: "gemini_cross_check",
+                profile: profile.as_deref(),
+                timeout_secs,
+                priority,
+                progress: Some(&progress),
+                ..Default::default()
+            };
+            let context = &context;
+            async move { self.run_generation(&request, call, context).await }
+        };
+
+        let mut response = generate(prompt.clone()).await?;
+        let mut usage = response.usage;
+        let mut history = Vec::new();
+        let mut approved = false;
+        for round in 1..=rounds {
+            if let Some(block) = response.safety {
+                return safety_blocked(block, serde_json::json!({ "model": model, "rounds": history }));
+            }
+
+            progress.report(format!("Asking the client's model to review the answer (round {} of {})", round, rounds)).await;
+            let critique = sampling::ask(
+                &context,
+                crosscheck::REVIEWER_INSTRUCTION,
+                crosscheck::critique_prompt(&prompt, &response.text),
+            ).await
+                .map_err(command_error)?;
+            approved = crosscheck::approves(&critique);
+            history.push(serde_json::json!({ "answer": response.text.clone(), "critique": critique.clone() }));
+            if approved {
+                break;
+            }
+
+            progress.report(format!("Gemini is revising its answer (round {} of {})", round, rounds)).await;
+            response = generate(crosscheck::revision_prompt(&prompt, &response.text, &critique)).await?;
+            usage += response.usage;
+        }
+        if let Some(block) = response.safety {
+            return safety_blocked(block, serde_json::json!({ "model": model, "rounds": history }));
+        }
+
+        let (text, continuation) = self.paginate(response.text);
+        Ok(CallToolResult::success(vec![
+            Content::text(text),
+            Content::json(serde_json::json!({
+                "model": model,
+                "approved": approved,
+                "rounds": history,
+                "continuation": continuation,
+                "usage": usage,
+            }))?,
+        ]))
+    }
+
+    #[tool(description = "Search the audit log of past tool calls by text, tool, status and time")]
+    async fn gemini_audit_search(
+        &self,
+        Parameters(GeminiAuditSearchArgs { query, tool, request_id, status, since, limit }): Parameters<GeminiAuditSearchArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let Some(audit) = &self.audit else {
+            return Err(errors::invalid_argument("Audit logging is disabled; set audit_log to enable it"));
+        };
+        let since = match since.as_deref() {
+            Some(since) => Some(parse_since(since)?),
+            None => None,
+        };
+        let limit = limit.unwrap_or(50).clamp(1, MAX_AUDIT_RESULTS);
+
+        // The whole log is scanned, which could take a while for a large one
+        let audit = audit.clone();
+        let entries = tokio::task::spawn_blocking(move || {
+            audit.search(&AuditQuery {
+                text: query.as_deref(),
+                tool: tool.as_deref(),
+                request_id: request_id.as_deref(),
+                status: status.as_deref(),
+                since,
+                limit,
+            })
+        })
+        .await
+        .map_err(|e| errors::internal(e.to_string()))?
+        .map_err(|e| errors::internal(format!("{:#}", e)))?;
+
+        Ok(CallToolResult::success(vec![Content::json(serde_json::json!({
+            "count": entries.len(),
+            "entries": entries,
+        }))?]))
+    }
+
+    #[tool(description = "Fetch a web page on the server and have Gemini summarize it, for clients that cannot browse the web themselves")]
+    async fn gemini_summarize_url(
+        &self,
+        Parameters(GeminiSummarizeUrlArgs { url, focus, model, timeout_secs, profile, priority }): Parameters<GeminiSummarizeUrlArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let page = tokio::select! {
+            page = self.fetcher.fetch(&url) => page.map_err(command_error)?,
+            _ = context.ct.cancelled() => return Err(command_error(CancelledError.into())),
+        };
+        tracing::info!("Fetched {} bytes of {} from {}", page.bytes, page.content_type, page.url);
+
+        let request = GenerateRequest {
+            prompt: fetch::summary_prompt(&page, focus.as_deref()),
+            model: self.live.resolve_model(model),
+            system_instruction: Some(fetch::SUMMARY_INSTRUCTION.to_string()),
+            ..Default::default()
+        };
+
+        let call = CallOptions {
+            tool: "gemini_summarize_url",
+            profile: profile.as_deref(),
+            timeout_secs,
+            priority,
+            ..Default::default()
+        };
+        let response = self.run_generation(&request, call, &context).await?;
+
+        let mut metadata = with_fields(generation_metadata(&response, request.model.as_deref()), serde_json::json!({
+            "url": page.url,
+            "title": page.title,
+            "content_type": page.content_type,
+            "fetched_bytes": page.bytes,
+        }));
+        if let Some(block) = response.safety {
+            return safety_blocked(block, metadata);
+        }
+
+        let (text, continuation) = self.paginate(response.text);
+        metadata["continuation"] = serde_json::json!(continuation);
+        Ok(CallToolResult::success(vec![
+            Content::text(text),
+            Content::json(metadata)?,
+        ]))
+    }
+
+    #[tool(description = "Search the web with the gemini CLI's google_web_search tool and answer from the results, returning the answer with the titles and URLs it used")]
+    async fn gemini_web_search(
+        &self,
+        Parameters(GeminiWebSearchArgs { query, model, timeout_secs, profile, priority }): Parameters<GeminiWebSearchArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        // Only the CLI has the search tool; the API gets the same answer through grounding
+        let via_cli = self.backend.cli().is_some();
+        let request = if via_cli {
+            GenerateRequest {
+                prompt: websearch::search_prompt(&query),
+                model: self.live.resolve_model(model),
+                system_instruction: self.config.system_instruction.clone(),
+                ..Default::default()
+            }
+        } else {
+            GenerateRequest {
+                prompt: query,
+                model: self.live.resolve_model(model),
+                system_instruction: self.config.system_instruction.clone(),
+                google_search: true,
+                ..Default::default()
+            }
+        };
+
+        tracing::info!("Calling gemini with web search via {} backend", self.backend.name());
+
+        let call = CallOptions {
+            tool: "gemini_web_search",
+            profile: profile.as_deref(),
+            timeout_secs,
+            priority,
+            ..Default::default()
+        };
+        let response = self.run_generation(&request, call, &context).await?;
+
+        if let Some(block) = response.safety {
+            return safety_blocked(block, serde_json::json!({
+                "model": request.model,
+                "usage": response.usage,
+            }));
+        }
+
+        let metadata = generation_metadata(&response, request.model.as_deref());
+        let (answer, results, search_queries) = if via_cli {
+            let (answer, results) = websearch::split_sources(&response.text);
+            (answer, results, Vec::new())
+        } else {
+            let grounding = response.grounding.unwrap_or_default();
+            (response.text, grounding.citations, grounding.search_queries)
+        };
 
-#[derive(Debug, Deserialize, schemars::JsonSchema)]
-struct GeminiPromptArgs {
-    #[schemars(description = "The prompt to send to Gemini")]
-    prompt: String,
-    #[schemars(description = "The model to use (optional)")]
-    #[serde(default)]
-    model: Option<String>,
-    #[schemars(description = "Maximum number of tokens (optional)")]
-    #[serde(default)]
-    max_tokens: Option<u32>,
-    #[schemars(description = "Temperature for sampling (optional)")]
-    #[serde(default)]
-    temperature: Option<f32>,
-}
+        let (text, continuation) = self.paginate(answer);
+        Ok(CallToolResult::success(vec![
+            Content::text(text),
+            Content::json(with_fields(metadata, serde_json::json!({
+                "search": if via_cli { websearch::SEARCH_TOOL } else { "grounding" },
+                "results": results,
+                "search_queries": search_queries,
+                "continuation": continuation,
+            })))?,
+        ]))
+    }
 
-#[derive(Debug, Deserialize, schemars::JsonSchema)]
-struct GeminiConfigArgs {
-    #[schemars(description = "API key for Gemini (optional)")]
-    #[serde(default)]
-    api_key: Option<String>,
-}
+    #[tool(description = "List the gemini CLI's installed extensions and whether each is enabled")]
+    async fn gemini_list_extensions(
+        &self,
+        Parameters(GeminiListExtensionsArgs { cwd }): Parameters<GeminiListExtensionsArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let cli = self.cli_backend("extensions")?;
+        let scope = self.scope(&context, cwd.is_some()).await?;
+        let cwd = working_dir(cwd, &scope)?;
+        self.check_live("gemini_list_extensions")?;
+
+        let _permit = self.scheduler.acquire(self.config.priority("gemini_list_extensions"), None, &context.ct).await
+            .map_err(command_error)?;
+        let listing = cli.list_extensions(cwd.as_deref(), self.exec_options("gemini_list_extensions", None, &context)).await
+            .map_err(|e| self.cli_error(e))?;
+        Ok(CallToolResult::success(vec![Content::json(listing)?]))
+    }
+
+    #[tool(description = "Install a gemini CLI extension from a git repository or local path, e.g. to add capabilities later prompts need; requires extension_management")]
+    async fn gemini_install_extension(
+        &self,
+        Parameters(GeminiInstallExtensionArgs { source, git_ref }): Parameters<GeminiInstallExtensionArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        self.check_extension_management()?;
+        let cli = self.cli_backend("extensions")?;
+        let local = !source.contains("://") && !source.starts_with("git@");
+        if local {
+            let scope = self.scope(&context, true).await?;
+            scope.check(std::path::Path::new(&source))
+                .map_err(command_error)?;
+        }
 
+        // Enabling extension_management is the operator's consent, so skip the CLI's prompt
+        let mut args = vec!["install".to_string(), source.clone(), "--consent".to_string()];
+        if let Some(git_ref) = git_ref {
+            args.extend(["--ref".to_string(), git_ref]);
+        }
+        if self.config.dry_run {
+            return plan_result(cli.plan_extensions(&args, None));
+        }
+        self.check_live("gemini_install_extension")?;
+        tracing::info!("Installing gemini CLI extension from {}", source);
 
-async fn run_gemini_command(args: Vec<String>) -> Result<String> {
-    use tokio::process::Command;
+        let _permit = self.scheduler.acquire(self.config.priority("gemini_install_extension"), None, &context.ct).await
+            .map_err(command_error)?;
+        let output = cli.manage_extensions(&args, None, self.exec_options("gemini_install_extension", None, &context)).await
+            .map_err(|e| self.cli_error(e))?;
+        Ok(CallToolResult::success(vec![Content::text(output)]))
+    }
 
-    tracing::debug!("Running gemini command with args: {:?}", args);
+    #[tool(description = "Enable or disable an installed gemini CLI extension, for every project or one workspace; requires extension_management")]
+    async fn gemini_enable_extension(
+        &self,
+        Parameters(GeminiEnableExtensionArgs { name, enabled, scope: setting_scope, cwd }): Parameters<GeminiEnableExtensionArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        self.check_extension_management()?;
+        let cli = self.cli_backend("extensions")?;
+        let setting_scope = setting_scope.unwrap_or_else(|| "user".to_string());
+        if !matches!(setting_scope.as_str(), "user" | "workspace") {
+            return Err(errors::invalid_argument(
+                format!("Unknown scope {:?}; expected user or workspace", setting_scope),
+            ));
+        }
+        let scope = self.scope(&context, cwd.is_some()).await?;
+        let cwd = working_dir(cwd, &scope)?;
 
-    let mut cmd = Command::new("gemini");
+        let action = if enabled.unwrap_or(true) { "enable" } else { "disable" };
+        let args = vec![action.to_string(), name.clone(), "--scope".to_string(), setting_scope];
+        if self.config.dry_run {
+            return plan_result(cli.plan_extensions(&args, cwd.as_deref()));
+        }
+        self.check_live("gemini_enable_extension")?;
+        tracing::info!("Running gemini extensions {} {}", action, name);
 
-    // Set environment variables from .env if they exist
-    if let Ok(api_key) = std::env::var("GOOGLE_API_KEY") {
-        cmd.env("GOOGLE_API_KEY", api_key);
+        let _permit = self.scheduler.acquire(self.config.priority("gemini_enable_extension"), None, &context.ct).await
+            .map_err(command_error)?;
+        let output = cli.manage_extensions(&args, cwd.as_deref(), self.exec_options("gemini_enable_extension", None, &context)).await
+            .map_err(|e| self.cli_error(e))?;
+        Ok(CallToolResult::success(vec![Content::text(output)]))
     }
-    if let Ok(project) = std::env::var("GOOGLE_CLOUD_PROJECT") {
-        cmd.env("GOOGLE_CLOUD_PROJECT", project);
+
+    #[tool(description = "Summarize many files, even whole repositories larger than a context window: each file or chunk is summarized on its own with a fast model, then the summaries are combined with a stronger one")]
+    async fn gemini_summarize_files(
+        &self,
+        Parameters(GeminiSummarizeFilesArgs {
+            files, focus, cwd, map_model, reduce_model, chunk_tokens, max_parallel, timeout_secs, profile, priority,
+        }): Parameters<GeminiSummarizeFilesArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        if files.is_empty() {
+            return Err(errors::invalid_argument("files must not be empty"));
+        }
+        let chunk_tokens = chunk_tokens.unwrap_or(mapreduce::DEFAULT_CHUNK_TOKENS);
+        if chunk_tokens < mapreduce::MIN_CHUNK_TOKENS {
+            return Err(errors::invalid_argument(format!("chunk_tokens must be at least {}", mapreduce::MIN_CHUNK_TOKENS)));
+        }
+
+        let scope = self.scope(&context, true).await?;
+        let cwd = working_dir(cwd, &scope)?;
+        let attachments = self.attach(&files, cwd.as_deref(), &scope).await?;
+        let chunks = mapreduce::split(&attachments, chunk_tokens);
+        if chunks.len() > mapreduce::MAX_CHUNKS {
+            return Err(errors::invalid_argument(format!(
+                "The files make {} chunks, more than the {} one call may summarize; raise chunk_tokens or pass fewer files",
+                chunks.len(), mapreduce::MAX_CHUNKS
+            )));
+        }
+
+        let map_model = self.live.resolve_model(Some(map_model.unwrap_or_else(|| mapreduce::MAP_MODEL.to_string())));
+        let reduce_model = self.live.resolve_model(Some(reduce_model.unwrap_or_else(|| mapreduce::REDUCE_MODEL.to_string())));
+        let parallel = max_parallel.unwrap_or(self.config.max_concurrency).clamp(1, self.config.max_concurrency.max(1));
+        let total = chunks.len();
+
+        tracing::info!("Summarizing {} files in {} chunks, {} at a time", attachments.len(), total, parallel);
+
+        // Interleaved output from several requests would be unreadable, so only completions are reported
+        let progress = ProgressReporter::new(&context);
+        let muted = ProgressReporter::muted(&context);
+        let generate = |prompt: String, model: Option<String>| {
+            let request = GenerateRequest {
+                prompt,
+                model,
+                system_instruction: self.config.system_instruction.clone(),
+                ..Default::default()
+            };
+            let call = CallOptions {
+                tool: "gemini_summarize_files",
+                profile: profile.as_deref(),
+                timeout_secs,
+                priority,
+                progress: Some(&muted),
+                ..Default::default()
+            };
+            let context = &context;
+            async move { self.run_generation(&request, call, context).await }
+        };
+
+        let mut usage = Usage::default();
+        let mut add_usage = |response: &GenerateResponse| {
+            usage += response.usage;
+        };
+
+        // Map: every chunk on its own; failed chunks are reported and left out
+        let mut pending = futures::stream::iter(chunks.iter().enumerate())
+            .map(|(index, chunk)| {
+                let generated = generate(mapreduce::map_prompt(chunk, focus.as_deref()), map_model.clone());
+                async move { (index, generated.await) }
+            })
+            .buffer_unordered(parallel);
+        let mut summaries: Vec<Option<String>> = vec![None; total];
+        let mut failed = Vec::new();
+        let mut done = 0;
+        while let Some((index, outcome)) = pending.next().await {
+            let label = chunks[index].label();
+            match outcome {
+                Ok(response) => {
+                    add_usage(&response);
+                    match response.safety {
+                        None => summaries[index] = Some(format!("## {}\n{}", label, response.text.trim())),
+                        Some(block) => failed.push(serde_json::json!({
+                            "chunk": label,
+                            "error": format!("Gemini blocked the {} for safety reasons ({})", block.blocked, block.reason),
+                        })),
+                    }
+                }
+                Err(e) => failed.push(serde_json::json!({ "chunk": label, "error": e.message, "data": e.data })),
+            }
+            done += 1;
+            progress.report(format!("Summarized {} of {} chunks", done, total)).await;
+        }
+        drop(pending);
+        let mut level: Vec<String> = summaries.into_iter().flatten().collect();
+        if level.is_empty() {
+            return Err(errors::error(
+                ErrorKind::BackendFailed,
+                "Every chunk failed to summarize",
+                serde_json::json!({ "failed": failed }),
+            ));
+        }
+
+        // Reduce: combine in groups that fit a chunk until one final pass can take them all
+        let mut reduce_passes = 0;
+        let summary = loop {
+            let groups = mapreduce::groups(std::mem::take(&mut level), chunk_tokens);
+            let merging = groups.len() > 1 && groups.iter().any(|group| group.len() > 1);
+            if !merging || reduce_passes == mapreduce::MAX_REDUCE_PASSES {
+                progress.report("Combining the summaries".to_string()).await;
+                let summaries: Vec<String> = groups.into_iter().flatten().collect();
+                let response = generate(mapreduce::reduce_prompt(&summaries, focus.as_deref(), true), reduce_model.clone()).await?;
+                add_usage(&response);
+                if let Some(block) = response.safety {
+                    return safety_blocked(block, serde_json::json!({ "model": reduce_model, "failed": failed }));
+                }
+                break response.text;
+            }
+
+            reduce_passes += 1;
+            progress.report(format!("Combining {} groups of summaries (pass {})", groups.len(), reduce_passes)).await;
+            let combined = futures::future::join_all(groups.iter().map(|group| {
+                generate(mapreduce::reduce_prompt(group, focus.as_deref(), false), reduce_model.clone())
+            })).await;
+            for (group, outcome) in groups.into_iter().zip(combined) {
+                let response = outcome?;
+                add_usage(&response);
+                // A blocked group keeps its parts for the next pass rather than losing them
+                match response.safety {
+                    None => level.push(response.text),
+                    Some(_) => level.extend(group),
+                }
+            }
+        };
+
+        let (text, continuation) = self.paginate(summary);
+        Ok(CallToolResult::success(vec![
+            Content::text(text),
+            Content::json(serde_json::json!({
+                "files": attachments.len(),
+                "chunks": total,
+                "map_model": map_model,
+                "reduce_model": reduce_model,
+                "reduce_passes": reduce_passes,
+                "failed": failed,
+                "continuation": continuation,
+                "usage": usage,
+            }))?,
+        ]))
     }
 
-    let mut child = cmd
-        .args(&args)
-        .stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::piped())
-        .stderr(std::process::Stdio::piped())
-        .spawn()
-        .context("Failed to spawn gemini command")?;
+    #[tool(description = "Have Gemini write a file, or rewrite an existing one, from instructions; returns a diff against the current contents and, with write, saves the result after backing up the old file")]
+    async fn gemini_generate_file(
+        &self,
+        Parameters(GeminiGenerateFileArgs { path, instructions, files, write, cwd, model, timeout_secs, profile, priority }): Parameters<GeminiGenerateFileArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let scope = self.scope(&context, true).await?;
+        let cwd = working_dir(cwd, &scope)?;
+        let target = files::resolve(cwd.as_deref(), &path);
+        // Refuse before spending a generation on a file that can't be saved
+        if write {
+            scope.check_writable(&target).map_err(command_error)?;
+        }
+        let existing = if target.exists() {
+            let mut current = files::read_all(std::slice::from_ref(&path), cwd.as_deref(), self.config.max_file_bytes, &scope).await
+                .map_err(command_error)?;
+            Some(current.remove(0).contents)
+        } else {
+            None
+        };
+        let references = self.attach(&files, cwd.as_deref(), &scope).await?;
+
+        let request = GenerateRequest {
+            prompt: codegen::prompt(&path, &instructions, existing.as_deref(), &references),
+            model: self.live.resolve_model(model),
+            system_instruction: self.config.system_instruction.clone(),
+            ..Default::default()
+        };
+        let call = CallOptions {
+            tool: "gemini_generate_file",
+            profile: profile.as_deref(),
+            timeout_secs,
+            priority,
+            ..Default::default()
+        };
+        let response = self.run_generation(&request, call, &context).await?;
+        if let Some(block) = response.safety {
+            return safety_blocked(block, serde_json::json!({ "model": request.model, "usage": response.usage }));
+        }
+        // A dry run's text is the command, which is neither code nor worth saving
+        if self.config.dry_run {
+            return Ok(CallToolResult::success(vec![Content::text(response.text)]));
+        }
 
-    // Close stdin to signal EOF
-    if let Some(stdin) = child.stdin.take() {
-        drop(stdin);
+        let contents = codegen::extract(&response.text);
+        let diff = diff::unified(&path, existing.as_deref(), &contents);
+        let changed = !diff.is_empty();
+        let backup = if write && changed {
+            codegen::write(&target, &contents).map_err(command_error)?
+        } else {
+            None
+        };
+        let summary = if diff.is_empty() { format!("{} is unchanged", path) } else { diff };
+        Ok(CallToolResult::success(vec![
+            Content::text(summary),
+            Content::json(with_fields(generation_metadata(&response, request.model.as_deref()), serde_json::json!({
+                "path": path,
+                "created": existing.is_none(),
+                "changed": changed,
+                "written": write && changed,
+                "backup": backup.map(|backup| backup.display().to_string()),
+                "contents": contents,
+            })))?,
+        ]))
     }
 
-    let output = child.wait_with_output().await
-        .context("Failed to wait for gemini command")?;
+    #[tool(description = "Ask Gemini for a change as a unified diff and check that it applies cleanly; applies it only with apply, so a diff can be reviewed first and passed back as patch to apply it")]
+    async fn gemini_propose_patch(
+        &self,
+        Parameters(GeminiProposePatchArgs { instructions, files, patch, apply, cwd, model, timeout_secs, profile, priority }): Parameters<GeminiProposePatchArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let scope = self.scope(&context, true).await?;
+        let cwd = working_dir(cwd, &scope)?;
+
+        let (patch, response) = match patch {
+            Some(patch) => (patch, None),
+            None => {
+                let Some(instructions) = instructions.filter(|instructions| !instructions.trim().is_empty()) else {
+                    return Err(errors::invalid_argument("instructions are required unless a patch is given"));
+                };
+                if files.is_empty() {
+                    return Err(errors::invalid_argument("files must not be empty"));
+                }
+                let attachments = self.attach(&files, cwd.as_deref(), &scope).await?;
 
-    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_owned();
-    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+                let request = GenerateRequest {
+                    prompt: codegen::patch_prompt(&instructions, &attachments),
+                    model: self.live.resolve_model(model),
+                    system_instruction: self.config.system_instruction.clone(),
+                    ..Default::default()
+                };
+                let call = CallOptions {
+                    tool: "gemini_propose_patch",
+                    profile: profile.as_deref(),
+                    timeout_secs,
+                    priority,
+                    ..Default::default()
+                };
+                let mut response = self.run_generation(&request, call, &context).await?;
+                if let Some(block) = response.safety.take() {
+                    return safety_blocked(block, serde_json::json!({ "model": request.model, "usage": response.usage }));
+                }
+                if self.config.dry_run {
+                    return Ok(CallToolResult::success(vec![Content::text(response.text)]));
+                }
+                (codegen::extract(&response.text), Some((request.model, response)))
+            }
+        };
+        let generation = response.as_ref().map(|(model, response)| generation_metadata(response, model.as_deref()));
 
-    tracing::debug!("Command stdout: {}", stdout);
-    tracing::debug!("Command stderr: {}", stderr);
+        let patches = match diff::parse(&patch) {
+            Ok(patches) => patches,
+            // A diff the caller wrote is their mistake; one from Gemini is reported like a failed check
+            Err(e) if response.is_none() => return Err(errors::invalid_argument(format!("Invalid patch: {}", e))),
+            Err(e) => {
+                return Ok(CallToolResult::success(vec![
+                    Content::text(patch.clone()),
+                    Content::json(serde_json::json!({
+                        "patch": patch,
+                        "applies": false,
+                        "applied": false,
+                        "error": e,
+                        "files": [],
+                        "generation": generation,
+                    }))?,
+                ]));
+            }
+        };
 
-    if output.status.success() {
-        Ok(stdout)
-    } else {
-        anyhow::bail!(
-            "Gemini command failed: {}",
-            stderr
-        )
+        // Dry run every file before anything is written
+        let mut results = Vec::with_capacity(patches.len());
+        let mut changes = Vec::with_capacity(patches.len());
+        for file_patch in &patches {
+            let source = file_patch.old_path.as_deref().map(|path| files::resolve(cwd.as_deref(), path));
+            let target = file_patch.new_path.as_deref().map(|path| files::resolve(cwd.as_deref(), path));
+            let original = match (&file_patch.old_path, &source) {
+                (Some(path), Some(source)) if source.exists() => {
+                    let mut read = files::read_all(std::slice::from_ref(path), cwd.as_deref(), self.config.max_file_bytes, &scope).await
+                        .map_err(command_error)?;
+                    Some(read.remove(0).contents)
+                }
+                _ => None,
+            };
+            if let Some(target) = &target {
+                scope.check(target).map_err(command_error)?;
+            }
+            let status = match (&file_patch.old_path, &file_patch.new_path) {
+                (None, _) => "created",
+                (_, None) => "deleted",
+                (Some(old), Some(new)) if old != new => "renamed",
+                _ => "modified",
+            };
+            let (added, removed) = file_patch.stats();
+            let outcome = file_patch.apply(original.as_deref());
+            results.push(serde_json::json!({
+                "path": file_patch.path(),
+                "old_path": file_patch.old_path,
+                "status": status,
+                "added": added,
+                "removed": removed,
+                "applies": outcome.is_ok(),
+                "error": outcome.as_ref().err(),
+            }));
+            if let Ok(patched) = outcome {
+                changes.push((source, target, patched));
+            }
+        }
+        let applies = changes.len() == patches.len();
+
+        let mut backups = Vec::new();
+        if apply && applies {
+            for (source, target, _) in &changes {
+                for path in [source, target].into_iter().flatten() {
+                    scope.check_writable(path).map_err(command_error)?;
+                }
+            }
+            for (source, target, patched) in changes {
+                let (backup, moved) = match (target, patched) {
+                    (Some(target), Some(contents)) => {
+                        let backup = codegen::write(&target, &contents).map_err(command_error)?;
+                        // The old name of a renamed file goes away
+                        let moved = match source {
+                            Some(source) if source != target => Some(codegen::remove(&source).map_err(command_error)?),
+                            _ => None,
+                        };
+                        (backup, moved)
+                    }
+                    _ => (source.as_deref().map(codegen::remove).transpose().map_err(command_error)?, None),
+                };
+                backups.extend(backup.into_iter().chain(moved).map(|backup| backup.display().to_string()));
+            }
+        }
+
+        let summary = if !applies {
+            format!("The patch doesn't apply cleanly:\n\n{}", patch)
+        } else if apply {
+            format!("Applied the patch to {} file(s):\n\n{}", patches.len(), patch)
+        } else {
+            patch.clone()
+        };
+        Ok(CallToolResult::success(vec![
+            Content::text(summary),
+            Content::json(serde_json::json!({
+                "patch": patch,
+                "applies": applies,
+                "applied": apply && applies,
+                "files": results,
+                "backups": backups,
+                "generation": generation,
+            }))?,
+        ]))
     }
-}
 
-#[derive(Clone)]
-struct GeminiServer {
-    tool_router: ToolRouter<Self>,
-}
+    #[tool(description = "Have Gemini write unit tests for source files, using the project's test framework; returns the test files with diffs and, with write, saves them")]
+    async fn gemini_generate_tests(
+        &self,
+        Parameters(GeminiGenerateTestsArgs {
+            files, framework, context_files, instructions, write, cwd, model, timeout_secs, profile, priority,
+        }): Parameters<GeminiGenerateTestsArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        if files.is_empty() {
+            return Err(errors::invalid_argument("files must not be empty"));
+        }
+        let scope = self.scope(&context, true).await?;
+        let cwd = working_dir(cwd, &scope)?;
+        let sources = self.attach(&files, cwd.as_deref(), &scope).await?;
+        let references = self.attach(&context_files, cwd.as_deref(), &scope).await?;
+        let project = cwd.clone().or_else(|| std::env::current_dir().ok());
+        let framework = framework.or_else(|| project.as_deref().and_then(codegen::detect_framework).map(str::to_string));
 
-#[tool_router]
-impl GeminiServer {
-    fn new() -> Self {
-        Self {
-            tool_router: Self::tool_router(),
+        let request = GenerateRequest {
+            prompt: format::prompt(
+                codegen::tests_prompt(framework.as_deref(), instructions.as_deref(), &sources, &references),
+                OutputFormat::Json,
+            ),
+            model: self.live.resolve_model(model),
+            system_instruction: self.config.system_instruction.clone(),
+            output_format: OutputFormat::Json,
+            ..Default::default()
+        };
+        let call = CallOptions {
+            tool: "gemini_generate_tests",
+            profile: profile.as_deref(),
+            timeout_secs,
+            priority,
+            ..Default::default()
+        };
+        let response = self.run_generation(&request, call, &context).await?;
+        if let Some(block) = response.safety {
+            return safety_blocked(block, serde_json::json!({ "model": request.model, "usage": response.usage }));
         }
+        if self.config.dry_run {
+            return Ok(CallToolResult::success(vec![Content::text(response.text)]));
+        }
+        let generated = codegen::parse_files(&response.text)
+            .map_err(|e| command_error(e.into()))?;
+
+        // Gemini picked these paths, so they must stay inside the workspace even
+        // to be read for the diff; every one is checked before any is written
+        let mut tests = Vec::with_capacity(generated.len());
+        for file in generated {
+            let target = files::resolve(cwd.as_deref(), &file.path);
+            scope.check_writable(&target).map_err(command_error)?;
+            let existing = if target.exists() {
+                let mut current = files::read_all(std::slice::from_ref(&file.path), cwd.as_deref(), self.config.max_file_bytes, &scope).await
+                    .map_err(command_error)?;
+                Some(current.remove(0).contents)
+            } else {
+                None
+            };
+            let contents = format!("{}\n", file.contents.trim_end_matches('\n'));
+            let diff = diff::unified(&file.path, existing.as_deref(), &contents);
+            tests.push((file.path, target, existing.is_none(), contents, diff));
+        }
+
+        let mut summary = Vec::with_capacity(tests.len());
+        let mut results = Vec::with_capacity(tests.len());
+        for (path, target, created, contents, diff) in tests {
+            let changed = !diff.is_empty();
+            let backup = if write && changed {
+                codegen::write(&target, &contents).map_err(command_error)?
+            } else {
+                None
+            };
+            summary.push(if changed { diff } else { format!("{} is unchanged\n", path) });
+            results.push(serde_json::json!({
+                "path": path,
+                "created": created,
+                "changed": changed,
+                "written": write && changed,
+                "backup": backup.map(|backup| backup.display().to_string()),
+                "contents": contents,
+            }));
+        }
+
+        let (text, continuation) = self.paginate(summary.join("\n"));
+        Ok(CallToolResult::success(vec![
+            Content::text(text),
+            Content::json(with_fields(generation_metadata(&response, request.model.as_deref()), serde_json::json!({
+                "files": results,
+                "framework": framework,
+                "continuation": continuation,
+            })))?,
+        ]))
     }
 
-    #[tool(description = "Send a prompt to the Gemini CLI")]
-    async fn gemini_prompt(
+    #[tool(description = "Write a commit message for the staged changes of a git repository, following a convention such as Conventional Commits or gitmoji; returns the message ready for git commit -F")]
+    async fn gemini_commit_message(
         &self,
-        Parameters(GeminiPromptArgs { prompt, model, max_tokens: _max_tokens, temperature: _temperature }): Parameters<GeminiPromptArgs>,
-    ) -> Result<String, McpError> {
-        let mut cmd_args = vec![];
+        Parameters(GeminiCommitMessageArgs { repo_path, convention, guidelines, model, timeout_secs, profile, priority }): Parameters<GeminiCommitMessageArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let repo = std::path::Path::new(&repo_path);
+        self.scope(&context, true).await?
+            .check(repo)
+            .map_err(command_error)?;
+        let spec = review::DiffSpec {
+            staged: true,
+            range: None,
+            paths: &[],
+        };
+        let diff = review::git_diff(repo, &spec).await
+            .map_err(|e| errors::invalid_argument(format!("{:#}", e)))?;
+        if diff.trim().is_empty() {
+            return Err(errors::invalid_argument(format!("Nothing is staged in {}; stage the changes with git add first", repo_path)));
+        }
+        let mut warnings = Vec::new();
+        let diff = match tokens::truncate_middle(&diff, commit::MAX_DIFF_TOKENS) {
+            Some(truncated) => {
+                warnings.push(format!("The staged diff was cut to about {} tokens; the message may miss changes in the middle", commit::MAX_DIFF_TOKENS));
+                truncated
+            }
+            None => diff,
+        };
+        let convention = convention.unwrap_or(self.config.commit_convention);
+        let guidelines = guidelines.or_else(|| self.config.commit_guidelines.clone());
+        let max_subject_chars = self.config.commit_subject_max_chars;
+        let recent = commit::recent_subjects(repo).await;
 
-        // Add prompt
-        cmd_args.push("--prompt".to_string());
-        cmd_args.push(prompt);
+        let request = GenerateRequest {
+            prompt: commit::prompt(&diff, convention, max_subject_chars, guidelines.as_deref(), &recent),
+            model: self.live.resolve_model(model),
+            system_instruction: self.config.system_instruction.clone(),
+            ..Default::default()
+        };
+        let call = CallOptions {
+            tool: "gemini_commit_message",
+            profile: profile.as_deref(),
+            timeout_secs,
+            priority,
+            ..Default::default()
+        };
+        let response = self.run_generation(&request, call, &context).await?;
+        if let Some(block) = response.safety {
+            return safety_blocked(block, serde_json::json!({ "model": request.model, "usage": response.usage }));
+        }
+        if self.config.dry_run {
+            return Ok(CallToolResult::success(vec![Content::text(response.text)]));
+        }
+        let metadata = generation_metadata(&response, request.model.as_deref());
+        let mut usage = response.usage;
+        warnings.extend(response.warnings);
+        let mut message = commit::parse(&response.text);
+        let mut problems = commit::problems(&message, convention, max_subject_chars);
 
-        // Add optional model
-        if let Some(model_str) = model {
-            cmd_args.push("--model".to_string());
-            cmd_args.push(model_str);
+        // One chance to fix a message that breaks the rules; the better of the two is kept
+        let mut revised = false;
+        if !problems.is_empty() {
+            let revision = GenerateRequest {
+                prompt: commit::revision_prompt(&message, &problems),
+                ..request.clone()
+            };
+            let response = self.run_generation(&revision, call, &context).await?;
+            usage += response.usage;
+            if response.safety.is_none() {
+                let candidate = commit::parse(&response.text);
+                let candidate_problems = commit::problems(&candidate, convention, max_subject_chars);
+                if candidate_problems.len() < problems.len() {
+                    message = candidate;
+                    problems = candidate_problems;
+                    revised = true;
+                }
+            }
         }
 
-        // Note: gemini CLI doesn't seem to support max_tokens or temperature directly
-        // but keeping them here for potential future support
+        Ok(CallToolResult::success(vec![
+            Content::text(message.to_text()),
+            Content::json(with_fields(metadata, serde_json::json!({
+                "subject": message.subject,
+                "body": message.body,
+                "convention": convention,
+                "problems": problems,
+                "revised": revised,
+                "warnings": warnings,
+                "usage": usage,
+            })))?,
+        ]))
+    }
+
+    #[tool(description = "Draft a pull request description (title, summary, changes, test plan) from the commits, diffstat and diff between a base branch and head")]
+    async fn gemini_pr_description(
+        &self,
+        Parameters(GeminiPrDescriptionArgs { repo_path, base, head, instructions, model, timeout_secs, profile, priority }): Parameters<GeminiPrDescriptionArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let repo = std::path::Path::new(&repo_path);
+        self.scope(&context, true).await?
+            .check(repo)
+            .map_err(command_error)?;
+        let head = head.unwrap_or_else(|| "HEAD".to_string());
+        git::check_ref(&base).map_err(errors::invalid_argument)?;
+        git::check_ref(&head).map_err(errors::invalid_argument)?;
+
+        let git_error = |e: anyhow::Error| errors::invalid_argument(format!("{:#}", e));
+        let commits = git::log(repo, &format!("{}..{}", base, head)).await.map_err(git_error)?;
+        if commits.is_empty() {
+            return Err(errors::invalid_argument(format!("{} has no commits that {} doesn't have", head, base)));
+        }
+        // Three dots compare against the merge base, as pull requests do
+        let range = format!("{}...{}", base, head);
+        let diffstat = git::diffstat(repo, &range).await.map_err(git_error)?;
+        let spec = review::DiffSpec {
+            staged: false,
+            range: Some(range.as_str()),
+            paths: &[],
+        };
+        let diff = review::git_diff(repo, &spec).await.map_err(git_error)?;
+        let mut warnings = Vec::new();
+        let diff = match tokens::truncate_middle(&diff, pr::MAX_DIFF_TOKENS) {
+            Some(truncated) => {
+                warnings.push(format!("The diff was cut to about {} tokens; the commits and diffstat cover the rest", pr::MAX_DIFF_TOKENS));
+                truncated
+            }
+            None => diff,
+        };
 
-        tracing::info!("Calling gemini with prompt");
+        let request = GenerateRequest {
+            prompt: format::prompt(
+                pr::prompt(&base, &head, &commits, &diffstat, &diff, instructions.as_deref()),
+                OutputFormat::Json,
+            ),
+            model: self.live.resolve_model(model),
+            system_instruction: self.config.system_instruction.clone(),
+            output_format: OutputFormat::Json,
+            ..Default::default()
+        };
+        let call = CallOptions {
+            tool: "gemini_pr_description",
+            profile: profile.as_deref(),
+            timeout_secs,
+            priority,
+            ..Default::default()
+        };
+        let response = self.run_generation(&request, call, &context).await?;
+        if let Some(block) = response.safety {
+            return safety_blocked(block, serde_json::json!({ "model": request.model, "usage": response.usage }));
+        }
+        if self.config.dry_run {
+            return Ok(CallToolResult::success(vec![Content::text(response.text)]));
+        }
+        let description = pr::parse(&response.text)
+            .map_err(|e| command_error(e.into()))?;
+        let metadata = generation_metadata(&response, request.model.as_deref());
+        warnings.extend(response.warnings);
 
-        run_gemini_command(cmd_args).await
-            .map_err(|e| McpError::internal_error(e.to_string(), None))
+        Ok(CallToolResult::success(vec![
+            Content::text(format!("# {}\n\n{}", description.title.trim(), description.to_markdown())),
+            Content::json(with_fields(metadata, serde_json::json!({
+                "title": description.title,
+                "body": description.to_markdown(),
+                "summary": description.summary,
+                "changes": description.changes,
+                "test_plan": description.test_plan,
+                "base": base,
+                "head": head,
+                "commits": commits.len(),
+                "warnings": warnings,
+            })))?,
+        ]))
     }
 
+    #[tool(description = "Draft release notes from the commits and tags between two git refs, grouped by kind of change, in a configurable format such as Keep a Changelog")]
+    async fn gemini_changelog(
+        &self,
+        Parameters(GeminiChangelogArgs {
+            repo_path, from, to, version, format, instructions, model, timeout_secs, profile, priority,
+        }): Parameters<GeminiChangelogArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let repo = std::path::Path::new(&repo_path);
+        self.scope(&context, true).await?
+            .check(repo)
+            .map_err(command_error)?;
+        let to = to.unwrap_or_else(|| "HEAD".to_string());
+        git::check_ref(&from).map_err(errors::invalid_argument)?;
+        git::check_ref(&to).map_err(errors::invalid_argument)?;
 
-    #[tool(description = "Configure Gemini CLI settings")]
-    async fn gemini_config(
+        let git_error = |e: anyhow::Error| errors::invalid_argument(format!("{:#}", e));
+        let mut commits = git::log(repo, &format!("{}..{}", from, to)).await.map_err(git_error)?;
+        if commits.is_empty() {
+            return Err(errors::invalid_argument(format!("There are no commits between {} and {}", from, to)));
+        }
+        let mut warnings = Vec::new();
+        if commits.len() > changelog::MAX_COMMITS {
+            warnings.push(format!(
+                "Only the latest {} of {} commits were used",
+                changelog::MAX_COMMITS, commits.len()
+            ));
+            commits.truncate(changelog::MAX_COMMITS);
+        }
+        let tags = git::tags_between(repo, &from, &to).await.map_err(git_error)?;
+        let date = git::date(repo, &to).await.map_err(git_error)?;
+        let version = version.unwrap_or_else(|| {
+            if tags.contains(&to) { to.clone() } else { "Unreleased".to_string() }
+        });
+        let format = format.unwrap_or(self.config.changelog_format);
+
+        let mut groups: Vec<(&str, Vec<&git::CommitInfo>)> = Vec::new();
+        for commit in &commits {
+            let name = changelog::group(commit);
+            match groups.iter_mut().find(|(group, _)| *group == name) {
+                Some((_, members)) => members.push(commit),
+                None => groups.push((name, vec![commit])),
+            }
+        }
+
+        let request = GenerateRequest {
+            prompt: changelog::prompt(format, &version, &date, &groups, &tags, instructions.as_deref()),
+            model: self.live.resolve_model(model),
+            system_instruction: self.config.system_instruction.clone(),
+            ..Default::default()
+        };
+        let call = CallOptions {
+            tool: "gemini_changelog",
+            profile: profile.as_deref(),
+            timeout_secs,
+            priority,
+            ..Default::default()
+        };
+        let response = self.run_generation(&request, call, &context).await?;
+        if let Some(block) = response.safety {
+            return safety_blocked(block, serde_json::json!({ "model": request.model, "usage": response.usage }));
+        }
+        let metadata = generation_metadata(&response, request.model.as_deref());
+        warnings.extend(response.warnings);
+
+        let (text, continuation) = self.paginate(response.text);
+        Ok(CallToolResult::success(vec![
+            Content::text(text),
+            Content::json(with_fields(metadata, serde_json::json!({
+                "from": from,
+                "to": to,
+                "version": version,
+                "format": format,
+                "commits": commits.len(),
+                "groups": groups.iter().map(|(group, members)| (group.to_string(), members.len())).collect::<BTreeMap<_, _>>(),
+                "tags": tags,
+                "continuation": continuation,
+                "warnings": warnings,
+            })))?,
+        ]))
+    }
+
+    #[tool(description = "Explain the root cause of a stack trace, panic or compiler error and suggest a fix; the source lines it refers to are pulled into the prompt automatically")]
+    async fn gemini_explain_error(
         &self,
-        Parameters(GeminiConfigArgs { api_key }): Parameters<GeminiConfigArgs>,
-    ) -> Result<String, McpError> {
-        // Note: gemini CLI configuration is typically done through environment variables
-        if let Some(_key) = api_key {
-            Ok("Note: Gemini API key should be set via GOOGLE_API_KEY environment variable".to_string())
-        } else {
-            Ok("Gemini CLI configuration:\n- API key: Set via GOOGLE_API_KEY environment variable\n- Model: Use --model flag (default: gemini-2.5-pro)".to_string())
+        Parameters(GeminiExplainErrorArgs { error, files, question, cwd, model, timeout_secs, profile, priority }): Parameters<GeminiExplainErrorArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        if error.trim().is_empty() {
+            return Err(errors::invalid_argument("error must not be empty"));
+        }
+        let scope = self.scope(&context, true).await?;
+        let cwd = working_dir(cwd, &scope)?;
+        let sources = self.attach(&files, cwd.as_deref(), &scope).await?;
+
+        // Frames in files that are missing, too large or outside the roots are only listed
+        let mut excerpts = Vec::new();
+        let mut referenced = Vec::new();
+        let mut skipped = Vec::new();
+        for (path, lines) in traces::by_file(&traces::references(&error)).into_iter().take(traces::MAX_FILES) {
+            match files::read_all(std::slice::from_ref(&path), cwd.as_deref(), self.config.max_file_bytes, &scope).await {
+                Ok(mut read) => {
+                    excerpts.push(traces::excerpt(&read.remove(0), &lines));
+                    referenced.push(serde_json::json!({ "path": path, "lines": lines }));
+                }
+                Err(e) => skipped.push(serde_json::json!({ "path": path, "reason": e.to_string() })),
+            }
+        }
+
+        let request = GenerateRequest {
+            prompt: traces::prompt(&error, question.as_deref(), &excerpts, &sources),
+            model: self.live.resolve_model(model),
+            system_instruction: self.config.system_instruction.clone(),
+            ..Default::default()
+        };
+        let call = CallOptions {
+            tool: "gemini_explain_error",
+            profile: profile.as_deref(),
+            timeout_secs,
+            priority,
+            ..Default::default()
+        };
+        let response = self.run_generation(&request, call, &context).await?;
+        if let Some(block) = response.safety {
+            return safety_blocked(block, serde_json::json!({ "model": request.model, "usage": response.usage }));
         }
+
+        let metadata = generation_metadata(&response, request.model.as_deref());
+        let (text, continuation) = self.paginate(response.text);
+        Ok(CallToolResult::success(vec![
+            Content::text(text),
+            Content::json(with_fields(metadata, serde_json::json!({
+                "referenced": referenced,
+                "skipped": skipped,
+                "files": sources.iter().map(|a| a.path.display().to_string()).collect::<Vec<_>>(),
+                "continuation": continuation,
+            })))?,
+        ]))
+    }
+
+    #[tool(description = "Summarize a log file: find its anomalies and reconstruct a timeline, after filtering it with grep, exclude, dedup and tail on the server to fit the context window")]
+    async fn gemini_summarize_logs(
+        &self,
+        Parameters(GeminiSummarizeLogsArgs {
+            path, grep, exclude, dedup, tail, question, cwd, model, timeout_secs, profile, priority,
+        }): Parameters<GeminiSummarizeLogsArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let pattern = |name: &str, pattern: Option<String>| {
+            pattern
+                .map(|pattern| regex::Regex::new(&pattern))
+                .transpose()
+                .map_err(|e| errors::invalid_argument(format!("{} is not a valid regular expression: {}", name, e)))
+        };
+        let filters = logs::Filters {
+            grep: pattern("grep", grep)?,
+            exclude: pattern("exclude", exclude)?,
+            dedup: dedup.unwrap_or(true),
+            tail,
+        };
+        let scope = self.scope(&context, true).await?;
+        let cwd = working_dir(cwd, &scope)?;
+        let resolved = files::resolve(cwd.as_deref(), &path);
+        scope.check(&resolved).map_err(command_error)?;
+        let (log, cut) = logs::read_tail(&resolved, self.config.max_log_bytes).await
+            .map_err(|e| errors::invalid_argument(format!("{:#}", e)))?;
+        let mut warnings = Vec::new();
+        if cut {
+            warnings.push(format!("Only the last {} bytes of the log were read", self.config.max_log_bytes));
+        }
+
+        let mut request = GenerateRequest {
+            model: self.live.resolve_model(model),
+            system_instruction: self.config.system_instruction.clone(),
+            output_format: OutputFormat::Json,
+            ..Default::default()
+        };
+        // What the prompt leaves of the context window goes to the log
+        let empty = logs::filter("", &filters, 0);
+        let overhead = tokens::estimate(&format::prompt(logs::prompt(&path, question.as_deref(), &empty), OutputFormat::Json));
+        let max_log_tokens = self.prompt_budget(&request)
+            .map(|budget| budget.max_prompt_tokens.saturating_sub(budget.other_tokens + overhead))
+            .unwrap_or(logs::DEFAULT_MAX_LOG_TOKENS);
+        let filtered = logs::filter(&log, &filters, max_log_tokens);
+        if filtered.kept_lines == 0 {
+            return Err(errors::invalid_argument(format!(
+                "No lines of {} are left after filtering ({} lines in total)",
+                path, filtered.total_lines
+            )));
+        }
+        if filtered.dropped_lines > 0 && filters.tail.is_none_or(|tail| filtered.kept_lines < tail) {
+            warnings.push(format!(
+                "The earliest {} lines were left out to fit the context window",
+                filtered.dropped_lines
+            ));
+        }
+        request.prompt = format::prompt(logs::prompt(&path, question.as_deref(), &filtered), OutputFormat::Json);
+
+        let call = CallOptions {
+            tool: "gemini_summarize_logs",
+            profile: profile.as_deref(),
+            timeout_secs,
+            priority,
+            ..Default::default()
+        };
+        let response = self.run_generation(&request, call, &context).await?;
+        if let Some(block) = response.safety {
+            return safety_blocked(block, serde_json::json!({ "model": request.model, "usage": response.usage }));
+        }
+        if self.config.dry_run {
+            return Ok(CallToolResult::success(vec![Content::text(response.text)]));
+        }
+        let summary = logs::parse(&response.text)
+            .map_err(|e| command_error(e.into()))?;
+        let metadata = generation_metadata(&response, request.model.as_deref());
+        warnings.extend(response.warnings);
+
+        let (text, continuation) = self.paginate(summary.to_markdown());
+        Ok(CallToolResult::success(vec![
+            Content::text(text),
+            Content::json(with_fields(metadata, serde_json::json!({
+                "summary": summary.summary,
+                "anomalies": summary.anomalies,
+                "timeline": summary.timeline,
+                "lines": filtered,
+                "continuation": continuation,
+                "warnings": warnings,
+            })))?,
+        ]))
+    }
+
+    #[tool(description = "Run a chain of Gemini prompts in which each step's template is filled in with the outputs before it, e.g. extract, critique, then rewrite, optionally with a different model per step")]
+    async fn gemini_pipeline(
+        &self,
+        Parameters(GeminiPipelineArgs { input, steps, model, timeout_secs, profile, priority }): Parameters<GeminiPipelineArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let pipeline = Pipeline::compile(&steps).map_err(errors::invalid_argument)?;
+        let progress = ProgressReporter::new(&context);
+        let total = steps.len();
+
+        tracing::info!("Running a pipeline of {} steps", total);
+
+        let mut outputs: Vec<String> = Vec::new();
+        let mut results = Vec::new();
+        let mut usage = Usage::default();
+        for (index, step) in steps.into_iter().enumerate() {
+            let name = pipeline.name(index);
+            progress.report(format!("Running step {} of {}: {}", index + 1, total, name)).await;
+
+            let request = GenerateRequest {
+                prompt: pipeline.render(index, &input, &outputs).map_err(errors::invalid_argument)?,
+                model: self.live.resolve_model(step.model.or_else(|| model.clone())),
+                system_instruction: step.system_instruction.or_else(|| self.config.system_instruction.clone()),
+                ..Default::default()
+            };
+            let call = CallOptions {
+                tool: "gemini_pipeline",
+                profile: profile.as_deref(),
+                timeout_secs,
+                priority,
+                progress: Some(&progress),
+                ..Default::default()
+            };
+            let response = self.run_generation(&request, call, &context).await?;
+            if let Some(block) = response.safety {
+                return safety_blocked(block, serde_json::json!({ "failed_step": name, "steps": results }));
+            }
+
+            usage += response.usage;
+            results.push(with_fields(generation_metadata(&response, request.model.as_deref()), serde_json::json!({
+                "name": name,
+                "text": response.text,
+            })));
+            outputs.push(response.text);
+        }
+
+        let last = outputs.pop().unwrap_or_default();
+        let (text, continuation) = self.paginate(last);
+        Ok(CallToolResult::success(vec![
+            Content::text(text),
+            Content::json(serde_json::json!({
+                "steps": results,
+                "continuation": continuation,
+                "usage": usage,
+            }))?,
+        ]))
+    }
+}
+
+/// Tells the model reading a truncated result how to get the rest.
+fn continuation_note(continuation: &Continuation) -> String {
+    format!(
+        "\n\n[Truncated: {} more bytes. Call gemini_fetch_continuation with token \"{}\" for the rest.]",
+        continuation.remaining_bytes, continuation.token
+    )
+}
+
+/// Validate `include_directories`, which must name existing directories inside `scope`.
+fn included_dirs(dirs: &[String], base: Option<&std::path::Path>, scope: &Scope) -> Result<Vec<std::path::PathBuf>, McpError> {
+    dirs.iter()
+        .map(|dir| {
+            let joined = match base {
+                Some(base) => base.join(dir),
+                None => std::path::PathBuf::from(dir),
+            };
+            match std::fs::canonicalize(&joined) {
+                Ok(path) if path.is_dir() => {
+                    scope.check(&path).map_err(command_error)?;
+                    Ok(path)
+                }
+                Ok(_) => Err(errors::invalid_argument(format!("include_directories entry {} is not a directory", dir))),
+                Err(e) => Err(errors::invalid_argument(format!("include_directories entry {}: {}", dir, e))),
+            }
+        })
+        .collect()
+}
+
+/// Parse a `since` argument: an RFC 3339 timestamp, or a date meaning its start in UTC.
+fn parse_since(since: &str) -> Result<chrono::DateTime<chrono::Utc>, McpError> {
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(since) {
+        return Ok(time.with_timezone(&chrono::Utc));
+    }
+    chrono::NaiveDate::parse_from_str(since, "%Y-%m-%d")
+        .map(|date| date.and_time(chrono::NaiveTime::MIN).and_utc())
+        .map_err(|_| errors::invalid_argument(format!("Invalid since '{}', expected RFC 3339 or YYYY-MM-DD", since)))
+}
+
+/// Validate a `cwd` argument, which must name an existing directory inside `scope`.
+fn working_dir(cwd: Option<String>, scope: &Scope) -> Result<Option<std::path::PathBuf>, McpError> {
+    let Some(cwd) = cwd else {
+        return Ok(None);
+    };
+    match std::fs::canonicalize(&cwd) {
+        Ok(path) if path.is_dir() => {
+            scope.check(&path).map_err(command_error)?;
+            Ok(Some(path))
+        }
+        Ok(_) => Err(errors::invalid_argument(format!("cwd {} is not a directory", cwd))),
+        Err(e) => Err(errors::invalid_argument(format!("cwd {}: {}", cwd, e))),
+    }
+}
+
+/// What every generating tool reports about its Gemini call: the `model`
+/// asked for and the one it fell back to, retries, CLI warnings, PII
+/// redactions and token usage.
+fn generation_metadata(response: &GenerateResponse, model: Option<&str>) -> serde_json::Value {
+    serde_json::json!({
+        "model": model,
+        "fallback_model": response.fallback_model,
+        "retries": response.retries,
+        "warnings": response.warnings,
+        "redactions": response.redactions,
+        "usage": response.usage,
+    })
+}
+
+/// `metadata` with a tool's own `fields` added, replacing common ones it
+/// reports differently, such as the total `usage` of several calls.
+fn with_fields(mut metadata: serde_json::Value, fields: serde_json::Value) -> serde_json::Value {
+    if let (Some(metadata), serde_json::Value::Object(fields)) = (metadata.as_object_mut(), fields) {
+        metadata.extend(fields);
     }
+    metadata
+}
+
+/// The command a dry run would have run, as the tool's result.
+fn plan_result(plan: Result<impl serde::Serialize>) -> Result<CallToolResult, McpError> {
+    let plan = plan.map_err(command_error)?;
+    let text = serde_json::to_string_pretty(&serde_json::json!({ "backend": "cli", "command": plan }))
+        .map_err(|e| errors::internal(e.to_string()))?;
+    Ok(CallToolResult::success(vec![Content::text(text)]))
+}
+
+/// Tool error result explaining why Gemini withheld its answer.
+fn safety_blocked(block: SafetyBlock, mut metadata: serde_json::Value) -> Result<CallToolResult, McpError> {
+    let text = format!("Gemini blocked the {} for safety reasons ({})", block.blocked, block.reason);
+    metadata["kind"] = serde_json::json!(ErrorKind::SafetyBlocked);
+    metadata["code"] = serde_json::json!(ErrorKind::SafetyBlocked.code().0);
+    metadata["safety_block"] = serde_json::to_value(&block)
+        .map_err(|e| errors::internal(e.to_string()))?;
+    Ok(CallToolResult::error(vec![Content::text(text), Content::json(metadata)?]))
 }
 
-#[tool_handler]
 impl ServerHandler for GeminiServer {
+    async fn list_tools(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, McpError> {
+        // Clients that listed tools hear when the config switches some on or off
+        self.subscriber.attach(&context.peer);
+        let mut tools = self.tool_router.list_all();
+        for tool in &mut tools {
+            if let Some(description) = i18n::tool_description(self.config.locale, &tool.name) {
+                tool.description = description.into();
+            }
+            tool.annotations = annotations::for_tool(&tool.name);
+        }
+        if let Some(bridge) = &self.bridge {
+            tools.extend(bridge.tools().iter().cloned());
+        }
+        tools.retain(|tool| self.toggles.enabled(&tool.name));
+        Ok(ListToolsResult {
+            next_cursor: None,
+            tools,
+        })
+    }
+
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParam,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        // Every log line of the call carries its id, which the result reports too
+        let request_id = request_id::generate();
+        let span = tracing::info_span!("call", request_id = %request_id, tool = %request.name);
+        let result = self.handle_call(request, &request_id, context).instrument(span).await;
+        request_id::attach(result, &request_id)
+    }
+
     fn get_info(&self) -> ServerInfo {
         ServerInfo {
-            instructions: Some(r#"Gemini CLI MCP Server - Access Google's Gemini AI models through Claude
+            instructions: Some(self.instructions.to_string()),
+            capabilities: ServerCapabilities::builder()
+                .enable_completions()
+                .enable_logging()
+                .enable_tools()
+                .enable_tool_list_changed()
+                .enable_prompts()
+                .enable_prompts_list_changed()
+                .enable_resources()
+                .enable_resources_subscribe()
+                .enable_resources_list_changed()
+                .build(),
+            ..Default::default()
+        }
+    }
 
-## How to reference files
-When you want Gemini to analyze files, specify the file paths in your prompt.
-Claude will automatically read all the file contents and include them in the context.
-You can reference as many files as needed - just mention them in your prompt!
+    async fn complete(
+        &self,
+        CompleteRequestParam { r#ref, argument }: CompleteRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<CompleteResult, McpError> {
+        let sessions = || -> Vec<String> {
+            let mut sessions = self.sessions.list();
+            sessions.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+            sessions.into_iter().map(|session| session.id).collect()
+        };
+        let templates = || -> Vec<String> { self.prompts().templates().iter().map(|template| template.name.clone()).collect() };
+        let candidates = match (&r#ref, argument.name.as_str()) {
+            (_, "model") => self.model_names().await,
+            (_, "session_id") => sessions(),
+            (Reference::Resource(resource), "id") if resource.uri == resources::SESSION_URI_TEMPLATE => sessions(),
+            (_, "template") => templates(),
+            (Reference::Resource(resource), "name") if resource.uri == resources::PROMPT_URI_TEMPLATE => templates(),
+            _ => Vec::new(),
+        };
+        Ok(CompleteResult {
+            completion: completion::matching(candidates, &argument.value),
+        })
+    }
 
-## Usage Examples:
+    async fn set_level(
+        &self,
+        SetLevelRequestParam { level }: SetLevelRequestParam,
+        context: RequestContext<RoleServer>,
+    ) -> Result<(), McpError> {
+        self.client_log.set_level(&context.peer, level);
+        Ok(())
+    }
 
-### Simple prompts:
-- "What is the difference between async and sync in JavaScript?"
-- "Rustのownershipについて説明して"
+    async fn list_prompts(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<ListPromptsResult, McpError> {
+        // Clients that listed prompts hear when the templates are reloaded
+        self.subscriber.attach(&context.peer);
+        Ok(ListPromptsResult {
+            next_cursor: None,
+            prompts: self.prompts().list(),
+        })
+    }
 
-### File analysis (specify one or many file paths):
-- "analyze the code in src/main.rs and suggest improvements"
-- "package.jsonとpackage-lock.jsonを比較して、依存関係の問題を指摘して"
-- "review src/api/handler.ts, tests/handler.test.ts, and src/api/types.ts together"
-- "check if src/server.js, src/routes/*.js, and src/middleware/*.js follow best practices"
+    async fn get_prompt(
+        &self,
+        GetPromptRequestParam { name, arguments }: GetPromptRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<GetPromptResult, McpError> {
+        self.prompts().render(&name, arguments.as_ref())
+    }
 
-### Code refactoring (any number of files):
-- "refactor the database logic across db/connection.js, db/models.js, and db/migrations/*.js"
-- "test/*.pyとsrc/*.pyの整合性を確認して改善案を提案して"
-- "optimize lib/parser.js, lib/tokenizer.js, and their test files"
+    async fn list_resources(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<ListResourcesResult, McpError> {
+        self.subscriber.attach(&context.peer);
 
-### Model selection:
-- "Using gemini-2.5-flash, summarize the README.md"
-- "src/complex_algorithm.rsの複雑なアルゴリズムを最適化して"
+        let mut resources = vec![
+            resources::json_resource(resources::USAGE_TODAY_URI, "usage-today", "Token usage and estimated cost for the current UTC day"),
+            resources::json_resource(resources::CONFIG_URI, "config", "The server's effective configuration"),
+        ];
+        resources.extend(self.sessions.list().iter().map(|session| {
+            resources::json_resource(
+                &resources::session_uri(&session.id),
+                &format!("session-{}", session.id),
+                &match &session.title {
+                    Some(title) => format!("gemini_chat session \"{}\" with {} messages", title, session.messages.len()),
+                    None => format!("gemini_chat session with {} messages", session.messages.len()),
+                },
+            )
+        }));
+        resources.extend(self.prompts().templates().iter().map(|template| {
+            resources::json_resource(
+                &resources::prompt_uri(&template.name),
+                &format!("prompt-{}", template.name),
+                &format!("The {} prompt template and the JSON Schema of its arguments", template.name),
+            )
+        }));
 
-## Tips:
-- Specify file paths when you want Gemini to analyze specific files
-- Gemini reads the files automatically - you don't need to paste contents
-- Default model is gemini-2.5-pro, but gemini-2.5-flash is faster for simple tasks
-"#.into()),
-            capabilities: ServerCapabilities::builder().enable_tools().build(),
-            ..Default::default()
-        }
+        Ok(ListResourcesResult {
+            next_cursor: None,
+            resources,
+        })
+    }
+
+    async fn list_resource_templates(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListResourceTemplatesResult, McpError> {
+        Ok(ListResourceTemplatesResult {
+            next_cursor: None,
+            resource_templates: vec![
+                RawResourceTemplate {
+                    uri_template: resources::SESSION_URI_TEMPLATE.to_string(),
+                    name: "session".to_string(),
+                    description: Some("A gemini_chat session's full message history".to_string()),
+                    mime_type: Some("application/json".to_string()),
+                }.no_annotation(),
+                RawResourceTemplate {
+                    uri_template: resources::PROMPT_URI_TEMPLATE.to_string(),
+                    name: "prompt".to_string(),
+                    description: Some("A prompt template with the JSON Schema of its arguments".to_string()),
+                    mime_type: Some("application/json".to_string()),
+                }.no_annotation(),
+            ],
+        })
+    }
+
+    async fn read_resource(
+        &self,
+        ReadResourceRequestParam { uri }: ReadResourceRequestParam,
+        context: RequestContext<RoleServer>,
+    ) -> Result<ReadResourceResult, McpError> {
+        self.subscriber.attach(&context.peer);
+
+        let prompts = self.prompts();
+        let value = if uri == resources::USAGE_TODAY_URI {
+            serde_json::to_value(self.usage.today())
+        } else if uri == resources::CONFIG_URI {
+            let mut config = serde_json::to_value(&*self.config);
+            if let Ok(config) = &mut config {
+                config["source"] = serde_json::json!(self.config.source);
+            }
+            config
+        } else if let Some(session) = resources::session_id(&uri).and_then(|id| self.sessions.get(id)) {
+            serde_json::to_value(session)
+        } else if let Some(template) = resources::prompt_name(&uri).and_then(|name| prompts.get(name)) {
+            Ok(serde_json::json!({
+                "name": template.name,
+                "description": template.description,
+                "template": template.template,
+                "arguments_schema": template.schema(),
+            }))
+        } else {
+            return Err(McpError::resource_not_found(format!("Unknown resource {}", uri), None));
+        };
+
+        let text = value
+            .and_then(|value| serde_json::to_string_pretty(&value))
+            .map_err(|e| errors::internal(e.to_string()))?;
+        Ok(ReadResourceResult {
+            contents: vec![ResourceContents::text(text, uri)],
+        })
+    }
+
+    async fn subscribe(
+        &self,
+        SubscribeRequestParam { uri }: SubscribeRequestParam,
+        context: RequestContext<RoleServer>,
+    ) -> Result<(), McpError> {
+        self.subscriber.attach(&context.peer);
+        self.subscriber.subscribe(uri);
+        Ok(())
+    }
+
+    async fn unsubscribe(
+        &self,
+        UnsubscribeRequestParam { uri }: UnsubscribeRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<(), McpError> {
+        self.subscriber.unsubscribe(&uri);
+        Ok(())
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), McpError> {
+    let command = args::Args::parse().command();
+
     // Load .env file from $HOME directory if exists
     if let Ok(home) = std::env::var("HOME") {
         let home_env_path = std::path::Path::new(&home).join(".env");
@@ -190,20 +4075,232 @@ async fn main() -> Result<(), McpError> {
     // Load .env file from current directory (overwrites $HOME/.env values)
     dotenv::dotenv().ok();
 
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
+    match command {
+        Command::Serve(flags) => serve(flags).await,
+        Command::Doctor { flags, skip_prompt, json } => diagnose(flags, skip_prompt, json).await,
+        Command::Config { command: ConfigCommand::Check { flags } } => check_config(flags),
+        Command::Bench { flags, requests, concurrency, sizes, models, json } => {
+            let plan = bench::Plan {
+                requests,
+                concurrency: concurrency.max(1),
+                sizes: if sizes.is_empty() { vec![100] } else { sizes },
+                models: if models.is_empty() { vec![None] } else { models.into_iter().map(Some).collect() },
+            };
+            run_bench(flags, plan, json).await
+        }
+        Command::Version { flags } => print_version(flags).await,
+    }
+}
+
+/// Load the configuration and install its redactor, which every subcommand needs.
+fn load_config(flags: Flags) -> Result<Config, McpError> {
+    let config = Config::load(flags)
+        .map_err(|e| McpError::invalid_params(format!("{:#}", e), None))?;
+
+    let redactor = redact::Redactor::from_config(&config)
+        .map_err(|e| McpError::invalid_params(format!("{:#}", e), None))?;
+    redact::init(redactor);
+    Ok(config)
+}
+
+/// `serve`: run the MCP server until the client goes away.
+async fn serve(flags: Flags) -> Result<(), McpError> {
+    let config = load_config(flags.clone())?;
+
+    let log_file = match &config.log_file {
+        Some(path) => Some(
+            logfile::RotatingFile::open(path, config.log_max_bytes, config.log_max_files)
+                .map_err(|e| McpError::invalid_params(format!("{:#}", e), None))?,
+        ),
+        None => None,
+    };
+
+    // Log to stderr so stdout stays free for the stdio transport
+    tracing_subscriber::registry()
+        .with(EnvFilter::new(&config.log_level))
+        .with(tracing_subscriber::fmt::layer().with_writer(redact::RedactingStderr))
+        .with(log_file.map(|file| tracing_subscriber::fmt::layer().json().with_writer(file)))
+        .with(ClientLogLayer)
         .init();
 
     tracing::info!("Starting Gemini CLI MCP server");
 
-    use rmcp::transport::io::stdio;
+    if let Some(path) = &config.source {
+        tracing::info!("Loaded config from {}", path.display());
+    }
 
-    let service = GeminiServer::new()
-        .serve(stdio())
-        .await
-        .map_err(|e| McpError::internal_error(format!("Failed to start server: {:?}", e), None))?;
+    let transport = Transport::from_config(&config)
+        .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+    let instructions = instructions::load(config.instructions_file.as_deref(), config.locale)
+        .map_err(|e| McpError::invalid_params(format!("{:#}", e), None))?;
+
+    let backend = Backend::from_config(&config)
+        .map_err(|e| McpError::internal_error(format!("Failed to configure backend: {}", e), None))?;
+
+    tracing::info!("Using {} backend", backend.name());
+    let replaying = config.recording == Recording::Replay;
+    if let Some(dir) = config.recordings_path().filter(|_| config.recording != Recording::Off) {
+        let verb = if replaying { "Replaying answers from" } else { "Recording answers to" };
+        tracing::info!("{} {}", verb, dir.display());
+    }
+    // Replaying never runs the CLI, which need not even be installed
+    if let Some(cli) = backend.cli().filter(|_| !replaying) {
+        match cli.detect_version().await {
+            Some(version) => tracing::info!("Found gemini-cli {}", version),
+            None => tracing::warn!("Could not tell the gemini-cli version; flags are not checked against it"),
+        }
+    }
+
+    let audit = match &config.audit_log {
+        Some(path) => {
+            tracing::info!("Writing audit log to {}", path.display());
+            Some(AuditLog::open(path).map_err(|e| McpError::invalid_params(format!("{:#}", e), None))?)
+        }
+        None => None,
+    };
+
+    let bridge = if config.bridge {
+        let bridge = Bridge::start(&config).await;
+        tracing::info!("Bridging {} tools from gemini's MCP servers", bridge.tools().len());
+        Some(bridge)
+    } else {
+        None
+    };
+
+    children::spawn_reaper();
+    let source = config.source.clone();
+    let prompts_dir = config.prompts_path();
+    let server = GeminiServer::new(config, backend, instructions, audit, bridge);
+    if let Some(path) = source {
+        let reloadable = Reloadable {
+            settings: server.live.clone(),
+            toggles: server.toggles.clone(),
+            prompts: server.prompts.clone(),
+        };
+        reload::watch(path, flags, reloadable, server.resources.clone(), prompts_dir);
+    }
+    let served = transport::serve(server, transport).await;
+    // Don't leave gemini processes behind once the client is gone
+    children::kill_all();
+    served.map_err(|e| McpError::internal_error(e.to_string(), None))?;
+
+    Ok(())
+}
+
+/// `doctor`: run the checks of `gemini_doctor` and print them, exiting with 1 when one failed.
+async fn diagnose(flags: Flags, skip_prompt: bool, json: bool) -> Result<(), McpError> {
+    let config = load_config(flags)?;
+
+    let mut checks = doctor::check_binary(&config).await;
+    checks.extend(doctor::check_auth(&config));
+    checks.extend(doctor::check_vertex(&config));
+    if !skip_prompt && doctor::healthy(&checks) {
+        checks.push(match Backend::from_config(&config) {
+            Ok(backend) => doctor::check_prompt(&config, &backend).await,
+            Err(e) => doctor::Check::error("test_prompt", format!("{:#}", e), "Fix the backend settings; `config check` validates them"),
+        });
+    }
+
+    let healthy = doctor::healthy(&checks);
+    if json {
+        let report = serde_json::json!({
+            "healthy": healthy,
+            "backend": config.backend,
+            "checks": checks,
+        });
+        println!("{}", serde_json::to_string_pretty(&report).map_err(|e| McpError::internal_error(e.to_string(), None))?);
+    } else {
+        println!("{}", doctor::summary(&checks));
+    }
+    if !healthy {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// `config check`: validate the configuration as `serve` would, then print the settings in effect.
+fn check_config(flags: Flags) -> Result<(), McpError> {
+    let config = load_config(flags)?;
+    Transport::from_config(&config)
+        .map_err(|e| McpError::invalid_params(format!("{:#}", e), None))?;
+    Backend::from_config(&config)
+        .map_err(|e| McpError::invalid_params(format!("Failed to configure backend: {:#}", e), None))?;
+    instructions::load(config.instructions_file.as_deref(), config.locale)
+        .map_err(|e| McpError::invalid_params(format!("{:#}", e), None))?;
+
+    let settings = toml::to_string(&config).map_err(|e| McpError::internal_error(e.to_string(), None))?;
+    match &config.source {
+        Some(path) => println!("# Configuration is valid; loaded from {}", path.display()),
+        None => println!("# Configuration is valid; no config file, so built-in defaults apply"),
+    }
+    println!("{}", redact::redact(&settings));
+    Ok(())
+}
 
-    service.waiting().await.map_err(|e| McpError::internal_error(format!("Server error: {:?}", e), None))?;
+/// `bench`: send `plan`'s prompts with `gemini_prompt` through an in-process
+/// server, so rate limits, queueing, retries and the warm pool apply as they
+/// would for a client, and print how long they took.
+async fn run_bench(flags: Flags, plan: bench::Plan, json: bool) -> Result<(), McpError> {
+    let config = load_config(flags)?;
+    let backend = Backend::from_config(&config)
+        .map_err(|e| McpError::invalid_params(format!("Failed to configure backend: {:#}", e), None))?;
+    if let Some(cli) = backend.cli().filter(|_| config.recording != Recording::Replay) {
+        cli.detect_version().await;
+    }
+    let backend_name = backend.name().to_string();
+    let instructions = instructions::load(config.instructions_file.as_deref(), config.locale)
+        .map_err(|e| McpError::invalid_params(format!("{:#}", e), None))?;
+    let server = GeminiServer::new(config, backend, instructions, None, None);
+    let client = transport::connect(server).await
+        .map_err(|e| McpError::internal_error(format!("{:#}", e), None))?;
+
+    eprintln!("Sending {} prompts, {} at once", plan.requests, plan.concurrency);
+    let started = std::time::Instant::now();
+    let samples: Vec<bench::Sample> = futures::stream::iter(0..plan.requests)
+        .map(|index| {
+            let (model, size) = plan.case(index);
+            let mut arguments = serde_json::Map::new();
+            arguments.insert("prompt".to_string(), serde_json::json!(bench::prompt(index, size)));
+            if let Some(model) = &model {
+                arguments.insert("model".to_string(), serde_json::json!(model));
+            }
+            let request = CallToolRequestParam { name: "gemini_prompt".into(), arguments: Some(arguments) };
+            let client = &client;
+            async move {
+                let sent = std::time::Instant::now();
+                let error = match client.call_tool(request).await {
+                    Ok(result) if result.is_error == Some(true) => Some("tool_error".to_string()),
+                    Ok(_) => None,
+                    Err(rmcp::service::ServiceError::McpError(error)) => Some(
+                        error.data.as_ref().and_then(|data| data["kind"].as_str()).unwrap_or("error").to_string(),
+                    ),
+                    Err(_) => Some("transport".to_string()),
+                };
+                bench::Sample { model, size, elapsed: sent.elapsed(), error }
+            }
+        })
+        .buffer_unordered(plan.concurrency)
+        .collect()
+        .await;
+    let report = bench::Report::new(&backend_name, &plan, &samples, started.elapsed());
+    let _ = client.cancel().await;
+    children::kill_all();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report).map_err(|e| McpError::internal_error(e.to_string(), None))?);
+    } else {
+        print!("{}", report.to_table());
+    }
+    Ok(())
+}
 
+/// `version`: print this server's version and the installed gemini CLI's.
+async fn print_version(flags: Flags) -> Result<(), McpError> {
+    let config = load_config(flags)?;
+    println!("gemini-cli-mcp {}", env!("CARGO_PKG_VERSION"));
+    match backend::CliBackend::new(&config).detect_version().await {
+        Some(version) => println!("gemini-cli {}", version),
+        None => println!("gemini-cli not found ({})", config.gemini_binary),
+    }
     Ok(())
 }
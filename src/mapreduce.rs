@@ -0,0 +1,111 @@
+use crate::chunking;
+use crate::files::Attachment;
+use crate::tokens;
+
+/// Model that summarizes each chunk, where speed and price matter most.
+pub const MAP_MODEL: &str = "gemini-2.5-flash";
+
+/// Model that combines the partial summaries into one.
+pub const REDUCE_MODEL: &str = "gemini-2.5-pro";
+
+/// Estimated tokens of one chunk when the call doesn't say.
+pub const DEFAULT_CHUNK_TOKENS: u32 = 100_000;
+
+/// Smallest `chunk_tokens` accepted, below which the prompts are mostly instructions.
+pub const MIN_CHUNK_TOKENS: u32 = 1_000;
+
+/// Most chunks one call may summarize.
+pub const MAX_CHUNKS: usize = 1_000;
+
+/// How many times partial summaries may be combined in groups before the final pass.
+pub const MAX_REDUCE_PASSES: usize = 4;
+
+/// A piece of a file small enough to summarize in one request.
+pub struct Chunk {
+    pub path: String,
+    /// 1-based position among the file's chunks.
+    pub part: usize,
+    pub parts: usize,
+    pub text: String,
+}
+
+impl Chunk {
+    /// How summaries and failures refer to the chunk.
+    pub fn label(&self) -> String {
+        if self.parts == 1 {
+            self.path.clone()
+        } else {
+            format!("{} (part {} of {})", self.path, self.part, self.parts)
+        }
+    }
+}
+
+/// Cut every attachment into chunks of at most about `max_tokens`, at line breaks.
+pub fn split(attachments: &[Attachment], max_tokens: u32) -> Vec<Chunk> {
+    let mut chunks = Vec::new();
+    for attachment in attachments {
+        let path = attachment.path.display().to_string();
+        let pieces = chunking::split(&attachment.contents, max_tokens, 0);
+        let parts = pieces.len();
+        chunks.extend(pieces.into_iter().enumerate().map(|(index, text)| Chunk {
+            path: path.clone(),
+            part: index + 1,
+            parts,
+            text,
+        }));
+    }
+    chunks
+}
+
+/// Prompt summarizing one chunk.
+pub fn map_prompt(chunk: &Chunk, focus: Option<&str>) -> String {
+    let part = if chunk.parts == 1 {
+        String::new()
+    } else {
+        format!(" This is part {} of {} of the file; summarize only this part.", chunk.part, chunk.parts)
+    };
+    format!(
+        "Summarize the following file in a few concise bullet points: what it contains or does, and anything \
+         notable such as key types, functions, decisions or problems.{}{}\n\n<file path=\"{}\">\n{}\n</file>",
+        part,
+        focus_line(focus),
+        chunk.path,
+        chunk.text.trim_end()
+    )
+}
+
+/// Prompt combining partial summaries, `last` being the pass whose answer is returned.
+pub fn reduce_prompt(summaries: &[String], focus: Option<&str>, last: bool) -> String {
+    let task = if last {
+        "Combine these summaries of individual files into one coherent summary of the whole. Start with a short \
+         overview, then cover the main parts and how they relate. Keep concrete names; drop repetition."
+    } else {
+        "Combine these summaries of individual files into one shorter summary that keeps every file's key points \
+         and names the files they come from. It will be combined with others later."
+    };
+    format!("{}{}\n\n{}", task, focus_line(focus), summaries.join("\n\n"))
+}
+
+fn focus_line(focus: Option<&str>) -> String {
+    focus.map(|focus| format!(" Concentrate on: {}.", focus.trim())).unwrap_or_default()
+}
+
+/// Pack `summaries` in order into groups of at most about `max_tokens` each.
+pub fn groups(summaries: Vec<String>, max_tokens: u32) -> Vec<Vec<String>> {
+    let mut groups: Vec<Vec<String>> = Vec::new();
+    let mut group_tokens = 0;
+    for summary in summaries {
+        let summary_tokens = tokens::estimate(&summary);
+        match groups.last_mut() {
+            Some(group) if group_tokens + summary_tokens <= max_tokens => {
+                group.push(summary);
+                group_tokens += summary_tokens;
+            }
+            _ => {
+                groups.push(vec![summary]);
+                group_tokens = summary_tokens;
+            }
+        }
+    }
+    groups
+}
@@ -0,0 +1,75 @@
+use serde::Serialize;
+
+use crate::backend::Usage;
+use crate::config::ChatHistory;
+use crate::session::{ChatMessage, ChatRole, Session};
+use crate::tokens;
+
+/// Share of the model's context window, in percent, replayed chat history may
+/// take before its oldest turns are trimmed.
+pub const WINDOW_PERCENT: u32 = 80;
+
+/// Tokens set aside for the summary that replaces trimmed turns.
+pub const SUMMARY_TOKENS: u32 = 1_000;
+
+/// How a session's history was trimmed before a turn, as reported in the result.
+#[derive(Debug, Serialize)]
+pub struct Trimmed {
+    /// `summarize` or `evict`; `evict` also when the summary could not be written.
+    pub strategy: &'static str,
+    /// Messages no longer replayed after this turn.
+    pub messages: usize,
+    /// Estimated tokens of the prompt before and after trimming.
+    pub tokens_before: u32,
+    pub tokens_after: u32,
+    /// Tokens the summary call used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+/// How many of the messages `session` replays to drop, in whole turns from
+/// the oldest, so that the prompt for `message` fits in half of `limit`,
+/// leaving room for the next turns. 0 while the prompt is within `limit`.
+pub fn to_trim(session: &Session, message: &str, limit: u32, strategy: ChatHistory) -> usize {
+    let history = session.history();
+    let mut tokens = tokens::estimate(&session.transcript_with(message));
+    if strategy == ChatHistory::Keep || tokens <= limit || history.len() < 2 {
+        return 0;
+    }
+    if strategy == ChatHistory::Summarize {
+        tokens += SUMMARY_TOKENS;
+    }
+    let mut trimmed = 0;
+    for turn in history.chunks(2) {
+        if tokens <= limit / 2 {
+            break;
+        }
+        tokens = tokens.saturating_sub(turn.iter().map(|message| tokens::estimate(&message.content) + 2).sum());
+        trimmed += turn.len();
+    }
+    trimmed
+}
+
+/// Prompt asking for a summary of `messages`, the oldest turns of a chat, that
+/// takes in `previous`, the summary of the turns before them.
+pub fn summary_prompt(previous: Option<&str>, messages: &[ChatMessage]) -> String {
+    let mut prompt = format!(
+        "Summarize the conversation below between a user and you (Gemini) in at most {} words, so that it can \
+         continue from the summary alone. Keep the user's goals and preferences, the decisions made, facts, \
+         names and figures established, code or text agreed on, and questions still open; leave out \
+         pleasantries and anything superseded later. Answer with the summary only.",
+        SUMMARY_TOKENS * 3 / 5
+    );
+    if let Some(previous) = previous {
+        prompt.push_str(&format!("\n\nSummary of the conversation before it: {}", previous));
+    }
+    prompt.push_str("\n\n");
+    for message in messages {
+        let speaker = match message.role {
+            ChatRole::User => "User",
+            ChatRole::Model => "Gemini",
+        };
+        prompt.push_str(&format!("{}: {}\n\n", speaker, message.content));
+    }
+    prompt
+}
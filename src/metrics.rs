@@ -0,0 +1,164 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::backend::{CancelledError, StalledError, TimeoutError, Usage};
+use crate::breaker::BackendUnhealthyError;
+use crate::ratelimit::RateLimitedError;
+use crate::retry;
+
+/// Upper bounds, in seconds, of the request duration histogram buckets.
+const DURATION_BUCKETS: &[f64] = &[0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0];
+
+#[derive(Default)]
+struct Histogram {
+    /// Count per bucket of [`DURATION_BUCKETS`], not cumulative.
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; DURATION_BUCKETS.len()];
+        }
+        if let Some(bucket) = DURATION_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// Keyed by (backend, model, outcome).
+    requests: BTreeMap<(String, String, &'static str), u64>,
+    /// Keyed by model.
+    durations: BTreeMap<String, Histogram>,
+    /// Keyed by (backend, kind).
+    failures: BTreeMap<(String, &'static str), u64>,
+    retries: u64,
+    /// Keyed by (model, prompt|completion).
+    tokens: BTreeMap<(String, &'static str), u64>,
+}
+
+/// Counters exposed in the Prometheus text format at `/metrics` on the http transport.
+#[derive(Default)]
+pub struct Metrics {
+    state: Mutex<State>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a finished tool-level generation, including all retries.
+    pub fn record_request(&self, backend: &str, model: &str, outcome: &'static str, elapsed: Duration) {
+        let mut state = self.state.lock().unwrap();
+        *state.requests.entry((backend.to_string(), model.to_string(), outcome)).or_default() += 1;
+        state.durations.entry(model.to_string()).or_default().observe(elapsed.as_secs_f64());
+    }
+
+    /// Record one failed attempt; for the cli backend these are failed gemini processes.
+    pub fn record_failure(&self, backend: &str, kind: &'static str) {
+        *self.state.lock().unwrap().failures.entry((backend.to_string(), kind)).or_default() += 1;
+    }
+
+    pub fn record_retry(&self) {
+        self.state.lock().unwrap().retries += 1;
+    }
+
+    pub fn record_tokens(&self, model: &str, usage: &Usage) {
+        let mut state = self.state.lock().unwrap();
+        *state.tokens.entry((model.to_string(), "prompt")).or_default() += usage.prompt_tokens as u64;
+        *state.tokens.entry((model.to_string(), "completion")).or_default() += usage.completion_tokens as u64;
+    }
+
+    /// Render all metrics plus the scheduler gauges in the Prometheus text format.
+    pub fn render(&self, queued: usize, in_flight: usize) -> String {
+        let state = self.state.lock().unwrap();
+        let mut out = String::new();
+
+        out.push_str("# HELP gemini_mcp_requests_total Generations handled, by outcome.\n");
+        out.push_str("# TYPE gemini_mcp_requests_total counter\n");
+        for ((backend, model, outcome), count) in &state.requests {
+            let _ = writeln!(
+                out,
+                "gemini_mcp_requests_total{{backend=\"{}\",model=\"{}\",outcome=\"{}\"}} {}",
+                escape(backend), escape(model), outcome, count
+            );
+        }
+
+        out.push_str("# HELP gemini_mcp_request_duration_seconds Time from request to result, including queueing and retries.\n");
+        out.push_str("# TYPE gemini_mcp_request_duration_seconds histogram\n");
+        for (model, histogram) in &state.durations {
+            let model = escape(model);
+            let mut cumulative = 0;
+            for (bound, count) in DURATION_BUCKETS.iter().zip(&histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "gemini_mcp_request_duration_seconds_bucket{{model=\"{}\",le=\"{}\"}} {}",
+                    model, bound, cumulative
+                );
+            }
+            let _ = writeln!(out, "gemini_mcp_request_duration_seconds_bucket{{model=\"{}\",le=\"+Inf\"}} {}", model, histogram.count);
+            let _ = writeln!(out, "gemini_mcp_request_duration_seconds_sum{{model=\"{}\"}} {}", model, histogram.sum);
+            let _ = writeln!(out, "gemini_mcp_request_duration_seconds_count{{model=\"{}\"}} {}", model, histogram.count);
+        }
+
+        out.push_str("# HELP gemini_mcp_backend_failures_total Failed attempts; for the cli backend, failed gemini processes.\n");
+        out.push_str("# TYPE gemini_mcp_backend_failures_total counter\n");
+        for ((backend, kind), count) in &state.failures {
+            let _ = writeln!(out, "gemini_mcp_backend_failures_total{{backend=\"{}\",kind=\"{}\"}} {}", escape(backend), kind, count);
+        }
+
+        out.push_str("# HELP gemini_mcp_retries_total Retries after transient failures.\n");
+        out.push_str("# TYPE gemini_mcp_retries_total counter\n");
+        let _ = writeln!(out, "gemini_mcp_retries_total {}", state.retries);
+
+        out.push_str("# HELP gemini_mcp_tokens_total Tokens used, as reported by Gemini or estimated.\n");
+        out.push_str("# TYPE gemini_mcp_tokens_total counter\n");
+        for ((model, kind), count) in &state.tokens {
+            let _ = writeln!(out, "gemini_mcp_tokens_total{{model=\"{}\",type=\"{}\"}} {}", escape(model), kind, count);
+        }
+
+        out.push_str("# HELP gemini_mcp_queue_depth Requests waiting for a free slot.\n");
+        out.push_str("# TYPE gemini_mcp_queue_depth gauge\n");
+        let _ = writeln!(out, "gemini_mcp_queue_depth {}", queued);
+
+        out.push_str("# HELP gemini_mcp_in_flight Generations currently running.\n");
+        out.push_str("# TYPE gemini_mcp_in_flight gauge\n");
+        let _ = writeln!(out, "gemini_mcp_in_flight {}", in_flight);
+
+        out
+    }
+}
+
+/// Escape a label value for the text exposition format.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// The `kind` label for a failed attempt.
+pub fn failure_kind(error: &anyhow::Error) -> &'static str {
+    if error.downcast_ref::<TimeoutError>().is_some() {
+        "timeout"
+    } else if error.downcast_ref::<StalledError>().is_some() {
+        "stalled"
+    } else if error.downcast_ref::<BackendUnhealthyError>().is_some() {
+        "unhealthy"
+    } else if error.downcast_ref::<CancelledError>().is_some() {
+        "cancelled"
+    } else if error.downcast_ref::<RateLimitedError>().is_some() {
+        "rate_limited"
+    } else if retry::is_transient(error) {
+        "transient"
+    } else {
+        "error"
+    }
+}
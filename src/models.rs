@@ -0,0 +1,150 @@
+use serde::Serialize;
+
+/// What a model accepts and produces, plus its token limits.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelInfo {
+    pub name: String,
+    pub display_name: String,
+    pub input_token_limit: u32,
+    pub output_token_limit: u32,
+    pub input_modalities: Vec<&'static str>,
+    pub output_modalities: Vec<&'static str>,
+}
+
+/// Standard-tier list price in USD per million tokens.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Pricing {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+impl Pricing {
+    pub fn cost(&self, prompt_tokens: u32, completion_tokens: u32) -> f64 {
+        (prompt_tokens as f64 * self.input_per_million + completion_tokens as f64 * self.output_per_million)
+            / 1_000_000.0
+    }
+}
+
+struct RegistryEntry {
+    name: &'static str,
+    display_name: &'static str,
+    input_token_limit: u32,
+    output_token_limit: u32,
+    input_modalities: &'static [&'static str],
+    output_modalities: &'static [&'static str],
+    pricing: Pricing,
+}
+
+const MULTIMODAL_INPUT: &[&str] = &["text", "image", "audio", "video", "pdf"];
+const TEXT_OUTPUT: &[&str] = &["text"];
+
+/// Maintained list of generally available models, used when the API cannot be queried.
+const REGISTRY: &[RegistryEntry] = &[
+    RegistryEntry {
+        name: "gemini-2.5-pro",
+        display_name: "Gemini 2.5 Pro",
+        input_token_limit: 1_048_576,
+        output_token_limit: 65_536,
+        input_modalities: MULTIMODAL_INPUT,
+        output_modalities: TEXT_OUTPUT,
+        pricing: Pricing {
+            input_per_million: 1.25,
+            output_per_million: 10.0,
+        },
+    },
+    RegistryEntry {
+        name: "gemini-2.5-flash",
+        display_name: "Gemini 2.5 Flash",
+        input_token_limit: 1_048_576,
+        output_token_limit: 65_536,
+        input_modalities: MULTIMODAL_INPUT,
+        output_modalities: TEXT_OUTPUT,
+        pricing: Pricing {
+            input_per_million: 0.3,
+            output_per_million: 2.5,
+        },
+    },
+    RegistryEntry {
+        name: "gemini-2.5-flash-lite",
+        display_name: "Gemini 2.5 Flash-Lite",
+        input_token_limit: 1_048_576,
+        output_token_limit: 65_536,
+        input_modalities: MULTIMODAL_INPUT,
+        output_modalities: TEXT_OUTPUT,
+        pricing: Pricing {
+            input_per_million: 0.1,
+            output_per_million: 0.4,
+        },
+    },
+    RegistryEntry {
+        name: "gemini-2.0-flash",
+        display_name: "Gemini 2.0 Flash",
+        input_token_limit: 1_048_576,
+        output_token_limit: 8_192,
+        input_modalities: MULTIMODAL_INPUT,
+        output_modalities: TEXT_OUTPUT,
+        pricing: Pricing {
+            input_per_million: 0.1,
+            output_per_million: 0.4,
+        },
+    },
+    RegistryEntry {
+        name: "gemini-2.0-flash-lite",
+        display_name: "Gemini 2.0 Flash-Lite",
+        input_token_limit: 1_048_576,
+        output_token_limit: 8_192,
+        input_modalities: MULTIMODAL_INPUT,
+        output_modalities: TEXT_OUTPUT,
+        pricing: Pricing {
+            input_per_million: 0.075,
+            output_per_million: 0.3,
+        },
+    },
+];
+
+impl RegistryEntry {
+    fn to_info(&self) -> ModelInfo {
+        ModelInfo {
+            name: self.name.to_string(),
+            display_name: self.display_name.to_string(),
+            input_token_limit: self.input_token_limit,
+            output_token_limit: self.output_token_limit,
+            input_modalities: self.input_modalities.to_vec(),
+            output_modalities: self.output_modalities.to_vec(),
+        }
+    }
+}
+
+/// All models in the built-in registry.
+pub fn registry() -> Vec<ModelInfo> {
+    REGISTRY.iter().map(RegistryEntry::to_info).collect()
+}
+
+/// Look up a model in the built-in registry by name.
+pub fn lookup(name: &str) -> Option<ModelInfo> {
+    let name = name.strip_prefix("models/").unwrap_or(name);
+    REGISTRY.iter().find(|entry| entry.name == name).map(RegistryEntry::to_info)
+}
+
+/// List price for a registry model, if known.
+pub fn pricing(name: &str) -> Option<Pricing> {
+    let name = name.strip_prefix("models/").unwrap_or(name);
+    REGISTRY.iter().find(|entry| entry.name == name).map(|entry| entry.pricing)
+}
+
+/// Modalities for a model the registry may not know about, guessed from its family.
+pub fn modalities_for(name: &str) -> (Vec<&'static str>, Vec<&'static str>) {
+    if let Some(info) = lookup(name) {
+        return (info.input_modalities, info.output_modalities);
+    }
+    if name.contains("embedding") {
+        return (vec!["text"], vec!["embedding"]);
+    }
+    if name.contains("imagen") || name.contains("image-generation") {
+        return (vec!["text"], vec!["image"]);
+    }
+    if name.starts_with("gemini") {
+        return (MULTIMODAL_INPUT.to_vec(), TEXT_OUTPUT.to_vec());
+    }
+    (vec!["text"], TEXT_OUTPUT.to_vec())
+}
@@ -0,0 +1,78 @@
+use handlebars::Handlebars;
+use serde::Deserialize;
+
+/// Most steps `gemini_pipeline` runs in one call.
+pub const MAX_STEPS: usize = 10;
+
+/// One prompt of a `gemini_pipeline`, rendered from the outputs before it.
+#[derive(Debug, Clone, Deserialize, schemars::JsonSchema)]
+pub struct PipelineStep {
+    #[schemars(description = "Name later steps refer to this step's output by, as {{steps.<name>}}: letters, digits and underscores (optional, defaults to step1, step2, ...)")]
+    #[serde(default)]
+    pub name: Option<String>,
+    #[schemars(description = "Handlebars template of the step's prompt. {{input}} is the pipeline's input, {{previous}} the output of the step before (the input for the first step) and {{steps.<name>}} the output of an earlier step")]
+    pub template: String,
+    #[schemars(description = "The model for this step (optional, defaults to the pipeline's model)")]
+    #[serde(default)]
+    pub model: Option<String>,
+    #[schemars(description = "System instruction for this step (optional, defaults to the configured one)")]
+    #[serde(default)]
+    pub system_instruction: Option<String>,
+}
+
+/// The steps of a pipeline with their templates compiled.
+pub struct Pipeline {
+    registry: Handlebars<'static>,
+    names: Vec<String>,
+}
+
+impl Pipeline {
+    /// Check and compile `steps`, failing with a message for the caller.
+    pub fn compile(steps: &[PipelineStep]) -> Result<Self, String> {
+        if steps.is_empty() || steps.len() > MAX_STEPS {
+            return Err(format!("A pipeline needs between 1 and {} steps", MAX_STEPS));
+        }
+
+        let mut registry = Handlebars::new();
+        registry.register_escape_fn(handlebars::no_escape);
+        // A misspelled {{steps.name}} should fail rather than render as nothing
+        registry.set_strict_mode(true);
+
+        let mut names: Vec<String> = Vec::new();
+        for (index, step) in steps.iter().enumerate() {
+            let name = step.name.clone().unwrap_or_else(|| format!("step{}", index + 1));
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(format!("Invalid step name '{}'", name));
+            }
+            if names.contains(&name) {
+                return Err(format!("Step name '{}' is used twice", name));
+            }
+            registry
+                .register_template_string(&name, &step.template)
+                .map_err(|e| format!("Invalid template of step '{}': {}", name, e))?;
+            names.push(name);
+        }
+        Ok(Self { registry, names })
+    }
+
+    pub fn name(&self, index: usize) -> &str {
+        &self.names[index]
+    }
+
+    /// The prompt of step `index`, given the pipeline's `input` and the outputs of the steps before it.
+    pub fn render(&self, index: usize, input: &str, outputs: &[String]) -> Result<String, String> {
+        let steps: serde_json::Map<String, serde_json::Value> = self.names
+            .iter()
+            .zip(outputs)
+            .map(|(name, output)| (name.clone(), serde_json::Value::String(output.clone())))
+            .collect();
+        let data = serde_json::json!({
+            "input": input,
+            "previous": outputs.last().map_or(input, String::as_str),
+            "steps": steps,
+        });
+        self.registry
+            .render(self.name(index), &data)
+            .map_err(|e| format!("Failed to render step '{}': {}", self.name(index), e))
+    }
+}
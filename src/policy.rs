@@ -0,0 +1,93 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models;
+
+/// Which models requests may use, matched after aliases are resolved.
+///
+/// Patterns are model names in which `*` matches any run of characters,
+/// e.g. `gemini-2.5-*` or `*-exp*`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelPolicy {
+    /// Only these models may be used; empty allows every model not denied
+    /// (`GEMINI_ALLOWED_MODELS`, comma-separated).
+    pub allow: Vec<String>,
+    /// These models may never be used, even when allowed (`GEMINI_DENIED_MODELS`, comma-separated).
+    pub deny: Vec<String>,
+}
+
+impl ModelPolicy {
+    pub fn is_restricted(&self) -> bool {
+        !self.allow.is_empty() || !self.deny.is_empty()
+    }
+
+    pub fn permits(&self, model: &str) -> bool {
+        (self.allow.is_empty() || self.allow.iter().any(|pattern| matches(pattern, model)))
+            && !self.deny.iter().any(|pattern| matches(pattern, model))
+    }
+
+    /// Fail with a [`ModelNotAllowedError`] unless `model` is permitted.
+    pub fn check(&self, model: &str) -> Result<(), ModelNotAllowedError> {
+        if self.permits(model) {
+            return Ok(());
+        }
+        Err(ModelNotAllowedError {
+            model: model.to_string(),
+            allowed: self.alternatives(),
+        })
+    }
+
+    /// Known models the policy permits: the built-in registry's, then those
+    /// named in `allow` without a wildcard.
+    pub fn alternatives(&self) -> Vec<String> {
+        let mut allowed: Vec<String> = models::registry().into_iter().map(|model| model.name).collect();
+        for name in self.allow.iter().filter(|pattern| !pattern.contains('*')) {
+            if !allowed.contains(name) {
+                allowed.push(name.clone());
+            }
+        }
+        allowed.retain(|model| self.permits(model));
+        allowed
+    }
+}
+
+/// Returned when a request names a model the server's `model_policy` forbids.
+#[derive(Debug)]
+pub struct ModelNotAllowedError {
+    pub model: String,
+    pub allowed: Vec<String>,
+}
+
+impl std::fmt::Display for ModelNotAllowedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Model {} is not allowed by the server's model policy", self.model)?;
+        if self.allowed.is_empty() {
+            write!(f, "; no known model is allowed")
+        } else {
+            write!(f, "; use one of {}", self.allowed.join(", "))
+        }
+    }
+}
+
+impl std::error::Error for ModelNotAllowedError {}
+
+/// Whether `model` matches `pattern`, in which `*` matches any run of characters.
+fn matches(pattern: &str, model: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = model.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard at all
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
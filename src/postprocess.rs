@@ -0,0 +1,101 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use crate::config::Config;
+
+/// Key in `postprocess` whose steps apply to tools without an entry of their own.
+const DEFAULT_KEY: &str = "*";
+
+/// Opening lines that only announce the answer, e.g. "Sure, here's the summary:".
+const PREAMBLE: &str = r"(?i)^\s*(?:sure|certainly|of course|absolutely|okay|ok|alright|great|here(?:'s| is| are))\b[^\n]{0,200}[:!.]\s*$";
+
+/// One step applied to Gemini's answer before a tool returns it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum PostProcessor {
+    /// Remove the lines that open and close Markdown code fences, keeping the code.
+    StripFences,
+    /// Drop an opening line such as "Sure, here's the code:".
+    TrimPreamble,
+    /// Replace every match of `pattern`; `replacement` may use `$1` or `${name}` groups.
+    Replace { pattern: String, replacement: String },
+    /// Cut the answer to at most `chars` characters.
+    MaxLength { chars: usize },
+}
+
+enum Step {
+    StripFences,
+    TrimPreamble,
+    Replace(Regex, String),
+    MaxLength(usize),
+}
+
+impl Step {
+    fn apply(&self, text: String) -> String {
+        static FENCE_LINE: OnceLock<Regex> = OnceLock::new();
+        static PREAMBLE_LINE: OnceLock<Regex> = OnceLock::new();
+
+        match self {
+            Self::StripFences => {
+                let fence_line = FENCE_LINE.get_or_init(|| Regex::new(r"(?m)^\s*```.*\n?").expect("fence line pattern is valid"));
+                fence_line.replace_all(&text, "").into_owned()
+            }
+            Self::TrimPreamble => {
+                let preamble = PREAMBLE_LINE.get_or_init(|| Regex::new(PREAMBLE).expect("preamble pattern is valid"));
+                let trimmed = text.trim_start();
+                match trimmed.split_once('\n') {
+                    // Only when something follows, so a one-line answer is left alone
+                    Some((first, rest)) if preamble.is_match(first) && !rest.trim().is_empty() => rest.trim_start().to_string(),
+                    _ => text,
+                }
+            }
+            Self::Replace(pattern, replacement) => pattern.replace_all(&text, replacement.as_str()).into_owned(),
+            Self::MaxLength(chars) => match text.char_indices().nth(*chars) {
+                Some((end, _)) => text[..end].trim_end().to_string(),
+                None => text,
+            },
+        }
+    }
+}
+
+/// The `postprocess` steps of every tool, compiled.
+#[derive(Default)]
+pub struct PostProcessing {
+    pipelines: BTreeMap<String, Vec<Step>>,
+}
+
+impl PostProcessing {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let mut pipelines = BTreeMap::new();
+        for (tool, processors) in &config.postprocess {
+            let steps = processors
+                .iter()
+                .map(|processor| {
+                    Ok(match processor {
+                        PostProcessor::StripFences => Step::StripFences,
+                        PostProcessor::TrimPreamble => Step::TrimPreamble,
+                        PostProcessor::Replace { pattern, replacement } => {
+                            let regex = Regex::new(pattern)
+                                .with_context(|| format!("Invalid postprocess pattern '{}' for {}", pattern, tool))?;
+                            Step::Replace(regex, replacement.clone())
+                        }
+                        PostProcessor::MaxLength { chars } => Step::MaxLength(*chars),
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            pipelines.insert(tool.clone(), steps);
+        }
+        Ok(Self { pipelines })
+    }
+
+    /// `text` after the steps configured for `tool`, in order.
+    pub fn apply(&self, tool: &str, text: String) -> String {
+        let Some(steps) = self.pipelines.get(tool).or_else(|| self.pipelines.get(DEFAULT_KEY)) else {
+            return text;
+        };
+        steps.iter().fold(text, |text, step| step.apply(text))
+    }
+}
@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+
+use crate::format::{self, InvalidOutputError};
+use crate::git::CommitInfo;
+
+/// Most of the branch's diff sent to Gemini, in estimated tokens; the middle of a
+/// larger diff is cut out, leaving the commits and diffstat to describe it.
+pub const MAX_DIFF_TOKENS: u32 = 80_000;
+
+/// A pull request description in the parts reviewers look for.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PrDescription {
+    pub title: String,
+    /// What the change does and why, in a short paragraph or two.
+    pub summary: String,
+    /// The notable changes, one per entry.
+    #[serde(default)]
+    pub changes: Vec<String>,
+    /// How the change was or should be verified, one step per entry.
+    #[serde(default)]
+    pub test_plan: Vec<String>,
+}
+
+impl PrDescription {
+    /// The description's body as Markdown, ready to post.
+    pub fn to_markdown(&self) -> String {
+        let list = |items: &[String]| items.iter().map(|item| format!("- {}", item.trim())).collect::<Vec<_>>().join("\n");
+        let mut markdown = format!("## Summary\n\n{}\n", self.summary.trim());
+        if !self.changes.is_empty() {
+            markdown.push_str(&format!("\n## Changes\n\n{}\n", list(&self.changes)));
+        }
+        if !self.test_plan.is_empty() {
+            markdown.push_str(&format!("\n## Test plan\n\n{}\n", list(&self.test_plan)));
+        }
+        markdown
+    }
+}
+
+/// Prompt asking for the description of the commits and diff between `base` and `head`.
+pub fn prompt(base: &str, head: &str, commits: &[CommitInfo], diffstat: &str, diff: &str, instructions: Option<&str>) -> String {
+    let commits: Vec<String> = commits
+        .iter()
+        .rev()
+        .map(|commit| {
+            let short = &commit.hash[..commit.hash.len().min(10)];
+            if commit.body.is_empty() {
+                format!("- {} {}", short, commit.subject)
+            } else {
+                format!("- {} {}\n  {}", short, commit.subject, commit.body.replace('\n', "\n  "))
+            }
+        })
+        .collect();
+    let mut prompt = format!(
+        "Write the pull request description for merging {} into {}. Base it on the commits, diffstat and diff \
+         below. Explain what the change does and why, for a reviewer who hasn't seen it; don't narrate the \
+         commits one by one or pad with generic statements.",
+        head, base
+    );
+    if let Some(instructions) = instructions.filter(|instructions| !instructions.trim().is_empty()) {
+        prompt.push_str(&format!("\n\n{}", instructions.trim()));
+    }
+    prompt.push_str(&format!(
+        "\n\nAnswer with a JSON object {{\"title\": ..., \"summary\": ..., \"changes\": [...], \"test_plan\": [...]}}: \
+         a title of at most 72 characters, a summary of one or two short paragraphs, the notable changes as short \
+         entries, and the steps that verify the change, using the tests the diff adds where there are any.\n\n\
+         Commits, oldest first:\n{}\n\nDiffstat:\n{}\n\n```diff\n{}\n```",
+        commits.join("\n"),
+        diffstat.trim_end(),
+        diff.trim_end()
+    ));
+    prompt
+}
+
+/// The description in Gemini's answer to [`prompt`].
+pub fn parse(answer: &str) -> Result<PrDescription, InvalidOutputError> {
+    let value = format::parse_json(answer)?;
+    serde_json::from_value(value).map_err(|e| InvalidOutputError {
+        reason: format!("expected {{\"title\", \"summary\", \"changes\", \"test_plan\"}}: {}", e),
+    })
+}
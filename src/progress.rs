@@ -0,0 +1,78 @@
+use rmcp::{model::*, service::RequestContext, Peer, RoleServer};
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Forwards chunks of gemini output to the client as MCP progress notifications.
+///
+/// Progress is only reported when the client supplied a progress token with the request.
+#[derive(Clone)]
+pub struct ProgressReporter {
+    peer: Peer<RoleServer>,
+    token: Option<ProgressToken>,
+    progress: Arc<AtomicU32>,
+    /// When the last notification was sent, or the reporter created.
+    last_sent: Arc<Mutex<Instant>>,
+}
+
+impl ProgressReporter {
+    pub fn new(context: &RequestContext<RoleServer>) -> Self {
+        Self {
+            peer: context.peer.clone(),
+            token: context.meta.get_progress_token(),
+            progress: Arc::new(AtomicU32::new(0)),
+            last_sent: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// A reporter that never sends anything, for work whose progress is reported elsewhere.
+    pub fn muted(context: &RequestContext<RoleServer>) -> Self {
+        Self {
+            token: None,
+            ..Self::new(context)
+        }
+    }
+
+    pub async fn report(&self, message: String) {
+        let Some(token) = self.token.clone() else {
+            return;
+        };
+        let progress = self.progress.fetch_add(1, Ordering::SeqCst) + 1;
+        *self.last_sent.lock().unwrap() = Instant::now();
+
+        let result = self.peer.notify_progress(ProgressNotificationParam {
+            progress_token: token,
+            progress,
+            total: None,
+            message: Some(message),
+        }).await;
+
+        if let Err(e) = result {
+            tracing::warn!("Failed to send progress notification: {:?}", e);
+        }
+    }
+
+    /// Run `work`, telling the client whenever it has heard nothing for `interval`
+    /// that the call is still going, since some clients give up on silent tool calls.
+    pub async fn keepalive<T>(&self, interval: Option<Duration>, work: impl Future<Output = T>) -> T {
+        let Some(interval) = interval.filter(|_| self.token.is_some()) else {
+            return work.await;
+        };
+        let started = Instant::now();
+        let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+        tokio::pin!(work);
+
+        loop {
+            tokio::select! {
+                output = &mut work => return output,
+                _ = ticker.tick() => {
+                    let silent = self.last_sent.lock().unwrap().elapsed() >= interval;
+                    if silent {
+                        self.report(format!("Still generating, {}s elapsed", started.elapsed().as_secs())).await;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,363 @@
+use anyhow::{Context, Result};
+use rmcp::{model::*, Error as McpError};
+use handlebars::Handlebars;
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::errors;
+
+/// A reusable prompt exposed through the MCP prompts capability.
+///
+/// Templates are TOML files whose `template` is a Handlebars template:
+/// `{{name}}` is replaced with the argument of that name, `{{#each files}}`
+/// loops over an array argument and `{{> name}}` includes a partial.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PromptTemplate {
+    /// Defaults to the file name without its extension.
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub arguments: Vec<TemplateArgument>,
+    pub template: String,
+}
+
+impl PromptTemplate {
+    /// JSON Schema of the arguments, as an object schema.
+    pub fn schema(&self) -> serde_json::Value {
+        let properties: serde_json::Map<String, serde_json::Value> = self.arguments
+            .iter()
+            .map(|argument| {
+                let mut property = serde_json::json!({ "type": argument.kind.name() });
+                if argument.kind == ArgumentType::Array {
+                    property["items"] = serde_json::json!({ "type": argument.items.unwrap_or_default().name() });
+                }
+                if let Some(description) = &argument.description {
+                    property["description"] = serde_json::json!(description);
+                }
+                if let Some(default) = &argument.default {
+                    property["default"] = default.clone();
+                }
+                (argument.name.clone(), property)
+            })
+            .collect();
+        let required: Vec<&str> = self.arguments
+            .iter()
+            .filter(|argument| argument.required)
+            .map(|argument| argument.name.as_str())
+            .collect();
+        serde_json::json!({
+            "type": "object",
+            "properties": properties,
+            "required": required,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateArgument {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub required: bool,
+    /// Used when the client leaves an optional argument out.
+    #[serde(default)]
+    pub default: Option<serde_json::Value>,
+    #[serde(default, rename = "type")]
+    pub kind: ArgumentType,
+    /// Type of the elements of an `array` argument (default: string).
+    #[serde(default)]
+    pub items: Option<ArgumentType>,
+}
+
+/// The JSON type an argument's value is converted to before rendering.
+///
+/// MCP clients send prompt arguments as strings, so other types are parsed
+/// from them: numbers and booleans as written, arrays and objects as JSON,
+/// and arrays that aren't JSON one element per non-empty line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArgumentType {
+    #[default]
+    String,
+    Number,
+    Integer,
+    Boolean,
+    Array,
+    Object,
+}
+
+impl ArgumentType {
+    fn name(self) -> &'static str {
+        match self {
+            Self::String => "string",
+            Self::Number => "number",
+            Self::Integer => "integer",
+            Self::Boolean => "boolean",
+            Self::Array => "array",
+            Self::Object => "object",
+        }
+    }
+
+    /// `value` as this type, or `None` when it can't be converted.
+    fn convert(self, value: &serde_json::Value, items: ArgumentType) -> Option<serde_json::Value> {
+        use serde_json::Value;
+
+        let text = value.as_str().map(str::trim);
+        match self {
+            Self::String => Some(match value {
+                Value::String(_) => value.clone(),
+                other => Value::String(other.to_string()),
+            }),
+            Self::Number => match value {
+                Value::Number(_) => Some(value.clone()),
+                _ => text?.parse::<f64>().ok().and_then(serde_json::Number::from_f64).map(Value::Number),
+            },
+            Self::Integer => match value {
+                Value::Number(number) if number.is_i64() || number.is_u64() => Some(value.clone()),
+                _ => text?.parse::<i64>().ok().map(Value::from),
+            },
+            Self::Boolean => match value {
+                Value::Bool(_) => Some(value.clone()),
+                _ => text?.parse::<bool>().ok().map(Value::Bool),
+            },
+            Self::Array => {
+                let elements = match value {
+                    Value::Array(elements) => elements.clone(),
+                    _ => {
+                        let text = text?;
+                        match serde_json::from_str(text) {
+                            Ok(Value::Array(elements)) => elements,
+                            _ => text.lines().map(str::trim).filter(|line| !line.is_empty()).map(Value::from).collect(),
+                        }
+                    }
+                };
+                elements
+                    .iter()
+                    .map(|element| items.convert(element, ArgumentType::String))
+                    .collect::<Option<Vec<_>>>()
+                    .map(Value::Array)
+            }
+            Self::Object => match value {
+                Value::Object(_) => Some(value.clone()),
+                _ => serde_json::from_str::<Value>(text?).ok().filter(Value::is_object),
+            },
+        }
+    }
+}
+
+const BUILTIN: &[(&str, &str)] = &[
+    ("code_review", r#"
+description = "Review code for bugs, security problems and readability"
+template = """
+Review the following code. Point out bugs, security problems, unclear naming and missing error handling, most important first.
+Focus: {{focus}}
+
+{{code}}
+"""
+
+[[arguments]]
+name = "code"
+description = "The code, or paths of the files to review"
+required = true
+
+[[arguments]]
+name = "focus"
+description = "What to pay most attention to"
+default = "correctness"
+"#),
+    ("summarize", r#"
+description = "Summarize a text or document"
+template = """
+Summarize the following in {{length}}. Keep names, numbers and decisions; drop filler.
+
+{{text}}
+"""
+
+[[arguments]]
+name = "text"
+description = "The text, or paths of the files to summarize"
+required = true
+
+[[arguments]]
+name = "length"
+description = "How long the summary should be"
+default = "a few bullet points"
+"#),
+    ("translate", r#"
+description = "Translate text into another language"
+template = """
+Translate the following into {{language}}. Preserve formatting, code blocks and technical terms. Reply with the translation only.
+
+{{text}}
+"""
+
+[[arguments]]
+name = "text"
+description = "The text to translate"
+required = true
+
+[[arguments]]
+name = "language"
+description = "The target language"
+required = true
+"#),
+];
+
+/// The built-in templates plus any loaded from the prompts directory.
+pub struct PromptLibrary {
+    templates: Vec<PromptTemplate>,
+    /// Every template, registered under its name, and the partials.
+    registry: Handlebars<'static>,
+}
+
+impl PromptLibrary {
+    /// Load the built-in templates, then every `*.toml` template and `*.hbs`
+    /// partial in `dir`.
+    ///
+    /// A file named like a built-in template replaces it. Unreadable files and
+    /// templates that don't compile are skipped with a warning so one bad
+    /// template does not hide the rest.
+    pub fn load(dir: Option<&Path>) -> Self {
+        let mut templates: Vec<PromptTemplate> = BUILTIN
+            .iter()
+            .map(|(name, source)| parse(name, source).expect("built-in prompt template is valid"))
+            .collect();
+        let mut registry = Handlebars::new();
+        // Prompts are plain text, so nothing is HTML-escaped
+        registry.register_escape_fn(handlebars::no_escape);
+
+        if let Some(dir) = dir.filter(|dir| dir.is_dir()) {
+            match load_dir(dir) {
+                Ok((loaded, partials)) => {
+                    for template in loaded {
+                        templates.retain(|existing| existing.name != template.name);
+                        templates.push(template);
+                    }
+                    for (name, source) in partials {
+                        if let Err(e) = registry.register_partial(&name, source) {
+                            tracing::warn!("Skipping prompt partial {}: {}", name, e);
+                        }
+                    }
+                }
+                Err(e) => tracing::warn!("Failed to read prompts directory {}: {:#}", dir.display(), e),
+            }
+        }
+
+        templates.retain(|template| match registry.register_template_string(&template.name, &template.template) {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::warn!("Skipping prompt template {}: {}", template.name, e);
+                false
+            }
+        });
+        templates.sort_by(|a, b| a.name.cmp(&b.name));
+        Self { templates, registry }
+    }
+
+    pub fn list(&self) -> Vec<Prompt> {
+        self.templates
+            .iter()
+            .map(|template| {
+                let arguments = template.arguments
+                    .iter()
+                    .map(|argument| PromptArgument {
+                        name: argument.name.clone(),
+                        description: argument.description.clone(),
+                        required: Some(argument.required),
+                    })
+                    .collect();
+                Prompt::new(&template.name, template.description.as_deref(), Some(arguments))
+            })
+            .collect()
+    }
+
+    pub fn templates(&self) -> &[PromptTemplate] {
+        &self.templates
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PromptTemplate> {
+        self.templates.iter().find(|template| template.name == name)
+    }
+
+    /// Fill in the template `name` with `arguments`, converted to their declared types.
+    pub fn render(&self, name: &str, arguments: Option<&JsonObject>) -> Result<GetPromptResult, McpError> {
+        let template = self.get(name)
+            .ok_or_else(|| errors::invalid_argument(format!("Unknown prompt '{}'", name)))?;
+
+        let mut data = serde_json::Map::new();
+        for argument in &template.arguments {
+            let given = arguments.and_then(|arguments| arguments.get(&argument.name));
+            let value = match given.or(argument.default.as_ref()) {
+                Some(value) => argument.kind.convert(value, argument.items.unwrap_or_default()).ok_or_else(|| {
+                    errors::invalid_argument(format!(
+                        "Argument '{}' of prompt '{}' must be of type {}",
+                        argument.name, name, argument.kind.name()
+                    ))
+                })?,
+                None if argument.required => {
+                    return Err(errors::invalid_argument(
+                        format!("Prompt '{}' requires the argument '{}'", name, argument.name),
+                    ));
+                }
+                None => serde_json::Value::String(String::new()),
+            };
+            data.insert(argument.name.clone(), value);
+        }
+
+        let text = self.registry.render(name, &data)
+            .map_err(|e| errors::invalid_argument(format!("Failed to render prompt '{}': {}", name, e)))?;
+        Ok(GetPromptResult {
+            description: template.description.clone(),
+            messages: vec![PromptMessage::new_text(PromptMessageRole::User, text.trim())],
+        })
+    }
+}
+
+/// The templates and partials, by name, in `dir`.
+fn load_dir(dir: &Path) -> Result<(Vec<PromptTemplate>, Vec<(String, String)>)> {
+    let mut templates = Vec::new();
+    let mut partials = Vec::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        if extension != Some("toml") && extension != Some("hbs") {
+            continue;
+        }
+        let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+
+        let source = match std::fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) => {
+                tracing::warn!("Skipping prompt file {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        if extension == Some("hbs") {
+            partials.push((stem.to_string(), source));
+            continue;
+        }
+        match parse(stem, &source) {
+            Ok(template) => templates.push(template),
+            Err(e) => tracing::warn!("Skipping prompt template {}: {:#}", path.display(), e),
+        }
+    }
+
+    Ok((templates, partials))
+}
+
+fn parse(default_name: &str, source: &str) -> Result<PromptTemplate> {
+    let mut template: PromptTemplate = toml::from_str(source)
+        .context("Invalid prompt template")?;
+    if template.name.is_empty() {
+        template.name = default_name.to_string();
+    }
+    Ok(template)
+}
@@ -0,0 +1,277 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+use crate::backend::CancelledError;
+use crate::progress::ProgressReporter;
+
+/// Key in `rate_limits` whose limits apply to every model without an entry of its own.
+const DEFAULT_KEY: &str = "*";
+
+/// Client-side quota for one model.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub requests_per_minute: Option<u32>,
+    /// Counts the estimated prompt tokens up front and the completion tokens once known.
+    pub tokens_per_minute: Option<u32>,
+}
+
+/// What happens to a request that would exceed its model's quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitPolicy {
+    /// Wait until the quota allows the request.
+    #[default]
+    Queue,
+    /// Fail immediately with a `rate_limited` error.
+    Reject,
+}
+
+/// Returned when a request was rejected by the client-side rate limiter.
+#[derive(Debug)]
+pub struct RateLimitedError {
+    pub model: String,
+    pub retry_after: Duration,
+}
+
+impl std::fmt::Display for RateLimitedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Rate limit for {} exceeded, retry in {:.1}s",
+            self.model,
+            self.retry_after.as_secs_f64()
+        )
+    }
+}
+
+impl std::error::Error for RateLimitedError {}
+
+/// A bucket holding up to `capacity` units that refills at `capacity` per minute.
+///
+/// The level may go negative when actual usage exceeds what was reserved,
+/// which delays later requests until the debt is repaid.
+struct Bucket {
+    capacity: f64,
+    level: f64,
+}
+
+impl Bucket {
+    fn new(per_minute: u32) -> Self {
+        Self { capacity: per_minute as f64, level: per_minute as f64 }
+    }
+
+    fn refill(&mut self, elapsed: Duration) {
+        self.level = (self.level + elapsed.as_secs_f64() * self.capacity / 60.0).min(self.capacity);
+    }
+
+    /// How long until `amount` units are available; zero when they already are.
+    fn wait_for(&self, amount: f64) -> Duration {
+        // A request larger than the whole bucket only waits for a full bucket
+        let missing = amount.min(self.capacity) - self.level;
+        if missing <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(missing * 60.0 / self.capacity)
+        }
+    }
+}
+
+struct ModelBuckets {
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+    refilled: Instant,
+}
+
+impl ModelBuckets {
+    fn new(limit: &RateLimit) -> Self {
+        Self {
+            requests: limit.requests_per_minute.map(Bucket::new),
+            tokens: limit.tokens_per_minute.map(Bucket::new),
+            refilled: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let elapsed = self.refilled.elapsed();
+        self.refilled = Instant::now();
+        for bucket in [&mut self.requests, &mut self.tokens].into_iter().flatten() {
+            bucket.refill(elapsed);
+        }
+    }
+
+    /// Take one request and `tokens` tokens, or say how long to wait first.
+    fn try_take(&mut self, tokens: u32) -> Result<(), Duration> {
+        self.refill();
+        let wait = [
+            self.requests.as_ref().map(|bucket| bucket.wait_for(1.0)),
+            self.tokens.as_ref().map(|bucket| bucket.wait_for(tokens as f64)),
+        ]
+        .into_iter()
+        .flatten()
+        .max()
+        .unwrap_or(Duration::ZERO);
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+
+        if let Some(bucket) = &mut self.requests {
+            bucket.level -= 1.0;
+        }
+        if let Some(bucket) = &mut self.tokens {
+            bucket.level -= tokens as f64;
+        }
+        Ok(())
+    }
+}
+
+/// Token-bucket limits on requests and tokens per minute, per model.
+pub struct RateLimiter {
+    limits: BTreeMap<String, RateLimit>,
+    buckets: Mutex<HashMap<String, ModelBuckets>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: BTreeMap<String, RateLimit>) -> Self {
+        Self { limits, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    /// Reserve one request and `tokens` prompt tokens for `model`.
+    ///
+    /// With [`RateLimitPolicy::Queue`] this waits, reporting progress, until
+    /// the quota allows it; with [`RateLimitPolicy::Reject`] it fails with
+    /// [`RateLimitedError`] instead.
+    pub async fn acquire(
+        &self,
+        model: &str,
+        tokens: u32,
+        policy: RateLimitPolicy,
+        progress: Option<&ProgressReporter>,
+        ct: &CancellationToken,
+    ) -> Result<()> {
+        loop {
+            let wait = match self.try_take(model, tokens) {
+                Ok(()) => return Ok(()),
+                Err(wait) => wait,
+            };
+
+            if policy == RateLimitPolicy::Reject {
+                return Err(RateLimitedError { model: model.to_string(), retry_after: wait }.into());
+            }
+
+            tracing::debug!("Rate limit for {} reached, waiting {:?}", model, wait);
+            if let Some(progress) = progress {
+                progress.report(format!("Rate limited: waiting {:.1}s for {} quota", wait.as_secs_f64(), model)).await;
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = ct.cancelled() => return Err(CancelledError.into()),
+            }
+        }
+    }
+
+    /// Charge tokens that were only known after the request, e.g. the completion.
+    pub fn consume(&self, model: &str, tokens: u32) {
+        let mut buckets = self.buckets.lock().unwrap();
+        if let Some(bucket) = buckets.get_mut(model).and_then(|buckets| buckets.tokens.as_mut()) {
+            bucket.level -= tokens as f64;
+        }
+    }
+
+    fn try_take(&self, model: &str, tokens: u32) -> Result<(), Duration> {
+        let Some(limit) = self.limits.get(model).or_else(|| self.limits.get(DEFAULT_KEY)) else {
+            return Ok(());
+        };
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets
+            .entry(model.to_string())
+            .or_insert_with(|| ModelBuckets::new(limit))
+            .try_take(tokens)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(limits: &[(&str, RateLimit)]) -> RateLimiter {
+        RateLimiter::new(limits.iter().map(|(model, limit)| (model.to_string(), *limit)).collect())
+    }
+
+    async fn reject(limiter: &RateLimiter, model: &str, tokens: u32) -> Result<()> {
+        limiter.acquire(model, tokens, RateLimitPolicy::Reject, None, &CancellationToken::new()).await
+    }
+
+    fn requests(per_minute: u32) -> RateLimit {
+        RateLimit { requests_per_minute: Some(per_minute), tokens_per_minute: None }
+    }
+
+    fn tokens(per_minute: u32) -> RateLimit {
+        RateLimit { requests_per_minute: None, tokens_per_minute: Some(per_minute) }
+    }
+
+    #[tokio::test]
+    async fn models_without_a_limit_are_always_admitted() {
+        let limiter = limiter(&[("gemini-2.5-pro", requests(1))]);
+        for _ in 0..10 {
+            reject(&limiter, "gemini-2.5-flash", 1_000_000).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_beyond_the_quota_are_rejected_with_a_retry_delay() {
+        let limiter = limiter(&[("gemini-2.5-pro", requests(2))]);
+        reject(&limiter, "gemini-2.5-pro", 0).await.unwrap();
+        reject(&limiter, "gemini-2.5-pro", 0).await.unwrap();
+
+        let error = reject(&limiter, "gemini-2.5-pro", 0).await.unwrap_err();
+        let error = error.downcast_ref::<RateLimitedError>().expect("rate limited");
+        assert_eq!(error.model, "gemini-2.5-pro");
+        // One request refills every 30 seconds
+        assert!(error.retry_after > Duration::from_secs(29), "{:?}", error.retry_after);
+        assert!(error.retry_after <= Duration::from_secs(30), "{:?}", error.retry_after);
+    }
+
+    #[tokio::test]
+    async fn default_limit_applies_to_each_unlisted_model_separately() {
+        let limiter = limiter(&[(DEFAULT_KEY, requests(1))]);
+        reject(&limiter, "gemini-2.5-pro", 0).await.unwrap();
+        reject(&limiter, "gemini-2.5-flash", 0).await.unwrap();
+        assert!(reject(&limiter, "gemini-2.5-pro", 0).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn prompt_larger_than_the_bucket_waits_only_for_a_full_bucket() {
+        let limiter = limiter(&[("gemini-2.5-pro", tokens(1_000))]);
+        reject(&limiter, "gemini-2.5-pro", 5_000).await.unwrap();
+
+        let error = reject(&limiter, "gemini-2.5-pro", 5_000).await.unwrap_err();
+        let error = error.downcast_ref::<RateLimitedError>().expect("rate limited");
+        assert!(error.retry_after <= Duration::from_secs(60), "{:?}", error.retry_after);
+    }
+
+    #[tokio::test]
+    async fn consumed_completion_tokens_delay_later_requests() {
+        let limiter = limiter(&[("gemini-2.5-pro", tokens(1_000))]);
+        reject(&limiter, "gemini-2.5-pro", 100).await.unwrap();
+        reject(&limiter, "gemini-2.5-pro", 100).await.unwrap();
+
+        limiter.consume("gemini-2.5-pro", 2_000);
+        assert!(reject(&limiter, "gemini-2.5-pro", 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn cancelled_wait_for_quota_fails_with_cancelled() {
+        let limiter = limiter(&[("gemini-2.5-pro", requests(1))]);
+        reject(&limiter, "gemini-2.5-pro", 0).await.unwrap();
+
+        let ct = CancellationToken::new();
+        ct.cancel();
+        let error = limiter.acquire("gemini-2.5-pro", 0, RateLimitPolicy::Queue, None, &ct).await.unwrap_err();
+        assert!(error.downcast_ref::<CancelledError>().is_some(), "{}", error);
+    }
+}
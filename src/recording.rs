@@ -0,0 +1,185 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+use crate::backend::{Backend, ExecOptions, GenerateRequest, GenerateResponse, Grounding, Usage};
+use crate::config::{Config, Recording};
+use crate::functions::FunctionCall;
+
+/// Saves Gemini's answers under `recordings_dir`, one file per request, or
+/// serves them from there instead of asking Gemini, as `recording` says.
+///
+/// A request is identified by what decides its answer: the prompt, images,
+/// model, system instruction, sampling and safety settings, functions and
+/// output format. Its directories and auth profile are left out, so
+/// recordings replay on another machine or in CI.
+pub struct Recorder {
+    replay: bool,
+    dir: PathBuf,
+}
+
+/// Returned in replay mode for a request nothing was recorded for, or for a
+/// tool whose results are never recorded; `key` is then the tool's name.
+#[derive(Debug)]
+pub struct NotRecordedError {
+    pub key: String,
+    pub dir: PathBuf,
+    pub recordable: bool,
+}
+
+impl std::fmt::Display for NotRecordedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.recordable {
+            return write!(f, "{} can't be replayed: only Gemini's answers are recorded in {}", self.key, self.dir.display());
+        }
+        write!(
+            f,
+            "No recording of this request in {} (key {}); run it once with recording = \"record\"",
+            self.dir.display(),
+            self.key
+        )
+    }
+}
+
+impl std::error::Error for NotRecordedError {}
+
+/// One recorded answer, with the request it answers for people reading the file.
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    request: serde_json::Value,
+    recorded_at: DateTime<Utc>,
+    text: String,
+    usage: Usage,
+    #[serde(default)]
+    grounding: Option<Grounding>,
+    #[serde(default)]
+    function_calls: Vec<FunctionCall>,
+    #[serde(default)]
+    warnings: Vec<String>,
+}
+
+impl Recorder {
+    /// The recorder `config` asks for; `None` when `recording` is off.
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        if config.recording == Recording::Off {
+            return Ok(None);
+        }
+        let dir = config.recordings_path().context("recording needs recordings_dir, or HOME to default it")?;
+        Ok(Some(Self { replay: config.recording == Recording::Replay, dir }))
+    }
+
+    /// Fail in replay mode for `tool`, which runs the CLI or an API for
+    /// something other than an answer, so there is nothing to replay.
+    pub fn check_live(&self, tool: &str) -> Result<()> {
+        if self.replay {
+            return Err(NotRecordedError { key: tool.to_string(), dir: self.dir.clone(), recordable: false }.into());
+        }
+        Ok(())
+    }
+
+    /// Answer `request` from its recording when replaying; otherwise have
+    /// `backend` answer it and record the answer. Refusals aren't recorded.
+    pub async fn generate(&self, backend: &Backend, request: &GenerateRequest, options: ExecOptions<'_>) -> Result<GenerateResponse> {
+        let identity = identity(request);
+        let key = hash(&identity);
+        let path = self.dir.join(format!("{}.json", key));
+        if self.replay {
+            return match load(path.clone()).await? {
+                Some(entry) => {
+                    tracing::info!("Replaying the recorded answer {}", path.display());
+                    Ok(GenerateResponse {
+                        text: entry.text,
+                        usage: entry.usage,
+                        grounding: entry.grounding,
+                        function_calls: entry.function_calls,
+                        warnings: entry.warnings,
+                        ..Default::default()
+                    })
+                }
+                None => Err(NotRecordedError { key, dir: self.dir.clone(), recordable: true }.into()),
+            };
+        }
+
+        let response = backend.generate(request, options).await?;
+        if response.safety.is_none() {
+            let entry = Entry {
+                request: identity,
+                recorded_at: Utc::now(),
+                text: response.text.clone(),
+                usage: response.usage,
+                grounding: response.grounding.clone(),
+                function_calls: response.function_calls.clone(),
+                warnings: response.warnings.clone(),
+            };
+            match save(path.clone(), entry).await {
+                Ok(()) => tracing::info!("Recorded the answer to {}", path.display()),
+                Err(e) => tracing::warn!("Failed to record the answer to {}: {:#}", path.display(), e),
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// The parts of `request` that decide its answer, with images by their digest.
+fn identity(request: &GenerateRequest) -> serde_json::Value {
+    let images: Vec<serde_json::Value> = request.images
+        .iter()
+        .map(|image| serde_json::json!({ "mime_type": image.mime_type, "sha256": digest(&image.data) }))
+        .collect();
+    serde_json::json!({
+        "prompt": request.prompt,
+        "images": images,
+        "model": request.model,
+        "system_instruction": request.system_instruction,
+        "max_tokens": request.max_tokens,
+        "temperature": request.temperature,
+        "top_p": request.top_p,
+        "top_k": request.top_k,
+        "stop_sequences": request.stop_sequences,
+        "seed": request.seed,
+        "safety_settings": request.safety_settings,
+        "google_search": request.google_search,
+        "functions": request.functions,
+        "function_results": request.function_results,
+        "resume": request.resume,
+        "all_files": request.all_files,
+        "output_format": request.output_format,
+    })
+}
+
+/// Hex SHA-256 of `identity`; serde_json keeps object keys sorted, so the
+/// same request always hashes alike.
+fn hash(identity: &serde_json::Value) -> String {
+    digest(identity.to_string().as_bytes())
+}
+
+fn digest(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+async fn load(path: PathBuf) -> Result<Option<Entry>> {
+    let json = match tokio::fs::read(&path).await {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read recording {}", path.display())),
+    };
+    let entry = serde_json::from_slice(&json).with_context(|| format!("Invalid recording {}", path.display()))?;
+    Ok(Some(entry))
+}
+
+async fn save(path: PathBuf, entry: Entry) -> Result<()> {
+    tokio::task::spawn_blocking(move || write(&path, &entry)).await?
+}
+
+fn write(path: &Path, entry: &Entry) -> Result<()> {
+    let dir = path.parent().context("recording has no directory")?;
+    std::fs::create_dir_all(dir)?;
+    // Write a uniquely named file then rename it, so concurrent recordings
+    // of the same request don't clash and a replay never reads half a file
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    serde_json::to_writer_pretty(&mut file, entry)?;
+    file.persist(path)?;
+    Ok(())
+}
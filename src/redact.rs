@@ -0,0 +1,112 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::borrow::Cow;
+use std::io::Write;
+use std::sync::OnceLock;
+
+use crate::config::Config;
+
+const REPLACEMENT: &str = "[REDACTED]";
+
+/// Secrets that are recognizable by their shape alone.
+const BUILTIN_PATTERNS: &[&str] = &[
+    // Google API keys
+    r"AIza[0-9A-Za-z_\-]{35}",
+    // Google OAuth access and refresh tokens
+    r"ya29\.[0-9A-Za-z_\-]+",
+    r"1//[0-9A-Za-z_\-]{30,}",
+    // Authorization headers
+    r"(?i)bearer\s+[0-9A-Za-z._\-~+/]+=*",
+    // Key material in PEM files or service account JSON
+    r"-----BEGIN [A-Z ]*PRIVATE KEY-----[\s\S]*?-----END [A-Z ]*PRIVATE KEY-----",
+    // `key=value` style secrets in URLs and headers
+    r"(?i)(?:api[_-]?key|access[_-]?token|secret|password)=[^&\s]+",
+];
+
+/// Environment variables whose values are always redacted wherever they appear.
+const SECRET_ENV_VARS: &[&str] = &["GEMINI_API_KEY", "GOOGLE_API_KEY"];
+
+static REDACTOR: OnceLock<Redactor> = OnceLock::new();
+
+/// Replaces secrets in text with `[REDACTED]`.
+pub struct Redactor {
+    patterns: Vec<Regex>,
+    /// Literal secret values, e.g. the configured API key.
+    values: Vec<String>,
+}
+
+impl Redactor {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let mut patterns = BUILTIN_PATTERNS
+            .iter()
+            .map(|pattern| Regex::new(pattern).expect("built-in redaction pattern is valid"))
+            .collect::<Vec<_>>();
+        for pattern in &config.redact_patterns {
+            patterns.push(Regex::new(pattern)
+                .with_context(|| format!("Invalid redact_patterns entry '{}'", pattern))?);
+        }
+
+        // Very short values would redact ordinary words
+        let values = SECRET_ENV_VARS
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .filter(|value| value.len() >= 8)
+            .collect();
+
+        Ok(Self { patterns, values })
+    }
+
+    pub fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut redacted = Cow::Borrowed(text);
+        for value in &self.values {
+            if redacted.contains(value.as_str()) {
+                redacted = Cow::Owned(redacted.replace(value.as_str(), REPLACEMENT));
+            }
+        }
+        for pattern in &self.patterns {
+            if pattern.is_match(&redacted) {
+                redacted = Cow::Owned(pattern.replace_all(&redacted, REPLACEMENT).into_owned());
+            }
+        }
+        redacted
+    }
+}
+
+/// Install the process-wide redactor used by [`redact`] and the log writer.
+pub fn init(redactor: Redactor) {
+    REDACTOR.set(redactor).ok();
+}
+
+/// Redact `text` with the installed redactor; text passes through unchanged before [`init`].
+pub fn redact(text: &str) -> Cow<'_, str> {
+    match REDACTOR.get() {
+        Some(redactor) => redactor.redact(text),
+        None => Cow::Borrowed(text),
+    }
+}
+
+/// A tracing writer that redacts every formatted event before writing it to stderr.
+pub struct RedactingStderr;
+
+impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for RedactingStderr {
+    type Writer = RedactingWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter
+    }
+}
+
+pub struct RedactingWriter;
+
+impl Write for RedactingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // The formatter hands over one complete event per write
+        let text = String::from_utf8_lossy(buf);
+        std::io::stderr().write_all(redact(&text).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stderr().flush()
+    }
+}
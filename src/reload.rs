@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::config::{Config, Flags};
+use crate::prompts::PromptLibrary;
+use crate::resources::ResourceNotifier;
+use crate::toggles::ToolToggles;
+
+/// How often the config file and prompts directory are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The settings that take effect without a restart, shared by every
+/// connection and replaced when the config file changes.
+#[derive(Debug)]
+pub struct LiveSettings {
+    settings: RwLock<Settings>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Settings {
+    default_model: Option<String>,
+    model_aliases: BTreeMap<String, String>,
+    timeout: Duration,
+    tool_timeouts: BTreeMap<String, Duration>,
+}
+
+impl Settings {
+    fn from_config(config: &Config) -> Self {
+        Self {
+            default_model: config.default_model.clone(),
+            model_aliases: config.model_aliases.clone(),
+            timeout: config.timeout(),
+            tool_timeouts: config.tools
+                .iter()
+                .filter_map(|(tool, overrides)| Some((tool.clone(), Duration::from_secs(overrides.timeout_secs?))))
+                .collect(),
+        }
+    }
+}
+
+impl LiveSettings {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            settings: RwLock::new(Settings::from_config(config)),
+        }
+    }
+
+    /// How long a call may run without a tool override.
+    pub fn timeout(&self) -> Duration {
+        self.settings.read().unwrap().timeout
+    }
+
+    /// How long a call of `tool` may run, honoring its override.
+    pub fn tool_timeout(&self, tool: &str) -> Duration {
+        let settings = self.settings.read().unwrap();
+        settings.tool_timeouts.get(tool).copied().unwrap_or(settings.timeout)
+    }
+
+    /// `model`, or the default model when there is none, with an alias replaced by its model.
+    pub fn resolve_model(&self, model: Option<String>) -> Option<String> {
+        let default_model = self.settings.read().unwrap().default_model.clone();
+        model.or(default_model).map(|model| self.resolve_alias(model))
+    }
+
+    pub fn resolve_alias(&self, model: String) -> String {
+        self.settings.read().unwrap().model_aliases.get(&model).cloned().unwrap_or(model)
+    }
+
+    pub fn model_aliases(&self) -> BTreeMap<String, String> {
+        self.settings.read().unwrap().model_aliases.clone()
+    }
+
+    /// Take the settings from `config`, returning whether anything changed.
+    fn update(&self, config: &Config) -> bool {
+        let settings = Settings::from_config(config);
+        let mut current = self.settings.write().unwrap();
+        if *current == settings {
+            return false;
+        }
+        *current = settings;
+        true
+    }
+}
+
+/// What a config reload replaces.
+pub struct Reloadable {
+    pub settings: Arc<LiveSettings>,
+    pub toggles: Arc<ToolToggles>,
+    pub prompts: Arc<RwLock<Arc<PromptLibrary>>>,
+}
+
+/// Reload the configuration whenever `path` changes, and the prompt templates
+/// whenever the prompts directory does, applying the timeouts, default model,
+/// model aliases, tool toggles and templates and telling clients when the
+/// lists of tools or prompts changed. Other settings need a restart.
+///
+/// The config is loaded as at startup, so `flags` and the environment still
+/// override the file. A file that no longer loads is logged and changes nothing.
+pub fn watch(path: PathBuf, flags: Flags, reloadable: Reloadable, notifier: Arc<ResourceNotifier>, prompts_dir: Option<PathBuf>) {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    tokio::spawn(async move {
+        let mut seen: Option<SystemTime> = modified(&path);
+        let mut prompts_dir = prompts_dir;
+        let mut prompts_seen = dir_state(prompts_dir.as_deref());
+        let mut ticker = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let mut moved = false;
+            let current = modified(&path);
+            if current != seen {
+                seen = current;
+                match Config::load(Flags { config: Some(path.clone()), ..flags.clone() }) {
+                    Ok(config) => {
+                        let disabled = config.disabled_tools();
+                        tracing::info!("Reloaded {}; disabled tools: {:?}", path.display(), disabled);
+                        if reloadable.settings.update(&config) {
+                            tracing::info!("Applied the new timeouts, default model and model aliases");
+                        }
+                        if reloadable.toggles.update(disabled) {
+                            notifier.tools_changed().await;
+                        }
+                        if config.prompts_path() != prompts_dir {
+                            prompts_dir = config.prompts_path();
+                            moved = true;
+                        }
+                    }
+                    Err(e) => tracing::warn!("Keeping the current configuration: {:#}", e),
+                }
+            }
+
+            let prompts_current = dir_state(prompts_dir.as_deref());
+            if moved || prompts_current != prompts_seen {
+                prompts_seen = prompts_current;
+                let library = PromptLibrary::load(prompts_dir.as_deref());
+                tracing::info!("Reloaded {} prompt templates", library.templates().len());
+                *reloadable.prompts.write().unwrap() = Arc::new(library);
+                notifier.prompts_changed().await;
+                // Templates are listed as resources too
+                notifier.list_changed().await;
+            }
+        }
+    });
+}
+
+/// The template and partial files in `dir` with their modification times,
+/// sorted; `None` without a readable directory.
+fn dir_state(dir: Option<&Path>) -> Option<Vec<(PathBuf, Option<SystemTime>)>> {
+    let mut files: Vec<_> = std::fs::read_dir(dir?)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| matches!(path.extension().and_then(|ext| ext.to_str()), Some("toml" | "hbs")))
+        .map(|path| {
+            let modified = std::fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+            (path, modified)
+        })
+        .collect();
+    files.sort();
+    Some(files)
+}
@@ -0,0 +1,50 @@
+use rmcp::model::{CallToolResult, Content, RawContent};
+use rmcp::Error as McpError;
+
+/// A fresh id for one tool call, short enough to search logs for.
+pub fn generate() -> String {
+    uuid::Uuid::new_v4().simple().to_string()[..16].to_string()
+}
+
+/// `result` with `request_id` added: to the JSON block that closes a result
+/// of several blocks, which holds its metadata, or as a block of its own, and
+/// to the `data` of an error.
+pub fn attach(result: Result<CallToolResult, McpError>, request_id: &str) -> Result<CallToolResult, McpError> {
+    match result {
+        Ok(mut result) => {
+            let tagged = result.content.len() > 1 && result.content.last_mut().is_some_and(|content| tag(content, request_id));
+            if !tagged {
+                result.content.push(Content::json(serde_json::json!({ "request_id": request_id }))?);
+            }
+            Ok(result)
+        }
+        Err(mut error) => {
+            match &mut error.data {
+                Some(serde_json::Value::Object(data)) => {
+                    data.insert("request_id".to_string(), serde_json::json!(request_id));
+                }
+                data @ None => *data = Some(serde_json::json!({ "request_id": request_id })),
+                Some(_) => {}
+            }
+            Err(error)
+        }
+    }
+}
+
+/// Add `request_id` to `content` when it is a JSON object, returning whether it was.
+fn tag(content: &mut Content, request_id: &str) -> bool {
+    let RawContent::Text(text) = &mut content.raw else {
+        return false;
+    };
+    let Ok(serde_json::Value::Object(mut object)) = serde_json::from_str(&text.text) else {
+        return false;
+    };
+    object.insert("request_id".to_string(), serde_json::json!(request_id));
+    match serde_json::to_string(&object) {
+        Ok(json) => {
+            text.text = json;
+            true
+        }
+        Err(_) => false,
+    }
+}
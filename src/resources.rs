@@ -0,0 +1,135 @@
+use rmcp::{model::*, Peer, RoleServer};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, Weak};
+
+pub const USAGE_TODAY_URI: &str = "gemini://usage/today";
+pub const CONFIG_URI: &str = "gemini://config";
+pub const SESSION_URI_TEMPLATE: &str = "gemini://sessions/{id}";
+const SESSION_URI_PREFIX: &str = "gemini://sessions/";
+pub const PROMPT_URI_TEMPLATE: &str = "gemini://prompts/{name}";
+const PROMPT_URI_PREFIX: &str = "gemini://prompts/";
+
+pub fn session_uri(id: &str) -> String {
+    format!("{}{}", SESSION_URI_PREFIX, id)
+}
+
+/// The session id named by a `gemini://sessions/{id}` URI.
+pub fn session_id(uri: &str) -> Option<&str> {
+    uri.strip_prefix(SESSION_URI_PREFIX).filter(|id| !id.is_empty())
+}
+
+pub fn prompt_uri(name: &str) -> String {
+    format!("{}{}", PROMPT_URI_PREFIX, name)
+}
+
+/// The prompt template named by a `gemini://prompts/{name}` URI.
+pub fn prompt_name(uri: &str) -> Option<&str> {
+    uri.strip_prefix(PROMPT_URI_PREFIX).filter(|name| !name.is_empty())
+}
+
+pub fn json_resource(uri: &str, name: &str, description: &str) -> Resource {
+    let mut resource = RawResource::new(uri, name);
+    resource.description = Some(description.to_string());
+    resource.mime_type = Some("application/json".to_string());
+    resource.no_annotation()
+}
+
+/// One client connection's interest in resource and tool list changes.
+#[derive(Default)]
+pub struct Subscriber {
+    /// Set once the client first touches a resource or lists tools or prompts.
+    peer: Mutex<Option<Peer<RoleServer>>>,
+    subscriptions: Mutex<HashSet<String>>,
+}
+
+impl Subscriber {
+    pub fn attach(&self, peer: &Peer<RoleServer>) {
+        self.peer.lock().unwrap().get_or_insert_with(|| peer.clone());
+    }
+
+    pub fn subscribe(&self, uri: String) {
+        self.subscriptions.lock().unwrap().insert(uri);
+    }
+
+    pub fn unsubscribe(&self, uri: &str) {
+        self.subscriptions.lock().unwrap().remove(uri);
+    }
+
+    fn peer(&self) -> Option<Peer<RoleServer>> {
+        self.peer.lock().unwrap().clone()
+    }
+}
+
+/// Sends resource, tool and prompt list change notifications to every
+/// connected client that uses resources or listed tools or prompts.
+///
+/// Connections hold their [`Subscriber`]; once a connection is dropped its
+/// entry here is pruned.
+#[derive(Default)]
+pub struct ResourceNotifier {
+    subscribers: Mutex<Vec<Weak<Subscriber>>>,
+}
+
+impl ResourceNotifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Track a new client connection.
+    pub fn register(&self) -> Arc<Subscriber> {
+        let subscriber = Arc::new(Subscriber::default());
+        self.subscribers.lock().unwrap().push(Arc::downgrade(&subscriber));
+        subscriber
+    }
+
+    /// Tell every client that the set of resources changed.
+    pub async fn list_changed(&self) {
+        for peer in self.peers(|_| true) {
+            if let Err(e) = peer.notify_resource_list_changed().await {
+                tracing::debug!("Failed to send resource list change: {:?}", e);
+            }
+        }
+    }
+
+    /// Tell every client that the set of prompts changed.
+    pub async fn prompts_changed(&self) {
+        for peer in self.peers(|_| true) {
+            if let Err(e) = peer.notify_prompt_list_changed().await {
+                tracing::debug!("Failed to send prompt list change: {:?}", e);
+            }
+        }
+    }
+
+    /// Tell every client that listed tools that the set of tools changed.
+    pub async fn tools_changed(&self) {
+        for peer in self.peers(|_| true) {
+            if let Err(e) = peer.notify_tool_list_changed().await {
+                tracing::debug!("Failed to send tool list change: {:?}", e);
+            }
+        }
+    }
+
+    /// Tell clients subscribed to `uri` that its contents changed.
+    pub async fn updated(&self, uri: &str) {
+        let subscribed = |subscriber: &Subscriber| subscriber.subscriptions.lock().unwrap().contains(uri);
+        for peer in self.peers(subscribed) {
+            let result = peer.notify_resource_updated(ResourceUpdatedNotificationParam {
+                uri: uri.to_string(),
+            }).await;
+            if let Err(e) = result {
+                tracing::debug!("Failed to send resource update for {}: {:?}", uri, e);
+            }
+        }
+    }
+
+    fn peers(&self, filter: impl Fn(&Subscriber) -> bool) -> Vec<Peer<RoleServer>> {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|subscriber| subscriber.strong_count() > 0);
+        subscribers
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter(|subscriber| filter(subscriber))
+            .filter_map(|subscriber| subscriber.peer())
+            .collect()
+    }
+}
@@ -0,0 +1,85 @@
+use rand::Rng;
+use std::time::Duration;
+
+use crate::config::Config;
+
+/// Failure markers, matched case-insensitively, that indicate a failure worth retrying:
+/// rate limiting, server-side errors and dropped connections.
+const TRANSIENT_MARKERS: &[&str] = &[
+    "429",
+    "too many requests",
+    "resource_exhausted",
+    "rate limit",
+    "500 internal",
+    "internal server error",
+    "502",
+    "bad gateway",
+    "503",
+    "service unavailable",
+    "504",
+    "gateway timeout",
+    "deadline_exceeded",
+    "overloaded",
+    "econnreset",
+    "etimedout",
+    "eai_again",
+    "socket hang up",
+    "fetch failed",
+    "network error",
+];
+
+/// Failure markers, matched case-insensitively, that mean the model is out of
+/// quota or overloaded, so another model may still answer.
+const CAPACITY_MARKERS: &[&str] = &[
+    "429",
+    "too many requests",
+    "resource_exhausted",
+    "rate limit",
+    "quota",
+    "503",
+    "service unavailable",
+    "overloaded",
+];
+
+/// How often and how patiently transient failures are retried.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_retries: config.max_retries,
+            base_delay: Duration::from_millis(config.retry_base_delay_ms),
+            max_delay: Duration::from_millis(config.retry_max_delay_ms),
+        }
+    }
+
+    /// Delay before the given (1-based) retry: exponential growth capped at
+    /// `max_delay`, with the upper half randomized so clients don't retry in lockstep.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponential = self.base_delay.saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)));
+        let capped = exponential.min(self.max_delay);
+        let half = capped / 2;
+        half + half.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+}
+
+/// Whether a failure looks like a transient network or quota problem.
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    // Trying again with the same credentials fails the same way
+    if error.downcast_ref::<crate::auth::AuthError>().is_some() {
+        return false;
+    }
+    let message = format!("{:#}", error).to_lowercase();
+    TRANSIENT_MARKERS.iter().any(|marker| message.contains(marker))
+}
+
+/// Whether a failure means the model is out of quota or overloaded.
+pub fn is_capacity(error: &anyhow::Error) -> bool {
+    let message = format!("{:#}", error).to_lowercase();
+    CAPACITY_MARKERS.iter().any(|marker| message.contains(marker))
+}
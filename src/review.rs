@@ -0,0 +1,152 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use tokio::process::Command;
+
+/// Largest piece of a diff sent to Gemini in one request, in bytes.
+const CHUNK_BYTES: usize = 100_000;
+
+pub const SYSTEM_INSTRUCTION: &str = r#"You are an experienced code reviewer. Review the unified diff you are given.
+Report bugs, security problems, race conditions, error handling gaps, performance issues and unclear code.
+Only comment on lines the diff adds or changes. Do not praise the code or restate what it does.
+
+Respond with JSON only, no Markdown fences, in exactly this shape:
+{"findings": [{"file": "path/in/diff", "line": 42, "severity": "error|warning|suggestion", "comment": "..."}]}
+Use the line number in the new version of the file, or null when a finding is not tied to one line.
+Respond with {"findings": []} when there is nothing worth reporting."#;
+
+/// Which changes `git diff` compares.
+pub struct DiffSpec<'a> {
+    /// Compare the index against HEAD instead of the working tree against the index.
+    pub staged: bool,
+    /// Revision range such as `main...HEAD`; takes precedence over `staged`.
+    pub range: Option<&'a str>,
+    /// Limit the diff to these paths.
+    pub paths: &'a [String],
+}
+
+/// One review comment reported by Gemini.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Finding {
+    #[serde(default)]
+    pub file: String,
+    #[serde(default)]
+    pub line: Option<u32>,
+    #[serde(default)]
+    pub severity: String,
+    pub comment: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReviewReply {
+    #[serde(default)]
+    findings: Vec<Finding>,
+}
+
+/// Run `git diff` in `repo` and return its output.
+pub async fn git_diff(repo: &Path, spec: &DiffSpec<'_>) -> Result<String> {
+    let mut cmd = Command::new("git");
+    cmd.arg("-C").arg(repo).args(["diff", "--no-color", "--no-ext-diff"]);
+
+    match spec.range {
+        Some(range) => {
+            cmd.arg(range);
+        }
+        None if spec.staged => {
+            cmd.arg("--staged");
+        }
+        None => {}
+    }
+    if !spec.paths.is_empty() {
+        cmd.arg("--").args(spec.paths);
+    }
+
+    let output = cmd.output().await
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        anyhow::bail!("git diff failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Split a diff into pieces of at most [`CHUNK_BYTES`], breaking only between files.
+///
+/// A single file whose diff is larger than the limit becomes a chunk of its own.
+pub fn chunk(diff: &str) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+
+    for file in split_files(diff) {
+        if !current.is_empty() && current.len() + file.len() > CHUNK_BYTES {
+            chunks.push(std::mem::take(&mut current));
+        }
+        current.push_str(file);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+}
+
+/// Per-file sections of a diff, each starting at its `diff --git` header.
+fn split_files(diff: &str) -> Vec<&str> {
+    let mut starts: Vec<usize> = diff
+        .match_indices("diff --git ")
+        .map(|(pos, _)| pos)
+        .filter(|&pos| pos == 0 || diff.as_bytes()[pos - 1] == b'\n')
+        .collect();
+    if starts.first() != Some(&0) {
+        starts.insert(0, 0);
+    }
+
+    starts
+        .iter()
+        .zip(starts.iter().skip(1).chain(std::iter::once(&diff.len())))
+        .map(|(&start, &end)| &diff[start..end])
+        .filter(|section| !section.trim().is_empty())
+        .collect()
+}
+
+/// Prompt asking Gemini to review one chunk.
+pub fn prompt(chunk: &str) -> String {
+    format!("Review this diff:\n\n```diff\n{}\n```", chunk.trim_end())
+}
+
+/// System prompt asking the client's model to turn a reply that ignored the
+/// requested format into findings JSON.
+pub const REPAIR_INSTRUCTION: &str = r#"You convert free-form code review comments into JSON.
+Respond with JSON only, no Markdown fences, in exactly this shape:
+{"findings": [{"file": "path/in/diff", "line": 42, "severity": "error|warning|suggestion", "comment": "..."}]}
+Keep every comment from the review and do not add your own. Use null for a line you cannot tell."#;
+
+/// Prompt asking the client's model to repair one unparsed reply.
+pub fn repair_prompt(reply: &str) -> String {
+    format!("Convert this code review to the JSON format:\n\n{}", reply.trim())
+}
+
+/// Parse Gemini's reply, tolerating Markdown fences around the JSON.
+pub fn parse_findings(reply: &str) -> Option<Vec<Finding>> {
+    let trimmed = reply.trim();
+    let json = trimmed
+        .strip_prefix("```json")
+        .or_else(|| trimmed.strip_prefix("```"))
+        .and_then(|rest| rest.strip_suffix("```"))
+        .unwrap_or(trimmed);
+
+    serde_json::from_str::<ReviewReply>(json.trim()).ok().map(|reply| reply.findings)
+}
+
+/// Group findings by file, ordered by line within each file.
+pub fn group_by_file(findings: Vec<Finding>) -> BTreeMap<String, Vec<Finding>> {
+    let mut grouped: BTreeMap<String, Vec<Finding>> = BTreeMap::new();
+    for finding in findings {
+        grouped.entry(finding.file.clone()).or_default().push(finding);
+    }
+    for findings in grouped.values_mut() {
+        findings.sort_by_key(|finding| finding.line);
+    }
+    grouped
+}
@@ -0,0 +1,195 @@
+use anyhow::{Context, Result};
+use rmcp::service::RequestContext;
+use rmcp::RoleServer;
+use std::path::{Path, PathBuf};
+
+/// Returned when a request names a file or directory outside the client's roots.
+#[derive(Debug)]
+pub struct OutsideRootsError {
+    pub path: PathBuf,
+    pub roots: Vec<PathBuf>,
+}
+
+impl std::fmt::Display for OutsideRootsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let roots: Vec<String> = self.roots.iter().map(|root| root.display().to_string()).collect();
+        write!(
+            f,
+            "{} is outside the workspace the client permits ({})",
+            self.path.display(),
+            roots.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for OutsideRootsError {}
+
+/// The directories a request may read from, as announced by the client's MCP roots.
+///
+/// Clients without the roots capability, or that announce no roots, leave
+/// access unrestricted.
+#[derive(Debug, Default)]
+pub struct Scope {
+    roots: Option<Vec<PathBuf>>,
+}
+
+impl Scope {
+    pub fn unrestricted() -> Self {
+        Self::default()
+    }
+
+    /// Ask the client that sent `context` for its current roots.
+    pub async fn of(context: &RequestContext<RoleServer>) -> Result<Self> {
+        let supported = context.peer.peer_info().is_some_and(|info| info.capabilities.roots.is_some());
+        if !supported {
+            return Ok(Self::unrestricted());
+        }
+
+        let listed = context.peer.list_roots().await
+            .context("Failed to list the client's roots")?;
+        let roots: Vec<PathBuf> = listed.roots.iter()
+            .filter_map(|root| {
+                let path = reqwest::Url::parse(&root.uri).ok()?.to_file_path().ok();
+                if path.is_none() {
+                    tracing::debug!("Ignoring root that is not a file URI: {}", root.uri);
+                }
+                path
+            })
+            // Compare against resolved paths so symlinks cannot reach outside a root
+            .map(|path| std::fs::canonicalize(&path).unwrap_or(path))
+            .collect();
+
+        if roots.is_empty() {
+            return Ok(Self::unrestricted());
+        }
+        Ok(Self { roots: Some(roots) })
+    }
+
+    /// Fail unless `path` lies inside one of the roots.
+    pub fn check(&self, path: &Path) -> Result<()> {
+        let Some(roots) = &self.roots else {
+            return Ok(());
+        };
+        // Missing paths are checked as given; reading them fails later anyway
+        let resolved = std::fs::canonicalize(path)
+            .or_else(|_| std::path::absolute(path))
+            .unwrap_or_else(|_| path.to_path_buf());
+        if roots.iter().any(|root| resolved.starts_with(root)) {
+            return Ok(());
+        }
+        Err(OutsideRootsError {
+            path: path.to_path_buf(),
+            roots: roots.clone(),
+        }.into())
+    }
+
+    /// Fail unless `path`, which need not exist yet, may be written: it must lie
+    /// inside one of the roots or, for clients that announce none, inside the
+    /// server's working directory.
+    pub fn check_writable(&self, path: &Path) -> Result<()> {
+        let resolved = resolve_new(path)?;
+        let roots = match &self.roots {
+            Some(roots) => roots.clone(),
+            None => vec![std::env::current_dir()
+                .and_then(std::fs::canonicalize)
+                .context("Failed to resolve the working directory")?],
+        };
+        if roots.iter().any(|root| resolved.starts_with(root)) {
+            return Ok(());
+        }
+        Err(OutsideRootsError {
+            path: path.to_path_buf(),
+            roots,
+        }.into())
+    }
+}
+
+/// `path` with symlinks resolved as far as it exists, for checking a file about to be created.
+fn resolve_new(path: &Path) -> Result<PathBuf> {
+    let absolute = std::path::absolute(path)
+        .with_context(|| format!("Failed to resolve {}", path.display()))?;
+    let mut existing = absolute.as_path();
+    let mut missing = Vec::new();
+    while std::fs::symlink_metadata(existing).is_err() {
+        let (Some(parent), Some(name)) = (existing.parent(), existing.file_name()) else {
+            break;
+        };
+        missing.push(name);
+        existing = parent;
+    }
+    let mut resolved = std::fs::canonicalize(existing)
+        .with_context(|| format!("Failed to resolve {}", path.display()))?;
+    // `..` after a missing directory could step back out of a root
+    for name in missing.into_iter().rev() {
+        if name == ".." {
+            anyhow::bail!("{} climbs out of a directory that doesn't exist", path.display());
+        }
+        resolved.push(name);
+    }
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scope limited to a fresh directory, plus a directory outside it.
+    fn scoped() -> (Scope, tempfile::TempDir, tempfile::TempDir) {
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let scope = Scope { roots: Some(vec![std::fs::canonicalize(root.path()).unwrap()]) };
+        (scope, root, outside)
+    }
+
+    #[test]
+    fn unrestricted_scope_allows_any_path() {
+        assert!(Scope::unrestricted().check(Path::new("/etc/passwd")).is_ok());
+    }
+
+    #[test]
+    fn paths_inside_a_root_are_allowed() {
+        let (scope, root, _) = scoped();
+        std::fs::write(root.path().join("notes.txt"), "notes").unwrap();
+
+        scope.check(root.path()).unwrap();
+        scope.check(&root.path().join("notes.txt")).unwrap();
+        scope.check(&root.path().join("missing.txt")).unwrap();
+    }
+
+    #[test]
+    fn paths_outside_the_roots_are_rejected() {
+        let (scope, root, outside) = scoped();
+
+        let error = scope.check(outside.path()).unwrap_err();
+        let error = error.downcast_ref::<OutsideRootsError>().expect("outside roots");
+        assert_eq!(error.path, outside.path());
+        assert!(scope.check(&root.path().join("..").join("..")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_out_of_a_root_are_rejected() {
+        let (scope, root, outside) = scoped();
+        std::os::unix::fs::symlink(outside.path(), root.path().join("escape")).unwrap();
+
+        assert!(scope.check(&root.path().join("escape")).is_err());
+    }
+
+    #[test]
+    fn new_files_may_be_written_inside_a_root_only() {
+        let (scope, root, outside) = scoped();
+
+        scope.check_writable(&root.path().join("src/generated/new.rs")).unwrap();
+        assert!(scope.check_writable(&outside.path().join("new.rs")).is_err());
+        assert!(scope.check_writable(&root.path().join("missing/../../new.rs")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn new_files_cannot_be_written_through_a_symlink_out_of_a_root() {
+        let (scope, root, outside) = scoped();
+        std::os::unix::fs::symlink(outside.path(), root.path().join("escape")).unwrap();
+
+        assert!(scope.check_writable(&root.path().join("escape/new.rs")).is_err());
+    }
+}
@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+
+/// How aggressively Gemini blocks content in one harm category.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SafetyThreshold {
+    /// Always show content regardless of its probability of being unsafe
+    BlockNone,
+    /// Block only when the probability of unsafe content is high
+    BlockOnlyHigh,
+    /// Block when the probability is medium or high
+    BlockMediumAndAbove,
+    /// Block when the probability is low, medium or high
+    BlockLowAndAbove,
+    /// Turn the safety filter off for this category
+    Off,
+}
+
+impl SafetyThreshold {
+    pub fn api_name(self) -> &'static str {
+        match self {
+            Self::BlockNone => "BLOCK_NONE",
+            Self::BlockOnlyHigh => "BLOCK_ONLY_HIGH",
+            Self::BlockMediumAndAbove => "BLOCK_MEDIUM_AND_ABOVE",
+            Self::BlockLowAndAbove => "BLOCK_LOW_AND_ABOVE",
+            Self::Off => "OFF",
+        }
+    }
+}
+
+/// Per-category blocking thresholds; categories left unset use Gemini's defaults.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, schemars::JsonSchema)]
+pub struct SafetySettings {
+    #[schemars(description = "Threshold for harassment")]
+    #[serde(default)]
+    pub harassment: Option<SafetyThreshold>,
+    #[schemars(description = "Threshold for hate speech")]
+    #[serde(default)]
+    pub hate_speech: Option<SafetyThreshold>,
+    #[schemars(description = "Threshold for sexually explicit content")]
+    #[serde(default)]
+    pub sexually_explicit: Option<SafetyThreshold>,
+    #[schemars(description = "Threshold for dangerous content")]
+    #[serde(default)]
+    pub dangerous_content: Option<SafetyThreshold>,
+}
+
+impl SafetySettings {
+    /// `(API category, threshold)` pairs for every category that was set.
+    pub fn api_settings(&self) -> Vec<(&'static str, &'static str)> {
+        [
+            ("HARM_CATEGORY_HARASSMENT", self.harassment),
+            ("HARM_CATEGORY_HATE_SPEECH", self.hate_speech),
+            ("HARM_CATEGORY_SEXUALLY_EXPLICIT", self.sexually_explicit),
+            ("HARM_CATEGORY_DANGEROUS_CONTENT", self.dangerous_content),
+        ]
+        .into_iter()
+        .filter_map(|(category, threshold)| threshold.map(|t| (category, t.api_name())))
+        .collect()
+    }
+}
+
+/// One category's rating as reported by Gemini.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SafetyRating {
+    #[serde(default)]
+    pub category: String,
+    #[serde(default)]
+    pub probability: String,
+    #[serde(default)]
+    pub blocked: bool,
+}
+
+/// Why Gemini refused to answer.
+#[derive(Debug, Clone, Serialize)]
+pub struct SafetyBlock {
+    /// Whether the prompt itself or the generated response was blocked.
+    pub blocked: &'static str,
+    /// Gemini's block or finish reason, e.g. `SAFETY` or `PROHIBITED_CONTENT`.
+    pub reason: String,
+    pub ratings: Vec<SafetyRating>,
+}
+
+/// Candidate finish reasons that mean the response was withheld.
+pub const BLOCKING_FINISH_REASONS: &[&str] = &[
+    "SAFETY",
+    "RECITATION",
+    "BLOCKLIST",
+    "PROHIBITED_CONTENT",
+    "SPII",
+    "IMAGE_SAFETY",
+];
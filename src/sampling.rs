@@ -0,0 +1,62 @@
+use anyhow::{Context, Result};
+use rmcp::model::{Content, ContextInclusion, CreateMessageRequestParam, Role, SamplingMessage};
+use rmcp::service::RequestContext;
+use rmcp::RoleServer;
+
+use crate::backend::CancelledError;
+
+/// Most tokens asked of the client's model in one sampling request.
+const MAX_TOKENS: u32 = 4096;
+
+/// Returned when the connected client does not offer MCP sampling.
+#[derive(Debug)]
+pub struct SamplingUnavailableError;
+
+impl std::fmt::Display for SamplingUnavailableError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The MCP client does not support sampling, or client_sampling is disabled")
+    }
+}
+
+impl std::error::Error for SamplingUnavailableError {}
+
+/// Whether the client that sent `context` can answer sampling requests.
+pub fn supported(context: &RequestContext<RoleServer>) -> bool {
+    context.peer.peer_info().is_some_and(|info| info.capabilities.sampling.is_some())
+}
+
+/// Ask the client's own model to answer `prompt`, e.g. to check or repair
+/// something Gemini produced, and return its text reply.
+///
+/// The client usually shows the request to its user before running it, so
+/// this can take a while; it ends early when the tool call is cancelled.
+pub async fn ask(context: &RequestContext<RoleServer>, system_prompt: &str, prompt: String) -> Result<String> {
+    if !supported(context) {
+        return Err(SamplingUnavailableError.into());
+    }
+
+    let request = CreateMessageRequestParam {
+        messages: vec![SamplingMessage {
+            role: Role::User,
+            content: Content::text(prompt),
+        }],
+        model_preferences: None,
+        system_prompt: Some(system_prompt.to_string()),
+        // The prompt carries everything needed; the client's conversation would only distract
+        include_context: Some(ContextInclusion::None),
+        temperature: None,
+        max_tokens: MAX_TOKENS,
+        stop_sequences: None,
+        metadata: None,
+    };
+
+    let result = tokio::select! {
+        result = context.peer.create_message(request) => result.context("Client sampling request failed")?,
+        _ = context.ct.cancelled() => return Err(CancelledError.into()),
+    };
+    tracing::debug!("Client model {} answered sampling request", result.model);
+
+    let text = result.message.content.as_text()
+        .context("Client model did not reply with text")?;
+    Ok(text.text.trim().to_string())
+}
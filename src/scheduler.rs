@@ -0,0 +1,271 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::CancellationToken;
+
+use crate::backend::CancelledError;
+use crate::progress::ProgressReporter;
+
+/// How often a queued request re-checks and reports its queue position.
+const POSITION_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where a queued request waits relative to others; requests of the same
+/// priority are served in the order they arrived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Deserialize, Serialize, schemars::JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// Bulk work that can wait, such as batches.
+    Low,
+    #[default]
+    Normal,
+    /// Interactive work a user is waiting on, such as chat turns.
+    High,
+}
+
+impl Priority {
+    /// The priority of `tool`'s calls without a `priority` override: chat turns
+    /// go first and batches last.
+    pub fn default_for(tool: &str) -> Self {
+        match tool {
+            "gemini_chat" | "gemini_resume" => Self::High,
+            "gemini_batch_prompt" => Self::Low,
+            _ => Self::Normal,
+        }
+    }
+}
+
+/// Bounds how many generations run at once, queueing the rest by priority,
+/// then in FIFO order.
+pub struct Scheduler {
+    permits: Arc<Semaphore>,
+    max_concurrency: usize,
+    /// Waiting requests, highest priority first, then by ticket.
+    queue: Mutex<Vec<(Priority, u64)>>,
+    /// Notified whenever the queue changes, so waiters re-check whose turn it is.
+    changed: Notify,
+    next_ticket: AtomicU64,
+}
+
+impl Scheduler {
+    pub fn new(max_concurrency: usize) -> Self {
+        let max_concurrency = max_concurrency.max(1);
+        Self {
+            permits: Arc::new(Semaphore::new(max_concurrency)),
+            max_concurrency,
+            queue: Mutex::new(Vec::new()),
+            changed: Notify::new(),
+            next_ticket: AtomicU64::new(0),
+        }
+    }
+
+    /// Wait for a free slot, behind the waiting requests of at least `priority`,
+    /// reporting the queue position while waiting.
+    ///
+    /// The returned permit frees the slot when dropped.
+    pub async fn acquire(
+        &self,
+        priority: Priority,
+        progress: Option<&ProgressReporter>,
+        ct: &CancellationToken,
+    ) -> Result<OwnedSemaphorePermit> {
+        let ticket = self.next_ticket.fetch_add(1, Ordering::SeqCst);
+        {
+            let mut queue = self.queue.lock().unwrap();
+            if queue.is_empty() {
+                if let Ok(permit) = self.permits.clone().try_acquire_owned() {
+                    return Ok(permit);
+                }
+            }
+            let index = queue.partition_point(|(queued, _)| *queued >= priority);
+            queue.insert(index, (priority, ticket));
+        }
+        let _queued = QueueEntry { scheduler: self, ticket };
+        self.changed.notify_waiters();
+
+        let mut ticker = tokio::time::interval(POSITION_REPORT_INTERVAL);
+        let mut last_position = None;
+
+        loop {
+            // Registered before looking at the queue, so no change in between is missed
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+
+            // Only the request at the head of the queue waits for the semaphore,
+            // which is fair, so the next slot goes to it
+            let first = self.position(ticket) == 1;
+            let acquire = async {
+                if first {
+                    self.permits.clone().acquire_owned().await
+                } else {
+                    std::future::pending().await
+                }
+            };
+            tokio::pin!(acquire);
+
+            loop {
+                tokio::select! {
+                    permit = &mut acquire => return permit.context("Scheduler was closed"),
+                    _ = &mut changed => break,
+                    _ = ct.cancelled() => return Err(CancelledError.into()),
+                    _ = ticker.tick() => {
+                        let position = self.position(ticket);
+                        if last_position != Some(position) {
+                            last_position = Some(position);
+                            tracing::debug!("Request queued at position {}", position);
+                            if let Some(progress) = progress {
+                                progress.report(format!(
+                                    "Queued: position {} (max {} concurrent requests)",
+                                    position, self.max_concurrency
+                                )).await;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Requests waiting for a slot.
+    pub fn queued(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    /// Generations currently holding a slot.
+    pub fn in_flight(&self) -> usize {
+        self.max_concurrency - self.permits.available_permits()
+    }
+
+    /// 1-based position of `ticket` among the waiting requests.
+    fn position(&self, ticket: u64) -> usize {
+        let queue = self.queue.lock().unwrap();
+        queue.iter().position(|(_, t)| *t == ticket).map(|i| i + 1).unwrap_or(1)
+    }
+}
+
+/// Removes a waiter from the queue once it gets a slot or gives up, letting
+/// the next one take its place.
+struct QueueEntry<'a> {
+    scheduler: &'a Scheduler,
+    ticket: u64,
+}
+
+impl Drop for QueueEntry<'_> {
+    fn drop(&mut self) {
+        self.scheduler.queue.lock().unwrap().retain(|(_, t)| *t != self.ticket);
+        self.scheduler.changed.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Queue a request on `scheduler` that records `name` in `served` once it gets a slot.
+    fn queue(
+        scheduler: &Arc<Scheduler>,
+        priority: Priority,
+        name: &'static str,
+        served: &Arc<Mutex<Vec<&'static str>>>,
+    ) -> tokio::task::JoinHandle<()> {
+        let (scheduler, served) = (scheduler.clone(), served.clone());
+        tokio::spawn(async move {
+            let _permit = scheduler.acquire(priority, None, &CancellationToken::new()).await.unwrap();
+            served.lock().unwrap().push(name);
+        })
+    }
+
+    /// Wait until `count` requests are queued on `scheduler`.
+    async fn wait_for_queue(scheduler: &Scheduler, count: usize) {
+        while scheduler.queued() < count {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_beyond_the_limit_wait_for_a_slot() {
+        let scheduler = Arc::new(Scheduler::new(2));
+        let ct = CancellationToken::new();
+        let first = scheduler.acquire(Priority::Normal, None, &ct).await.unwrap();
+        let _second = scheduler.acquire(Priority::Normal, None, &ct).await.unwrap();
+        assert_eq!(scheduler.in_flight(), 2);
+
+        let served = Arc::new(Mutex::new(Vec::new()));
+        let third = queue(&scheduler, Priority::Normal, "third", &served);
+        wait_for_queue(&scheduler, 1).await;
+        assert!(served.lock().unwrap().is_empty());
+
+        drop(first);
+        third.await.unwrap();
+        assert_eq!(*served.lock().unwrap(), ["third"]);
+        assert_eq!(scheduler.queued(), 0);
+    }
+
+    #[tokio::test]
+    async fn waiting_requests_are_served_in_arrival_order() {
+        let scheduler = Arc::new(Scheduler::new(1));
+        let held = scheduler.acquire(Priority::Normal, None, &CancellationToken::new()).await.unwrap();
+
+        let served = Arc::new(Mutex::new(Vec::new()));
+        let mut waiting = Vec::new();
+        for (count, name) in ["a", "b", "c"].into_iter().enumerate() {
+            waiting.push(queue(&scheduler, Priority::Normal, name, &served));
+            wait_for_queue(&scheduler, count + 1).await;
+        }
+
+        drop(held);
+        for handle in waiting {
+            handle.await.unwrap();
+        }
+        assert_eq!(*served.lock().unwrap(), ["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn cancelled_request_leaves_the_queue() {
+        let scheduler = Scheduler::new(1);
+        let _held = scheduler.acquire(Priority::Normal, None, &CancellationToken::new()).await.unwrap();
+
+        let ct = CancellationToken::new();
+        let waiting = scheduler.acquire(Priority::Normal, None, &ct);
+        let cancel = async {
+            wait_for_queue(&scheduler, 1).await;
+            ct.cancel();
+        };
+        let (outcome, ()) = tokio::join!(waiting, cancel);
+
+        assert!(outcome.unwrap_err().downcast_ref::<CancelledError>().is_some());
+        assert_eq!(scheduler.queued(), 0);
+    }
+
+    #[tokio::test]
+    async fn higher_priority_requests_pass_queued_lower_ones() {
+        let scheduler = Arc::new(Scheduler::new(1));
+        let held = scheduler.acquire(Priority::Normal, None, &CancellationToken::new()).await.unwrap();
+
+        let served = Arc::new(Mutex::new(Vec::new()));
+        let mut waiting = Vec::new();
+        let requests = [(Priority::Low, "low1"), (Priority::Low, "low2"), (Priority::Normal, "normal"), (Priority::High, "high")];
+        for (count, (priority, name)) in requests.into_iter().enumerate() {
+            waiting.push(queue(&scheduler, priority, name, &served));
+            wait_for_queue(&scheduler, count + 1).await;
+        }
+        // Let the request that was first in line see it has been passed
+        tokio::task::yield_now().await;
+
+        drop(held);
+        for handle in waiting {
+            handle.await.unwrap();
+        }
+        assert_eq!(*served.lock().unwrap(), ["high", "normal", "low1", "low2"]);
+    }
+
+    #[test]
+    fn chat_goes_first_and_batches_last_by_default() {
+        assert_eq!(Priority::default_for("gemini_chat"), Priority::High);
+        assert_eq!(Priority::default_for("gemini_prompt"), Priority::Normal);
+        assert_eq!(Priority::default_for("gemini_batch_prompt"), Priority::Low);
+    }
+}
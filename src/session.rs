@@ -0,0 +1,557 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::backend::Usage;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatRole {
+    User,
+    Model,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatMessage {
+    pub role: ChatRole,
+    pub content: String,
+    pub timestamp: DateTime<Utc>,
+    /// Tokens used to generate a model message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Session {
+    pub id: String,
+    /// Name given with `gemini_session_rename`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub model: Option<String>,
+    /// Every message of the session, including those no longer replayed.
+    pub messages: Vec<ChatMessage>,
+    /// Summary of the first `trimmed` messages, replayed in their place.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// How many of the oldest messages were trimmed from the replayed history.
+    pub trimmed: usize,
+}
+
+impl Session {
+    fn new(id: String, model: Option<String>) -> Self {
+        let now = Utc::now();
+        Self {
+            id,
+            title: None,
+            created_at: now,
+            updated_at: now,
+            model,
+            messages: Vec::new(),
+            summary: None,
+            trimmed: 0,
+        }
+    }
+
+    /// A new session that is never stored, for dry runs of a chat's first turn.
+    pub fn unsaved(model: Option<String>) -> Self {
+        Self::new(uuid::Uuid::new_v4().to_string(), model)
+    }
+
+    /// The session without its messages, with their totals.
+    pub fn info(&self) -> SessionInfo {
+        let usage = self.messages.iter().filter_map(|message| message.usage);
+        SessionInfo {
+            id: self.id.clone(),
+            title: self.title.clone(),
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            model: self.model.clone(),
+            turn_count: self.messages.len() / 2,
+            trimmed: self.trimmed,
+            prompt_tokens: usage.clone().map(|usage| usage.prompt_tokens as u64).sum(),
+            completion_tokens: usage.map(|usage| usage.completion_tokens as u64).sum(),
+        }
+    }
+
+    /// The messages still replayed on every turn.
+    pub fn history(&self) -> &[ChatMessage] {
+        &self.messages[self.trimmed.min(self.messages.len())..]
+    }
+
+    /// Render the history plus a new user message as a single prompt.
+    ///
+    /// The gemini CLI is stateless between invocations, so the whole
+    /// conversation is replayed on every turn.
+    pub fn transcript_with(&self, message: &str) -> String {
+        if self.history().is_empty() && self.summary.is_none() {
+            return message.to_string();
+        }
+
+        let mut prompt = String::from(
+            "The following is a conversation between a user and you (Gemini). \
+             Continue it by replying to the last user message only.\n\n",
+        );
+        if let Some(summary) = &self.summary {
+            prompt.push_str(&format!("Summary of the earlier conversation: {}\n\n", summary));
+        }
+        for msg in self.history() {
+            let speaker = match msg.role {
+                ChatRole::User => "User",
+                ChatRole::Model => "Gemini",
+            };
+            prompt.push_str(&format!("{}: {}\n\n", speaker, msg.content));
+        }
+        prompt.push_str(&format!("User: {}\n\nGemini:", message));
+        prompt
+    }
+}
+
+/// What `gemini_sessions_list` reports of a session.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub model: Option<String>,
+    pub turn_count: usize,
+    /// Messages no longer replayed, see [`Session::trimmed`].
+    pub trimmed: usize,
+    /// Tokens used by every reply of the session together.
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+/// How large the session store may grow.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    /// Sessions unused for longer are deleted.
+    pub ttl: Option<Duration>,
+    /// Most bytes of messages all sessions may hold together; the least
+    /// recently used are deleted beyond it.
+    pub max_bytes: Option<u64>,
+}
+
+/// Chat sessions keyed by session id.
+///
+/// With a database path, sessions live in SQLite so they survive restarts and
+/// are shared by every server process using the same file; otherwise they are
+/// kept in memory for the lifetime of the process.
+pub struct SessionStore {
+    store: Mutex<Store>,
+    limits: Limits,
+}
+
+enum Store {
+    Memory(HashMap<String, Session>),
+    Sqlite(Connection),
+}
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS sessions (
+        id TEXT PRIMARY KEY,
+        created_at TEXT NOT NULL,
+        updated_at TEXT NOT NULL,
+        model TEXT,
+        summary TEXT,
+        trimmed INTEGER NOT NULL DEFAULT 0,
+        title TEXT
+    );
+    CREATE TABLE IF NOT EXISTS messages (
+        session_id TEXT NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+        seq INTEGER NOT NULL,
+        role TEXT NOT NULL,
+        content TEXT NOT NULL,
+        timestamp TEXT NOT NULL,
+        prompt_tokens INTEGER,
+        completion_tokens INTEGER,
+        estimated_tokens INTEGER,
+        PRIMARY KEY (session_id, seq)
+    );
+";
+
+/// Columns added to `sessions` since it was first created, with their definitions.
+const ADDED_COLUMNS: &[(&str, &str)] = &[
+    ("summary", "TEXT"),
+    ("trimmed", "INTEGER NOT NULL DEFAULT 0"),
+    ("title", "TEXT"),
+];
+
+impl SessionStore {
+    /// Open the session database at `path`, creating it when needed.
+    ///
+    /// Falls back to memory, with a warning, when there is no path or the
+    /// database cannot be opened. Sessions beyond `limits` are deleted now and
+    /// whenever one is created or continued.
+    pub fn open(path: Option<PathBuf>, limits: Limits) -> Self {
+        let store = match path.map(|path| (open_db(&path), path)) {
+            Some((Ok(conn), _)) => Store::Sqlite(conn),
+            Some((Err(e), path)) => {
+                tracing::warn!("Keeping chat sessions in memory, failed to open {}: {:#}", path.display(), e);
+                Store::Memory(HashMap::new())
+            }
+            None => Store::Memory(HashMap::new()),
+        };
+        let sessions = Self { store: Mutex::new(store), limits };
+        sessions.prune(&mut sessions.store.lock().unwrap(), None);
+        sessions
+    }
+
+    /// Delete the sessions that outlived the TTL, then the least recently used
+    /// ones until the rest fit in the storage limit, sparing `keep`.
+    fn prune(&self, store: &mut Store, keep: Option<&str>) {
+        if self.limits.ttl.is_none() && self.limits.max_bytes.is_none() {
+            return;
+        }
+        let entries = match &*store {
+            Store::Memory(sessions) => Ok(sessions
+                .values()
+                .map(|session| {
+                    let bytes = session.messages.iter().map(|message| message.content.len() as u64).sum::<u64>()
+                        + session.summary.as_ref().map_or(0, |summary| summary.len() as u64);
+                    (session.id.clone(), session.updated_at, bytes)
+                })
+                .collect()),
+            Store::Sqlite(conn) => sizes(conn),
+        };
+        let expired = match entries {
+            Ok(entries) => over_limits(entries, &self.limits, Utc::now(), keep),
+            Err(e) => {
+                tracing::warn!("Failed to measure chat sessions: {:#}", e);
+                return;
+            }
+        };
+        if expired.is_empty() {
+            return;
+        }
+        let result = match store {
+            Store::Memory(sessions) => {
+                for id in &expired {
+                    sessions.remove(id);
+                }
+                Ok(())
+            }
+            Store::Sqlite(conn) => delete_all(conn, &expired),
+        };
+        match result {
+            Ok(()) => tracing::info!("Deleted {} expired or least recently used chat sessions", expired.len()),
+            Err(e) => tracing::warn!("Failed to delete expired chat sessions: {:#}", e),
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<Session> {
+        match &*self.store.lock().unwrap() {
+            Store::Memory(sessions) => sessions.get(id).cloned(),
+            Store::Sqlite(conn) => load(conn, id)
+                .map_err(|e| tracing::warn!("Failed to load chat session {}: {:#}", id, e))
+                .ok()
+                .flatten(),
+        }
+    }
+
+    /// All sessions, oldest first.
+    pub fn list(&self) -> Vec<Session> {
+        let mut sessions = match &*self.store.lock().unwrap() {
+            Store::Memory(sessions) => sessions.values().cloned().collect(),
+            Store::Sqlite(conn) => load_all(conn)
+                .map_err(|e| tracing::warn!("Failed to list chat sessions: {:#}", e))
+                .unwrap_or_default(),
+        };
+        sessions.sort_by_key(|session| session.created_at);
+        sessions
+    }
+
+    /// Fetch an existing session, or create a new one when `id` is `None`.
+    pub fn get_or_create(&self, id: Option<&str>, model: Option<String>) -> anyhow::Result<Session> {
+        let mut store = self.store.lock().unwrap();
+        self.prune(&mut store, None);
+
+        if let Some(id) = id {
+            let session = match &*store {
+                Store::Memory(sessions) => sessions.get(id).cloned(),
+                Store::Sqlite(conn) => load(conn, id)?,
+            };
+            return session.ok_or_else(|| anyhow::anyhow!("Unknown session_id: {}", id));
+        }
+
+        let session = Session::new(uuid::Uuid::new_v4().to_string(), model);
+        match &mut *store {
+            Store::Memory(sessions) => {
+                sessions.insert(session.id.clone(), session.clone());
+            }
+            Store::Sqlite(conn) => {
+                conn.execute(
+                    "INSERT INTO sessions (id, created_at, updated_at, model) VALUES (?1, ?2, ?3, ?4)",
+                    params![session.id, session.created_at, session.updated_at, session.model],
+                ).context("Failed to save chat session")?;
+            }
+        }
+        Ok(session)
+    }
+
+    /// Stop replaying the first `trimmed` messages of a session, replaying
+    /// `summary` in their place.
+    pub fn trim(&self, id: &str, trimmed: usize, summary: Option<String>) -> anyhow::Result<Session> {
+        let mut store = self.store.lock().unwrap();
+        match &mut *store {
+            Store::Memory(sessions) => {
+                let session = sessions
+                    .get_mut(id)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown session_id: {}", id))?;
+                session.trimmed = trimmed;
+                session.summary = summary;
+                Ok(session.clone())
+            }
+            Store::Sqlite(conn) => {
+                conn.execute(
+                    "UPDATE sessions SET trimmed = ?1, summary = ?2 WHERE id = ?3",
+                    params![trimmed as i64, summary, id],
+                ).context("Failed to save trimmed chat session")?;
+                load(conn, id)?.ok_or_else(|| anyhow::anyhow!("Unknown session_id: {}", id))
+            }
+        }
+    }
+
+    /// Give a session a title, or remove it with `None`.
+    pub fn rename(&self, id: &str, title: Option<String>) -> anyhow::Result<Session> {
+        let mut store = self.store.lock().unwrap();
+        match &mut *store {
+            Store::Memory(sessions) => {
+                let session = sessions
+                    .get_mut(id)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown session_id: {}", id))?;
+                session.title = title;
+                Ok(session.clone())
+            }
+            Store::Sqlite(conn) => {
+                conn.execute("UPDATE sessions SET title = ?1 WHERE id = ?2", params![title, id])
+                    .context("Failed to rename chat session")?;
+                load(conn, id)?.ok_or_else(|| anyhow::anyhow!("Unknown session_id: {}", id))
+            }
+        }
+    }
+
+    /// Delete a session and its messages; false when there was none with `id`.
+    pub fn delete(&self, id: &str) -> anyhow::Result<bool> {
+        let mut store = self.store.lock().unwrap();
+        match &mut *store {
+            Store::Memory(sessions) => Ok(sessions.remove(id).is_some()),
+            Store::Sqlite(conn) => {
+                let deleted = conn.execute("DELETE FROM sessions WHERE id = ?1", params![id])
+                    .context("Failed to delete chat session")?;
+                Ok(deleted > 0)
+            }
+        }
+    }
+
+    /// Append a completed user/model exchange to a session.
+    pub fn record_turn(&self, id: &str, user: String, reply: String, usage: Usage) -> anyhow::Result<Session> {
+        let now = Utc::now();
+        let turn = [
+            ChatMessage {
+                role: ChatRole::User,
+                content: user,
+                timestamp: now,
+                usage: None,
+            },
+            ChatMessage {
+                role: ChatRole::Model,
+                content: reply,
+                timestamp: now,
+                usage: Some(usage),
+            },
+        ];
+
+        let mut store = self.store.lock().unwrap();
+        match &mut *store {
+            Store::Memory(sessions) => {
+                let session = sessions
+                    .get_mut(id)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown session_id: {}", id))?;
+                session.messages.extend(turn);
+                session.updated_at = now;
+            }
+            Store::Sqlite(conn) => {
+                append(conn, id, &turn, now).context("Failed to save chat turn")?;
+            }
+        }
+        self.prune(&mut store, Some(id));
+        let session = match &*store {
+            Store::Memory(sessions) => sessions.get(id).cloned(),
+            Store::Sqlite(conn) => load(conn, id)?,
+        };
+        session.ok_or_else(|| anyhow::anyhow!("Unknown session_id: {}", id))
+    }
+}
+
+fn open_db(path: &Path) -> anyhow::Result<Connection> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let conn = Connection::open(path)?;
+    // Several server processes may share the file, e.g. one per stdio client
+    conn.busy_timeout(Duration::from_secs(5))?;
+    conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA foreign_keys = ON;")?;
+    conn.execute_batch(SCHEMA)?;
+    // Databases created by older versions lack the newer columns
+    for (column, definition) in ADDED_COLUMNS {
+        let exists: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('sessions') WHERE name = ?1",
+            params![column],
+            |row| row.get(0),
+        )?;
+        if exists == 0 {
+            conn.execute_batch(&format!("ALTER TABLE sessions ADD COLUMN {} {};", column, definition))?;
+        }
+    }
+    Ok(conn)
+}
+
+fn load(conn: &Connection, id: &str) -> anyhow::Result<Option<Session>> {
+    let session = conn
+        .query_row(
+            "SELECT id, created_at, updated_at, model, summary, trimmed, title FROM sessions WHERE id = ?1",
+            params![id],
+            |row| Ok(Session {
+                id: row.get(0)?,
+                title: row.get(6)?,
+                created_at: row.get(1)?,
+                updated_at: row.get(2)?,
+                model: row.get(3)?,
+                messages: Vec::new(),
+                summary: row.get(4)?,
+                trimmed: row.get::<_, i64>(5)? as usize,
+            }),
+        )
+        .optional()?;
+    let Some(mut session) = session else {
+        return Ok(None);
+    };
+
+    let mut statement = conn.prepare(
+        "SELECT role, content, timestamp, prompt_tokens, completion_tokens, estimated_tokens
+         FROM messages WHERE session_id = ?1 ORDER BY seq",
+    )?;
+    session.messages = statement
+        .query_map(params![id], |row| {
+            let role: String = row.get(0)?;
+            let prompt_tokens: Option<u32> = row.get(3)?;
+            let completion_tokens: Option<u32> = row.get(4)?;
+            let estimated: Option<bool> = row.get(5)?;
+            Ok(ChatMessage {
+                role: if role == "model" { ChatRole::Model } else { ChatRole::User },
+                content: row.get(1)?,
+                timestamp: row.get(2)?,
+                usage: prompt_tokens.zip(completion_tokens).map(|(prompt_tokens, completion_tokens)| Usage {
+                    prompt_tokens,
+                    completion_tokens,
+                    estimated: estimated.unwrap_or(false),
+                }),
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+
+    Ok(Some(session))
+}
+
+fn load_all(conn: &Connection) -> anyhow::Result<Vec<Session>> {
+    let ids = conn
+        .prepare("SELECT id FROM sessions")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut sessions = Vec::with_capacity(ids.len());
+    for id in ids {
+        sessions.extend(load(conn, &id)?);
+    }
+    Ok(sessions)
+}
+
+/// Every session's id, last use and bytes of messages.
+fn sizes(conn: &Connection) -> anyhow::Result<Vec<(String, DateTime<Utc>, u64)>> {
+    let mut statement = conn.prepare(
+        "SELECT s.id, s.updated_at,
+                COALESCE(SUM(LENGTH(CAST(m.content AS BLOB))), 0) + COALESCE(LENGTH(CAST(s.summary AS BLOB)), 0)
+         FROM sessions s LEFT JOIN messages m ON m.session_id = s.id
+         GROUP BY s.id",
+    )?;
+    let sizes = statement
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get::<_, i64>(2)? as u64)))?
+        .collect::<Result<_, _>>()?;
+    Ok(sizes)
+}
+
+/// The ids of the sessions among `entries` that `limits` leave no room for:
+/// those unused for longer than the TTL, then the least recently used until the
+/// rest fit in the storage limit. `keep` is never among them.
+fn over_limits(mut entries: Vec<(String, DateTime<Utc>, u64)>, limits: &Limits, now: DateTime<Utc>, keep: Option<&str>) -> Vec<String> {
+    entries.sort_by_key(|(_, updated_at, _)| *updated_at);
+    let mut total: u64 = entries.iter().map(|(_, _, bytes)| bytes).sum();
+    let mut expired = Vec::new();
+    for (id, updated_at, bytes) in entries {
+        if keep == Some(id.as_str()) {
+            continue;
+        }
+        let unused = (now - updated_at).to_std().unwrap_or_default();
+        let too_old = limits.ttl.is_some_and(|ttl| unused > ttl);
+        let too_large = limits.max_bytes.is_some_and(|max| total > max);
+        if !too_old && !too_large {
+            break;
+        }
+        total -= bytes;
+        expired.push(id);
+    }
+    expired
+}
+
+fn delete_all(conn: &mut Connection, ids: &[String]) -> anyhow::Result<()> {
+    let tx = conn.transaction()?;
+    for id in ids {
+        tx.execute("DELETE FROM sessions WHERE id = ?1", params![id])?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn append(conn: &mut Connection, id: &str, messages: &[ChatMessage], now: DateTime<Utc>) -> anyhow::Result<()> {
+    let tx = conn.transaction()?;
+    let updated = tx.execute("UPDATE sessions SET updated_at = ?1 WHERE id = ?2", params![now, id])?;
+    if updated == 0 {
+        anyhow::bail!("Unknown session_id: {}", id);
+    }
+    let next: i64 = tx.query_row(
+        "SELECT COALESCE(MAX(seq) + 1, 0) FROM messages WHERE session_id = ?1",
+        params![id],
+        |row| row.get(0),
+    )?;
+    for (offset, message) in messages.iter().enumerate() {
+        let role = match message.role {
+            ChatRole::User => "user",
+            ChatRole::Model => "model",
+        };
+        tx.execute(
+            "INSERT INTO messages
+             (session_id, seq, role, content, timestamp, prompt_tokens, completion_tokens, estimated_tokens)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                id,
+                next + offset as i64,
+                role,
+                message.content,
+                message.timestamp,
+                message.usage.map(|usage| usage.prompt_tokens),
+                message.usage.map(|usage| usage.completion_tokens),
+                message.usage.map(|usage| usage.estimated),
+            ],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
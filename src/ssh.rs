@@ -0,0 +1,94 @@
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+
+use crate::config::SshConfig;
+
+/// Runs the gemini CLI on another host over SSH.
+#[derive(Debug)]
+pub struct Ssh {
+    config: SshConfig,
+    /// The gemini executable on the remote host.
+    gemini: String,
+}
+
+impl Ssh {
+    pub fn new(config: SshConfig, gemini: String) -> Self {
+        Self { config, gemini }
+    }
+
+    /// The remote path for local `path`, by the longest matching `path_map` prefix.
+    pub fn map_path(&self, path: &Path) -> String {
+        let mapped = self
+            .config
+            .path_map
+            .iter()
+            .filter_map(|(local, remote)| Some((local, remote, path.strip_prefix(local).ok()?)))
+            .max_by_key(|(local, _, _)| local.components().count());
+        match mapped {
+            Some((_, remote, rest)) if rest.as_os_str().is_empty() => remote.clone(),
+            Some((_, remote, rest)) => format!("{}/{}", remote.trim_end_matches('/'), rest.display()),
+            None => path.display().to_string(),
+        }
+    }
+
+    /// The ssh arguments that run `gemini <args>` on the remote host in the
+    /// mapped `cwd`, or the login directory when `None`.
+    ///
+    /// Variables named in `forward_env` are set with their values. Variables
+    /// naming a file in the local temporary directory, such as the system
+    /// prompt, are recreated on the remote host from the file's contents and
+    /// removed when the CLI exits.
+    pub fn args(&self, args: &[String], env: &[(OsString, OsString)], cwd: Option<&Path>) -> Vec<String> {
+        let mut script = Vec::new();
+        if let Some(cwd) = cwd {
+            script.push(format!("cd {}", quote(&self.map_path(cwd))));
+        }
+        let mut temp_files = Vec::new();
+        for (name, value) in env {
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            if let Some(contents) = temp_file(value) {
+                script.push(format!("{name}=$(mktemp) && printf '%s' {} > \"${name}\" && export {name}", quote(&contents)));
+                temp_files.push(format!("\"${}\"", name));
+            } else if self.config.forward_env.iter().any(|forwarded| forwarded == name) {
+                script.push(format!("export {}={}", name, quote(&value.to_string_lossy())));
+            }
+        }
+        if !temp_files.is_empty() {
+            script.push(format!("trap 'rm -f {}' EXIT", temp_files.join(" ")));
+        }
+        let command = std::iter::once(&self.gemini).chain(args).map(|arg| quote(arg)).collect::<Vec<_>>().join(" ");
+        script.push(command);
+
+        // -T: no terminal, so output stays byte-exact; BatchMode: fail instead of prompting for a password
+        let mut ssh_args = vec!["-T".to_string(), "-o".to_string(), "BatchMode=yes".to_string()];
+        if let Some(port) = self.config.port {
+            ssh_args.push("-p".to_string());
+            ssh_args.push(port.to_string());
+        }
+        if let Some(identity) = &self.config.identity_file {
+            ssh_args.push("-i".to_string());
+            ssh_args.push(identity.display().to_string());
+        }
+        ssh_args.extend(self.config.options.iter().cloned());
+        ssh_args.push(self.config.host.clone());
+        // The remote login shell may not be POSIX, so run the script with sh
+        ssh_args.push(format!("sh -c {}", quote(&script.join(" && "))));
+        ssh_args
+    }
+}
+
+/// The contents of `value` when it names a text file in the local temporary directory.
+fn temp_file(value: &OsStr) -> Option<String> {
+    let path = Path::new(value);
+    if !path.is_absolute() || !path.starts_with(std::env::temp_dir()) {
+        return None;
+    }
+    std::fs::read_to_string(path).ok()
+}
+
+/// Quote `text` as a single POSIX shell word.
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
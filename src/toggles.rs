@@ -0,0 +1,33 @@
+use std::collections::BTreeSet;
+use std::sync::RwLock;
+
+use crate::config::Config;
+
+/// Which tools are switched off, shared by every connection and updated when
+/// the config file changes.
+#[derive(Debug, Default)]
+pub struct ToolToggles {
+    disabled: RwLock<BTreeSet<String>>,
+}
+
+impl ToolToggles {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            disabled: RwLock::new(config.disabled_tools()),
+        }
+    }
+
+    pub fn enabled(&self, tool: &str) -> bool {
+        !self.disabled.read().unwrap().contains(tool)
+    }
+
+    /// Replace the disabled tools, returning whether anything changed.
+    pub fn update(&self, disabled: BTreeSet<String>) -> bool {
+        let mut current = self.disabled.write().unwrap();
+        if *current == disabled {
+            return false;
+        }
+        *current = disabled;
+        true
+    }
+}
@@ -0,0 +1,136 @@
+/// Tokens Gemini bills for one image of up to 384x384 pixels; larger images are
+/// tiled, so this undercounts them.
+pub const IMAGE_TOKENS: u32 = 258;
+
+/// Rough token estimate for when the countTokens API is unavailable.
+///
+/// Gemini tokenizers average about four characters per token for English
+/// text and code; CJK text is denser, so each non-ASCII character counts
+/// as a token of its own.
+pub fn estimate(text: &str) -> u32 {
+    let (ascii, other) = text.chars().fold((0u64, 0u64), |(ascii, other), c| {
+        if c.is_ascii() {
+            (ascii + 1, other)
+        } else {
+            (ascii, other + 1)
+        }
+    });
+    (ascii.div_ceil(4) + other).min(u32::MAX as u64) as u32
+}
+
+/// Returned when a prompt does not fit the model's context window after
+/// reserving room for the answer.
+#[derive(Debug)]
+pub struct PromptTooLargeError {
+    pub model: String,
+    /// Estimated tokens of the prompt, system instruction and images.
+    pub prompt_tokens: u32,
+    /// The most the prompt may take: the context window minus `reserved_tokens`.
+    pub max_prompt_tokens: u32,
+    pub input_token_limit: u32,
+    /// Tokens kept free for the answer.
+    pub reserved_tokens: u32,
+}
+
+impl std::fmt::Display for PromptTooLargeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The prompt is about {} tokens, but {} allows at most {} ({} context window minus {} reserved for the answer); split the input",
+            self.prompt_tokens, self.model, self.max_prompt_tokens, self.input_token_limit, self.reserved_tokens
+        )
+    }
+}
+
+impl std::error::Error for PromptTooLargeError {}
+
+/// Cut the middle out of `text` so that it estimates to at most `max_tokens`,
+/// keeping its beginning and end, where instructions and questions usually
+/// are, and cutting at line breaks where possible. `None` when it already fits.
+pub fn truncate_middle(text: &str, max_tokens: u32) -> Option<String> {
+    if estimate(text) <= max_tokens {
+        return None;
+    }
+    let marker = |omitted: usize| format!("\n\n[... {} characters omitted to fit the context window ...]\n\n", omitted);
+    // Costs in quarter tokens, matching `estimate`
+    let cost = |c: char| if c.is_ascii() { 1u64 } else { 4 };
+    let budget = (max_tokens as u64 * 4).saturating_sub(estimate(&marker(text.len())) as u64 * 4);
+
+    let mut head = 0;
+    let mut spent = 0;
+    for (index, c) in text.char_indices() {
+        if spent + cost(c) > budget / 2 {
+            break;
+        }
+        spent += cost(c);
+        head = index + c.len_utf8();
+    }
+    let mut tail = text.len();
+    for (index, c) in text[head..].char_indices().rev() {
+        if spent + cost(c) > budget {
+            break;
+        }
+        spent += cost(c);
+        tail = head + index;
+    }
+
+    // Back off to line breaks unless that would give up most of a side
+    if let Some(line_end) = text[..head].rfind('\n') {
+        if line_end + 1 >= head / 2 {
+            head = line_end + 1;
+        }
+    }
+    if let Some(line_start) = text[tail..].find('\n') {
+        if line_start <= (text.len() - tail) / 2 {
+            tail += line_start + 1;
+        }
+    }
+
+    let omitted = text[head..tail].chars().count();
+    Some(format!("{}{}{}", &text[..head], marker(omitted), &text[tail..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_counts_four_ascii_characters_per_token() {
+        assert_eq!(estimate(""), 0);
+        assert_eq!(estimate("abcd"), 1);
+        assert_eq!(estimate("abcde"), 2);
+        assert_eq!(estimate(&"x".repeat(400)), 100);
+    }
+
+    #[test]
+    fn estimate_counts_each_non_ascii_character_as_a_token() {
+        assert_eq!(estimate("日本語"), 3);
+        assert_eq!(estimate("ab日本"), 3);
+    }
+
+    /// A document of `lines` numbered lines of about 7 tokens each.
+    fn document(lines: usize) -> String {
+        (1..=lines).map(|line| format!("line {:04} of the text\n", line)).collect()
+    }
+
+    #[test]
+    fn truncate_middle_leaves_fitting_text_alone() {
+        assert_eq!(truncate_middle(&document(10), 1_000), None);
+    }
+
+    #[test]
+    fn truncate_middle_keeps_both_ends_at_line_breaks() {
+        let text = document(500);
+        let truncated = truncate_middle(&text, 500).expect("text is too long");
+
+        assert!(estimate(&truncated) <= 500, "{} tokens", estimate(&truncated));
+        assert!(truncated.starts_with("line 0001 of the text\n"));
+        assert!(truncated.ends_with("line 0500 of the text\n"));
+        assert!(truncated.contains("characters omitted to fit the context window"));
+        assert!(!truncated.contains("line 0250"));
+        let (head, rest) = truncated.split_once("\n\n[...").unwrap();
+        let (_, tail) = rest.split_once("...]\n\n").unwrap();
+        assert!(head.ends_with("of the text\n"), "head cut inside a line: {:?}", head);
+        assert!(tail.starts_with("line "), "tail cut inside a line: {:?}", tail);
+    }
+}
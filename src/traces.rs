@@ -0,0 +1,112 @@
+use regex::Regex;
+use std::sync::OnceLock;
+
+use crate::files::{self, Attachment};
+
+/// Most distinct files whose lines are pulled in from one error.
+pub const MAX_FILES: usize = 20;
+
+/// Lines shown before and after each referenced line.
+const RADIUS: usize = 10;
+
+/// Directories of dependencies and toolchains, whose frames rarely hold the bug.
+const VENDORED: &[&str] = &["/.cargo/registry/", "/rustc/", "node_modules/", "site-packages/", "dist-packages/", "/go/pkg/mod/", "<"];
+
+/// Source locations a stack trace or compiler error refers to, as `(path, line)`
+/// in order of first appearance, without dependencies' frames.
+pub fn references(error: &str) -> Vec<(String, usize)> {
+    static PYTHON: OnceLock<Regex> = OnceLock::new();
+    static LOCATION: OnceLock<Regex> = OnceLock::new();
+    // Python: File "app/main.py", line 12, in handler
+    let python = PYTHON.get_or_init(|| Regex::new(r#"File "([^"]+)", line (\d+)"#).expect("python frame pattern is valid"));
+    // Rust, Go, JavaScript, C and most compilers: src/main.rs:10:5, (/app/index.js:3:7)
+    let location = LOCATION.get_or_init(|| {
+        Regex::new(r"(?:^|[\s(\[`'])((?:[A-Za-z]:)?[\w.~/\\-]*[\w-]\.[A-Za-z][\w]{0,9}):(\d+)(?::\d+)?").expect("location pattern is valid")
+    });
+
+    let mut found: Vec<(usize, String, usize)> = Vec::new();
+    for pattern in [python, location] {
+        for captures in pattern.captures_iter(error) {
+            let (Some(path), Some(line)) = (captures.get(1), captures[2].parse::<usize>().ok()) else {
+                continue;
+            };
+            let path = path.as_str().strip_prefix("file://").unwrap_or(path.as_str()).to_string();
+            if line == 0 || VENDORED.iter().any(|vendored| path.contains(vendored)) {
+                continue;
+            }
+            if !found.iter().any(|(_, known, known_line)| *known == path && *known_line == line) {
+                found.push((captures.get(0).map_or(0, |m| m.start()), path, line));
+            }
+        }
+    }
+    found.sort_by_key(|(position, ..)| *position);
+    found.into_iter().map(|(_, path, line)| (path, line)).collect()
+}
+
+/// The lines around each of `lines` in `file`, numbered, with the referenced
+/// lines marked by `>`. Overlapping ranges are merged.
+pub fn excerpt(file: &Attachment, lines: &[usize]) -> String {
+    let source: Vec<&str> = file.contents.lines().collect();
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    let mut sorted = lines.to_vec();
+    sorted.sort_unstable();
+    for line in sorted.into_iter().filter(|line| *line <= source.len()) {
+        let (start, end) = (line.saturating_sub(RADIUS).max(1), (line + RADIUS).min(source.len()));
+        match ranges.last_mut() {
+            Some((_, last_end)) if start <= *last_end + 1 => *last_end = (*last_end).max(end),
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    let width = source.len().to_string().len();
+    let sections: Vec<String> = ranges
+        .iter()
+        .map(|(start, end)| {
+            (*start..=*end)
+                .map(|number| {
+                    let marker = if lines.contains(&number) { '>' } else { ' ' };
+                    format!("{}{:>width$} | {}", marker, number, source[number - 1], width = width)
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .collect();
+    format!("<excerpt path=\"{}\">\n{}\n</excerpt>", file.path.display(), sections.join("\n...\n"))
+}
+
+/// The referenced lines of each path, keeping the order paths first appear in.
+pub fn by_file(references: &[(String, usize)]) -> Vec<(String, Vec<usize>)> {
+    let mut files: Vec<(String, Vec<usize>)> = Vec::new();
+    for (path, line) in references {
+        match files.iter_mut().find(|(known, _)| known == path) {
+            Some((_, lines)) => lines.push(*line),
+            None => files.push((path.clone(), vec![*line])),
+        }
+    }
+    files
+}
+
+/// Prompt asking for the root cause of `error` and how to fix it.
+pub fn prompt(error: &str, question: Option<&str>, excerpts: &[String], sources: &[Attachment]) -> String {
+    let mut prompt = String::from(
+        "Explain the root cause of the error below and how to fix it. Trace it to the code responsible rather \
+         than the line that happened to fail, and say how sure you are when the code shown doesn't settle it. \
+         Answer in Markdown with a `## Root cause` section, then a `## Suggested fix` section with the concrete \
+         code change, and a `## Prevention` section only when a test or check would have caught it.",
+    );
+    if let Some(question) = question.filter(|question| !question.trim().is_empty()) {
+        prompt.push_str(&format!("\n\n{}", question.trim()));
+    }
+    prompt.push_str(&format!("\n\n<error>\n{}\n</error>", error.trim()));
+    if !excerpts.is_empty() {
+        prompt.push_str(&format!(
+            "\n\nThe source lines the error refers to, marked with >:\n\n{}",
+            excerpts.join("\n\n")
+        ));
+    }
+    if !sources.is_empty() {
+        prompt.push_str("\n\nFurther source files:");
+        prompt = files::inline(&prompt, sources);
+    }
+    prompt
+}
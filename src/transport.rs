@@ -0,0 +1,131 @@
+use anyhow::{Context, Result};
+use rmcp::service::RunningService;
+use rmcp::{RoleClient, ServiceExt};
+use std::net::SocketAddr;
+
+use crate::config::{Config, TransportKind};
+use crate::GeminiServer;
+
+/// How the server talks to MCP clients.
+pub enum Transport {
+    /// One client over stdin/stdout (the default).
+    Stdio,
+    /// Long-lived streamable HTTP endpoint at `/mcp` that many clients can share,
+    /// plus Prometheus metrics at `/metrics`.
+    Http { bind: SocketAddr },
+}
+
+impl Transport {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        match config.transport {
+            TransportKind::Stdio => Ok(Self::Stdio),
+            TransportKind::Http => Ok(Self::Http {
+                bind: config.bind.parse()
+                    .with_context(|| format!("Invalid bind address '{}'", config.bind))?,
+            }),
+        }
+    }
+}
+
+pub async fn serve(server: GeminiServer, transport: Transport) -> Result<()> {
+    match transport {
+        Transport::Stdio => serve_stdio(server).await,
+        Transport::Http { bind } => serve_http(server, bind).await,
+    }
+}
+
+async fn serve_stdio(server: GeminiServer) -> Result<()> {
+    use rmcp::transport::io::stdio;
+
+    let service = server
+        .serve(stdio())
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to start server: {:?}", e))?;
+
+    tokio::select! {
+        result = service.waiting() => {
+            result.map_err(|e| anyhow::anyhow!("Server error: {:?}", e))?;
+        }
+        _ = shutdown_signal() => tracing::info!("Shutting down"),
+    }
+
+    Ok(())
+}
+
+/// Run `server` in this process and connect a client to it through an
+/// in-memory pipe, so calls take the same path as those of a real client.
+pub async fn connect(server: GeminiServer) -> Result<RunningService<RoleClient, ()>> {
+    let (client_side, server_side) = tokio::io::duplex(1 << 20);
+    tokio::spawn(async move {
+        match server.serve(tokio::io::split(server_side)).await {
+            Ok(service) => {
+                let _ = service.waiting().await;
+            }
+            Err(e) => tracing::warn!("Failed to start in-process server: {:?}", e),
+        }
+    });
+    ().serve(tokio::io::split(client_side))
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to connect to in-process server: {:?}", e))
+}
+
+async fn serve_http(server: GeminiServer, bind: SocketAddr) -> Result<()> {
+    use rmcp::transport::streamable_http_server::{
+        session::local::LocalSessionManager, StreamableHttpService,
+    };
+
+    let metrics = server.metrics.clone();
+    let scheduler = server.scheduler.clone();
+
+    // Every HTTP session gets its own handle onto the shared server state
+    let service = StreamableHttpService::new(
+        move || Ok(server.connection()),
+        LocalSessionManager::default().into(),
+        Default::default(),
+    );
+
+    let router = axum::Router::new()
+        .nest_service("/mcp", service)
+        .route("/metrics", axum::routing::get(move || async move {
+            (
+                [(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+                metrics.render(scheduler.queued(), scheduler.in_flight()),
+            )
+        }));
+    let listener = tokio::net::TcpListener::bind(bind).await
+        .with_context(|| format!("Failed to bind {}", bind))?;
+
+    tracing::info!("Listening for MCP clients on http://{}/mcp, metrics on http://{}/metrics", bind, bind);
+
+    axum::serve(listener, router)
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .context("HTTP server error")?;
+
+    Ok(())
+}
+
+/// Resolves on Ctrl-C, or SIGTERM on Unix.
+///
+/// gemini processes run in their own process groups and don't see these
+/// signals, so the server has to stay alive long enough to kill them.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(_) => {
+                tokio::signal::ctrl_c().await.ok();
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.ok();
+}
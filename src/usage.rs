@@ -0,0 +1,164 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::backend::Usage;
+use crate::models;
+
+/// Accumulated token counts and estimated cost.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Totals {
+    pub calls: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Cost at list prices; models without known pricing contribute nothing.
+    pub estimated_cost_usd: f64,
+    /// Calls whose token counts were estimated locally rather than reported by Gemini.
+    #[serde(default)]
+    pub estimated_token_calls: u64,
+}
+
+impl Totals {
+    fn add(&mut self, usage: &Usage, cost: f64) {
+        self.calls += 1;
+        self.prompt_tokens += usage.prompt_tokens as u64;
+        self.completion_tokens += usage.completion_tokens as u64;
+        self.estimated_cost_usd += cost;
+        if usage.estimated {
+            self.estimated_token_calls += 1;
+        }
+    }
+
+    fn merge(&mut self, other: &Totals) {
+        self.calls += other.calls;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.estimated_cost_usd += other.estimated_cost_usd;
+        self.estimated_token_calls += other.estimated_token_calls;
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DayUsage {
+    #[serde(flatten)]
+    pub totals: Totals,
+    #[serde(default)]
+    pub by_model: BTreeMap<String, Totals>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct UsageState {
+    /// Keyed by UTC date (`YYYY-MM-DD`).
+    #[serde(default)]
+    days: BTreeMap<String, DayUsage>,
+    /// Keyed by chat session id.
+    #[serde(default)]
+    sessions: BTreeMap<String, Totals>,
+}
+
+/// Per-day and per-session usage totals, persisted as JSON after every call.
+pub struct UsageTracker {
+    path: Option<PathBuf>,
+    state: Mutex<UsageState>,
+}
+
+impl UsageTracker {
+    /// Load totals from `path`, starting empty when it does not exist yet.
+    ///
+    /// With no path, usage is only kept in memory.
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let state = path
+            .as_ref()
+            .filter(|path| path.exists())
+            .and_then(|path| match std::fs::read(path) {
+                Ok(bytes) => serde_json::from_slice(&bytes)
+                    .map_err(|e| tracing::warn!("Ignoring unreadable usage file {}: {}", path.display(), e))
+                    .ok(),
+                Err(e) => {
+                    tracing::warn!("Failed to read usage file {}: {}", path.display(), e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+
+        Self {
+            path,
+            state: Mutex::new(state),
+        }
+    }
+
+    /// Record one call and return its estimated cost in USD.
+    pub fn record(&self, model: &str, session_id: Option<&str>, usage: &Usage) -> f64 {
+        let cost = models::pricing(model)
+            .map(|pricing| pricing.cost(usage.prompt_tokens, usage.completion_tokens))
+            .unwrap_or(0.0);
+
+        let mut state = self.state.lock().unwrap();
+
+        let day = state.days.entry(today()).or_default();
+        day.totals.add(usage, cost);
+        day.by_model.entry(model.to_string()).or_default().add(usage, cost);
+
+        if let Some(session_id) = session_id {
+            state.sessions.entry(session_id.to_string()).or_default().add(usage, cost);
+        }
+
+        self.persist(&state);
+        cost
+    }
+
+    /// Totals for the current UTC day.
+    pub fn today(&self) -> DayUsage {
+        self.state.lock().unwrap().days.get(&today()).cloned().unwrap_or_default()
+    }
+
+    /// Usage for the last `days` days (including today) plus an optional session.
+    pub fn report(&self, days: u32, session_id: Option<&str>) -> serde_json::Value {
+        let state = self.state.lock().unwrap();
+
+        let since = (Utc::now() - ChronoDuration::days(days.saturating_sub(1) as i64))
+            .format("%Y-%m-%d")
+            .to_string();
+
+        let mut total = Totals::default();
+        let mut daily = BTreeMap::new();
+        for (date, usage) in state.days.range(since..) {
+            total.merge(&usage.totals);
+            daily.insert(date.clone(), usage.clone());
+        }
+
+        serde_json::json!({
+            "today": state.days.get(&today()).cloned().unwrap_or_default(),
+            "days": daily,
+            "total": total,
+            "session": session_id.map(|id| state.sessions.get(id).cloned().unwrap_or_default()),
+        })
+    }
+
+    fn persist(&self, state: &UsageState) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let result = (|| -> anyhow::Result<()> {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            // Write then rename so a crash never leaves a truncated file behind
+            let tmp = path.with_extension("json.tmp");
+            std::fs::write(&tmp, serde_json::to_vec_pretty(state)?)?;
+            std::fs::rename(&tmp, path)?;
+            Ok(())
+        })();
+
+        if let Err(e) = result {
+            tracing::warn!("Failed to persist usage to {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn today() -> String {
+    Utc::now().format("%Y-%m-%d").to_string()
+}
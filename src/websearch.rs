@@ -0,0 +1,66 @@
+use regex::Regex;
+use std::sync::OnceLock;
+
+use crate::backend::Citation;
+
+/// Name of the gemini CLI's built-in search tool.
+pub const SEARCH_TOOL: &str = "google_web_search";
+
+/// Prompt asking the gemini CLI to search with its built-in tool, answer from
+/// the results and list them in a form [`split_sources`] can read back.
+pub fn search_prompt(query: &str) -> String {
+    format!(
+        "Use the {} tool to search the web for the question below, then answer it from the results. \
+         Search again if the first results do not answer it.\n\n\
+         End your reply with a line that says exactly \"Sources:\", followed by one line per result \
+         you used, formatted as \"- [title](url)\".\n\n\
+         Question: {}",
+        SEARCH_TOOL,
+        query.trim()
+    )
+}
+
+/// Split a reply into the answer and the results listed under its trailing
+/// `Sources:` heading.
+///
+/// Replies without the heading are returned whole, with every link they
+/// contain as a result.
+pub fn split_sources(reply: &str) -> (String, Vec<Citation>) {
+    static HEADING: OnceLock<Regex> = OnceLock::new();
+    let heading = HEADING.get_or_init(|| {
+        Regex::new(r"(?im)^[#*\s]*sources\s*:?[*\s]*$").expect("sources heading pattern is valid")
+    });
+
+    match heading.find_iter(reply).last() {
+        Some(found) => {
+            let answer = reply[..found.start()].trim_end().to_string();
+            (answer, links(&reply[found.end()..]))
+        }
+        None => (reply.trim().to_string(), links(reply)),
+    }
+}
+
+/// Markdown links and bare URLs in `text`, in order and without repeats.
+fn links(text: &str) -> Vec<Citation> {
+    static LINK: OnceLock<Regex> = OnceLock::new();
+    let link = LINK.get_or_init(|| {
+        Regex::new(r"\[([^\]]+)\]\((https?://[^)\s]+)\)|(https?://[^\s)>\]]+)").expect("link pattern is valid")
+    });
+
+    let mut citations: Vec<Citation> = Vec::new();
+    for captures in link.captures_iter(text) {
+        let (title, uri) = match (captures.get(1), captures.get(2), captures.get(3)) {
+            (Some(title), Some(uri), _) => (Some(title.as_str().trim().to_string()), uri.as_str()),
+            (_, _, Some(uri)) => (None, uri.as_str().trim_end_matches(['.', ',', ';'])),
+            _ => continue,
+        };
+        if citations.iter().any(|citation| citation.uri == uri) {
+            continue;
+        }
+        citations.push(Citation {
+            title,
+            uri: uri.to_string(),
+        });
+    }
+    citations
+}
@@ -0,0 +1,964 @@
+//! End-to-end tests of the cli backend against a scripted gemini CLI.
+#![cfg(unix)]
+
+mod support;
+
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use support::{body, kind, metadata, text, FakeApi, MockGemini, Server, CONFIG_PATH};
+
+#[tokio::test]
+async fn prompt_returns_the_cli_answer() {
+    let mock = MockGemini::new().stdout("mock answer\n");
+    let server = Server::start(&mock).await;
+
+    let result = server.call("gemini_prompt", json!({ "prompt": "hello" })).await.expect("prompt succeeds");
+
+    assert!(text(&result).contains("mock answer"), "unexpected result: {}", text(&result));
+    let calls = mock.calls();
+    assert_eq!(calls.first().map(String::as_str), Some("--version"), "version is probed at startup");
+    assert!(calls.iter().any(|call| call.starts_with("--prompt hello")), "unexpected calls: {:?}", calls);
+}
+
+#[tokio::test]
+async fn cli_failure_is_backend_failed() {
+    let mock = MockGemini::new().stderr("something broke").exit_code(1);
+    let server = Server::start(&mock).await;
+
+    let error = server.call("gemini_prompt", json!({ "prompt": "hello" })).await.expect_err("prompt fails");
+
+    assert_eq!(error.code.0, -32000);
+    assert_eq!(kind(&error), "backend_failed");
+    assert!(error.message.contains("something broke"), "unexpected message: {}", error.message);
+}
+
+#[tokio::test]
+async fn missing_credentials_are_auth_failed_with_remediation() {
+    let mock = MockGemini::new()
+        .stderr("Please set an Auth method in your settings.json or specify GEMINI_API_KEY")
+        .exit_code(1);
+    let server = Server::start(&mock).await;
+
+    let error = server.call("gemini_prompt", json!({ "prompt": "hello" })).await.expect_err("prompt fails");
+
+    assert_eq!(error.code.0, -32002);
+    assert_eq!(kind(&error), "auth_failed");
+    let data = error.data.expect("error has data");
+    assert_eq!(data["problem"], "missing_credentials");
+    assert_eq!(data["env_var"], "GEMINI_API_KEY");
+    assert_eq!(data["retryable"], false);
+}
+
+#[tokio::test]
+async fn exhausted_quota_is_quota_exceeded() {
+    let mock = MockGemini::new().stderr("RESOURCE_EXHAUSTED: Quota exceeded for this project").exit_code(1);
+    let server = Server::start(&mock).await;
+
+    let error = server.call("gemini_prompt", json!({ "prompt": "hello" })).await.expect_err("prompt fails");
+
+    assert_eq!(error.code.0, -32003);
+    assert_eq!(kind(&error), "quota_exceeded");
+}
+
+#[tokio::test]
+async fn slow_cli_times_out() {
+    let mock = MockGemini::new().stdout("too late").delay(Duration::from_secs(30));
+    let server = Server::start(&mock).await;
+
+    let started = std::time::Instant::now();
+    let error = server
+        .call("gemini_prompt", json!({ "prompt": "hello", "timeout_secs": 1 }))
+        .await
+        .expect_err("prompt times out");
+
+    assert_eq!(error.code.0, -32005);
+    assert_eq!(kind(&error), "timeout");
+    assert_eq!(error.data.expect("error has data")["timeout_secs"], 1);
+    assert!(started.elapsed() < Duration::from_secs(15), "the mock was not killed in time");
+}
+
+#[tokio::test]
+async fn old_cli_lacks_json_output() {
+    let mock = MockGemini::new().version("0.5.2").stdout("{}");
+    let server = Server::start(&mock).await;
+
+    let error = server
+        .call("gemini_prompt", json!({ "prompt": "hello", "output_format": "json" }))
+        .await
+        .expect_err("prompt is rejected");
+
+    assert_eq!(error.code.0, -32011);
+    assert_eq!(kind(&error), "cli_too_old");
+    let data = error.data.expect("error has data");
+    assert_eq!(data["feature"], "--output-format");
+    assert_eq!(data["found_version"], "0.5.2");
+    assert_eq!(mock.calls(), vec!["--version"], "gemini never runs the prompt");
+}
+
+#[tokio::test]
+async fn missing_binary_is_binary_missing() {
+    let home = tempfile::tempdir().expect("create directory");
+    let server = Server::start_with_binary(&home.path().join("no-such-gemini")).await;
+
+    let error = server.call("gemini_prompt", json!({ "prompt": "hello" })).await.expect_err("prompt fails");
+
+    assert_eq!(error.code.0, -32001);
+    assert_eq!(kind(&error), "binary_missing");
+}
+
+#[tokio::test]
+async fn silent_cli_stalls() {
+    let mock = MockGemini::new().stdout("too late").delay(Duration::from_secs(30));
+    let server = Server::builder().env("GEMINI_STALL_TIMEOUT_SECS", "1").start(&mock).await;
+
+    let started = std::time::Instant::now();
+    let error = server.call("gemini_prompt", json!({ "prompt": "hello" })).await.expect_err("prompt stalls");
+
+    assert_eq!(error.code.0, -32013);
+    assert_eq!(kind(&error), "stalled");
+    assert!(error.data.expect("error has data")["idle_secs"].as_u64().is_some());
+    assert!(started.elapsed() < Duration::from_secs(15), "the mock was not stopped in time");
+}
+
+#[tokio::test]
+async fn slow_json_cli_does_not_stall() {
+    let mock = MockGemini::new()
+        .stdout(r#"{"response": "{\"ok\": true}", "stats": {}}"#)
+        .delay(Duration::from_secs(3));
+    let server = Server::builder().env("GEMINI_STALL_TIMEOUT_SECS", "1").start(&mock).await;
+
+    let result = server
+        .call("gemini_prompt", json!({ "prompt": "hello", "output_format": "json" }))
+        .await
+        .expect("prompt succeeds");
+
+    assert!(text(&result).contains("\"ok\""), "unexpected result: {}", text(&result));
+}
+
+#[tokio::test]
+async fn generated_tests_cannot_name_files_outside_the_workspace() {
+    let answer = json!({ "files": [{ "path": "/etc/passwd", "contents": "stolen" }] }).to_string();
+    let mock = MockGemini::new().stdout(&json!({ "response": answer }).to_string());
+    let server = Server::start(&mock).await;
+    std::fs::write(server.home().join("lib.py"), "def add(a, b):\n    return a + b\n").expect("write source file");
+
+    let error = server
+        .call("gemini_generate_tests", json!({ "files": ["lib.py"] }))
+        .await
+        .expect_err("the path is refused");
+
+    assert_eq!(kind(&error), "outside_roots");
+    assert!(!error.message.contains("root:"), "the file was read: {}", error.message);
+}
+
+#[tokio::test]
+async fn high_priority_request_passes_queued_low_ones() {
+    let mock = MockGemini::new().stdout("done").delay(Duration::from_secs(2));
+    let server = Server::builder().env("GEMINI_MAX_CONCURRENCY", "1").start(&mock).await;
+
+    // The first call holds the only slot while the others queue behind it
+    let call = |prompt: &'static str, priority: &'static str, after_ms: u64| {
+        let server = &server;
+        async move {
+            tokio::time::sleep(Duration::from_millis(after_ms)).await;
+            server.call("gemini_prompt", json!({ "prompt": prompt, "priority": priority })).await
+        }
+    };
+    let (first, low1, low2, urgent) = tokio::join!(
+        call("first", "normal", 0),
+        call("low1", "low", 300),
+        call("low2", "low", 600),
+        call("urgent", "high", 900),
+    );
+    for result in [first, low1, low2, urgent] {
+        result.expect("prompt succeeds");
+    }
+
+    let calls = mock.calls();
+    let position = |prompt: &str| {
+        calls.iter().position(|call| call.starts_with(&format!("--prompt {}", prompt)))
+            .unwrap_or_else(|| panic!("{} never ran: {:?}", prompt, calls))
+    };
+    assert!(position("first") < position("urgent"), "unexpected order: {:?}", calls);
+    assert!(position("urgent") < position("low1"), "unexpected order: {:?}", calls);
+    assert!(position("urgent") < position("low2"), "unexpected order: {:?}", calls);
+}
+
+#[tokio::test]
+async fn dry_run_extension_install_runs_nothing() {
+    let mock = MockGemini::new();
+    let server = Server::builder()
+        .env("GEMINI_EXTENSION_MANAGEMENT", "true")
+        .env("GEMINI_DRY_RUN", "true")
+        .start(&mock)
+        .await;
+
+    let result = server
+        .call("gemini_install_extension", json!({ "source": "https://github.com/example/extension" }))
+        .await
+        .expect("dry run succeeds");
+
+    let plan: serde_json::Value = serde_json::from_str(&text(&result)).expect("plan is JSON");
+    assert_eq!(plan["backend"], "cli");
+    let calls = mock.calls();
+    assert!(calls.iter().all(|call| !call.starts_with("extensions")), "the CLI ran: {:?}", calls);
+}
+
+#[tokio::test]
+async fn dry_run_chat_creates_no_session() {
+    let mock = MockGemini::new();
+    let server = Server::start(&mock).await;
+
+    server
+        .call("gemini_chat", json!({ "message": "hello", "dry_run": true }))
+        .await
+        .expect("dry run succeeds");
+
+    let listed = server.call("gemini_sessions_list", json!({})).await.expect("list succeeds");
+    assert_eq!(body(&listed)["total"], 0);
+    assert!(mock.prompts().is_empty(), "the CLI ran: {:?}", mock.prompts());
+}
+
+#[tokio::test]
+async fn recorded_answers_replay_without_the_cli() {
+    let recordings = tempfile::tempdir().expect("create directory");
+    let dir = recordings.path().to_str().expect("UTF-8 path");
+    let mock = MockGemini::new().stdout("recorded answer\n");
+    let recorder = Server::builder()
+        .env("GEMINI_RECORDING", "record")
+        .env("GEMINI_RECORDINGS_DIR", dir)
+        .start(&mock)
+        .await;
+    recorder.call("gemini_prompt", json!({ "prompt": "hello" })).await.expect("prompt succeeds");
+
+    // No CLI at all, so every answer must come from the recordings
+    let missing = recordings.path().join("no-such-gemini");
+    let replayer = Server::builder()
+        .env("GEMINI_RECORDING", "replay")
+        .env("GEMINI_RECORDINGS_DIR", dir)
+        .start_with_binary(&missing)
+        .await;
+
+    let result = replayer.call("gemini_prompt", json!({ "prompt": "hello" })).await.expect("replay succeeds");
+    assert!(text(&result).contains("recorded answer"), "unexpected result: {}", text(&result));
+
+    let error = replayer.call("gemini_prompt", json!({ "prompt": "goodbye" })).await.expect_err("nothing recorded");
+    assert_eq!(error.code.0, -32015);
+    assert_eq!(kind(&error), "not_recorded");
+
+    let error = replayer.call("gemini_list_extensions", json!({})).await.expect_err("listings aren't recorded");
+    assert_eq!(kind(&error), "not_recorded");
+}
+
+#[tokio::test]
+async fn version_prints_both_versions() {
+    let mock = MockGemini::new().version("0.31.2");
+    let output = Server::builder().run(&mock, &["version"]).await;
+
+    assert!(output.status.success(), "version failed: {}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("gemini-cli-mcp "), "unexpected output: {}", stdout);
+    assert!(stdout.contains("gemini-cli 0.31.2"), "unexpected output: {}", stdout);
+}
+
+#[tokio::test]
+async fn config_check_reports_the_file_it_loaded() {
+    let mock = MockGemini::new();
+    let output = Server::builder()
+        .config("default_model = \"gemini-2.5-flash\"\n")
+        .run(&mock, &["config", "check"])
+        .await;
+
+    assert!(output.status.success(), "config check failed: {}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Configuration is valid; loaded from"), "unexpected output: {}", stdout);
+    assert!(stdout.contains("gemini-2.5-flash"), "unexpected output: {}", stdout);
+}
+
+#[tokio::test]
+async fn docker_backend_execs_the_cli_in_the_container() {
+    let docker = MockGemini::new().stdout("from the container\n");
+    let server = Server::builder()
+        .config(&format!("[docker]\ncontainer = \"gemini-box\"\nbinary = \"{}\"\n", docker.path().display()))
+        .env("GEMINI_BINARY", "gemini")
+        .start(&docker)
+        .await;
+
+    let result = server.call("gemini_prompt", json!({ "prompt": "hello" })).await.expect("prompt succeeds");
+
+    assert!(text(&result).contains("from the container"), "unexpected result: {}", text(&result));
+    let prompts = docker.prompts();
+    let call = prompts.iter().find(|call| call.contains("--prompt hello")).expect("the prompt ran");
+    assert!(call.starts_with("exec -i "), "unexpected call: {}", call);
+    assert!(call.contains(" gemini-box gemini --prompt hello"), "unexpected call: {}", call);
+}
+
+#[tokio::test]
+async fn ssh_backend_runs_the_cli_on_the_host() {
+    let ssh = MockGemini::new().stdout("from the remote host\n");
+    let server = Server::builder()
+        .config(&format!("[ssh]\nhost = \"build-box\"\nbinary = \"{}\"\n", ssh.path().display()))
+        .env("GEMINI_BINARY", "gemini")
+        .start(&ssh)
+        .await;
+
+    let result = server.call("gemini_prompt", json!({ "prompt": "hello" })).await.expect("prompt succeeds");
+
+    assert!(text(&result).contains("from the remote host"), "unexpected result: {}", text(&result));
+    let prompts = ssh.prompts();
+    let call = prompts.iter().find(|call| call.contains("hello")).expect("the prompt ran");
+    assert!(call.starts_with("-T -o BatchMode=yes build-box "), "unexpected call: {}", call);
+}
+
+#[tokio::test]
+async fn profile_env_reaches_the_cli() {
+    let mock = MockGemini::new().stdout("done");
+    let server = Server::builder()
+        .config("[profiles.work.env]\nGEMINI_API_KEY = \"work-key\"\n")
+        .start(&mock)
+        .await;
+
+    server.call("gemini_prompt", json!({ "prompt": "hello", "profile": "work" })).await.expect("prompt succeeds");
+    assert_eq!(mock.env("GEMINI_API_KEY").as_deref(), Some("work-key"));
+
+    let error = server
+        .call("gemini_prompt", json!({ "prompt": "hello", "profile": "personal" }))
+        .await
+        .expect_err("the profile is unknown");
+    assert_eq!(error.code.0, -32602);
+    assert_eq!(kind(&error), "invalid_argument");
+}
+
+#[tokio::test]
+async fn vertex_settings_reach_the_cli() {
+    let mock = MockGemini::new().stdout("done");
+    let server = Server::builder()
+        .config("[vertex]\nproject = \"billing-project\"\nlocation = \"us-central1\"\n")
+        .start(&mock)
+        .await;
+
+    server.call("gemini_prompt", json!({ "prompt": "hello" })).await.expect("prompt succeeds");
+
+    assert_eq!(mock.env("GOOGLE_GENAI_USE_VERTEXAI").as_deref(), Some("true"));
+    assert_eq!(mock.env("GOOGLE_CLOUD_PROJECT").as_deref(), Some("billing-project"));
+    assert_eq!(mock.env("GOOGLE_CLOUD_LOCATION").as_deref(), Some("us-central1"));
+}
+
+#[tokio::test]
+async fn sampling_parameters_the_cli_lacks_are_ignored_with_warnings() {
+    let mock = MockGemini::new().stdout("done");
+    let server = Server::start(&mock).await;
+
+    let result = server
+        .call("gemini_prompt", json!({ "prompt": "hello", "top_p": 0.5, "seed": 7 }))
+        .await
+        .expect("prompt succeeds");
+
+    let warnings = metadata(&result)["warnings"].to_string();
+    assert!(warnings.contains("top_p was ignored"), "unexpected warnings: {}", warnings);
+    assert!(warnings.contains("seed was ignored"), "unexpected warnings: {}", warnings);
+}
+
+#[tokio::test]
+async fn function_calls_come_back_as_structured_content() {
+    let api = FakeApi::start(&[json!({
+        "candidates": [{
+            "content": { "role": "model", "parts": [{ "functionCall": { "name": "get_weather", "args": { "city": "Tokyo" } } }] },
+        }],
+        "usageMetadata": { "promptTokenCount": 12, "candidatesTokenCount": 3 },
+    })]);
+    let mock = MockGemini::new();
+    let server = Server::builder()
+        .env("GEMINI_API_KEY", "test-key")
+        .env("GEMINI_API_BASE_URL", api.url())
+        .start(&mock)
+        .await;
+
+    let functions = json!([{
+        "name": "get_weather",
+        "description": "Current weather for a city",
+        "parameters": { "type": "object", "properties": { "city": { "type": "string" } }, "required": ["city"] },
+    }]);
+    let result = server
+        .call("gemini_prompt", json!({ "prompt": "What's the weather in Tokyo?", "functions": functions }))
+        .await
+        .expect("prompt succeeds");
+
+    let answer = body(&result);
+    assert_eq!(answer["function_calls"][0]["name"], "get_weather");
+    assert_eq!(answer["function_calls"][0]["args"]["city"], "Tokyo");
+    let requests = api.requests();
+    assert!(requests.iter().any(|request| request.contains("get_weather")), "functions weren't sent: {:?}", requests);
+    assert!(mock.prompts().is_empty(), "the CLI ran: {:?}", mock.prompts());
+}
+
+// The client sends no progress token, so this only shows keepalives don't get in the way
+#[tokio::test]
+async fn slow_call_with_keepalive_still_answers() {
+    let mock = MockGemini::new().stdout("worth the wait").delay(Duration::from_secs(3));
+    let server = Server::builder().env("GEMINI_KEEPALIVE_SECS", "1").start(&mock).await;
+
+    let result = server.call("gemini_prompt", json!({ "prompt": "hello" })).await.expect("prompt succeeds");
+
+    assert!(text(&result).contains("worth the wait"), "unexpected result: {}", text(&result));
+}
+
+#[tokio::test]
+async fn instructions_file_replaces_the_builtin_instructions() {
+    let mock = MockGemini::new();
+    let server = Server::builder()
+        .file("team.md", "Use gemini-2.5-flash for quick questions.\n")
+        .env("GEMINI_INSTRUCTIONS_FILE", "team.md")
+        .start(&mock)
+        .await;
+
+    assert_eq!(server.instructions().trim(), "Use gemini-2.5-flash for quick questions.");
+}
+
+#[tokio::test]
+async fn japanese_locale_translates_instructions_and_descriptions() {
+    let mock = MockGemini::new();
+    let server = Server::builder().env("GEMINI_LOCALE", "ja").start(&mock).await;
+
+    assert!(server.instructions().starts_with("Gemini CLI MCP サーバー"), "unexpected instructions: {}", server.instructions());
+    let tools = server.tools().await;
+    let prompt = tools.iter().find(|tool| tool["name"] == "gemini_prompt").expect("gemini_prompt is listed");
+    assert_eq!(prompt["description"], "Gemini にプロンプトを送信します");
+}
+
+#[tokio::test]
+async fn tools_carry_annotations() {
+    let mock = MockGemini::new();
+    let server = Server::start(&mock).await;
+
+    let tools = server.tools().await;
+    let annotations = |name: &str| {
+        tools.iter().find(|tool| tool["name"] == name).unwrap_or_else(|| panic!("{} is listed", name))["annotations"].clone()
+    };
+    assert_eq!(annotations("gemini_prompt")["readOnlyHint"], true);
+    assert_eq!(annotations("gemini_prompt")["openWorldHint"], true);
+    assert_eq!(annotations("gemini_session_delete")["readOnlyHint"], false);
+    assert_eq!(annotations("gemini_session_delete")["destructiveHint"], true);
+    assert_eq!(annotations("gemini_session_delete")["idempotentHint"], true);
+}
+
+#[tokio::test]
+async fn disabled_tool_is_hidden_and_refused() {
+    let mock = MockGemini::new();
+    let server = Server::builder().config("[tools.gemini_chat]\nenabled = false\n").start(&mock).await;
+
+    let tools = server.tools().await;
+    assert!(tools.iter().all(|tool| tool["name"] != "gemini_chat"), "gemini_chat is listed");
+    assert!(tools.iter().any(|tool| tool["name"] == "gemini_prompt"), "gemini_prompt is not listed");
+
+    let error = server.call("gemini_chat", json!({ "message": "hello" })).await.expect_err("the tool is disabled");
+    assert_eq!(kind(&error), "invalid_argument");
+    assert!(error.message.contains("disabled"), "unexpected message: {}", error.message);
+}
+
+#[tokio::test]
+async fn denied_model_is_policy_denied() {
+    let mock = MockGemini::new();
+    let server = Server::builder().env("GEMINI_DENIED_MODELS", "gemini-2.5-flash").start(&mock).await;
+
+    let error = server
+        .call("gemini_prompt", json!({ "prompt": "hello", "model": "gemini-2.5-flash" }))
+        .await
+        .expect_err("the model is denied");
+
+    assert_eq!(error.code.0, -32012);
+    assert_eq!(kind(&error), "policy_denied");
+    assert!(mock.prompts().is_empty(), "the CLI ran: {:?}", mock.prompts());
+}
+
+/// Leaves 2000 tokens of gemini-2.5-pro's context window for the prompt.
+const SMALL_PROMPT_BUDGET: (&str, &str) = ("GEMINI_OUTPUT_TOKEN_RESERVE", "1046576");
+
+/// A prompt of about 4000 tokens, over [`SMALL_PROMPT_BUDGET`].
+fn long_prompt() -> String {
+    (1..=700).map(|line| format!("line {:04} of the document\n", line)).collect()
+}
+
+#[tokio::test]
+async fn oversized_prompt_is_rejected_or_truncated() {
+    let (reserve, tokens) = SMALL_PROMPT_BUDGET;
+    let mock = MockGemini::new().stdout("done");
+    let server = Server::builder().env(reserve, tokens).start(&mock).await;
+
+    let error = server.call("gemini_prompt", json!({ "prompt": long_prompt() })).await.expect_err("the prompt is too large");
+    assert_eq!(error.code.0, -32602);
+    assert_eq!(kind(&error), "prompt_too_large");
+    assert_eq!(error.data.expect("error has data")["max_prompt_tokens"], 2000);
+    assert!(mock.prompts().is_empty(), "the CLI ran: {:?}", mock.prompts());
+
+    let server = Server::builder().env(reserve, tokens).env("GEMINI_PROMPT_OVERFLOW", "truncate").start(&mock).await;
+    let result = server.call("gemini_prompt", json!({ "prompt": long_prompt() })).await.expect("prompt succeeds");
+    let warnings = metadata(&result)["warnings"].to_string();
+    assert!(warnings.contains("truncated"), "unexpected warnings: {}", warnings);
+    let prompts = mock.prompts();
+    assert!(prompts[0].contains("line 0001") && !prompts[0].contains("line 0350"), "the middle was kept: {}", prompts[0]);
+}
+
+#[tokio::test]
+async fn personal_data_is_scrubbed_from_prompts() {
+    let mock = MockGemini::new().stdout("done");
+    let server = Server::builder().env("GEMINI_PII_FILTER", "true").start(&mock).await;
+
+    let result = server
+        .call("gemini_prompt", json!({ "prompt": "Email jane.doe@example.com about the launch" }))
+        .await
+        .expect("prompt succeeds");
+
+    let prompts = mock.prompts();
+    assert!(prompts[0].contains("Email [EMAIL] about the launch"), "unexpected prompt: {}", prompts[0]);
+    assert!(!prompts[0].contains("jane.doe"), "the address was sent: {}", prompts[0]);
+    assert_eq!(metadata(&result)["redactions"]["email"], 1);
+
+    let counted = server
+        .call("gemini_count_tokens", json!({ "text": "Email jane.doe@example.com about the launch" }))
+        .await
+        .expect("count succeeds");
+    assert_eq!(body(&counted)["redactions"]["email"], 1);
+}
+
+#[tokio::test]
+async fn answers_are_post_processed() {
+    let mock = MockGemini::new().stdout("Sure, here's the answer:\nThe colour is blue.\n");
+    let server = Server::builder()
+        .config(
+            "[[postprocess.gemini_prompt]]\nkind = \"trim_preamble\"\n\n\
+             [[postprocess.gemini_prompt]]\nkind = \"replace\"\npattern = \"colour\"\nreplacement = \"color\"\n",
+        )
+        .start(&mock)
+        .await;
+
+    let result = server.call("gemini_prompt", json!({ "prompt": "hello" })).await.expect("prompt succeeds");
+
+    assert!(text(&result).contains("The color is blue."), "unexpected result: {}", text(&result));
+    assert!(!text(&result).contains("Sure"), "the preamble was kept: {}", text(&result));
+}
+
+#[tokio::test]
+async fn prompt_templates_render_with_defaults() {
+    let mock = MockGemini::new();
+    let server = Server::builder()
+        .file(
+            ".config/gemini-mcp/prompts/greet.toml",
+            "description = \"Greet someone\"\ntemplate = \"Say hello to {{name}} in {{language}}.\"\n\n\
+             [[arguments]]\nname = \"name\"\nrequired = true\n\n\
+             [[arguments]]\nname = \"language\"\ndefault = \"English\"\n",
+        )
+        .start(&mock)
+        .await;
+
+    let prompt = server.get_prompt(json!({ "name": "greet", "arguments": { "name": "Ada" } })).await;
+
+    assert_eq!(prompt["messages"][0]["content"]["text"], "Say hello to Ada in English.");
+}
+
+#[tokio::test]
+async fn pipeline_feeds_each_step_the_previous_output() {
+    let mock = MockGemini::new().stdout("draft\n");
+    let server = Server::start(&mock).await;
+
+    let steps = json!([{ "template": "Outline {{input}}" }, { "template": "Polish {{previous}}" }]);
+    server.call("gemini_pipeline", json!({ "input": "rust", "steps": steps })).await.expect("pipeline succeeds");
+
+    let prompts = mock.prompts();
+    assert_eq!(prompts.len(), 2, "unexpected calls: {:?}", prompts);
+    assert!(prompts[0].starts_with("--prompt Outline rust"), "unexpected calls: {:?}", prompts);
+    assert!(prompts[1].starts_with("--prompt Polish draft"), "unexpected calls: {:?}", prompts);
+}
+
+#[tokio::test]
+async fn summarize_files_maps_each_file_then_reduces() {
+    let mock = MockGemini::new().stdout("a summary\n");
+    let server = Server::builder()
+        .file("notes/a.md", "# Alpha\nThe first file.\n")
+        .file("notes/b.md", "# Beta\nThe second file.\n")
+        .start(&mock)
+        .await;
+
+    let result = server.call("gemini_summarize_files", json!({ "files": ["notes/*.md"] })).await.expect("summary succeeds");
+
+    let metadata = metadata(&result);
+    assert_eq!(metadata["files"], 2);
+    assert_eq!(metadata["chunks"], 2);
+    assert_eq!(mock.prompts().len(), 3, "expected two map calls and one reduce: {:?}", mock.prompts());
+}
+
+#[tokio::test]
+async fn oversized_prompt_is_answered_in_parts() {
+    let (reserve, tokens) = SMALL_PROMPT_BUDGET;
+    let mock = MockGemini::new().stdout("partial answer\n");
+    let server = Server::builder().env(reserve, tokens).env("GEMINI_PROMPT_OVERFLOW", "chunk").start(&mock).await;
+
+    let result = server.call("gemini_prompt", json!({ "prompt": long_prompt() })).await.expect("prompt succeeds");
+
+    let warnings = metadata(&result)["warnings"].to_string();
+    assert!(warnings.contains("answered in"), "unexpected warnings: {}", warnings);
+    let prompts = mock.prompts();
+    assert!(prompts.len() >= 3, "expected two or more parts and a consolidation: {:?}", prompts);
+    assert!(prompts.iter().any(|prompt| prompt.contains("<part index=\"1\"")), "no part was sent: {:?}", prompts);
+}
+
+#[tokio::test]
+async fn generated_file_is_written_when_asked() {
+    let mock = MockGemini::new().stdout("```python\ndef add(a, b):\n    return a + b\n```\n");
+    let server = Server::start(&mock).await;
+
+    let result = server
+        .call("gemini_generate_file", json!({ "path": "calc.py", "instructions": "Add two numbers", "write": true }))
+        .await
+        .expect("generation succeeds");
+
+    let metadata = metadata(&result);
+    assert_eq!(metadata["created"], true);
+    assert_eq!(metadata["written"], true);
+    let written = std::fs::read_to_string(server.home().join("calc.py")).expect("the file was written");
+    assert_eq!(written, "def add(a, b):\n    return a + b\n");
+}
+
+#[tokio::test]
+async fn given_patch_is_checked_before_it_is_applied() {
+    let mock = MockGemini::new();
+    let server = Server::builder().file("notes.txt", "one\ntwo\n").start(&mock).await;
+    let patch = |old: &str| {
+        format!("--- a/notes.txt\n+++ b/notes.txt\n@@ -1,2 +1,2 @@\n one\n-{}\n+three\n", old)
+    };
+
+    let result = server
+        .call("gemini_propose_patch", json!({ "patch": patch("deux"), "apply": true }))
+        .await
+        .expect("the check succeeds");
+    assert_eq!(metadata(&result)["applies"], false);
+    assert_eq!(std::fs::read_to_string(server.home().join("notes.txt")).unwrap(), "one\ntwo\n");
+
+    let result = server
+        .call("gemini_propose_patch", json!({ "patch": patch("two"), "apply": true }))
+        .await
+        .expect("the patch applies");
+    assert_eq!(metadata(&result)["applied"], true);
+    assert_eq!(std::fs::read_to_string(server.home().join("notes.txt")).unwrap(), "one\nthree\n");
+    assert!(mock.prompts().is_empty(), "the CLI ran: {:?}", mock.prompts());
+}
+
+/// Run git in `repo` as a throwaway identity, failing the test if it fails.
+fn git(repo: &Path, args: &[&str]) {
+    let output = std::process::Command::new("git")
+        .args(["-c", "user.name=Test", "-c", "user.email=test@example.com", "-c", "commit.gpgsign=false"])
+        .args(args)
+        .current_dir(repo)
+        .output()
+        .expect("run git");
+    assert!(output.status.success(), "git {:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+}
+
+/// A repository in `dir` on `main` with one commit, tagged `v0.1.0`.
+fn repo(dir: &Path) -> PathBuf {
+    let repo = dir.join("repo");
+    std::fs::create_dir(&repo).expect("create repository");
+    git(&repo, &["init", "-q", "-b", "main"]);
+    std::fs::write(repo.join("README.md"), "# Demo\n").expect("write file");
+    git(&repo, &["add", "README.md"]);
+    git(&repo, &["commit", "-q", "-m", "chore: start the project"]);
+    git(&repo, &["tag", "v0.1.0"]);
+    repo
+}
+
+#[tokio::test]
+async fn commit_message_describes_the_staged_diff() {
+    let mock = MockGemini::new().stdout("feat: add a greeting\n\nSay hello on startup.\n");
+    let server = Server::start(&mock).await;
+    let repo = repo(server.home());
+    std::fs::write(repo.join("greeting.txt"), "hello\n").expect("write file");
+    git(&repo, &["add", "greeting.txt"]);
+
+    let result = server
+        .call("gemini_commit_message", json!({ "repo_path": repo.to_str().unwrap() }))
+        .await
+        .expect("message succeeds");
+
+    assert!(text(&result).starts_with("feat: add a greeting"), "unexpected result: {}", text(&result));
+    assert_eq!(metadata(&result)["problems"], json!([]));
+    let prompts = mock.prompts();
+    assert!(prompts[0].contains("+hello"), "the diff wasn't sent: {}", prompts[0]);
+}
+
+#[tokio::test]
+async fn pr_description_covers_the_branch_commits() {
+    let description = json!({
+        "title": "Add a greeting",
+        "summary": "Says hello on startup.",
+        "changes": ["Add greeting.txt"],
+        "test_plan": ["Start the app"],
+    });
+    let mock = MockGemini::new().stdout(&json!({ "response": description.to_string() }).to_string());
+    let server = Server::start(&mock).await;
+    let repo = repo(server.home());
+    git(&repo, &["checkout", "-q", "-b", "greeting"]);
+    std::fs::write(repo.join("greeting.txt"), "hello\n").expect("write file");
+    git(&repo, &["add", "greeting.txt"]);
+    git(&repo, &["commit", "-q", "-m", "feat: add a greeting"]);
+
+    let result = server
+        .call("gemini_pr_description", json!({ "repo_path": repo.to_str().unwrap(), "base": "main" }))
+        .await
+        .expect("description succeeds");
+
+    assert!(text(&result).starts_with("# Add a greeting"), "unexpected result: {}", text(&result));
+    assert_eq!(metadata(&result)["commits"], 1);
+    assert!(mock.prompts()[0].contains("feat: add a greeting"), "the commits weren't sent");
+}
+
+#[tokio::test]
+async fn changelog_groups_the_commits_since_a_tag() {
+    let mock = MockGemini::new().stdout("## Unreleased\n- Greeting\n");
+    let server = Server::start(&mock).await;
+    let repo = repo(server.home());
+    for (file, subject) in [("greeting.txt", "feat: add a greeting"), ("README.md", "fix: correct a typo")] {
+        std::fs::write(repo.join(file), subject).expect("write file");
+        git(&repo, &["add", file]);
+        git(&repo, &["commit", "-q", "-m", subject]);
+    }
+
+    let result = server
+        .call("gemini_changelog", json!({ "repo_path": repo.to_str().unwrap(), "from": "v0.1.0" }))
+        .await
+        .expect("changelog succeeds");
+
+    let metadata = metadata(&result);
+    assert_eq!(metadata["commits"], 2);
+    assert_eq!(metadata["groups"], json!({ "features": 1, "fixes": 1 }));
+    assert_eq!(metadata["version"], "Unreleased");
+}
+
+#[tokio::test]
+async fn explained_error_pulls_in_the_lines_it_names() {
+    let mock = MockGemini::new().stdout("The input isn't a number.\n");
+    let server = Server::builder()
+        .file("src/lib.rs", "pub fn parse(input: &str) -> u32 {\n    input.parse().unwrap()\n}\n")
+        .start(&mock)
+        .await;
+
+    let error = "thread 'main' panicked at src/lib.rs:2:19:\n\
+                 called `Result::unwrap()` on an `Err` value: ParseIntError { kind: InvalidDigit }";
+    let result = server.call("gemini_explain_error", json!({ "error": error })).await.expect("explanation succeeds");
+
+    let referenced = &metadata(&result)["referenced"];
+    assert_eq!(referenced[0]["path"], "src/lib.rs");
+    assert_eq!(referenced[0]["lines"], json!([2]));
+    assert!(mock.prompts()[0].contains("input.parse().unwrap()"), "the source wasn't sent");
+}
+
+#[tokio::test]
+async fn log_is_filtered_before_it_is_summarized() {
+    let summary = json!({ "summary": "The disk filled up.", "anomalies": [], "timeline": [] });
+    let mock = MockGemini::new().stdout(&json!({ "response": summary.to_string() }).to_string());
+    let server = Server::builder()
+        .file("app.log", "INFO service starting\nERROR disk full\nINFO request served\n")
+        .start(&mock)
+        .await;
+
+    let result = server
+        .call("gemini_summarize_logs", json!({ "path": "app.log", "grep": "ERROR" }))
+        .await
+        .expect("summary succeeds");
+
+    assert!(text(&result).contains("The disk filled up."), "unexpected result: {}", text(&result));
+    let lines = &metadata(&result)["lines"];
+    assert_eq!(lines["kept_lines"], 1);
+    assert_eq!(lines["filtered_lines"], 2);
+    let prompts = mock.prompts();
+    assert!(prompts[0].contains("ERROR disk full"), "the match wasn't sent: {}", prompts[0]);
+    assert!(!prompts[0].contains("request served"), "filtered lines were sent: {}", prompts[0]);
+}
+
+#[tokio::test]
+async fn chat_history_over_its_budget_drops_the_oldest_turns() {
+    let mock = MockGemini::new().stdout(&format!("{}\n", "a long answer ".repeat(7)));
+    let server = Server::builder()
+        .env("GEMINI_CHAT_HISTORY", "evict")
+        .env("GEMINI_CHAT_HISTORY_TOKENS", "40")
+        .start(&mock)
+        .await;
+
+    let first = server.call("gemini_chat", json!({ "message": "first question" })).await.expect("chat succeeds");
+    let session_id = metadata(&first)["session_id"].as_str().expect("session id").to_string();
+    let second = server
+        .call("gemini_chat", json!({ "message": "second question", "session_id": session_id }))
+        .await
+        .expect("chat succeeds");
+
+    let trimmed = &metadata(&second)["trimmed"];
+    assert_eq!(trimmed["strategy"], "evict");
+    assert_eq!(trimmed["messages"], 2);
+    let prompts = mock.prompts();
+    assert!(!prompts[1].contains("first question"), "the old turn was replayed: {}", prompts[1]);
+}
+
+#[tokio::test]
+async fn sessions_can_be_listed_renamed_and_deleted() {
+    let mock = MockGemini::new().stdout("hi there\n");
+    let server = Server::start(&mock).await;
+    let chat = server.call("gemini_chat", json!({ "message": "hello" })).await.expect("chat succeeds");
+    let session_id = metadata(&chat)["session_id"].as_str().expect("session id").to_string();
+
+    server
+        .call("gemini_session_rename", json!({ "session_id": session_id, "title": "Release planning" }))
+        .await
+        .expect("rename succeeds");
+    let listed = server.call("gemini_sessions_list", json!({})).await.expect("list succeeds");
+    let listed = body(&listed);
+    assert_eq!(listed["total"], 1);
+    assert_eq!(listed["sessions"][0]["title"], "Release planning");
+
+    let session = server.call("gemini_session_get", json!({ "session_id": session_id })).await.expect("get succeeds");
+    let session = body(&session);
+    assert_eq!(session["messages"].as_array().map(Vec::len), Some(2));
+
+    server.call("gemini_session_delete", json!({ "session_id": session_id })).await.expect("delete succeeds");
+    let error = server
+        .call("gemini_session_get", json!({ "session_id": session_id }))
+        .await
+        .expect_err("the session is gone");
+    assert_eq!(kind(&error), "invalid_argument");
+}
+
+#[tokio::test]
+async fn sessions_over_the_storage_quota_are_deleted_oldest_first() {
+    let mock = MockGemini::new().stdout(&format!("{}\n", "x".repeat(60)));
+    let server = Server::builder().env("GEMINI_MAX_SESSION_BYTES", "100").start(&mock).await;
+
+    let first = server.call("gemini_chat", json!({ "message": "first" })).await.expect("chat succeeds");
+    let first_id = metadata(&first)["session_id"].as_str().expect("session id").to_string();
+    let second = server.call("gemini_chat", json!({ "message": "second" })).await.expect("chat succeeds");
+    let second_id = metadata(&second)["session_id"].as_str().expect("session id").to_string();
+
+    let error = server
+        .call("gemini_session_get", json!({ "session_id": first_id }))
+        .await
+        .expect_err("the oldest session was deleted");
+    assert_eq!(kind(&error), "invalid_argument");
+    server.call("gemini_session_get", json!({ "session_id": second_id })).await.expect("the newest session is kept");
+}
+
+#[tokio::test]
+async fn arguments_complete_from_models_and_sessions() {
+    let mock = MockGemini::new().stdout("hi there\n");
+    let server = Server::start(&mock).await;
+    let chat = server.call("gemini_chat", json!({ "message": "hello" })).await.expect("chat succeeds");
+    let session_id = metadata(&chat)["session_id"].as_str().expect("session id").to_string();
+
+    let complete = |argument: &str, value: &str| {
+        server.complete(json!({
+            "ref": { "type": "ref/prompt", "name": "any" },
+            "argument": { "name": argument, "value": value },
+        }))
+    };
+    let models = complete("model", "gemini-2.5-fl").await;
+    assert!(models["completion"]["values"].as_array().unwrap().contains(&json!("gemini-2.5-flash")), "unexpected completion: {}", models);
+    let sessions = complete("session_id", &session_id[..4]).await;
+    assert_eq!(sessions["completion"]["values"], json!([session_id]));
+}
+
+// Log messages aren't observed; this checks that a client asking for them can still call tools
+#[tokio::test]
+async fn calls_work_after_raising_the_log_level() {
+    let mock = MockGemini::new().stdout("mock answer\n");
+    let server = Server::start(&mock).await;
+
+    server.set_level("debug").await;
+    let result = server.call("gemini_prompt", json!({ "prompt": "hello" })).await.expect("prompt succeeds");
+
+    assert!(text(&result).contains("mock answer"), "unexpected result: {}", text(&result));
+}
+
+#[tokio::test]
+async fn edited_config_disables_a_tool_without_a_restart() {
+    let mock = MockGemini::new().stdout("mock answer\n");
+    let server = Server::builder().config("default_model = \"gemini-2.5-pro\"\n").start(&mock).await;
+    server.call("gemini_prompt", json!({ "prompt": "hello" })).await.expect("prompt succeeds");
+
+    // Past the file's modification time granularity, so the edit is seen as a change
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    std::fs::write(server.home().join(CONFIG_PATH), "default_model = \"gemini-2.5-pro\"\n\n[tools.gemini_prompt]\nenabled = false\n")
+        .expect("edit config");
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(20);
+    while server.tools().await.iter().any(|tool| tool["name"] == "gemini_prompt") {
+        assert!(std::time::Instant::now() < deadline, "the edit was not picked up");
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    let error = server.call("gemini_prompt", json!({ "prompt": "hello" })).await.expect_err("the tool is disabled");
+    assert_eq!(kind(&error), "invalid_argument");
+}
+
+#[tokio::test]
+async fn timeout_keeps_the_partial_output() {
+    let mock = MockGemini::new().partial("The first half").delay(Duration::from_secs(30));
+    let server = Server::start(&mock).await;
+
+    let error = server
+        .call("gemini_prompt", json!({ "prompt": "hello", "timeout_secs": 1 }))
+        .await
+        .expect_err("prompt times out");
+    assert_eq!(kind(&error), "timeout");
+    assert_eq!(error.data.expect("error has data")["partial_output"], "The first half");
+
+    let server = Server::builder().env("GEMINI_PARTIAL_ON_TIMEOUT", "true").start(&mock).await;
+    let result = server
+        .call("gemini_prompt", json!({ "prompt": "hello", "timeout_secs": 1 }))
+        .await
+        .expect("the partial output is the answer");
+    assert!(text(&result).starts_with("The first half"), "unexpected result: {}", text(&result));
+    let warnings = metadata(&result)["warnings"].to_string();
+    assert!(warnings.contains("timed out"), "unexpected warnings: {}", warnings);
+}
+
+#[tokio::test]
+async fn repeated_auth_failures_open_the_circuit_breaker() {
+    let mock = MockGemini::new()
+        .stderr("Please set an Auth method in your settings.json or specify GEMINI_API_KEY")
+        .exit_code(1);
+    let server = Server::builder().env("GEMINI_CIRCUIT_BREAKER_FAILURES", "2").start(&mock).await;
+
+    for _ in 0..2 {
+        let error = server.call("gemini_prompt", json!({ "prompt": "hello" })).await.expect_err("prompt fails");
+        assert_eq!(kind(&error), "auth_failed");
+    }
+    let error = server.call("gemini_prompt", json!({ "prompt": "hello" })).await.expect_err("the breaker is open");
+
+    assert_eq!(error.code.0, -32014);
+    assert_eq!(kind(&error), "backend_unhealthy");
+    assert_eq!(mock.prompts().len(), 2, "the CLI ran while the breaker was open");
+}
+
+#[tokio::test]
+async fn results_and_errors_carry_a_request_id() {
+    let mock = MockGemini::new().stdout("mock answer\n");
+    let server = Server::start(&mock).await;
+    let result = server.call("gemini_prompt", json!({ "prompt": "hello" })).await.expect("prompt succeeds");
+    let error = server.call("gemini_session_get", json!({ "session_id": "missing" })).await.expect_err("no such session");
+
+    let succeeded = metadata(&result)["request_id"].as_str().expect("result has a request_id").to_string();
+    let failed = error.data.expect("error has data")["request_id"].as_str().expect("error has a request_id").to_string();
+    assert_eq!(succeeded.len(), 16);
+    assert_ne!(succeeded, failed);
+}
+
+#[tokio::test]
+async fn bench_reports_every_request() {
+    let mock = MockGemini::new().stdout("ok\n");
+    let output = Server::builder()
+        .run(&mock, &["bench", "--requests", "2", "--concurrency", "1", "--json"])
+        .await;
+
+    assert!(output.status.success(), "bench failed: {}", String::from_utf8_lossy(&output.stderr));
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).expect("report is JSON");
+    assert_eq!(report["backend"], "cli");
+    assert_eq!(report["total"]["requests"], 2);
+    assert_eq!(report["total"]["failed"], 0);
+    assert_eq!(mock.prompts().len(), 2, "unexpected calls: {:?}", mock.prompts());
+}
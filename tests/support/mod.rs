@@ -0,0 +1,367 @@
+//! Runs the server against a scripted stand-in for the gemini CLI.
+//!
+//! The crate is a binary, so the server can't be built in the test process:
+//! each test starts the built binary and talks to it over stdio with an MCP
+//! client that does run in-process. The client handles no notifications, so
+//! progress pings, log messages and `list_changed` are only seen through
+//! their effects on later requests.
+
+use rmcp::model::{CallToolRequestParam, CallToolResult};
+use rmcp::service::{RunningService, ServiceError, ServiceExt};
+use rmcp::transport::TokioChildProcess;
+use rmcp::{Error as McpError, RoleClient};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A fake `gemini` executable that answers every prompt the same way and
+/// records the arguments and environment of each run.
+pub struct MockGemini {
+    dir: tempfile::TempDir,
+    version: String,
+    partial: String,
+    stdout: String,
+    stderr: String,
+    exit_code: i32,
+    delay: Duration,
+}
+
+impl MockGemini {
+    /// A CLI recent enough for every flag that prints nothing and succeeds.
+    pub fn new() -> Self {
+        Self {
+            dir: tempfile::tempdir().expect("create mock gemini directory"),
+            version: "0.30.0".to_string(),
+            partial: String::new(),
+            stdout: String::new(),
+            stderr: String::new(),
+            exit_code: 0,
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// What `gemini --version` prints.
+    pub fn version(mut self, version: &str) -> Self {
+        self.version = version.to_string();
+        self
+    }
+
+    /// What each run prints before its delay, e.g. the start of an answer that times out.
+    pub fn partial(mut self, partial: &str) -> Self {
+        self.partial = partial.to_string();
+        self
+    }
+
+    pub fn stdout(mut self, stdout: &str) -> Self {
+        self.stdout = stdout.to_string();
+        self
+    }
+
+    pub fn stderr(mut self, stderr: &str) -> Self {
+        self.stderr = stderr.to_string();
+        self
+    }
+
+    pub fn exit_code(mut self, exit_code: i32) -> Self {
+        self.exit_code = exit_code;
+        self
+    }
+
+    /// How long each run takes before answering.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Where the script is written, for settings that name it, such as `docker.binary`.
+    pub fn path(&self) -> PathBuf {
+        self.dir.path().join("gemini")
+    }
+
+    /// The arguments of every run so far, one string each, `--version` probes included.
+    pub fn calls(&self) -> Vec<String> {
+        std::fs::read_to_string(self.dir.path().join("calls"))
+            .unwrap_or_default()
+            .split_terminator('\0')
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// The runs so far that weren't `--version` probes.
+    pub fn prompts(&self) -> Vec<String> {
+        self.calls().into_iter().filter(|call| call != "--version").collect()
+    }
+
+    /// `name` in the environment of the latest run other than a `--version` probe.
+    pub fn env(&self, name: &str) -> Option<String> {
+        let env = std::fs::read_to_string(self.dir.path().join("env")).ok()?;
+        env.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix('='))
+            .map(str::to_string)
+    }
+
+    /// Write the script and return its path.
+    fn install(&self) -> PathBuf {
+        let dir = self.dir.path();
+        std::fs::write(dir.join("version"), format!("{}\n", self.version)).expect("write mock version");
+        std::fs::write(dir.join("partial"), &self.partial).expect("write mock partial output");
+        std::fs::write(dir.join("stdout"), &self.stdout).expect("write mock stdout");
+        std::fs::write(dir.join("stderr"), &self.stderr).expect("write mock stderr");
+
+        // Outputs live in files next to the script so they need no shell quoting, and
+        // calls are NUL-separated since a prompt may span lines
+        let script = format!(
+            "#!/bin/sh\n\
+             printf '%s\\000' \"$*\" >> '{dir}/calls'\n\
+             if [ \"$1\" = \"--version\" ]; then cat '{dir}/version'; exit 0; fi\n\
+             env > '{dir}/env'\n\
+             cat '{dir}/partial'\n\
+             sleep {delay}\n\
+             cat '{dir}/stdout'\n\
+             cat '{dir}/stderr' >&2\n\
+             exit {code}\n",
+            dir = dir.display(),
+            delay = self.delay.as_secs_f64(),
+            code = self.exit_code,
+        );
+        let path = self.path();
+        std::fs::write(&path, script).expect("write mock gemini");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).expect("make mock gemini executable");
+        path
+    }
+}
+
+impl Default for MockGemini {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The server binary, connected to over stdio by an in-process MCP client.
+pub struct Server {
+    client: RunningService<RoleClient, ()>,
+    home: tempfile::TempDir,
+}
+
+/// How to start a [`Server`].
+pub struct Builder {
+    home: tempfile::TempDir,
+    env: Vec<(String, String)>,
+}
+
+impl Server {
+    /// Start the server with `mock` as its gemini CLI.
+    pub async fn start(mock: &MockGemini) -> Self {
+        Self::builder().start(mock).await
+    }
+
+    /// Start the server with `binary` as its gemini CLI, which need not exist.
+    pub async fn start_with_binary(binary: &Path) -> Self {
+        Self::builder().start_with_binary(binary).await
+    }
+
+    pub fn builder() -> Builder {
+        Builder {
+            // An empty home, so no config file, .env or gemini settings of the machine apply
+            home: tempfile::tempdir().expect("create server home"),
+            env: Vec::new(),
+        }
+    }
+
+    /// The server's home and working directory.
+    pub fn home(&self) -> &Path {
+        self.home.path()
+    }
+
+    /// Call `tool` with `arguments`, which must be a JSON object.
+    pub async fn call(&self, tool: &str, arguments: serde_json::Value) -> Result<CallToolResult, McpError> {
+        let request = CallToolRequestParam {
+            name: tool.to_string().into(),
+            arguments: arguments.as_object().cloned(),
+        };
+        match self.client.call_tool(request).await {
+            Ok(result) => Ok(result),
+            Err(ServiceError::McpError(error)) => Err(error),
+            Err(e) => panic!("calling {} failed outside MCP: {}", tool, e),
+        }
+    }
+
+    /// Every tool the server lists, as clients see them.
+    pub async fn tools(&self) -> Vec<serde_json::Value> {
+        let tools = self.client.list_all_tools().await.expect("list tools");
+        tools.into_iter().map(|tool| serde_json::to_value(tool).expect("tool is JSON")).collect()
+    }
+
+    /// The instructions the server sent when the client connected.
+    pub fn instructions(&self) -> String {
+        self.client.peer_info()
+            .and_then(|info| info.instructions.clone())
+            .expect("server sends instructions")
+    }
+
+    /// Send `completion/complete` with `params` as they go over the wire.
+    pub async fn complete(&self, params: serde_json::Value) -> serde_json::Value {
+        let params = serde_json::from_value(params).expect("completion request");
+        let result = self.client.complete(params).await.expect("complete");
+        serde_json::to_value(result).expect("completion is JSON")
+    }
+
+    /// Send `prompts/get` with `params` as they go over the wire.
+    pub async fn get_prompt(&self, params: serde_json::Value) -> serde_json::Value {
+        let params = serde_json::from_value(params).expect("prompt request");
+        let result = self.client.get_prompt(params).await.expect("get prompt");
+        serde_json::to_value(result).expect("prompt is JSON")
+    }
+
+    /// Send `logging/setLevel` for `level`, e.g. `debug`.
+    pub async fn set_level(&self, level: &str) {
+        let params = serde_json::from_value(serde_json::json!({ "level": level })).expect("log level");
+        self.client.set_level(params).await.expect("set log level");
+    }
+}
+
+impl Builder {
+    /// Set `name` for the server, e.g. a `GEMINI_*` setting.
+    pub fn env(mut self, name: &str, value: &str) -> Self {
+        self.env.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Write `contents` to `path` in the server's home, creating its directories.
+    pub fn file(self, path: &str, contents: &str) -> Self {
+        let path = self.home.path().join(path);
+        std::fs::create_dir_all(path.parent().expect("file has a directory")).expect("create directory");
+        std::fs::write(&path, contents).expect("write file");
+        self
+    }
+
+    /// Use `toml` as the config file, where the server looks for it by default.
+    pub fn config(self, toml: &str) -> Self {
+        self.file(CONFIG_PATH, toml)
+    }
+
+    pub async fn start(self, mock: &MockGemini) -> Server {
+        let binary = mock.install();
+        self.start_with_binary(&binary).await
+    }
+
+    pub async fn start_with_binary(self, binary: &Path) -> Server {
+        let mut cmd = self.command(binary);
+        cmd.kill_on_drop(true);
+
+        let transport = TokioChildProcess::new(cmd).expect("start server");
+        let client = ().serve(transport).await.expect("initialize server");
+        Server { client, home: self.home }
+    }
+
+    /// Run the subcommand `args`, e.g. `["version"]`, with `mock` as the gemini CLI.
+    pub async fn run(self, mock: &MockGemini, args: &[&str]) -> Output {
+        let binary = mock.install();
+        self.command(&binary).args(args).output().await.expect("run server binary")
+    }
+
+    fn command(&self, binary: &Path) -> tokio::process::Command {
+        let mut cmd = tokio::process::Command::new(env!("CARGO_BIN_EXE_gemini-cli-mcp"));
+        cmd.env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("HOME", self.home.path())
+            .env("GEMINI_BINARY", binary)
+            .env("GEMINI_MAX_RETRIES", "0")
+            .envs(self.env.clone())
+            .current_dir(self.home.path());
+        cmd
+    }
+}
+
+/// Where the server finds its config file, relative to its home.
+pub const CONFIG_PATH: &str = ".config/gemini-mcp/config.toml";
+
+/// A stand-in for the Gemini API that streams the same events in answer to
+/// every request and keeps the bodies of the requests it got.
+pub struct FakeApi {
+    url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl FakeApi {
+    /// Listen on a free local port, answering with `events`, each sent as one server-sent event.
+    pub fn start(events: &[serde_json::Value]) -> Self {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind fake API");
+        let url = format!("http://{}", listener.local_addr().expect("fake API address"));
+        let body: String = events.iter().map(|event| format!("data: {}\r\n\r\n", event)).collect();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        // Lives as long as the test process; every request is answered on its own connection
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                answer(stream, &body, &received);
+            }
+        });
+        Self { url, requests }
+    }
+
+    /// The base URL, for `GEMINI_API_BASE_URL`.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The body of every request so far.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Read one HTTP request from `stream`, keep its body in `received`, then answer
+/// it with the event stream `body`.
+fn answer(mut stream: std::net::TcpStream, body: &str, received: &Mutex<Vec<String>>) -> Option<()> {
+    let mut reader = BufReader::new(stream.try_clone().ok()?);
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        if line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().ok()?;
+            }
+        }
+    }
+    let mut request = vec![0; length];
+    reader.read_exact(&mut request).ok()?;
+    received.lock().unwrap().push(String::from_utf8_lossy(&request).into_owned());
+
+    let response = format!("HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\n{}", body);
+    stream.write_all(response.as_bytes()).ok()
+}
+
+/// Every text block of `result`, joined by newlines.
+pub fn text(result: &CallToolResult) -> String {
+    result
+        .content
+        .iter()
+        .filter_map(|content| content.as_text().map(|text| text.text.clone()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The first block of `result` as JSON, for tools that answer with JSON alone.
+pub fn body(result: &CallToolResult) -> serde_json::Value {
+    let text = result.content.first().and_then(|content| content.as_text()).expect("result starts with a text block");
+    serde_json::from_str(&text.text).expect("body is JSON")
+}
+
+/// The JSON block that closes `result`, holding its metadata.
+pub fn metadata(result: &CallToolResult) -> serde_json::Value {
+    let text = result.content.last().and_then(|content| content.as_text()).expect("result ends with a text block");
+    serde_json::from_str(&text.text).expect("metadata is JSON")
+}
+
+/// `data.kind` of an error the server returned.
+pub fn kind(error: &McpError) -> &str {
+    error.data.as_ref().and_then(|data| data["kind"].as_str()).unwrap_or_default()
+}